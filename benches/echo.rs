@@ -0,0 +1,56 @@
+//! Benchmarks the round trip of a message through an echo server on localhost, including the
+//! event loops of both endpoints.
+//!
+//! Run with `cargo bench --bench echo`.
+
+extern crate criterion;
+extern crate ws;
+
+use std::sync::mpsc;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ws::{Builder, CloseCode, Message, Sender};
+
+const SIZES: &[usize] = &[16, 1024, 65_536, 1 << 20];
+
+fn echo(c: &mut Criterion) {
+    let server = Builder::new()
+        .spawn_local(|| |out: Sender| move |msg| out.send(msg))
+        .unwrap();
+
+    let (opened, senders) = mpsc::channel();
+    let (echoed, messages) = mpsc::channel();
+    let url = server.url().to_string();
+    let client = thread::spawn(move || {
+        ws::connect(url, |out| {
+            opened.send(out).unwrap();
+            let echoed = echoed.clone();
+            move |msg: Message| {
+                echoed.send(msg).unwrap();
+                Ok(())
+            }
+        }).unwrap();
+    });
+    let out = senders.recv().unwrap();
+
+    let mut group = c.benchmark_group("echo");
+    for &size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        let payload = vec![b'x'; size];
+        group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| {
+            b.iter(|| {
+                out.send(payload.clone()).unwrap();
+                messages.recv().unwrap()
+            })
+        });
+    }
+    group.finish();
+
+    out.close(CloseCode::Normal).unwrap();
+    client.join().unwrap();
+    server.stop().unwrap();
+}
+
+criterion_group!(benches, echo);
+criterion_main!(benches);
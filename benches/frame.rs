@@ -0,0 +1,87 @@
+//! Benchmarks for formatting, parsing and masking frames of a few typical payload sizes.
+//!
+//! Run with `cargo bench --bench frame`.
+
+extern crate criterion;
+extern crate ws;
+
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use ws::{default_mask_fn, Frame, OpCode};
+
+const SIZES: &[usize] = &[16, 1024, 65_536, 1 << 20];
+
+fn masked_frame(size: usize) -> Frame {
+    let mut frame = Frame::message(vec![b'x'; size], OpCode::Binary, true);
+    frame.set_mask();
+    frame
+}
+
+fn format(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame/format");
+    for &size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        let frame = masked_frame(size);
+        let mut buf = Vec::with_capacity(frame.len());
+        group.bench_with_input(BenchmarkId::new("format_into", size), &frame, |b, frame| {
+            b.iter(|| {
+                buf.clear();
+                frame.format_into(&mut buf, default_mask_fn()).unwrap();
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("format_with", size), &frame, |b, frame| {
+            b.iter_batched_ref(
+                || frame.clone(),
+                |frame| {
+                    buf.clear();
+                    frame.format_with(&mut buf, default_mask_fn()).unwrap();
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame/parse");
+    for &size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        let mut bytes = Vec::new();
+        masked_frame(size)
+            .format_into(&mut bytes, default_mask_fn())
+            .unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(size), &bytes, |b, bytes| {
+            b.iter_batched(
+                || Cursor::new(bytes.clone()),
+                |mut cursor| {
+                    let mut frame = Frame::parse(&mut cursor, u64::MAX)
+                        .unwrap()
+                        .unwrap();
+                    frame.remove_mask_with(default_mask_fn());
+                    frame
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn mask(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame/mask");
+    for &size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        let mut buf = vec![b'x'; size];
+        for (name, mask_fn) in ws::bench::mask_fns() {
+            group.bench_function(BenchmarkId::new(name, size), |b| {
+                b.iter(|| mask_fn(&mut buf, [0x12, 0x34, 0x56, 0x78]))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, format, parse, mask);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+//! Benchmarks for parsing the opening handshake and building the response to it.
+//!
+//! Run with `cargo bench --bench handshake`.
+
+extern crate criterion;
+extern crate ws;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use ws::{Request, Response};
+
+const REQUEST: &[u8] = b"GET /chat HTTP/1.1\r\n\
+    Host: server.example.com\r\n\
+    Upgrade: websocket\r\n\
+    Connection: Upgrade\r\n\
+    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+    Origin: http://example.com\r\n\
+    Sec-WebSocket-Protocol: chat, superchat\r\n\
+    Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\
+    Sec-WebSocket-Version: 13\r\n\r\n";
+
+fn parse_request(c: &mut Criterion) {
+    c.bench_function("handshake/parse_request", |b| {
+        b.iter(|| Request::parse(REQUEST).unwrap().unwrap())
+    });
+}
+
+fn respond(c: &mut Criterion) {
+    let req = Request::parse(REQUEST).unwrap().unwrap();
+    c.bench_function("handshake/respond", |b| {
+        b.iter(|| {
+            let mut buf = Vec::with_capacity(256);
+            Response::from_request(&req)
+                .unwrap()
+                .format(&mut buf)
+                .unwrap();
+            buf
+        })
+    });
+    let res = {
+        let mut buf = Vec::new();
+        Response::from_request(&req)
+            .unwrap()
+            .format(&mut buf)
+            .unwrap();
+        buf
+    };
+    c.bench_function("handshake/parse_response", |b| {
+        b.iter(|| Response::parse(&res).unwrap().unwrap())
+    });
+}
+
+fn accept_key(c: &mut Criterion) {
+    c.bench_function("handshake/accept_key", |b| {
+        b.iter(|| ws::bench::accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="))
+    });
+}
+
+criterion_group!(benches, parse_request, respond, accept_key);
+criterion_main!(benches);
@@ -1,16 +1,14 @@
 /// WebSocket server used for testing the bench example.
 extern crate ws;
 
-use ws::{Builder, Sender, Settings};
+use ws::{bin_tools, Settings};
 
 fn main() {
-    Builder::new()
-        .with_settings(Settings {
+    bin_tools::echo_server(
+        "127.0.0.1:3012",
+        Settings {
             max_connections: 10_000,
             ..Settings::default()
-        })
-        .build(|out: Sender| move |msg| out.send(msg))
-        .unwrap()
-        .listen("127.0.0.1:3012")
-        .unwrap();
+        },
+    ).unwrap();
 }
@@ -0,0 +1,24 @@
+/// Measure the round trip time of messages sent to an echo server such as the bench-server
+/// example.
+extern crate env_logger;
+extern crate ws;
+
+use ws::bin_tools::{self, Rate};
+
+fn main() {
+    env_logger::init();
+
+    let report = bin_tools::latency_probe(
+        "ws://127.0.0.1:3012",
+        Rate {
+            per_second: 100,
+            messages: 1_000,
+        },
+    ).unwrap();
+
+    println!("Echoed {} messages, lost {}", report.samples.len(), report.lost);
+    println!("min  {:?}", report.min().unwrap_or_default());
+    println!("mean {:?}", report.mean().unwrap_or_default());
+    println!("p99  {:?}", report.percentile(99.0).unwrap_or_default());
+    println!("max  {:?}", report.max().unwrap_or_default());
+}
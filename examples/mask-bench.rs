@@ -0,0 +1,41 @@
+/// Measure the throughput of the functions used to mask frame payloads. Run it with
+/// `cargo run --release --example mask-bench`.
+extern crate ws;
+
+use std::time::Instant;
+
+const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+fn mask_bytes(buf: &mut [u8], mask: [u8; 4]) {
+    for (byte, &key) in buf.iter_mut().zip(mask.iter().cycle()) {
+        *byte ^= key
+    }
+}
+
+// Mask `size` bytes repeatedly and return the throughput in MiB per second.
+fn throughput(mask_fn: ws::MaskFn, size: usize) -> f64 {
+    let mut buf = vec![0u8; size];
+    let rounds = (256 << 20) / size;
+    let start = Instant::now();
+    for _ in 0..rounds {
+        mask_fn(&mut buf, MASK);
+    }
+    let elapsed = start.elapsed();
+    // keep the result alive so that the work is not optimized away
+    assert!(buf.len() == size);
+    (rounds * size) as f64 / (1 << 20) as f64 / elapsed.as_secs_f64()
+}
+
+fn main() {
+    let candidates: &[(&str, ws::MaskFn)] = &[
+        ("bytes", mask_bytes),
+        ("apply_mask_fast", ws::apply_mask_fast),
+        ("default_mask_fn", ws::default_mask_fn()),
+    ];
+    for &size in &[64, 4096, 1 << 20] {
+        println!("{} byte payloads", size);
+        for &(name, mask_fn) in candidates {
+            println!("  {:<16} {:>10.0} MiB/s", name, throughput(mask_fn, size));
+        }
+    }
+}
@@ -0,0 +1,92 @@
+// The envelope of messages sent with `Sender::send_tracked` and of their acknowledgments, see
+// `Settings::acks` for the format.
+
+use communication::AckToken;
+use message::Message;
+
+const DATA: &[u8] = b"\0ack:";
+const ACK: &[u8] = b"\0ack!";
+
+/// What a received message turned out to be once its envelope is removed.
+pub enum Envelope {
+    /// A tracked message to acknowledge, whose payload starts at the given offset.
+    Tracked(AckToken, usize),
+    /// The acknowledgment of a message sent with `Sender::send_tracked`.
+    Ack(AckToken),
+    /// A message without an envelope.
+    Plain,
+}
+
+/// Wrap the payload of a message in the envelope of a tracked message.
+pub fn wrap(msg: Message, token: AckToken) -> Message {
+    let prefix = format!("\0ack:{}:", token);
+    match msg {
+        Message::Text(text) => Message::Text(prefix + &text),
+        Message::Binary(data) => {
+            let mut payload = Vec::with_capacity(prefix.len() + data.len());
+            payload.extend_from_slice(prefix.as_bytes());
+            payload.extend_from_slice(&data);
+            Message::Binary(payload)
+        }
+    }
+}
+
+/// The acknowledgment of a tracked message.
+pub fn ack(token: AckToken) -> Message {
+    Message::Text(format!("\0ack!{}", token))
+}
+
+/// Recognize the envelope of a received message. Acknowledgments are always text messages.
+pub fn parse(payload: &[u8], text: bool) -> Envelope {
+    if payload.starts_with(DATA) {
+        let rest = &payload[DATA.len()..];
+        if let Some(end) = rest.iter().position(|&b| b == b':') {
+            if let Some(token) = parse_token(&rest[..end]) {
+                return Envelope::Tracked(token, DATA.len() + end + 1);
+            }
+        }
+    } else if text && payload.starts_with(ACK) {
+        if let Some(token) = parse_token(&payload[ACK.len()..]) {
+            return Envelope::Ack(token);
+        }
+    }
+    Envelope::Plain
+}
+
+fn parse_token(digits: &[u8]) -> Option<AckToken> {
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    ::std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .map(AckToken::new)
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+
+    #[test]
+    fn envelopes() {
+        let token = AckToken::new(42);
+        let wrapped = wrap(Message::text("hello"), token);
+        assert_eq!(wrapped, Message::text("\0ack:42:hello"));
+        match parse(&wrapped.into_data(), true) {
+            Envelope::Tracked(parsed, offset) => {
+                assert_eq!(parsed, token);
+                assert_eq!(offset, 8);
+            }
+            _ => panic!("the tracked message was not recognized"),
+        }
+        match parse(&ack(token).into_data(), true) {
+            Envelope::Ack(parsed) => assert_eq!(parsed, token),
+            _ => panic!("the acknowledgment was not recognized"),
+        }
+        // acknowledgments are text messages
+        assert!(matches!(parse(b"\0ack!42", false), Envelope::Plain));
+        assert!(matches!(parse(b"\0ack:x:hello", true), Envelope::Plain));
+        assert!(matches!(parse(b"\0ack:42", true), Envelope::Plain));
+        assert!(matches!(parse(b"hello", true), Envelope::Plain));
+    }
+}
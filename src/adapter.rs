@@ -0,0 +1,137 @@
+//! A factory that hands each connection to the application as a pair of channels.
+//!
+//! The `ChannelAdapter` runs an internal handler for every connection. Once a connection opens,
+//! the application receives its `Channels` and can read messages from `incoming` and write
+//! messages to `outgoing` on any thread, which keeps business logic outside of the `Handler`
+//! trait and fits worker architectures that are already built around channels.
+//!
+//! ```
+//! use std::thread;
+//!
+//! let (adapter, connections) = ws::channel_adapter();
+//! let server = ws::Builder::new().spawn_local(move || adapter).unwrap();
+//!
+//! // an echo worker that knows nothing about handlers
+//! thread::spawn(move || {
+//!     for channels in connections {
+//!         let ws::Channels { incoming, outgoing, .. } = channels;
+//!         thread::spawn(move || {
+//!             for msg in incoming {
+//!                 if outgoing.send(msg).is_err() {
+//!                     break;
+//!                 }
+//!             }
+//!         });
+//!     }
+//! });
+//!
+//! ws::connect(server.url().as_str(), |out| {
+//!     out.send("Hello").unwrap();
+//!     move |msg| {
+//!         assert_eq!(msg, ws::Message::text("Hello"));
+//!         out.close(ws::CloseCode::Normal)
+//!     }
+//! }).unwrap();
+//!
+//! server.stop().unwrap();
+//! ```
+use std::sync::mpsc;
+use std::thread;
+
+use mio::Token;
+
+use communication::Sender;
+use factory::Factory;
+use handler::Handler;
+use handshake::Handshake;
+use message::Message;
+use protocol::CloseCode;
+use result::Result;
+
+/// Create a factory that hands every opened connection to the application as a set of
+/// `Channels`, along with the receiver that those channels are delivered on.
+pub fn channel_adapter() -> (ChannelAdapter, mpsc::Receiver<Channels>) {
+    let (tx, rx) = mpsc::channel();
+    (ChannelAdapter { connections: tx }, rx)
+}
+
+/// The channels of one open connection, created by a `ChannelAdapter`.
+#[derive(Debug)]
+pub struct Channels {
+    /// The token of the connection within the WebSocket.
+    pub token: Token,
+    /// The messages received from the other endpoint. The channel is disconnected once the
+    /// connection closes.
+    pub incoming: mpsc::Receiver<Message>,
+    /// The messages to send to the other endpoint. The connection is closed normally once every
+    /// clone of this sender has been dropped, so drop it when `incoming` disconnects to release
+    /// the thread that forwards these messages to the event loop.
+    pub outgoing: mpsc::Sender<Message>,
+}
+
+/// A factory that creates `ChannelHandler`s. Use `channel_adapter` to create one.
+pub struct ChannelAdapter {
+    connections: mpsc::Sender<Channels>,
+}
+
+impl Factory for ChannelAdapter {
+    type Handler = ChannelHandler;
+
+    fn connection_made(&mut self, out: Sender) -> ChannelHandler {
+        ChannelHandler {
+            out,
+            connections: self.connections.clone(),
+            incoming: None,
+        }
+    }
+}
+
+/// The handler that connects a WebSocket connection to its `Channels`.
+pub struct ChannelHandler {
+    out: Sender,
+    connections: mpsc::Sender<Channels>,
+    incoming: Option<mpsc::Sender<Message>>,
+}
+
+impl Handler for ChannelHandler {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        let (incoming_tx, incoming_rx) = mpsc::channel();
+        let (outgoing_tx, outgoing_rx) = mpsc::channel::<Message>();
+        let channels = Channels {
+            token: self.out.token(),
+            incoming: incoming_rx,
+            outgoing: outgoing_tx,
+        };
+        if self.connections.send(channels).is_err() {
+            debug!("No application is receiving connections from the channel adapter.");
+            return self.out.close(CloseCode::Away);
+        }
+        self.incoming = Some(incoming_tx);
+
+        let out = self.out.clone();
+        thread::spawn(move || {
+            for msg in outgoing_rx {
+                if out.send(msg).is_err() {
+                    return;
+                }
+            }
+            let _ = out.close(CloseCode::Normal);
+        });
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        if let Some(ref incoming) = self.incoming {
+            if incoming.send(msg).is_err() {
+                trace!("Application stopped receiving messages for {:?}.", self.out.token());
+            }
+        }
+        Ok(())
+    }
+
+    fn on_close(&mut self, code: CloseCode, reason: &str) {
+        debug!("Channel adapter connection closing due to ({:?}) {}", code, reason);
+        // disconnect the incoming channel
+        self.incoming = None;
+    }
+}
@@ -0,0 +1,5 @@
+//! Access to internals of the library for the benchmarks in `benches/`. This is not part of the
+//! public API and may change without notice.
+
+pub use frame::mask_fns;
+pub use handshake::hash_key as accept_key;
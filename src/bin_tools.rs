@@ -0,0 +1,240 @@
+//! Ready-made endpoints for measuring the throughput and latency of a deployment.
+//!
+//! These are the same tools used by the benchmarking examples. Keeping them in the crate means
+//! that measurements taken against different versions of WS-RS use identical code.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::ToSocketAddrs;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use mio::Token;
+use url;
+
+use super::{Builder, Settings};
+use communication::Sender;
+use handler::Handler;
+use handshake::Handshake;
+use message::Message;
+use protocol::CloseCode;
+use result::{Error, Kind, Result};
+
+const SEND: Token = Token(1);
+
+/// Run a server that echoes every message back to the endpoint that sent it.
+///
+/// This function blocks until the server is shut down.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ws::{bin_tools, Settings};
+///
+/// bin_tools::echo_server("127.0.0.1:3012", Settings {
+///     max_connections: 10_000,
+///     ..Settings::default()
+/// }).unwrap();
+/// ```
+pub fn echo_server<A>(addr: A, settings: Settings) -> Result<()>
+where
+    A: ToSocketAddrs,
+{
+    Builder::new()
+        .with_settings(settings)
+        .build(|out: Sender| move |msg| out.send(msg))?
+        .listen(addr)?;
+    Ok(())
+}
+
+/// The pace at which `latency_probe` sends messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    /// How many messages to send each second. The event loop timer has a resolution of one
+    /// millisecond, so rates above 1,000 are sent at 1,000 messages per second.
+    pub per_second: u32,
+    /// How many messages to send before closing the connection.
+    pub messages: u32,
+}
+
+impl Default for Rate {
+    fn default() -> Rate {
+        Rate {
+            per_second: 10,
+            messages: 100,
+        }
+    }
+}
+
+/// The round trip times measured by `latency_probe`.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyReport {
+    /// The round trip time of each echoed message in the order that the echoes arrived.
+    pub samples: Vec<Duration>,
+    /// The number of messages that were sent but never echoed.
+    pub lost: usize,
+}
+
+impl LatencyReport {
+    /// The shortest round trip.
+    pub fn min(&self) -> Option<Duration> {
+        self.samples.iter().min().cloned()
+    }
+
+    /// The longest round trip.
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().max().cloned()
+    }
+
+    /// The average round trip.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let total = self.samples.iter().fold(Duration::from_secs(0), |sum, &d| sum + d);
+        Some(total / self.samples.len() as u32)
+    }
+
+    /// The round trip time below which the given percentage of samples fall, for example 99.0
+    /// for the 99th percentile.
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        let rank = (percent.clamp(0.0, 100.0) / 100.0 * (sorted.len() - 1) as f64).round();
+        Some(sorted[rank as usize])
+    }
+}
+
+struct Probe {
+    out: Sender,
+    rate: Rate,
+    interval: u64,
+    sent: u32,
+    in_flight: HashMap<u32, Instant>,
+    report: Rc<RefCell<LatencyReport>>,
+}
+
+impl Probe {
+    fn send_next(&mut self) -> Result<()> {
+        self.in_flight.insert(self.sent, Instant::now());
+        self.out.send(self.sent.to_string())?;
+        self.sent += 1;
+        if self.sent < self.rate.messages {
+            self.out.timeout(self.interval, SEND)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.sent == self.rate.messages && self.in_flight.is_empty()
+    }
+}
+
+impl Handler for Probe {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        if self.rate.messages == 0 {
+            return self.out.close(CloseCode::Normal);
+        }
+        self.send_next()
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        let seq = msg
+            .as_text()?
+            .parse::<u32>()
+            .map_err(|err| Error::new(Kind::Protocol, format!("Unexpected echo: {}", err)))?;
+        if let Some(sent) = self.in_flight.remove(&seq) {
+            self.report.borrow_mut().samples.push(sent.elapsed());
+        }
+        if self.finished() {
+            self.out.close(CloseCode::Normal)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn on_timeout(&mut self, event: Token) -> Result<()> {
+        if event == SEND {
+            self.send_next()
+        } else {
+            Err(Error::new(
+                Kind::Internal,
+                "Invalid timeout token encountered!",
+            ))
+        }
+    }
+
+    fn on_close(&mut self, _: CloseCode, _: &str) {
+        self.report.borrow_mut().lost = self.in_flight.len();
+    }
+}
+
+/// Connect to an echo server and measure the round trip time of messages sent at the given rate.
+///
+/// This function blocks until every message has been echoed or the connection closes.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ws::bin_tools::{self, Rate};
+///
+/// let report = bin_tools::latency_probe("ws://127.0.0.1:3012", Rate {
+///     per_second: 100,
+///     messages: 1_000,
+/// }).unwrap();
+/// println!("p99 {:?}", report.percentile(99.0));
+/// ```
+pub fn latency_probe(url: &str, rate: Rate) -> Result<LatencyReport> {
+    let url = url::Url::parse(url).map_err(|err| {
+        Error::new(
+            Kind::Internal,
+            format!("Unable to parse {} as url due to {:?}", url, err),
+        )
+    })?;
+    let interval = 1_000 / u64::from(rate.per_second.clamp(1, 1_000));
+
+    let report = Rc::new(RefCell::new(LatencyReport::default()));
+    let handler_report = report.clone();
+
+    let mut ws = Builder::new().build(move |out| Probe {
+        out,
+        rate,
+        interval,
+        sent: 0,
+        in_flight: HashMap::new(),
+        report: handler_report.clone(),
+    })?;
+    ws.connect(url)?;
+    ws.run()?;
+
+    let report = report.borrow().clone();
+    Ok(report)
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+
+    #[test]
+    fn report_statistics() {
+        let report = LatencyReport {
+            samples: vec![
+                Duration::from_millis(4),
+                Duration::from_millis(1),
+                Duration::from_millis(3),
+                Duration::from_millis(2),
+                Duration::from_millis(5),
+            ],
+            lost: 0,
+        };
+        assert_eq!(report.min(), Some(Duration::from_millis(1)));
+        assert_eq!(report.max(), Some(Duration::from_millis(5)));
+        assert_eq!(report.mean(), Some(Duration::from_millis(3)));
+        assert_eq!(report.percentile(50.0), Some(Duration::from_millis(3)));
+        assert_eq!(report.percentile(100.0), Some(Duration::from_millis(5)));
+        assert_eq!(LatencyReport::default().mean(), None);
+    }
+}
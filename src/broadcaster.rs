@@ -0,0 +1,163 @@
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+
+use mio::Token;
+use url;
+
+use communication::Sender;
+use message::Message;
+use protocol::CloseCode;
+use result::{Error, Kind, Result};
+
+#[derive(Debug, Default)]
+struct Attachment {
+    sender: Option<Sender>,
+    // incremented on every attach so that a stopped WebSocket only detaches itself
+    generation: u64,
+}
+
+/// A broadcaster that outlives the WebSocket it sends to.
+///
+/// The broadcaster returned by `WebSocket::broadcaster` stops working once its event loop ends,
+/// so an application that restarts its WebSocket would have to hand the new broadcaster to every
+/// component that sends messages. A `PersistentBroadcaster` can be cloned and shared instead.
+/// Passed to `Builder::with_persistent_broadcaster`, it attaches to each WebSocket built by the
+/// builder, and again whenever one of them starts running, and detaches when that WebSocket's
+/// event loop ends. While it is detached, every method returns an error of kind `Internal`
+/// without sending anything.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::thread;
+/// use ws::{Builder, PersistentBroadcaster};
+///
+/// let broadcaster = PersistentBroadcaster::new();
+/// let mut builder = Builder::new();
+/// builder.with_persistent_broadcaster(broadcaster.clone());
+///
+/// let ticker = broadcaster.clone();
+/// thread::spawn(move || loop {
+///     // fails while the WebSocket is restarting
+///     let _ = ticker.send("tick");
+///     thread::sleep(std::time::Duration::from_secs(1));
+/// });
+///
+/// loop {
+///     let ws = builder.build(|out: ws::Sender| move |msg| out.send(msg)).unwrap();
+///     if let Err(err) = ws.listen("127.0.0.1:3012") {
+///         println!("Restarting after error: {}", err);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PersistentBroadcaster {
+    attachment: Arc<Mutex<Attachment>>,
+}
+
+impl PersistentBroadcaster {
+    /// Create a broadcaster that is not attached to any WebSocket.
+    pub fn new() -> PersistentBroadcaster {
+        PersistentBroadcaster::default()
+    }
+
+    /// Send through the given broadcaster from now on, replacing any previous one.
+    pub fn attach(&self, sender: Sender) {
+        self.attach_generation(sender);
+    }
+
+    #[doc(hidden)]
+    pub fn attach_generation(&self, sender: Sender) -> u64 {
+        let mut attachment = self.lock();
+        attachment.generation += 1;
+        attachment.sender = Some(sender);
+        attachment.generation
+    }
+
+    /// Stop sending until a broadcaster is attached again.
+    pub fn detach(&self) {
+        self.lock().sender = None;
+    }
+
+    #[doc(hidden)]
+    pub fn detach_generation(&self, generation: u64) {
+        let mut attachment = self.lock();
+        if attachment.generation == generation {
+            attachment.sender = None;
+        }
+    }
+
+    /// Whether the broadcaster is currently attached to a WebSocket.
+    pub fn is_attached(&self) -> bool {
+        self.lock().sender.is_some()
+    }
+
+    /// Get the broadcaster of the WebSocket that this is currently attached to. The returned
+    /// sender does not follow later restarts.
+    pub fn sender(&self) -> Result<Sender> {
+        self.lock().sender.clone().ok_or_else(|| {
+            Error::new(
+                Kind::Internal,
+                "The persistent broadcaster is not attached to a running WebSocket.",
+            )
+        })
+    }
+
+    /// Send a message to every connection. See `Sender::broadcast`.
+    pub fn send<M>(&self, msg: M) -> Result<()>
+    where
+        M: Into<Message>,
+    {
+        self.sender()?.broadcast(msg)
+    }
+
+    /// Send a preformatted frame to every open server connection. See `Sender::broadcast_raw`.
+    pub fn broadcast_raw(&self, frame: Vec<u8>) -> Result<()> {
+        self.sender()?.broadcast_raw(frame)
+    }
+
+    /// Close every connection. See `Sender::close`.
+    pub fn close(&self, code: CloseCode) -> Result<()> {
+        self.sender()?.close(code)
+    }
+
+    /// Close every connection with a reason. See `Sender::close_with_reason`.
+    pub fn close_with_reason<S>(&self, code: CloseCode, reason: S) -> Result<()>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.sender()?.close_with_reason(code, reason)
+    }
+
+    /// Ping every connection. See `Sender::ping`.
+    pub fn ping(&self, data: Vec<u8>) -> Result<()> {
+        self.sender()?.ping(data)
+    }
+
+    /// Queue a new connection to the given URL. See `Sender::connect`.
+    pub fn connect(&self, url: url::Url) -> Result<()> {
+        self.sender()?.connect(url)
+    }
+
+    /// Get the tokens of every connection. See `Sender::tokens`.
+    pub fn tokens(&self) -> Result<Vec<Token>> {
+        self.sender()?.tokens()
+    }
+
+    /// Get a sender for a single connection. See `Sender::sender_for`.
+    pub fn sender_for(&self, token: Token, connection_id: u64) -> Result<Sender> {
+        Ok(self.sender()?.sender_for(token, connection_id))
+    }
+
+    /// Shut down the WebSocket that this is attached to. See `Sender::shutdown`.
+    pub fn shutdown(&self) -> Result<()> {
+        self.sender()?.shutdown()
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, Attachment> {
+        // the attachment is always left consistent, so a panic elsewhere does not matter
+        self.attachment
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+}
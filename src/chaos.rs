@@ -0,0 +1,154 @@
+//! Fault injection for testing how applications cope with unreliable connections.
+//!
+//! While a `Chaos` configuration is enabled, every read from and write to a WebSocket stream may
+//! fail, stall or drop the connection with the configured probabilities. This makes it possible to
+//! exercise reconnect and error handling logic without external tools. The configuration applies
+//! to all WebSockets in the process, so tests that use it should not run alongside tests that
+//! expect reliable connections.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use ws::chaos::{self, Chaos};
+//!
+//! chaos::enable(Chaos {
+//!     disconnect: 0.01,
+//!     delay: 0.1,
+//!     delay_duration: Duration::from_millis(5),
+//!     ..Chaos::default()
+//! });
+//! // run the application against a flaky network here
+//! chaos::disable();
+//! ```
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use rand;
+
+static CONFIG: Mutex<Option<Chaos>> = Mutex::new(None);
+
+static READ_ERRORS: AtomicU64 = AtomicU64::new(0);
+static WRITE_ERRORS: AtomicU64 = AtomicU64::new(0);
+static DISCONNECTS: AtomicU64 = AtomicU64::new(0);
+static DELAYS: AtomicU64 = AtomicU64::new(0);
+
+/// The probabilities of the faults to inject, each between 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chaos {
+    /// The probability that a read fails with `ConnectionAborted`.
+    pub read_error: f64,
+    /// The probability that a write fails with `BrokenPipe`.
+    pub write_error: f64,
+    /// The probability that a read or write fails with `ConnectionReset`, which disconnects the
+    /// connection.
+    pub disconnect: f64,
+    /// The probability that a read or write is delayed by `delay_duration`. The delay blocks the
+    /// thread running the event loop, like a slow system call would.
+    pub delay: f64,
+    /// How long delayed reads and writes are held up.
+    pub delay_duration: Duration,
+}
+
+impl Default for Chaos {
+    fn default() -> Chaos {
+        Chaos {
+            read_error: 0.0,
+            write_error: 0.0,
+            disconnect: 0.0,
+            delay: 0.0,
+            delay_duration: Duration::from_millis(10),
+        }
+    }
+}
+
+/// The number of faults injected since faults were last enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    /// The number of reads that failed.
+    pub read_errors: u64,
+    /// The number of writes that failed.
+    pub write_errors: u64,
+    /// The number of reads and writes that disconnected their connection.
+    pub disconnects: u64,
+    /// The number of reads and writes that were delayed.
+    pub delays: u64,
+}
+
+/// Start injecting faults into every WebSocket stream in the process, replacing any previous
+/// configuration, and reset the counters.
+pub fn enable(chaos: Chaos) {
+    *config() = Some(chaos);
+    for counter in [&READ_ERRORS, &WRITE_ERRORS, &DISCONNECTS, &DELAYS].iter() {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Stop injecting faults. The counters keep their values until faults are enabled again.
+pub fn disable() {
+    *config() = None;
+}
+
+/// The number of faults injected since faults were last enabled.
+pub fn stats() -> Stats {
+    Stats {
+        read_errors: READ_ERRORS.load(Ordering::Relaxed),
+        write_errors: WRITE_ERRORS.load(Ordering::Relaxed),
+        disconnects: DISCONNECTS.load(Ordering::Relaxed),
+        delays: DELAYS.load(Ordering::Relaxed),
+    }
+}
+
+fn config() -> MutexGuard<'static, Option<Chaos>> {
+    CONFIG.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn happens(probability: f64) -> bool {
+    probability > 0.0 && rand::random::<f64>() < probability
+}
+
+// Delay or fail an operation, returning the error that the operation should fail with.
+fn inject<P>(probability: P, kind: io::ErrorKind, errors: &AtomicU64) -> Option<io::Error>
+where
+    P: Fn(&Chaos) -> f64,
+{
+    let chaos = (*config())?;
+    if happens(chaos.delay) {
+        DELAYS.fetch_add(1, Ordering::Relaxed);
+        thread::sleep(chaos.delay_duration);
+    }
+    if happens(chaos.disconnect) {
+        DISCONNECTS.fetch_add(1, Ordering::Relaxed);
+        return Some(io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            "Injected disconnect.",
+        ));
+    }
+    if happens(probability(&chaos)) {
+        errors.fetch_add(1, Ordering::Relaxed);
+        return Some(io::Error::new(kind, "Injected fault."));
+    }
+    None
+}
+
+/// Called by streams before reading.
+#[doc(hidden)]
+pub fn before_read() -> Option<io::Error> {
+    inject(
+        |chaos| chaos.read_error,
+        io::ErrorKind::ConnectionAborted,
+        &READ_ERRORS,
+    )
+}
+
+/// Called by streams before writing.
+#[doc(hidden)]
+pub fn before_write() -> Option<io::Error> {
+    inject(
+        |chaos| chaos.write_error,
+        io::ErrorKind::BrokenPipe,
+        &WRITE_ERRORS,
+    )
+}
@@ -0,0 +1,396 @@
+//! An interactive command line client for debugging WebSocket servers.
+//!
+//! The client connects to a URL, sends each line of its input as a text message and prints
+//! everything that it receives. It is also a compact reference for the client side of the API:
+//! customizing the opening handshake, observing frames and closing with a code and reason.
+//!
+//! ```text
+//! cargo run --example cli --features cli -- -p chat -H "Authorization: Bearer abc" ws://127.0.0.1:3012
+//! ```
+//!
+//! Lines beginning with `/` are commands:
+//!
+//! - `/close [code] [reason]` closes the connection, with a normal close code by default.
+//! - `/help` prints the available commands.
+//!
+//! Any other line is sent as a text message. Reaching the end of the input closes the connection
+//! normally.
+use std::io::{BufRead, Write};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use url;
+
+use super::Builder;
+use communication::Sender;
+use frame::Frame;
+use handler::Handler;
+use handshake::{Handshake, Request};
+use message::Message;
+use protocol::CloseCode;
+use result::{Error, Kind, Result};
+
+/// The exit code used when the connection closes normally.
+pub const EXIT_NORMAL: i32 = 0;
+/// The exit code used when the connection could not be established or was lost without a closing
+/// handshake.
+pub const EXIT_FAILURE: i32 = 1;
+/// The exit code used when the connection was closed with a code other than Normal or Away.
+pub const EXIT_CLOSED: i32 = 2;
+
+const USAGE: &str = "\
+Usage: ws-cli [OPTIONS] URL
+
+Options:
+    -H, --header <NAME: VALUE>    Add a header to the opening handshake
+    -p, --protocol <PROTOCOL>     Request a subprotocol, may be given more than once
+    -f, --frames                  Print every frame that is received
+    -h, --help                    Print this message";
+
+const COMMANDS: &str = "\
+Type /close [code] [reason] to close the connection.
+Type /help to show these instructions.
+Other input will be sent as messages.";
+
+/// The configuration of the command line client.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    /// The URL of the WebSocket server.
+    pub url: String,
+    /// Extra headers to send with the opening handshake.
+    pub headers: Vec<(String, String)>,
+    /// The subprotocols to request, in order of preference.
+    pub protocols: Vec<String>,
+    /// Whether to print each frame as it is received in addition to the messages.
+    pub frames: bool,
+}
+
+impl Options {
+    /// Parse options from command line arguments, not including the name of the program.
+    ///
+    /// An error of kind `Internal` is returned for invalid arguments, and the details contain a
+    /// usage message when `--help` is requested.
+    pub fn from_args<I, S>(args: I) -> Result<Options>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut options = Options::default();
+        let mut url = None;
+        let mut args = args.into_iter().map(Into::into);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Err(Error::new(Kind::Internal, USAGE)),
+                "-f" | "--frames" => options.frames = true,
+                "-H" | "--header" => {
+                    let header = args.next().ok_or_else(|| missing(&arg))?;
+                    let mut parts = header.splitn(2, ':');
+                    match (parts.next(), parts.next()) {
+                        (Some(name), Some(value)) if !name.trim().is_empty() => options
+                            .headers
+                            .push((name.trim().into(), value.trim().into())),
+                        _ => {
+                            return Err(Error::new(
+                                Kind::Internal,
+                                format!("Invalid header {}, expected NAME: VALUE.", header),
+                            ))
+                        }
+                    }
+                }
+                "-p" | "--protocol" => {
+                    let protocol = args.next().ok_or_else(|| missing(&arg))?;
+                    options.protocols.push(protocol);
+                }
+                _ if arg.starts_with('-') => {
+                    return Err(Error::new(
+                        Kind::Internal,
+                        format!("Unknown option {}.\n\n{}", arg, USAGE),
+                    ))
+                }
+                _ if url.is_none() => url = Some(arg),
+                _ => {
+                    return Err(Error::new(
+                        Kind::Internal,
+                        format!("Unexpected argument {}.\n\n{}", arg, USAGE),
+                    ))
+                }
+            }
+        }
+
+        options.url = url.ok_or_else(|| {
+            Error::new(Kind::Internal, format!("Missing URL.\n\n{}", USAGE))
+        })?;
+        Ok(options)
+    }
+}
+
+fn missing(option: &str) -> Error {
+    Error::new(
+        Kind::Internal,
+        format!("The option {} requires a value.", option),
+    )
+}
+
+/// The exit code for a connection that closed with the given code.
+pub fn exit_code(code: CloseCode) -> i32 {
+    match code {
+        CloseCode::Normal | CloseCode::Away => EXIT_NORMAL,
+        CloseCode::Abnormal => EXIT_FAILURE,
+        _ => EXIT_CLOSED,
+    }
+}
+
+/// Render a message for display, showing binary data as hexadecimal.
+pub fn format_message(msg: &Message) -> String {
+    match *msg {
+        Message::Text(ref text) => text.clone(),
+        Message::Binary(ref data) => {
+            let hex: Vec<String> = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("[binary {} bytes] {}", data.len(), hex.join(" "))
+        }
+    }
+}
+
+enum Event {
+    Open(Sender),
+    Closed(CloseCode),
+}
+
+type Output = Arc<Mutex<dyn Write + Send>>;
+
+fn display(output: &Output, line: &str) {
+    if let Ok(mut output) = output.lock() {
+        // a closed output is not a reason to stop the session
+        let _ = writeln!(output, "{}", line);
+        let _ = output.flush();
+    }
+}
+
+struct Client {
+    out: Sender,
+    options: Arc<Options>,
+    output: Output,
+    events: mpsc::Sender<Event>,
+}
+
+impl Handler for Client {
+    fn build_request(&mut self, url: &url::Url) -> Result<Request> {
+        let mut req = Request::from_url(url)?;
+        for protocol in &self.options.protocols {
+            req.add_protocol(protocol);
+        }
+        req.headers_mut().extend(
+            self.options
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone().into_bytes())),
+        );
+        Ok(req)
+    }
+
+    fn on_open(&mut self, shake: Handshake) -> Result<()> {
+        match shake.response.protocol()? {
+            Some(protocol) => display(
+                &self.output,
+                &format!("Connected to {} using {}", self.options.url, protocol),
+            ),
+            None => display(&self.output, &format!("Connected to {}", self.options.url)),
+        }
+        self.events.send(Event::Open(self.out.clone())).map_err(|err| {
+            Error::new(
+                Kind::Internal,
+                format!("Unable to communicate between threads: {:?}.", err),
+            )
+        })
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Frame>> {
+        if self.options.frames {
+            display(
+                &self.output,
+                &format!(
+                    "<<< Frame<{} final: {} length: {}>",
+                    frame.opcode(),
+                    frame.is_final(),
+                    frame.payload().len()
+                ),
+            );
+        }
+        Ok(Some(frame))
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        display(&self.output, &format!("<<< {}", format_message(&msg)));
+        Ok(())
+    }
+
+    fn on_close(&mut self, code: CloseCode, reason: &str) {
+        if reason.is_empty() {
+            display(&self.output, &format!("<<< Closing<({:?})>", code));
+        } else {
+            display(
+                &self.output,
+                &format!("<<< Closing<({:?}) {}>", code, reason),
+            );
+        }
+        // the input loop may already have finished
+        let _ = self.events.send(Event::Closed(code));
+    }
+
+    fn on_error(&mut self, err: Error) {
+        display(&self.output, &format!("<<< Error<{:?}>", err))
+    }
+}
+
+// Handle one line of input, returning false once the connection is closing.
+fn command(line: &str, out: &Sender, output: &Output) -> Result<bool> {
+    if line.starts_with("/h") {
+        display(output, COMMANDS);
+        return Ok(true);
+    }
+    if !line.starts_with("/c") {
+        display(output, &format!(">>> {}", line));
+        out.send(line)?;
+        return Ok(true);
+    }
+
+    let mut args = line.splitn(3, ' ').skip(1);
+    let code = match args.next() {
+        Some(code) => match code.trim().parse::<u16>() {
+            Ok(code) => CloseCode::from(code),
+            Err(_) => {
+                // keep accepting input if the close arguments are invalid
+                display(output, &format!("Unable to parse {} as close code.", code));
+                return Ok(true);
+            }
+        },
+        None => CloseCode::Normal,
+    };
+    let reason = args.next().unwrap_or("").trim();
+    display(output, &format!("Closing with code: {:?}, please wait...", code));
+    out.close_with_reason(code, reason.to_string())?;
+    Ok(false)
+}
+
+/// Run the client until the connection closes, reading lines from `input` and writing a
+/// transcript of the session to `output`. Returns the exit code for the session.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io;
+/// use std::process;
+///
+/// use ws::cli::{self, Options};
+///
+/// let options = Options::from_args(vec!["ws://127.0.0.1:3012"]).unwrap();
+/// let stdin = io::stdin();
+/// let code = cli::run(options, stdin.lock(), io::stdout()).unwrap();
+/// process::exit(code);
+/// ```
+pub fn run<R, W>(options: Options, input: R, output: W) -> Result<i32>
+where
+    R: BufRead,
+    W: Write + Send + 'static,
+{
+    let output: Output = Arc::new(Mutex::new(output));
+    let options = Arc::new(options);
+    let (tx, rx) = mpsc::channel();
+
+    let client = {
+        let output = output.clone();
+        let options = options.clone();
+        thread::spawn(move || -> Result<()> {
+            let url = url::Url::parse(&options.url).map_err(|err| {
+                Error::new(
+                    Kind::Internal,
+                    format!("Unable to parse {} as url due to {:?}", options.url, err),
+                )
+            })?;
+            let mut ws = Builder::new().build(move |out| Client {
+                out,
+                options: options.clone(),
+                output: output.clone(),
+                events: tx.clone(),
+            })?;
+            ws.connect(url)?;
+            ws.run()?;
+            Ok(())
+        })
+    };
+
+    // the event loop drops the channel if the connection fails before opening
+    let mut closed = None;
+    if let Ok(Event::Open(out)) = rx.recv() {
+        display(&output, COMMANDS);
+        let mut open = true;
+        for line in input.lines() {
+            if let Ok(Event::Closed(code)) = rx.try_recv() {
+                closed = Some(code);
+                open = false;
+                break;
+            }
+            if !command(line?.trim(), &out, &output)? {
+                open = false;
+                break;
+            }
+        }
+        if open {
+            out.close(CloseCode::Normal)?;
+        }
+    }
+
+    client
+        .join()
+        .map_err(|_| Error::new(Kind::Internal, "The client thread panicked."))??;
+
+    if closed.is_none() {
+        closed = rx.try_iter().filter_map(|event| match event {
+            Event::Closed(code) => Some(code),
+            Event::Open(_) => None,
+        }).last();
+    }
+    Ok(closed.map(exit_code).unwrap_or(EXIT_FAILURE))
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+
+    #[test]
+    fn parse_args() {
+        let options = Options::from_args(vec![
+            "-H",
+            "Authorization: Bearer a:b",
+            "--protocol",
+            "chat",
+            "-f",
+            "ws://127.0.0.1:3012",
+        ]).unwrap();
+        assert_eq!(options.url, "ws://127.0.0.1:3012");
+        assert_eq!(
+            options.headers,
+            vec![("Authorization".to_string(), "Bearer a:b".to_string())]
+        );
+        assert_eq!(options.protocols, vec!["chat".to_string()]);
+        assert!(options.frames);
+
+        assert!(Options::from_args(Vec::<String>::new()).is_err());
+        assert!(Options::from_args(vec!["-H", "nocolon", "ws://a"]).is_err());
+        assert!(Options::from_args(vec!["ws://a", "ws://b"]).is_err());
+        assert!(Options::from_args(vec!["ws://a", "-p"]).is_err());
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(exit_code(CloseCode::Normal), EXIT_NORMAL);
+        assert_eq!(exit_code(CloseCode::Abnormal), EXIT_FAILURE);
+        assert_eq!(exit_code(CloseCode::Policy), EXIT_CLOSED);
+        assert_eq!(
+            format_message(&Message::binary(vec![0, 255])),
+            "[binary 2 bytes] 00 ff"
+        );
+    }
+}
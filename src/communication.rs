@@ -6,31 +6,142 @@ use mio::Token;
 use mio_extras::timer::Timeout;
 use url;
 
+use frame::{self, Frame};
 use io::ALL;
+use limits;
 use message;
 use protocol::CloseCode;
-use result::{Error, Result};
+use result::{Error, Kind, Result};
+use {LongCloseReasons, Settings};
 use std::cmp::PartialEq;
 use std::hash::{Hash, Hasher};
 use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// An identifier assigned to a message sent with `Sender::send_traced`. Identifiers are unique
+/// within the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageId(u64);
+
+impl MessageId {
+    fn next() -> MessageId {
+        MessageId(NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// The numeric value of the identifier.
+    #[inline]
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+static NEXT_ACK_TOKEN: AtomicU64 = AtomicU64::new(0);
+
+/// An identifier assigned to a message sent with `Sender::send_tracked`, passed to
+/// `Handler::on_ack` or `Handler::on_ack_timeout` once the message is acknowledged or the
+/// acknowledgment is overdue. Tokens are unique within the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AckToken(u64);
+
+impl AckToken {
+    fn next() -> AckToken {
+        AckToken(NEXT_ACK_TOKEN.fetch_add(1, Ordering::Relaxed))
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn new(value: u64) -> AckToken {
+        AckToken(value)
+    }
+
+    /// The numeric value of the token.
+    #[inline]
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for AckToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Tracing information attached to a message sent with `Sender::send_traced`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageMeta {
+    /// The identifier returned by `send_traced`.
+    pub id: MessageId,
+    /// When the message was placed on the event loop queue.
+    pub enqueued: Instant,
+}
+
+/// The connection attempts cancelled by `Sender::cancel_connect`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectTarget {
+    /// Every attempt to connect to this URL.
+    Url(url::Url),
+    /// The attempt assigned this token, as reported to `Handler::on_connect_result`.
+    Token(Token),
+}
+
+impl From<url::Url> for ConnectTarget {
+    fn from(url: url::Url) -> ConnectTarget {
+        ConnectTarget::Url(url)
+    }
+}
+
+impl From<Token> for ConnectTarget {
+    fn from(token: Token) -> ConnectTarget {
+        ConnectTarget::Token(token)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum Signal {
     Message(message::Message),
+    Traced(message::Message, MessageMeta),
+    Tracked(message::Message, AckToken),
+    Fragmented(message::Message, usize),
     Close(CloseCode, Cow<'static, str>),
+    CloseTokens(Vec<Token>, CloseCode, Cow<'static, str>),
     Ping(Vec<u8>),
     Pong(Vec<u8>),
-    Connect(url::Url),
+    Inject(Frame),
+    Raw(Arc<Vec<u8>>),
+    Connect(url::Url, Option<SocketAddr>, Option<Token>),
+    CancelConnect(ConnectTarget),
     Shutdown,
     Timeout { delay: u64, token: Token },
     Cancel(Timeout),
+    CancelAllTimeouts,
+    Tokens(mpsc::Sender<Vec<Token>>),
+    Flush(mpsc::Sender<bool>),
+    SuspendRead,
+    ResumeRead,
+    PauseAccept,
+    ResumeAccept,
+    Wake,
 }
 
 #[derive(Debug, Clone)]
 pub struct Command {
     token: Token,
     signal: Signal,
-    connection_id: u32,
+    connection_id: u64,
 }
 
 impl Command {
@@ -38,22 +149,237 @@ impl Command {
         self.token
     }
 
+    pub fn signal(&self) -> &Signal {
+        &self.signal
+    }
+
     pub fn into_signal(self) -> Signal {
         self.signal
     }
 
-    pub fn connection_id(&self) -> u32 {
+    pub fn connection_id(&self) -> u64 {
         self.connection_id
     }
 }
 
+/// Enqueue statistics for a producer registered with `Sender::register_producer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProducerStats {
+    /// The name given when the producer was registered.
+    pub name: String,
+    /// The number of live senders belonging to the producer, including clones.
+    pub senders: usize,
+    /// The number of commands that the producer placed on the event loop queue.
+    pub enqueued: u64,
+    /// The number of commands that the producer failed to place on the queue.
+    pub failed: u64,
+}
+
+/// The number of opening handshakes of server connections that ended with each outcome, which
+/// helps to tell attacks and misconfigured clients apart from real users.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HandshakeStats {
+    /// Handshakes answered with 101 Switching Protocols.
+    pub accepted: u64,
+    /// Handshakes answered with 400 Bad Request, such as malformed requests or, with
+    /// `Settings::request_key_strict`, requests with an invalid key.
+    pub bad_request: u64,
+    /// Handshakes answered with 403 Forbidden, such as cross-origin requests with
+    /// `Settings::same_origin_only`.
+    pub forbidden: u64,
+    /// Handshakes answered with 426 Upgrade Required because of an unsupported version, with
+    /// `Settings::version_strict`.
+    pub upgrade_required: u64,
+    /// Connections refused because of `Settings::max_connections_per_ip`. Unless the server is
+    /// encrypted, they are answered with 429 Too Many Requests.
+    pub too_many_requests: u64,
+    /// Handshakes answered with any other status, such as oversized requests or responses
+    /// returned by `Handler::on_request`.
+    pub other_rejected: u64,
+    /// Connections whose TLS handshake failed or timed out.
+    pub tls_failed: u64,
+}
+
+/// The counters behind `HandshakeStats`, updated by the event loop.
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub struct HandshakeCounters {
+    accepted: AtomicU64,
+    bad_request: AtomicU64,
+    forbidden: AtomicU64,
+    upgrade_required: AtomicU64,
+    too_many_requests: AtomicU64,
+    other_rejected: AtomicU64,
+    tls_failed: AtomicU64,
+}
+
+impl HandshakeCounters {
+    /// Record a handshake answered with the given status.
+    pub fn responded(&self, status: u16) {
+        let counter = match status {
+            101 => &self.accepted,
+            400 => &self.bad_request,
+            403 => &self.forbidden,
+            426 => &self.upgrade_required,
+            429 => &self.too_many_requests,
+            _ => &self.other_rejected,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a connection whose TLS handshake failed.
+    pub fn tls_failed(&self) {
+        self.tls_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> HandshakeStats {
+        HandshakeStats {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            bad_request: self.bad_request.load(Ordering::Relaxed),
+            forbidden: self.forbidden.load(Ordering::Relaxed),
+            upgrade_required: self.upgrade_required.load(Ordering::Relaxed),
+            too_many_requests: self.too_many_requests.load(Ordering::Relaxed),
+            other_rejected: self.other_rejected.load(Ordering::Relaxed),
+            tls_failed: self.tls_failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Producer {
+    name: String,
+    enqueued: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// The producers registered with the senders of one WebSocket, along with the number of commands
+/// waiting in its queue, the messages deferred until the current frame is handled and the
+/// outcomes of handshakes.
+#[doc(hidden)]
+#[derive(Clone, Default)]
+pub struct Producers {
+    registered: Arc<Mutex<Vec<Arc<Producer>>>>,
+    pending: Arc<AtomicUsize>,
+    deferred: Deferred,
+    handshakes: Arc<HandshakeCounters>,
+}
+
+impl Producers {
+    fn register(&self, name: String) -> Arc<Producer> {
+        let mut producers = self.registered.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(producer) = producers.iter().find(|producer| producer.name == name) {
+            return producer.clone();
+        }
+        let producer = Arc::new(Producer {
+            name,
+            enqueued: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        });
+        producers.push(producer.clone());
+        producer
+    }
+
+    /// Record that the event loop took a command off the queue.
+    #[inline]
+    pub fn dequeued(&self) {
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// The messages deferred with `Sender::enqueue_after_current`.
+    #[inline]
+    pub fn deferred(&self) -> Deferred {
+        self.deferred.clone()
+    }
+
+    /// The counters of handshake outcomes.
+    #[inline]
+    pub fn handshakes(&self) -> Arc<HandshakeCounters> {
+        self.handshakes.clone()
+    }
+
+    pub fn handshake_stats(&self) -> HandshakeStats {
+        self.handshakes.stats()
+    }
+
+    pub fn stats(&self) -> Vec<ProducerStats> {
+        let producers = self.registered.lock().unwrap_or_else(|err| err.into_inner());
+        producers
+            .iter()
+            .map(|producer| ProducerStats {
+                name: producer.name.clone(),
+                // the registry holds one reference
+                senders: Arc::strong_count(producer) - 1,
+                enqueued: producer.enqueued.load(Ordering::Relaxed),
+                failed: producer.failed.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Current {
+    // the connection that is handling a received frame
+    connection: Option<(Token, u64)>,
+    messages: Vec<message::Message>,
+}
+
+/// The messages queued with `Sender::enqueue_after_current` while a connection handles a received
+/// frame. The event loop handles one frame at a time, so a single slot serves every connection.
+#[doc(hidden)]
+#[derive(Debug, Clone, Default)]
+pub struct Deferred {
+    current: Arc<Mutex<Current>>,
+}
+
+impl Deferred {
+    /// Start deferring the messages of the given connection.
+    pub fn begin(&self, token: Token, connection_id: u64) {
+        let mut current = self.lock();
+        current.connection = Some((token, connection_id));
+        current.messages.clear();
+    }
+
+    /// Stop deferring and return the deferred messages in the order they were queued.
+    pub fn finish(&self) -> Vec<message::Message> {
+        let mut current = self.lock();
+        current.connection = None;
+        current.messages.split_off(0)
+    }
+
+    // Returns the message if its connection is not handling a frame.
+    fn defer(
+        &self,
+        token: Token,
+        connection_id: u64,
+        msg: message::Message,
+    ) -> Option<message::Message> {
+        let mut current = self.lock();
+        if current.connection == Some((token, connection_id)) {
+            current.messages.push(msg);
+            None
+        } else {
+            Some(msg)
+        }
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, Current> {
+        self.current.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
 /// A representation of the output of the WebSocket connection. Use this to send messages to the
 /// other endpoint.
 #[derive(Clone)]
 pub struct Sender {
     token: Token,
     channel: mio::channel::SyncSender<Command>,
-    connection_id: u32,
+    connection_id: u64,
+    producers: Producers,
+    producer: Option<Arc<Producer>>,
+    connected_at: Instant,
+    max_close_reason: usize,
+    long_close_reasons: LongCloseReasons,
+    acks: bool,
+    listener: Option<Token>,
 }
 
 impl fmt::Debug for Sender {
@@ -86,13 +412,133 @@ impl Sender {
     pub fn new(
         token: Token,
         channel: mio::channel::SyncSender<Command>,
-        connection_id: u32,
+        connection_id: u64,
     ) -> Sender {
         Sender {
             token,
             channel,
             connection_id,
+            producers: Producers::default(),
+            producer: None,
+            connected_at: Instant::now(),
+            max_close_reason: limits::MAX_CLOSE_REASON,
+            long_close_reasons: LongCloseReasons::Truncate,
+            acks: false,
+            listener: None,
+        }
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn with_producers(mut self, producers: Producers) -> Sender {
+        self.producers = producers;
+        self
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn with_settings(mut self, settings: &Settings) -> Sender {
+        self.max_close_reason = settings.max_close_reason;
+        self.long_close_reasons = settings.long_close_reasons;
+        self.acks = settings.acks;
+        self
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn with_listener(mut self, listener: Option<Token>) -> Sender {
+        self.listener = listener;
+        self
+    }
+
+    fn close_reason(&self, reason: Cow<'static, str>) -> Result<Cow<'static, str>> {
+        if reason.len() <= self.max_close_reason {
+            return Ok(reason);
+        }
+        match self.long_close_reasons {
+            LongCloseReasons::Truncate => {
+                let len = limits::truncate_close_reason(&reason, self.max_close_reason).len();
+                let mut reason = reason.into_owned();
+                reason.truncate(len);
+                Ok(reason.into())
+            }
+            LongCloseReasons::Reject => Err(Error::new(
+                Kind::Internal,
+                format!(
+                    "Close reason of {} bytes exceeds Settings::max_close_reason of {} bytes.",
+                    reason.len(),
+                    self.max_close_reason
+                ),
+            )),
+        }
+    }
+
+    #[inline]
+    fn enqueue(&self, command: Command) -> Result<()> {
+        self.enqueue_pending(command).map(|_| ())
+    }
+
+    // Returns the number of commands pending in the queue, including the new one.
+    fn enqueue_pending(&self, command: Command) -> Result<usize> {
+        // count the command before sending it so that the event loop never sees it uncounted
+        let pending = self.producers.pending.fetch_add(1, Ordering::Relaxed) + 1;
+        let res = self.channel.send(command).map_err(Error::from);
+        if res.is_err() {
+            self.producers.dequeued();
         }
+        if let Some(ref producer) = self.producer {
+            if res.is_ok() {
+                producer.enqueued.fetch_add(1, Ordering::Relaxed);
+            } else {
+                producer.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        res.map(|_| pending)
+    }
+
+    /// Create a sender for the same connection that counts the commands it enqueues under the
+    /// given name. Clones of the returned sender count toward the same producer, as do senders
+    /// registered again with the same name, so each component that sends messages can be given
+    /// its own producer to find out which one is filling the queue.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::thread;
+    /// use ws::Sender;
+    ///
+    /// fn start_ticker(out: &Sender) {
+    ///     let ticker = out.register_producer("ticker");
+    ///     thread::spawn(move || ticker.send("tick"));
+    /// }
+    /// ```
+    pub fn register_producer<S>(&self, name: S) -> Sender
+    where
+        S: Into<String>,
+    {
+        Sender {
+            producer: Some(self.producers.register(name.into())),
+            ..self.clone()
+        }
+    }
+
+    /// The name of the producer that this sender belongs to, if it was created with
+    /// `register_producer`.
+    #[inline]
+    pub fn producer(&self) -> Option<&str> {
+        self.producer.as_ref().map(|producer| &producer.name[..])
+    }
+
+    /// Statistics for every producer registered with the senders of this WebSocket, in the order
+    /// that they were registered.
+    pub fn producer_stats(&self) -> Vec<ProducerStats> {
+        self.producers.stats()
+    }
+
+    /// The number of handshakes of server connections on this WebSocket that ended with each
+    /// outcome so far.
+    pub fn handshake_stats(&self) -> HandshakeStats {
+        self.producers.handshake_stats()
     }
 
     /// A Token identifying this sender within the WebSocket.
@@ -101,25 +547,204 @@ impl Sender {
         self.token
     }
 
-    /// A connection_id identifying this sender within the WebSocket.
+    /// The token of the listener that accepted this connection, as returned by
+    /// `WebSocket::add_listener`. This is `None` for connections accepted by the listener of
+    /// `WebSocket::bind` and for client connections.
+    #[inline]
+    pub fn listener(&self) -> Option<Token> {
+        self.listener
+    }
+
+    /// A connection_id identifying this sender within the WebSocket. Unlike tokens, connection
+    /// ids are never reused, so they identify a connection even after it has closed. Log messages
+    /// about a connection include its id.
     #[inline]
-    pub fn connection_id(&self) -> u32 {
+    pub fn connection_id(&self) -> u64 {
         self.connection_id
     }
 
+    /// Get a sender for the connection identified by `token` and `connection_id`, as reported by
+    /// the `token` and `connection_id` methods of its own sender, for example when recording
+    /// metrics. This lets management tooling using the `Sender` returned by
+    /// `WebSocket::broadcaster` reach a single connection without having kept its sender.
+    ///
+    /// Tokens are reused once a connection is gone, so every command sent through the returned
+    /// sender is checked against the connection id when the event loop handles it, and is
+    /// dropped if the token now belongs to another connection or to none, as for any sender of a
+    /// closed connection. The returned sender reports the time it was created from
+    /// `connected_at`, belongs to no producer and reports no listener.
+    #[inline]
+    pub fn sender_for(&self, token: Token, connection_id: u64) -> Sender {
+        Sender {
+            token,
+            connection_id,
+            producer: None,
+            connected_at: Instant::now(),
+            listener: None,
+            ..self.clone()
+        }
+    }
+
+    /// When the connection was accepted by a server or initiated by a client. Clones of this
+    /// sender report the same instant. For a sender that broadcasts to every connection, this is
+    /// when the sender was created.
+    #[inline]
+    pub fn connected_at(&self) -> Instant {
+        self.connected_at
+    }
+
+    /// The time elapsed since the connection was accepted or initiated.
+    #[inline]
+    pub fn uptime(&self) -> Duration {
+        self.connected_at.elapsed()
+    }
+
     /// Send a message over the connection.
     #[inline]
     pub fn send<M>(&self, msg: M) -> Result<()>
     where
         M: Into<message::Message>,
     {
-        self.channel
-            .send(Command {
-                token: self.token,
-                signal: Signal::Message(msg.into()),
-                connection_id: self.connection_id,
-            })
-            .map_err(Error::from)
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Message(msg.into()),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Send a message over the connection once it has handled the frame that it is receiving,
+    /// ahead of any frame received after that one.
+    ///
+    /// Messages sent with `send` go through the event loop queue, so a handler that takes over a
+    /// frame in `on_frame` and answers it with `send` may find its answer written after the
+    /// responses to the frames that follow, such as the pong to a later ping. Messages queued with
+    /// this method while the connection handles a received frame are written in the order they
+    /// were queued, after any response that the connection itself makes to that frame and before
+    /// the next frame is handled. At any other time this is the same as `send`.
+    pub fn enqueue_after_current<M>(&self, msg: M) -> Result<()>
+    where
+        M: Into<message::Message>,
+    {
+        match self
+            .producers
+            .deferred
+            .defer(self.token, self.connection_id, msg.into())
+        {
+            Some(msg) => self.send(msg),
+            None => Ok(()),
+        }
+    }
+
+    /// Send a message over the connection and return the approximate number of commands pending
+    /// in the event loop queue, including this one.
+    ///
+    /// Producers can use the depth to slow down before the queue fills up and sending blocks. The
+    /// number is approximate because other senders may enqueue commands and the event loop may
+    /// take them off the queue at the same time.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// fn stream(out: &ws::Sender, chunks: Vec<Vec<u8>>) -> ws::Result<()> {
+    ///     for chunk in chunks {
+    ///         if out.send_with_feedback(chunk)? > 100 {
+    ///             thread::sleep(Duration::from_millis(10));
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub fn send_with_feedback<M>(&self, msg: M) -> Result<usize>
+    where
+        M: Into<message::Message>,
+    {
+        self.enqueue_pending(Command {
+            token: self.token,
+            signal: Signal::Message(msg.into()),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Send a message over the connection, splitting it into frames of at most `fragment_size`
+    /// bytes. This overrides the `fragment_size`, `text_fragment_size` and
+    /// `binary_fragment_size` settings for this message only.
+    #[inline]
+    pub fn send_with_fragment_size<M>(&self, msg: M, fragment_size: usize) -> Result<()>
+    where
+        M: Into<message::Message>,
+    {
+        if fragment_size == 0 {
+            return Err(Error::new(
+                Kind::Internal,
+                "Unable to send a message with a fragment size of zero.",
+            ));
+        }
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Fragmented(msg.into(), fragment_size),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Send a message over the connection and track it through the send pipeline.
+    ///
+    /// The returned identifier is passed to the handler's `on_message_written` method once the
+    /// final byte of the message has been written to the socket, which allows latency accounting
+    /// and delivery confirmation. If this is the sender returned by `WebSocket::broadcaster`, the
+    /// message is sent to all connections and each connection reports the write separately.
+    #[inline]
+    pub fn send_traced<M>(&self, msg: M) -> Result<MessageId>
+    where
+        M: Into<message::Message>,
+    {
+        let meta = MessageMeta {
+            id: MessageId::next(),
+            enqueued: Instant::now(),
+        };
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Traced(msg.into(), meta),
+            connection_id: self.connection_id,
+        })?;
+        Ok(meta.id)
+    }
+
+    /// Send a message over the connection and ask the other endpoint to acknowledge it.
+    ///
+    /// The message is wrapped in the envelope described by `Settings::acks`, which must be
+    /// enabled. Once the other endpoint acknowledges the message, the returned token is passed
+    /// to the handler's `on_ack` method, or to `on_ack_timeout` if no acknowledgment arrives
+    /// within `Settings::ack_timeout_ms`. Messages cannot be tracked across all connections, so
+    /// this fails with an error of kind `Internal` for the sender returned by
+    /// `WebSocket::broadcaster`.
+    #[inline]
+    pub fn send_tracked<M>(&self, msg: M) -> Result<AckToken>
+    where
+        M: Into<message::Message>,
+    {
+        if !self.acks {
+            return Err(Error::new(
+                Kind::Internal,
+                "Settings::acks must be enabled to send tracked messages.",
+            ));
+        }
+        if self.token == ALL {
+            return Err(Error::new(
+                Kind::Internal,
+                "Tracked messages must be sent to a single connection.",
+            ));
+        }
+        let token = AckToken::next();
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Tracked(msg.into(), token),
+            connection_id: self.connection_id,
+        })?;
+        Ok(token)
     }
 
     /// Send a message to the endpoints of all connections.
@@ -134,101 +759,332 @@ impl Sender {
     where
         M: Into<message::Message>,
     {
-        self.channel
-            .send(Command {
-                token: ALL,
-                signal: Signal::Message(msg.into()),
-                connection_id: self.connection_id,
-            })
-            .map_err(Error::from)
+        self.enqueue(Command {
+            token: ALL,
+            signal: Signal::Message(msg.into()),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Broadcast a frame that was formatted ahead of time, for example with `Frame::format`, to
+    /// every open server connection on this WebSocket.
+    ///
+    /// The bytes are appended to the outgoing buffer of each connection unchanged, so a payload
+    /// that is sent to many connections, such as a ticker update, is formatted once rather than
+    /// once per connection. Client connections are skipped because their frames must be masked.
+    /// The frame also bypasses `Handler::on_send_frame` and extensions, so it must be valid for
+    /// every connection as it is. Returns an error of kind `Internal` if `frame` is not exactly
+    /// one complete unmasked frame.
+    #[inline]
+    pub fn broadcast_raw(&self, frame: Vec<u8>) -> Result<()> {
+        if !frame::is_unmasked_frame(&frame) {
+            return Err(Error::new(
+                Kind::Internal,
+                "Raw broadcasts must contain exactly one complete unmasked frame.",
+            ));
+        }
+        self.enqueue(Command {
+            token: ALL,
+            signal: Signal::Raw(Arc::new(frame)),
+            connection_id: self.connection_id,
+        })
     }
 
     /// Send a close code to the other endpoint.
     #[inline]
     pub fn close(&self, code: CloseCode) -> Result<()> {
-        self.channel
-            .send(Command {
-                token: self.token,
-                signal: Signal::Close(code, "".into()),
-                connection_id: self.connection_id,
-            })
-            .map_err(Error::from)
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Close(code, "".into()),
+            connection_id: self.connection_id,
+        })
     }
 
     /// Send a close code and provide a descriptive reason for closing.
+    ///
+    /// Reasons longer than `Settings::max_close_reason` are truncated or rejected with an error of
+    /// kind `Internal`, according to `Settings::long_close_reasons`.
     #[inline]
     pub fn close_with_reason<S>(&self, code: CloseCode, reason: S) -> Result<()>
     where
         S: Into<Cow<'static, str>>,
     {
-        self.channel
-            .send(Command {
-                token: self.token,
-                signal: Signal::Close(code, reason.into()),
-                connection_id: self.connection_id,
-            })
-            .map_err(Error::from)
+        let reason = self.close_reason(reason.into())?;
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Close(code, reason),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Send a close code and reason to each of the connections identified by `tokens`. The
+    /// connections are all closed when the event loop handles a single command, so this is
+    /// cheaper than sending a close through a sender per connection, for example when kicking
+    /// every unauthenticated client from the `Sender` returned by `WebSocket::broadcaster`.
+    ///
+    /// Tokens that no longer belong to a connection are ignored. Tokens are reused once a
+    /// connection is gone, so they should be collected shortly before calling this method. Long
+    /// reasons are treated as by `close_with_reason`.
+    #[inline]
+    pub fn close_tokens<S>(&self, tokens: Vec<Token>, code: CloseCode, reason: S) -> Result<()>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let reason = self.close_reason(reason.into())?;
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::CloseTokens(tokens, code, reason),
+            connection_id: self.connection_id,
+        })
     }
 
     /// Send a ping to the other endpoint with the given test data.
     #[inline]
     pub fn ping(&self, data: Vec<u8>) -> Result<()> {
-        self.channel
-            .send(Command {
-                token: self.token,
-                signal: Signal::Ping(data),
-                connection_id: self.connection_id,
-            })
-            .map_err(Error::from)
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Ping(data),
+            connection_id: self.connection_id,
+        })
     }
 
     /// Send a pong to the other endpoint responding with the given test data.
     #[inline]
     pub fn pong(&self, data: Vec<u8>) -> Result<()> {
-        self.channel
-            .send(Command {
-                token: self.token,
-                signal: Signal::Pong(data),
-                connection_id: self.connection_id,
-            })
-            .map_err(Error::from)
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Pong(data),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Stop reading from the connection until `resume_read` is called. Incoming data is left in
+    /// the socket, so TCP flow control eventually slows down the other endpoint. Sending is not
+    /// affected, and reading continues once a closing handshake starts so that the connection can
+    /// still close cleanly.
+    #[inline]
+    pub fn suspend_read(&self) -> Result<()> {
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::SuspendRead,
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Resume reading from a connection suspended with `suspend_read`. Data that arrived while
+    /// reading was suspended is delivered as soon as the event loop processes this signal.
+    #[inline]
+    pub fn resume_read(&self) -> Result<()> {
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::ResumeRead,
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Wake the connection on the event loop. The handler's `on_wake` method is called and the
+    /// connection is registered again with its current interest, so any pending read or write is
+    /// retried.
+    #[inline]
+    pub fn wake(&self) -> Result<()> {
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Wake,
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Hand a received frame back to the connection through the event loop queue. The frame is
+    /// passed to `Handler::on_injected_frame` and then handled as if it had been returned by
+    /// `Handler::on_frame`. Frames injected after the connection started closing are dropped.
+    ///
+    /// This is intended for extensions that process received frames on another thread. Frames
+    /// injected with the same sender are handled in the order they were injected. Frames belong
+    /// to a single connection, so a broadcaster cannot inject them.
+    #[doc(hidden)]
+    #[inline]
+    pub fn inject_frame(&self, frame: Frame) -> Result<()> {
+        if self.token == ALL {
+            return Err(Error::new(
+                Kind::Internal,
+                "Unable to inject a received frame into every connection.",
+            ));
+        }
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Inject(frame),
+            connection_id: self.connection_id,
+        })
     }
 
     /// Queue a new connection on this WebSocket to the specified URL.
     #[inline]
     pub fn connect(&self, url: url::Url) -> Result<()> {
-        self.channel
-            .send(Command {
-                token: self.token,
-                signal: Signal::Connect(url),
-                connection_id: self.connection_id,
-            })
-            .map_err(Error::from)
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Connect(url, None, None),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Queue a new connection on this WebSocket to the specified URL, binding the outgoing socket
+    /// to the given local address. This overrides the `local_bind` setting for this connection.
+    #[inline]
+    pub fn connect_from(&self, url: url::Url, local_addr: SocketAddr) -> Result<()> {
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Connect(url, Some(local_addr), None),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Queue a new connection on this WebSocket to the specified URL and report the outcome to
+    /// the `on_connect_result` method of this sender's handler along with `user_token`.
+    ///
+    /// A successful result carries the token assigned to the new connection. It indicates that
+    /// the connection was initiated; the new connection's handler will be notified when the
+    /// WebSocket handshake completes.
+    ///
+    /// A broadcaster has no handler to report to, so calling this on the sender returned by
+    /// `WebSocket::broadcaster` fails with an error of kind `Internal`. Use `connect` instead.
+    #[inline]
+    pub fn connect_with_token(&self, url: url::Url, user_token: Token) -> Result<()> {
+        if self.token == ALL {
+            return Err(Error::new(
+                Kind::Internal,
+                "Unable to report the result of a connection requested by a broadcaster.",
+            ));
+        }
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Connect(url, None, Some(user_token)),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Cancel connection attempts that have not completed their opening handshake, identified
+    /// either by URL or by the token of the new connection.
+    ///
+    /// Each cancelled connection is disconnected and its handler receives an error of kind
+    /// `Cancelled`. If the attempt was requested with `connect_with_token`, the requesting
+    /// handler's `on_connect_result` is called again with the same error. Host names are resolved
+    /// when the event loop processes the connect signal, so an attempt can only be cancelled once
+    /// it is waiting for the TCP connection or the handshake.
+    #[inline]
+    pub fn cancel_connect<T>(&self, target: T) -> Result<()>
+    where
+        T: Into<ConnectTarget>,
+    {
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::CancelConnect(target.into()),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Get the tokens of all connections currently held by the WebSocket, including connections
+    /// that are still performing their opening handshake or are closing.
+    ///
+    /// This method blocks until the event loop services the request, so it must not be called
+    /// from a handler running on the event loop thread. It is intended for management tooling
+    /// using the `Sender` returned by `WebSocket::broadcaster`.
+    pub fn tokens(&self) -> Result<Vec<Token>> {
+        let (tx, rx) = mpsc::channel();
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Tokens(tx),
+            connection_id: self.connection_id,
+        })?;
+        rx.recv().map_err(|_| {
+            Error::new(
+                Kind::Internal,
+                "The WebSocket stopped before reporting its connections.",
+            )
+        })
+    }
+
+    /// Wait until the messages queued on this connection before the call have been written to
+    /// the socket, or until `timeout` expires. On a broadcaster, this waits for every connection.
+    /// This is useful before exiting the process, to make sure that final messages went out.
+    ///
+    /// Fails with an `Io` error of kind `TimedOut` if the timeout expires, and of kind
+    /// `NotConnected` if the connection closes before its messages are written. Like `tokens`,
+    /// this blocks until the event loop answers, so it must not be called from a handler running
+    /// on the event loop thread.
+    pub fn flush(&self, timeout: Duration) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Flush(tx),
+            connection_id: self.connection_id,
+        })?;
+        let deadline = Instant::now() + timeout;
+        let mut flushed = 0;
+        loop {
+            // each connection answers once, and the channel closes once every connection has
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(true) => flushed += 1,
+                Ok(false) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) if flushed > 0 || self.token == ALL => {
+                    return Ok(())
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    return Err(Error::from(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Timed out waiting for queued messages to be written.",
+                    )))
+                }
+            }
+        }
+        Err(Error::from(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "The connection closed before its queued messages were written.",
+        )))
+    }
+
+    /// Stop accepting new connections on the listening socket of this WebSocket, for example
+    /// while it is overloaded or about to be redeployed. Existing connections are not affected.
+    /// Incoming connections wait in the operating system's backlog until accepting is resumed
+    /// with `resume_accepting`, and are refused by the operating system once the backlog is full.
+    #[inline]
+    pub fn pause_accepting(&self) -> Result<()> {
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::PauseAccept,
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Resume accepting new connections after a call to `pause_accepting`.
+    #[inline]
+    pub fn resume_accepting(&self) -> Result<()> {
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::ResumeAccept,
+            connection_id: self.connection_id,
+        })
     }
 
     /// Request that all connections terminate and that the WebSocket stop running.
     #[inline]
     pub fn shutdown(&self) -> Result<()> {
-        self.channel
-            .send(Command {
-                token: self.token,
-                signal: Signal::Shutdown,
-                connection_id: self.connection_id,
-            })
-            .map_err(Error::from)
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Shutdown,
+            connection_id: self.connection_id,
+        })
     }
 
     /// Schedule a `token` to be sent to the WebSocket Handler's `on_timeout` method
-    /// after `ms` milliseconds
+    /// after `ms` milliseconds. The precision of timeouts is set by `Settings::timer_tick_ms`.
     #[inline]
     pub fn timeout(&self, ms: u64, token: Token) -> Result<()> {
-        self.channel
-            .send(Command {
-                token: self.token,
-                signal: Signal::Timeout { delay: ms, token },
-                connection_id: self.connection_id,
-            })
-            .map_err(Error::from)
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Timeout { delay: ms, token },
+            connection_id: self.connection_id,
+        })
     }
 
     /// Queue the cancellation of a previously scheduled timeout.
@@ -238,12 +1094,24 @@ impl Sender {
     /// handle spurious timeouts.
     #[inline]
     pub fn cancel(&self, timeout: Timeout) -> Result<()> {
-        self.channel
-            .send(Command {
-                token: self.token,
-                signal: Signal::Cancel(timeout),
-                connection_id: self.connection_id,
-            })
-            .map_err(Error::from)
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::Cancel(timeout),
+            connection_id: self.connection_id,
+        })
+    }
+
+    /// Queue the cancellation of all timeouts scheduled for this connection that have not yet
+    /// occurred. When called on a broadcaster, the timeouts of every connection are cancelled.
+    ///
+    /// Timeouts are also cancelled when a connection is removed from the event loop, so handlers
+    /// are never called for timeouts scheduled by a previous connection.
+    #[inline]
+    pub fn cancel_all_timeouts(&self) -> Result<()> {
+        self.enqueue(Command {
+            token: self.token,
+            signal: Signal::CancelAllTimeouts,
+            connection_id: self.connection_id,
+        })
     }
 }
@@ -1,12 +1,15 @@
 use std::borrow::Borrow;
+use std::cell::Cell;
+use std::cmp::{max, min};
 use std::collections::VecDeque;
-use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 use std::mem::replace;
 use std::net::SocketAddr;
 use std::str::from_utf8;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
-use mio::tcp::TcpStream;
-use mio::{Ready, Token};
+use mio::{Evented, Ready, Token};
 use mio_extras::timer::Timeout;
 use url;
 
@@ -15,18 +18,30 @@ use native_tls::HandshakeError;
 #[cfg(feature = "ssl")]
 use openssl::ssl::HandshakeError;
 
-use frame::Frame;
+use ack::{self, Envelope};
+use communication::{AckToken, Deferred, HandshakeCounters, MessageMeta, Sender, Signal};
+use event::{Direction, ErrorEvent, ErrorPhase};
+use frame::{self, Frame, MaskFn};
 use handler::Handler;
-use handshake::{Handshake, Request, Response};
-use message::Message;
+use handshake::{Handshake, HandshakeTimings, Request, Response, Version};
+use message::{Message, MessageInfo};
+use middleware::Chain;
+use pool::{self, Buffers, Memory, Reservation};
 use protocol::{CloseCode, OpCode};
+use proxy::{Progress, Proxy, Tunnel};
 use result::{Error, Kind, Result};
-use stream::{Stream, TryReadBuf, TryWriteBuf};
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+use sni::{self, ServerName};
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+use stream::TlsClientOptions;
+use stream::{connect_tcp, Stream, TryReadBuf, TryWriteBuf};
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+use writer::{Job, Writer};
 
 use self::Endpoint::*;
 use self::State::*;
 
-use super::Settings;
+use super::{AfterClose, EventOrder, HandshakeBody, LargeMessages, Settings};
 
 #[derive(Debug)]
 pub enum State {
@@ -57,7 +72,6 @@ impl State {
         }
     }
 
-    #[allow(dead_code)]
     #[inline]
     pub fn is_open(&self) -> bool {
         match *self {
@@ -75,6 +89,126 @@ impl State {
     }
 }
 
+// Errors encountered before the connection opens are attributed to the handshake, keeping the
+// direction of the data that was involved.
+fn error_event(token: Token, phase: ErrorPhase, connecting: bool, error: Error) -> ErrorEvent {
+    let direction = match phase {
+        ErrorPhase::Read => Some(Direction::Inbound),
+        ErrorPhase::Write | ErrorPhase::Command => Some(Direction::Outbound),
+        ErrorPhase::Handshake | ErrorPhase::Timer => None,
+    };
+    ErrorEvent {
+        phase: if connecting { ErrorPhase::Handshake } else { phase },
+        direction,
+        token,
+        error,
+    }
+}
+
+// Record when TLS negotiation completes during the handshake.
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+fn record_tls(socket: &Stream, timings: &mut HandshakeTimings) {
+    if timings.tls_established.is_none() && socket.is_tls_established() {
+        timings.tls_established = Some(Instant::now());
+    }
+}
+
+#[cfg(not(any(feature = "ssl", feature = "nativetls")))]
+fn record_tls(_: &Stream, _: &mut HandshakeTimings) {}
+
+// The number of bytes of a failed handshake response body included in the error.
+const MAX_FAILURE_BODY: usize = 1024;
+
+// The number of bytes read at a time while the handshake buffer is full.
+const HANDSHAKE_READ_SIZE: usize = 512;
+
+// The content type of the TLS record that carries a ClientHello.
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+// The length of the head of an HTTP message, including the blank line that ends it.
+fn header_len(data: &[u8]) -> usize {
+    data.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+        .unwrap_or_else(|| data.len())
+}
+
+// The length of the body announced by a request, or None if the length is not known in advance.
+fn body_len(request: &Request) -> Result<Option<usize>> {
+    if request.header("transfer-encoding").is_some() {
+        return Ok(None);
+    }
+    match request.header("content-length") {
+        Some(len) => from_utf8(len)
+            .ok()
+            .and_then(|len| len.trim().parse().ok())
+            .map(Some)
+            .ok_or_else(|| {
+                Error::new(
+                    Kind::Protocol,
+                    format!(
+                        "Invalid Content-Length in handshake request: {}",
+                        String::from_utf8_lossy(len)
+                    ),
+                )
+            }),
+        None => Ok(Some(0)),
+    }
+}
+
+// Render the body of a failed handshake response for error reporting, decoding it first if the
+// server compressed it and we are able to decompress it.
+fn decode_failure_body(response: &Response, body: &[u8]) -> String {
+    #[cfg(feature = "permessage-deflate")]
+    {
+        if let Some(encoding) = response.header("content-encoding") {
+            if encoding.eq_ignore_ascii_case(b"gzip") {
+                // decompress one byte more than is shown to learn whether the body was truncated
+                if let Ok(decoded) = ::deflate::gunzip(body, MAX_FAILURE_BODY + 1) {
+                    return truncate_failure_body(&decoded);
+                }
+            }
+        }
+    }
+    #[cfg(not(feature = "permessage-deflate"))]
+    let _ = response;
+
+    truncate_failure_body(body)
+}
+
+// Render at most `MAX_FAILURE_BODY` bytes of a body.
+fn truncate_failure_body(body: &[u8]) -> String {
+    if body.len() > MAX_FAILURE_BODY {
+        format!(
+            "{}... (truncated)",
+            String::from_utf8_lossy(&body[..MAX_FAILURE_BODY])
+        )
+    } else {
+        String::from_utf8_lossy(body).into_owned()
+    }
+}
+
+// A thread waiting in `Sender::flush`. It is told whether the messages queued before it were
+// written, and that they were not if the connection goes away first.
+struct FlushWaiter(Option<mpsc::Sender<bool>>);
+
+impl FlushWaiter {
+    fn flushed(mut self) {
+        if let Some(tx) = self.0.take() {
+            let _ = tx.send(true);
+        }
+    }
+}
+
+impl Drop for FlushWaiter {
+    fn drop(&mut self) {
+        if let Some(tx) = self.0.take() {
+            let _ = tx.send(false);
+        }
+    }
+}
+
 pub struct Connection<H>
 where
     H: Handler,
@@ -86,6 +220,25 @@ where
     events: Ready,
 
     fragments: VecDeque<Frame>,
+    fragments_size: usize,
+    // the number of frames of a large message passed to `on_fragment` without being buffered
+    streamed: usize,
+    read_pending: bool,
+    // whether the connection wrote first the last time that it was both readable and writable
+    wrote_first: bool,
+    after_close_frames: usize,
+    after_close_bytes: usize,
+    // Total bytes of frames buffered and written, used to tell when a traced message is written
+    buffered_bytes: u64,
+    written_bytes: u64,
+    traced: VecDeque<(u64, MessageMeta)>,
+    // messages sent with `Sender::send_tracked` awaiting acknowledgment, oldest first
+    pending_acks: VecDeque<(AckToken, Instant)>,
+    // The number of data messages received, used as the sequence number of the next one
+    received: u64,
+    // The most bytes pending in each buffer since buffers were last considered for shrinking
+    in_high_water: usize,
+    out_high_water: usize,
 
     in_buffer: Cursor<Vec<u8>>,
     out_buffer: Cursor<Vec<u8>>,
@@ -93,9 +246,42 @@ where
     handler: H,
 
     addresses: Vec<SocketAddr>,
+    local_addr: Option<SocketAddr>,
 
     settings: Settings,
-    connection_id: u32,
+    connection_id: u64,
+    timings: HandshakeTimings,
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    tls_client: TlsClientOptions,
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    writer: Option<Writer>,
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    tls_started: Option<Instant>,
+    // whether the first byte from the client still has to decide if the connection is encrypted
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    detect_tls: bool,
+    // whether reading waits for the rest of the ClientHello in order to find the server name
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    awaiting_hello: bool,
+    // the server name that the client asked for in its ClientHello
+    server_name: Option<String>,
+    writing: bool,
+    read_suspended: bool,
+    // whether the other endpoint has shut down its side of the connection
+    read_eof: bool,
+    middleware: Option<(Chain, Sender)>,
+    // the share of the event loop's buffer memory held by this connection
+    memory: Option<Reservation>,
+    // the counters of handshake outcomes of a server connection, taken once its outcome is counted
+    handshakes: Option<Arc<HandshakeCounters>>,
+    deferred: Deferred,
+    tunnel: Option<Tunnel>,
+    // the payloads of pings that have not been answered, oldest first, when `pong_must_match` is set
+    pings: VecDeque<Vec<u8>>,
+    // threads waiting for the outgoing buffer to be written
+    flushes: Vec<FlushWaiter>,
+    // Set when the socket is replaced so that the new socket is registered rather than reregistered
+    new_socket: Cell<bool>,
 }
 
 impl<H> Connection<H>
@@ -104,27 +290,115 @@ where
 {
     pub fn new(
         tok: Token,
-        sock: TcpStream,
+        sock: Stream,
         handler: H,
         settings: Settings,
-        connection_id: u32,
+        connection_id: u64,
+        buffers: Buffers,
     ) -> Connection<H> {
         Connection {
             token: tok,
-            socket: Stream::tcp(sock),
-            state: Connecting(
-                Cursor::new(Vec::with_capacity(2048)),
-                Cursor::new(Vec::with_capacity(2048)),
-            ),
+            socket: sock,
+            state: Connecting(Cursor::new(buffers.request), Cursor::new(buffers.response)),
             endpoint: Endpoint::Server,
             events: Ready::empty(),
-            fragments: VecDeque::with_capacity(settings.fragments_capacity),
-            in_buffer: Cursor::new(Vec::with_capacity(settings.in_buffer_capacity)),
-            out_buffer: Cursor::new(Vec::with_capacity(settings.out_buffer_capacity)),
+            fragments: buffers.fragments,
+            fragments_size: 0,
+            streamed: 0,
+            read_pending: false,
+            wrote_first: false,
+            after_close_frames: 0,
+            after_close_bytes: 0,
+            buffered_bytes: 0,
+            written_bytes: 0,
+            traced: VecDeque::new(),
+            pending_acks: VecDeque::new(),
+            received: 0,
+            in_high_water: 0,
+            out_high_water: 0,
+            in_buffer: Cursor::new(buffers.in_buffer),
+            out_buffer: Cursor::new(buffers.out_buffer),
             handler,
             addresses: Vec::new(),
+            local_addr: None,
             settings,
             connection_id,
+            timings: HandshakeTimings::default(),
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            tls_client: TlsClientOptions::default(),
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            writer: None,
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            tls_started: None,
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            detect_tls: false,
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            awaiting_hello: false,
+            server_name: None,
+            writing: false,
+            read_suspended: false,
+            read_eof: false,
+            middleware: None,
+            memory: None,
+            handshakes: None,
+            deferred: Deferred::default(),
+            tunnel: None,
+            pings: VecDeque::new(),
+            flushes: Vec::new(),
+            new_socket: Cell::new(false),
+        }
+    }
+
+    /// Pass the events of this connection through the given middleware before the handler.
+    pub fn with_middleware(mut self, chain: Chain, out: Sender) -> Connection<H> {
+        if !chain.is_empty() {
+            self.middleware = Some((chain, out));
+        }
+        self
+    }
+
+    /// Hold the messages that the handler queues with `Sender::enqueue_after_current` until the
+    /// frame being received is handled.
+    pub fn with_deferred(mut self, deferred: Deferred) -> Connection<H> {
+        self.deferred = deferred;
+        self
+    }
+
+    /// Account for the buffers of this connection in the memory shared by all connections on the
+    /// event loop.
+    pub fn with_memory(mut self, memory: Memory) -> Connection<H> {
+        let mut reservation = Reservation::new(memory);
+        // the event loop checks that there is room before accepting a connection
+        let _ = reservation.update(self.buffer_memory());
+        self.memory = Some(reservation);
+        self
+    }
+
+    /// Count the outcome of the opening handshake of this server connection.
+    pub fn with_handshakes(mut self, handshakes: Arc<HandshakeCounters>) -> Connection<H> {
+        self.handshakes = Some(handshakes);
+        self
+    }
+
+    /// The number of bytes held by the incoming and outgoing buffers of this connection and by
+    /// the fragments of a partially received message.
+    pub fn buffer_memory(&self) -> usize {
+        let fragments = if self.streamed > 0 {
+            0
+        } else {
+            self.fragments_size
+        };
+        self.in_buffer.get_ref().capacity() + self.out_buffer.get_ref().capacity() + fragments
+    }
+
+    // Record the current buffer memory of this connection, failing if the buffers of all
+    // connections now hold more than `Settings::max_total_buffer_memory`.
+    fn account(&mut self) -> Result<()> {
+        let bytes = self.buffer_memory();
+        if let Some(ref mut memory) = self.memory {
+            memory.update(bytes)
+        } else {
+            Ok(())
         }
     }
 
@@ -133,10 +407,16 @@ where
         Ok(())
     }
 
-    pub fn as_client(&mut self, url: url::Url, addrs: Vec<SocketAddr>) -> Result<()> {
+    pub fn as_client(
+        &mut self,
+        url: url::Url,
+        addrs: Vec<SocketAddr>,
+        local_addr: Option<SocketAddr>,
+    ) -> Result<()> {
         if let Connecting(ref mut req_buf, _) = self.state {
             let req = self.handler.build_request(&url)?;
             self.addresses = addrs;
+            self.local_addr = local_addr;
             self.events.insert(Ready::writable());
             self.endpoint = Endpoint::Client(url);
             req.format(req_buf.get_mut())
@@ -148,12 +428,136 @@ where
         }
     }
 
+    /// Tunnel this client connection through a proxy. The socket must be connected to the proxy.
+    pub fn set_proxy(&mut self, proxy: Proxy) -> Result<()> {
+        if let Client(ref url) = self.endpoint {
+            self.tunnel = Some(Tunnel::new(proxy, url)?);
+            Ok(())
+        } else {
+            Err(Error::new(
+                Kind::Internal,
+                "Tried to use a proxy for a server connection.",
+            ))
+        }
+    }
+
+    /// Whether the socket was replaced since the connection was last scheduled.
+    #[inline]
+    pub fn take_new_socket(&self) -> bool {
+        self.new_socket.replace(false)
+    }
+
+    // Whether the connection runs over TLS.
+    #[inline]
+    fn is_secure(&self) -> bool {
+        self.socket.is_encrypted()
+    }
+
+    #[inline]
+    fn is_tunnelling(&self) -> bool {
+        match self.tunnel {
+            Some(ref tunnel) => !tunnel.is_established(),
+            None => false,
+        }
+    }
+
+    fn write_tunnel(&mut self) -> Result<()> {
+        if let Some(ref mut tunnel) = self.tunnel {
+            if self.socket.try_write_buf(tunnel.request())?.is_some() && tunnel.is_request_written()
+            {
+                trace!("Sent CONNECT request for {} to proxy.", tunnel.target());
+                self.events.remove(Ready::writable());
+                self.events.insert(Ready::readable());
+            }
+        }
+        Ok(())
+    }
+
+    fn read_tunnel(&mut self) -> Result<()> {
+        let progress = match self.tunnel {
+            Some(ref mut tunnel) => match self.socket.try_read_buf(tunnel.response())? {
+                Some(0) => {
+                    return Err(Error::new(
+                        Kind::Protocol,
+                        format!(
+                            "The proxy closed the connection before the tunnel to {} was \
+                             established.",
+                            tunnel.target()
+                        ),
+                    ))
+                }
+                Some(_) => tunnel.progress()?,
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+
+        match progress {
+            Progress::Incomplete => return Ok(()),
+            Progress::Established => {
+                debug!("Tunnel through proxy {} established.", self.peer_addr());
+                #[cfg(any(feature = "ssl", feature = "nativetls"))]
+                {
+                    let secure = match self.endpoint {
+                        Client(ref url) => url.scheme() == "wss",
+                        Server => false,
+                    };
+                    if secure {
+                        self.encrypt()?;
+                    }
+                }
+            }
+            Progress::Retry => {
+                trace!("Retrying CONNECT request with proxy credentials.");
+            }
+            Progress::Reconnect => {
+                trace!("Reconnecting to proxy to retry CONNECT request with credentials.");
+                let addr = self.socket.peer_addr()?;
+                self.socket = Stream::tcp(connect_tcp(&addr, self.local_addr, self.settings.local_interface)?);
+                self.new_socket.set(true);
+            }
+        }
+        self.events.remove(Ready::readable());
+        self.events.insert(Ready::writable());
+        Ok(())
+    }
+
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn set_tls_client_options(&mut self, options: TlsClientOptions) {
+        self.tls_client = options;
+    }
+
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn set_writer(&mut self, writer: Writer) {
+        self.writer = Some(writer);
+    }
+
+    /// Whether the stream has been handed to a writer thread. The connection must not be
+    /// scheduled until the write completes.
+    #[inline]
+    pub fn is_writing(&self) -> bool {
+        self.writing
+    }
+
     #[cfg(any(feature = "ssl", feature = "nativetls"))]
     pub fn encrypt(&mut self) -> Result<()> {
-        let sock = self.socket().try_clone()?;
+        // The TCP stream is moved into the TLS stream rather than cloned. If the upgrade fails the
+        // stream is lost and the connection should be discarded.
+        let sock = self.socket.take_tcp().ok_or_else(|| {
+            Error::new(
+                Kind::Internal,
+                "Attempted to encrypt a connection that is already encrypted.",
+            )
+        })?;
+        self.tls_started = Some(Instant::now());
         let ssl_stream = match self.endpoint {
-            Server => self.handler.upgrade_ssl_server(sock),
-            Client(ref url) => self.handler.upgrade_ssl_client(sock, url),
+            Server => self
+                .handler
+                .upgrade_ssl_server_for(sock, self.server_name.as_deref()),
+            Client(ref url) => {
+                self.handler
+                    .upgrade_ssl_client_with_options(sock, url, &self.tls_client)
+            }
         };
 
         match ssl_stream {
@@ -191,23 +595,143 @@ where
         }
     }
 
+    /// Encrypt the connection once the client is found to begin with a TLS handshake.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn detect_tls(&mut self) {
+        self.detect_tls = true;
+    }
+
+    // Peek at the first byte from the client to find out whether it starts a TLS handshake, and
+    // encrypt the connection if it does. Returns true once the connection is known to be plain
+    // and the read can go on. An encrypted connection continues the TLS handshake when the client
+    // sends its next flight, as it would had it been encrypted when it was accepted.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn sniff_tls(&mut self) -> Result<bool> {
+        self.awaiting_hello = false;
+        let mut first = [0; 1];
+        match self.socket.peek(&mut first) {
+            Ok(len) => {
+                let encrypted = len > 0 && first[0] == TLS_HANDSHAKE_RECORD;
+                // servers that always encrypt only wait here to read the server name
+                if !encrypted && !self.settings.encrypt_server {
+                    self.detect_tls = false;
+                    return Ok(true);
+                }
+                if encrypted && self.settings.sniff_server_name && !self.sniff_server_name()? {
+                    return Ok(false);
+                }
+                self.detect_tls = false;
+                trace!("Detected TLS handshake from {}.", self.peer_addr());
+                if let Err(err) = self.encrypt() {
+                    // the socket was consumed by the failed upgrade
+                    let event = error_event(self.token, ErrorPhase::Read, true, err);
+                    self.handler.on_error_event(event);
+                    self.events = Ready::empty();
+                }
+                Ok(false)
+            }
+            Err(ref err) if err.kind() == ::std::io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Peek at the ClientHello for the server name that the client asked for. Returns false while
+    // the ClientHello has not been received completely, in which case reading is retried by the
+    // event loop once more of it may have arrived.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn sniff_server_name(&mut self) -> Result<bool> {
+        let mut hello = vec![0; sni::MAX_CLIENT_HELLO];
+        let len = match self.socket.peek(&mut hello) {
+            Ok(len) => len,
+            Err(ref err) if err.kind() == ::std::io::ErrorKind::WouldBlock => 0,
+            Err(err) => return Err(err.into()),
+        };
+        match sni::parse(&hello[..len]) {
+            // a ClientHello too large to peek at is encrypted without a name
+            ServerName::Incomplete if len < hello.len() => {
+                trace!("Waiting for the rest of the ClientHello from {}.", self.peer_addr());
+                self.awaiting_hello = true;
+                return Ok(false);
+            }
+            ServerName::Found(name) => {
+                debug!("Client {} asked for server name {}.", self.peer_addr(), name);
+                self.server_name = Some(name);
+            }
+            _ => debug!("Client {} did not send a server name.", self.peer_addr()),
+        }
+        Ok(true)
+    }
+
+    /// Whether reading waits for the rest of the ClientHello, which the event loop is not notified
+    /// of since the part already received remains unread.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn awaits_client_hello(&self) -> bool {
+        self.awaiting_hello
+    }
+
+    /// The time by which the TLS handshake must complete, if it is still in progress.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn tls_handshake_deadline(&self, timeout: Duration) -> Option<Instant> {
+        match self.tls_started {
+            // the stream is only handed to a writer once the handshake is complete
+            Some(started) if !self.writing && !self.socket.is_tls_established() => {
+                Some(started + timeout)
+            }
+            _ => None,
+        }
+    }
+
     pub fn token(&self) -> Token {
         self.token
     }
 
-    pub fn socket(&self) -> &TcpStream {
+    pub fn socket(&self) -> &dyn Evented {
         self.socket.evented()
     }
 
-    pub fn connection_id(&self) -> u32 {
+    /// Take the pending error of the socket and pass it to `Handler::on_socket_error`, returning
+    /// an equivalent error with which to fail the connection.
+    pub fn socket_error(&mut self) -> Option<io::Error> {
+        let err = match self.socket.take_error() {
+            Ok(Some(err)) => err,
+            Ok(None) => return None,
+            Err(err) => {
+                trace!("Unable to get the error of socket {:?}: {}", self.token, err);
+                return None;
+            }
+        };
+        let copy = match err.raw_os_error() {
+            Some(code) => io::Error::from_raw_os_error(code),
+            None => io::Error::new(err.kind(), err.to_string()),
+        };
+        self.handler.on_socket_error(err);
+        Some(copy)
+    }
+
+    pub fn peer_socket_addr(&self) -> Option<SocketAddr> {
+        self.socket.peer_addr().ok()
+    }
+
+    pub fn connection_id(&self) -> u64 {
         self.connection_id
     }
 
+    pub fn is_connecting(&self) -> bool {
+        self.state.is_connecting()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.is_open()
+    }
+
+    // The other endpoint as named in log messages, along with the id of the connection so that
+    // messages about a connection can be told apart from those about a later connection from the
+    // same address or with the same token.
     fn peer_addr(&self) -> String {
         if let Ok(addr) = self.socket.peer_addr() {
-            addr.to_string()
+            format!("{} (connection {})", addr, self.connection_id)
         } else {
-            "UNKNOWN".into()
+            format!("UNKNOWN (connection {})", self.connection_id)
         }
     }
 
@@ -219,13 +743,22 @@ where
             if let Connecting(ref mut req, ref mut res) = self.state {
                 req.set_position(0);
                 res.set_position(0);
+                self.timings = HandshakeTimings::default();
+                if let Some(ref mut tunnel) = self.tunnel {
+                    tunnel.restart();
+                }
                 self.events.remove(Ready::readable());
                 self.events.insert(Ready::writable());
 
                 if let Some(ref addr) = self.addresses.pop() {
-                    let sock = TcpStream::connect(addr)?;
-                    if self.socket.is_tls() {
-                        let ssl_stream = self.handler.upgrade_ssl_client(sock, url);
+                    let sock = connect_tcp(addr, self.local_addr, self.settings.local_interface)?;
+                    // tunnelled connections are encrypted after the proxy connects to the server
+                    if self.socket.is_tls() && self.tunnel.is_none() {
+                        let ssl_stream = self.handler.upgrade_ssl_client_with_options(
+                            sock,
+                            url,
+                            &self.tls_client,
+                        );
                         match ssl_stream {
                             Ok(stream) => {
                                 self.socket = Stream::tls_live(stream);
@@ -289,11 +822,15 @@ where
             if let Connecting(ref mut req, ref mut res) = self.state {
                 req.set_position(0);
                 res.set_position(0);
+                self.timings = HandshakeTimings::default();
+                if let Some(ref mut tunnel) = self.tunnel {
+                    tunnel.restart();
+                }
                 self.events.remove(Ready::readable());
                 self.events.insert(Ready::writable());
 
                 if let Some(ref addr) = self.addresses.pop() {
-                    let sock = TcpStream::connect(addr)?;
+                    let sock = connect_tcp(addr, self.local_addr, self.settings.local_interface)?;
                     self.socket = Stream::tcp(sock);
                     Ok(())
                 } else {
@@ -320,6 +857,73 @@ where
         self.events
     }
 
+    /// The events to register for. This differs from `events` when reading has been suspended by
+    /// the handler, in which case the connection stays active without asking for readable events.
+    pub fn interest(&self) -> Ready {
+        #[cfg(any(feature = "ssl", feature = "nativetls"))]
+        {
+            if self.awaiting_hello {
+                return self.events - Ready::readable();
+            }
+        }
+        if self.is_read_suspended() {
+            self.events - Ready::readable()
+        } else {
+            self.events
+        }
+    }
+
+    /// Reads are only suspended while the connection is open, so that handshakes complete.
+    fn is_read_suspended(&self) -> bool {
+        self.read_suspended && self.state.is_open()
+    }
+
+    pub fn suspend_read(&mut self) {
+        trace!("Suspending reads from {}.", self.peer_addr());
+        self.read_suspended = true;
+    }
+
+    pub fn resume_read(&mut self) {
+        trace!("Resuming reads from {}.", self.peer_addr());
+        self.read_suspended = false;
+    }
+
+    #[inline]
+    pub fn wake(&mut self) -> Result<()> {
+        self.handler.on_wake()
+    }
+
+    /// Apply a signal that concerns this connection alone, such as a message sent with its
+    /// `Sender`. Signals that act on the event loop are returned to the caller untouched.
+    pub fn deliver(&mut self, signal: Signal) -> ::std::result::Result<Result<()>, Signal> {
+        Ok(match signal {
+            Signal::Message(msg) => self.send_message(msg),
+            Signal::Traced(msg, meta) => self.send_traced(msg, meta),
+            Signal::Tracked(msg, token) => self.send_tracked(msg, token),
+            Signal::Fragmented(msg, fragment_size) => self.send_fragmented(msg, fragment_size),
+            Signal::Close(code, reason) => self.send_close(code, reason),
+            Signal::Ping(data) => self.send_ping(data),
+            Signal::Pong(data) => self.send_pong(data),
+            Signal::Inject(frame) => self.inject_frame(frame),
+            Signal::Raw(bytes) => self.send_raw(&bytes),
+            Signal::SuspendRead => {
+                self.suspend_read();
+                Ok(())
+            }
+            Signal::ResumeRead => {
+                self.resume_read();
+                Ok(())
+            }
+            Signal::Wake => self.wake(),
+            Signal::Flush(tx) => {
+                self.flushes.push(FlushWaiter(Some(tx)));
+                self.check_flushed();
+                Ok(())
+            }
+            signal => return Err(signal),
+        })
+    }
+
     pub fn is_client(&self) -> bool {
         match self.endpoint {
             Client(_) => true,
@@ -334,14 +938,27 @@ where
         }
     }
 
+    pub fn client_url(&self) -> Option<&url::Url> {
+        match self.endpoint {
+            Client(ref url) => Some(url),
+            Server => None,
+        }
+    }
+
     pub fn shutdown(&mut self) {
         self.handler.on_shutdown();
         if let Err(err) = self.send_close(CloseCode::Away, "Shutting down.") {
-            self.handler.on_error(err);
+            let connecting = self.state.is_connecting();
+            self.handler
+                .on_error_event(error_event(self.token, ErrorPhase::Command, connecting, err));
             self.disconnect()
         }
     }
 
+    pub fn connect_result(&mut self, user_token: Token, result: Result<Token>) -> Result<()> {
+        self.handler.on_connect_result(user_token, result)
+    }
+
     #[inline]
     pub fn new_timeout(&mut self, event: Token, timeout: Timeout) -> Result<()> {
         self.handler.on_new_timeout(event, timeout)
@@ -352,27 +969,40 @@ where
         self.handler.on_timeout(event)
     }
 
-    pub fn error(&mut self, err: Error) {
+    pub fn error(&mut self, phase: ErrorPhase, err: Error) {
+        let token = self.token;
+        let connecting = self.state.is_connecting();
+        if connecting && self.socket.is_tls_handshaking() {
+            if let Some(handshakes) = self.handshakes.take() {
+                handshakes.tls_failed();
+            }
+        }
+        let event = |err| error_event(token, phase, connecting, err);
         match self.state {
             Connecting(_, ref mut res) => match err.kind {
                 #[cfg(feature = "ssl")]
                 Kind::Ssl(_) => {
-                    self.handler.on_error(err);
+                    self.handler.on_error_event(event(err));
+                    self.events = Ready::empty();
+                }
+                #[cfg(any(feature = "ssl", feature = "nativetls"))]
+                Kind::TlsTimeout | Kind::TlsRenegotiation => {
+                    self.handler.on_error_event(event(err));
                     self.events = Ready::empty();
                 }
-                Kind::Io(_) => {
-                    self.handler.on_error(err);
+                Kind::Io(_) | Kind::HandshakeTimeout => {
+                    self.handler.on_error_event(event(err));
                     self.events = Ready::empty();
                 }
                 Kind::Protocol => {
                     let msg = err.to_string();
-                    self.handler.on_error(err);
+                    self.handler.on_error_event(event(err));
                     if let Server = self.endpoint {
                         res.get_mut().clear();
                         if let Err(err) =
                             write!(res.get_mut(), "HTTP/1.1 400 Bad Request\r\n\r\n{}", msg)
                         {
-                            self.handler.on_error(Error::from(err));
+                            self.handler.on_error_event(event(Error::from(err)));
                             self.events = Ready::empty();
                         } else {
                             self.events.remove(Ready::readable());
@@ -384,7 +1014,7 @@ where
                 }
                 _ => {
                     let msg = err.to_string();
-                    self.handler.on_error(err);
+                    self.handler.on_error_event(event(err));
                     if let Server = self.endpoint {
                         res.get_mut().clear();
                         if let Err(err) = write!(
@@ -392,7 +1022,7 @@ where
                             "HTTP/1.1 500 Internal Server Error\r\n\r\n{}",
                             msg
                         ) {
-                            self.handler.on_error(Error::from(err));
+                            self.handler.on_error_event(event(Error::from(err)));
                             self.events = Ready::empty();
                         } else {
                             self.events.remove(Ready::readable());
@@ -409,11 +1039,12 @@ where
                         if self.settings.panic_on_internal {
                             panic!("Panicking on internal error -- {}", err);
                         }
-                        let reason = format!("{}", err);
+                        let code = self.handler.close_code_for_error(&err);
+                        let reason = self.handler.close_reason_for_error(&err);
 
-                        self.handler.on_error(err);
-                        if let Err(err) = self.send_close(CloseCode::Error, reason) {
-                            self.handler.on_error(err);
+                        self.handler.on_error_event(event(err));
+                        if let Err(err) = self.send_close(code, reason) {
+                            self.handler.on_error_event(event(err));
                             self.disconnect()
                         }
                     }
@@ -421,11 +1052,12 @@ where
                         if self.settings.panic_on_capacity {
                             panic!("Panicking on capacity error -- {}", err);
                         }
-                        let reason = format!("{}", err);
+                        let code = self.handler.close_code_for_error(&err);
+                        let reason = self.handler.close_reason_for_error(&err);
 
-                        self.handler.on_error(err);
-                        if let Err(err) = self.send_close(CloseCode::Size, reason) {
-                            self.handler.on_error(err);
+                        self.handler.on_error_event(event(err));
+                        if let Err(err) = self.send_close(code, reason) {
+                            self.handler.on_error_event(event(err));
                             self.disconnect()
                         }
                     }
@@ -433,11 +1065,12 @@ where
                         if self.settings.panic_on_protocol {
                             panic!("Panicking on protocol error -- {}", err);
                         }
-                        let reason = format!("{}", err);
+                        let code = self.handler.close_code_for_error(&err);
+                        let reason = self.handler.close_reason_for_error(&err);
 
-                        self.handler.on_error(err);
-                        if let Err(err) = self.send_close(CloseCode::Protocol, reason) {
-                            self.handler.on_error(err);
+                        self.handler.on_error_event(event(err));
+                        if let Err(err) = self.send_close(code, reason) {
+                            self.handler.on_error_event(event(err));
                             self.disconnect()
                         }
                     }
@@ -445,34 +1078,40 @@ where
                         if self.settings.panic_on_encoding {
                             panic!("Panicking on encoding error -- {}", err);
                         }
-                        let reason = format!("{}", err);
+                        let code = self.handler.close_code_for_error(&err);
+                        let reason = self.handler.close_reason_for_error(&err);
 
-                        self.handler.on_error(err);
-                        if let Err(err) = self.send_close(CloseCode::Invalid, reason) {
-                            self.handler.on_error(err);
+                        self.handler.on_error_event(event(err));
+                        if let Err(err) = self.send_close(code, reason) {
+                            self.handler.on_error_event(event(err));
                             self.disconnect()
                         }
                     }
                     Kind::Http(_) => {
                         // This may happen if some handler writes a bad response
-                        self.handler.on_error(err);
+                        self.handler.on_error_event(event(err));
                         error!("Disconnecting WebSocket.");
                         self.disconnect()
                     }
                     Kind::Custom(_) => {
-                        self.handler.on_error(err);
+                        self.handler.on_error_event(event(err));
+                    }
+                    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+                    Kind::TlsTimeout | Kind::TlsRenegotiation => {
+                        self.handler.on_error_event(event(err));
+                        self.disconnect()
                     }
                     Kind::Queue(_) => {
                         if self.settings.panic_on_queue {
                             panic!("Panicking on queue error -- {}", err);
                         }
-                        self.handler.on_error(err);
+                        self.handler.on_error_event(event(err));
                     }
                     _ => {
                         if self.settings.panic_on_io {
                             panic!("Panicking on io error -- {}", err);
                         }
-                        self.handler.on_error(err);
+                        self.handler.on_error_event(event(err));
                         self.disconnect()
                     }
                 }
@@ -484,23 +1123,147 @@ where
         match self.state {
             RespondingClose | FinishedClose | Connecting(_, _) => (),
             _ => {
-                self.handler.on_close(CloseCode::Abnormal, "");
+                self.closed(CloseCode::Abnormal, "");
             }
         }
         self.events = Ready::empty()
     }
 
+    fn open(&mut self, shake: Handshake) -> Result<()> {
+        if let Some((ref chain, ref out)) = self.middleware {
+            chain.on_open(out, &shake)?;
+        }
+        self.handler.on_open(shake)
+    }
+
+    fn closed(&mut self, code: CloseCode, reason: &str) {
+        self.abandon_fragments();
+        if let Some((ref chain, ref out)) = self.middleware {
+            chain.on_close(out, code, reason);
+        }
+        self.handler.on_close(code, reason)
+    }
+
+    // A close frame whose reason is not valid UTF-8
+    fn closed_raw(&mut self, code: CloseCode, reason: &[u8]) {
+        self.abandon_fragments();
+        if let Some((ref chain, ref out)) = self.middleware {
+            chain.on_close(out, code, &String::from_utf8_lossy(reason));
+        }
+        self.handler.on_close_raw(code, reason)
+    }
+
+    fn abandon_fragments(&mut self) {
+        if !self.fragments.is_empty() {
+            // the rest of the message will never arrive
+            let fragments = self.fragments.drain(..).collect();
+            self.fragments_size = 0;
+            self.handler.on_incomplete_message(fragments);
+        }
+    }
+
+    fn middleware_frame(&self, frame: &Frame) -> Result<bool> {
+        if let Some((ref chain, ref out)) = self.middleware {
+            chain.on_frame(out, frame)
+        } else {
+            Ok(true)
+        }
+    }
+
+    fn next_info(&mut self) -> MessageInfo {
+        let info = MessageInfo {
+            sequence: self.received,
+            received: Instant::now(),
+            compressed: false,
+        };
+        self.received += 1;
+        info
+    }
+
+    fn deliver_text(&mut self, data: Vec<u8>) -> Result<()> {
+        let data = match self.unwrap_ack(data, true)? {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        if self.settings.message_info || !self.handler.borrows_text() {
+            // move the payload into the message rather than copying it
+            let text = String::from_utf8(data).map_err(|err| err.utf8_error())?;
+            let info = self.next_info();
+            return self.deliver_message(Message::text(text), info);
+        }
+        let text = from_utf8(&data)?;
+        self.next_info();
+        if let Some((ref chain, ref out)) = self.middleware {
+            // middleware needs an owned message, so only pay for the copy when it is present
+            if !chain.on_message(out, &Message::text(text))? {
+                return Ok(());
+            }
+        }
+        self.handler.on_text_borrowed(text)
+    }
+
+    fn deliver_binary(&mut self, data: Vec<u8>) -> Result<()> {
+        let data = match self.unwrap_ack(data, false)? {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        let info = self.next_info();
+        self.deliver_message(Message::binary(data), info)
+    }
+
+    fn deliver_message(&mut self, msg: Message, info: MessageInfo) -> Result<()> {
+        if let Some((ref chain, ref out)) = self.middleware {
+            if !chain.on_message(out, &msg)? {
+                return Ok(());
+            }
+        }
+        if self.settings.message_info {
+            self.handler.on_message_with_meta(msg, info)
+        } else {
+            self.handler.on_message(msg)
+        }
+    }
+
+    #[cfg(feature = "testing")]
     pub fn consume(self) -> H {
         self.handler
     }
 
+    /// Consume the connection, returning the handler along with the buffers so that they can be
+    /// reused by another connection.
+    pub fn recycle(self) -> (H, Buffers) {
+        let (request, response) = match self.state {
+            Connecting(req, res) => (req.into_inner(), res.into_inner()),
+            _ => (Vec::new(), Vec::new()),
+        };
+        let buffers = Buffers {
+            request,
+            response,
+            fragments: self.fragments,
+            in_buffer: self.in_buffer.into_inner(),
+            out_buffer: self.out_buffer.into_inner(),
+        };
+        (self.handler, buffers)
+    }
+
     fn write_handshake(&mut self) -> Result<()> {
+        if self.is_tunnelling() {
+            return self.write_tunnel();
+        }
         if let Connecting(ref mut req, ref mut res) = self.state {
             match self.endpoint {
                 Server => {
                     let mut done = false;
                     if self.socket.try_write_buf(res)?.is_some() {
+                        record_tls(&self.socket, &mut self.timings);
                         if res.position() as usize == res.get_ref().len() {
+                            self.timings.response = Some(Instant::now());
+                            if let Some(handshakes) = self.handshakes.take() {
+                                match Response::parse(res.get_ref()) {
+                                    Ok(Some(response)) => handshakes.responded(response.status()),
+                                    _ => handshakes.responded(500),
+                                }
+                            }
                             done = true
                         }
                     }
@@ -510,13 +1273,12 @@ where
                 }
                 Client(_) => {
                     if self.socket.try_write_buf(req)?.is_some() {
+                        record_tls(&self.socket, &mut self.timings);
                         if req.position() as usize == req.get_ref().len() {
+                            self.timings.request = Some(Instant::now());
                             trace!(
                                 "Finished writing handshake request to {}",
-                                self.socket
-                                    .peer_addr()
-                                    .map(|addr| addr.to_string())
-                                    .unwrap_or_else(|_| "UNKNOWN".into())
+                                self.peer_addr()
                             );
                             self.events.insert(Ready::readable());
                             self.events.remove(Ready::writable());
@@ -555,11 +1317,21 @@ where
                 self.events = Ready::empty();
                 return Ok(());
             } else {
-                self.handler.on_open(Handshake {
+                let version = request.negotiate_version().unwrap_or(Version::Rfc6455);
+                self.open(Handshake {
                     request,
                     response,
                     peer_addr: self.socket.peer_addr().ok(),
                     local_addr: self.socket.local_addr().ok(),
+                    timings: self.timings,
+                    version,
+                    connected_url: None,
+                    connected_addr: None,
+                    connection_id: self.connection_id,
+                    server_name: self
+                        .server_name
+                        .clone()
+                        .or_else(|| self.socket.server_name()),
                 })?;
                 debug!("Connection to {} is now open.", self.peer_addr());
                 self.events.insert(Ready::readable());
@@ -575,40 +1347,159 @@ where
     }
 
     fn read_handshake(&mut self) -> Result<()> {
+        if self.is_tunnelling() {
+            return self.read_tunnel();
+        }
+        let secure = self.is_secure();
         if let Connecting(ref mut req, ref mut res) = self.state {
             match self.endpoint {
                 Server => {
-                    if let Some(read) = self.socket.try_read_buf(req.get_mut())? {
+                    // never buffer more of the request than the configured limit
+                    let limit = self.settings.max_handshake_size;
+                    let start = req.get_ref().len();
+                    let chunk = min(
+                        limit.saturating_sub(start),
+                        max(req.get_ref().capacity() - start, HANDSHAKE_READ_SIZE),
+                    );
+                    if chunk == 0 {
+                        return Ok(());
+                    }
+                    req.get_mut().resize(start + chunk, 0);
+                    let read = self.socket.try_read(&mut req.get_mut()[start..]);
+                    let len = match read {
+                        Ok(Some(len)) => len,
+                        _ => 0,
+                    };
+                    req.get_mut().truncate(start + len);
+
+                    if let Some(read) = read? {
+                        record_tls(&self.socket, &mut self.timings);
                         if read == 0 {
                             self.events = Ready::empty();
                             return Ok(());
                         }
-                        if let Some(ref request) = Request::parse(req.get_ref())? {
-                            trace!("Handshake request received: \n{}", request);
-                            let response = self.handler.on_request(request)?;
-                            response.format(res.get_mut())?;
-                            self.events.remove(Ready::readable());
-                            self.events.insert(Ready::writable());
-                        }
-                    }
-                    return Ok(());
-                }
-                Client(_) => {
-                    if self.socket.try_read_buf(res.get_mut())?.is_some() {
-                        // TODO: see if this can be optimized with drain
-                        let end = {
-                            let data = res.get_ref();
-                            let end = data.iter()
-                                .enumerate()
-                                .take_while(|&(ind, _)| !data[..ind].ends_with(b"\r\n\r\n"))
-                                .count();
-                            if !data[..end].ends_with(b"\r\n\r\n") {
+                        let request = match Request::parse(req.get_ref())? {
+                            Some(request) => request,
+                            None => {
+                                if req.get_ref().len() >= limit {
+                                    let err = Error::new(
+                                        Kind::Capacity,
+                                        format!(
+                                            "Handshake request headers exceeded {} bytes.",
+                                            limit
+                                        ),
+                                    );
+                                    let event = error_event(self.token, ErrorPhase::Read, true, err);
+                                    self.handler.on_error_event(event);
+                                    Response::new(431, "Request Header Fields Too Large", vec![])
+                                        .format(res.get_mut())?;
+                                    self.events.remove(Ready::readable());
+                                    self.events.insert(Ready::writable());
+                                }
                                 return Ok(());
                             }
-                            self.in_buffer.get_mut().extend(&data[end..]);
-                            end
                         };
-                        res.get_mut().truncate(end);
+
+                        let head = header_len(req.get_ref());
+                        let buffered = req.get_ref().len() - head;
+                        let rejected = match body_len(&request)? {
+                            Some(0) if buffered == 0 => None,
+                            Some(body) if self.settings.handshake_body == HandshakeBody::Ignore
+                                && head + body <= limit =>
+                            {
+                                if buffered < body {
+                                    // wait for the rest of the body
+                                    return Ok(());
+                                }
+                                req.get_mut().truncate(head);
+                                None
+                            }
+                            _ => {
+                                let err = if self.settings.handshake_body == HandshakeBody::Reject {
+                                    Error::new(
+                                        Kind::Protocol,
+                                        "Received a handshake request with a body.",
+                                    )
+                                } else {
+                                    Error::new(
+                                        Kind::Capacity,
+                                        format!(
+                                            "Handshake request body was longer than {} \
+                                             bytes or had an unknown length.",
+                                            limit - head
+                                        ),
+                                    )
+                                };
+                                let event = error_event(self.token, ErrorPhase::Read, true, err);
+                                self.handler.on_error_event(event);
+                                Some(Response::new(413, "Payload Too Large", vec![]))
+                            }
+                        };
+
+                        self.timings.request = Some(Instant::now());
+                        trace!("Handshake request received: \n{}", request);
+                        let response = if let Some(response) = rejected {
+                            response
+                        } else if let (true, None) =
+                            (self.settings.version_strict, request.negotiate_version())
+                        {
+                            let err = Error::new(
+                                Kind::Protocol,
+                                format!(
+                                    "Unsupported WebSocket version: {}",
+                                    request.version().unwrap_or("none")
+                                ),
+                            );
+                            let event = error_event(self.token, ErrorPhase::Read, true, err);
+                            self.handler.on_error_event(event);
+                            Response::upgrade_required()
+                        } else if let (true, Err(err)) =
+                            (self.settings.request_key_strict, request.validate_key())
+                        {
+                            let event = error_event(self.token, ErrorPhase::Read, true, err);
+                            self.handler.on_error_event(event);
+                            Response::new(400, "Bad Request", b"Invalid WebSocket key".to_vec())
+                        } else if let (true, Err(err)) = (
+                            self.settings.same_origin_only,
+                            request.validate_same_origin(secure),
+                        ) {
+                            let event = error_event(self.token, ErrorPhase::Read, true, err);
+                            self.handler.on_error_event(event);
+                            Response::new(403, "Forbidden", b"Cross-origin request".to_vec())
+                        } else {
+                            self.handler.on_request(&request)?
+                        };
+                        response.format(res.get_mut())?;
+                        self.events.remove(Ready::readable());
+                        self.events.insert(Ready::writable());
+                    }
+                    return Ok(());
+                }
+                Client(_) => {
+                    if let Some(len) = self.socket.try_read_buf(res.get_mut())? {
+                        if len == 0 {
+                            return Err(Error::new(
+                                Kind::Protocol,
+                                "The server closed the connection during the handshake.",
+                            ));
+                        }
+                        // TODO: see if this can be optimized with drain
+                        let end = {
+                            let data = res.get_ref();
+                            let end = data.iter()
+                                .enumerate()
+                                .take_while(|&(ind, _)| !data[..ind].ends_with(b"\r\n\r\n"))
+                                .count();
+                            if !data[..end].ends_with(b"\r\n\r\n") {
+                                return Ok(());
+                            }
+                            // Anything after the response belongs to the first frames. It is
+                            // checked against the read limits once the handshake succeeds.
+                            self.in_buffer.get_mut().extend(&data[end..]);
+                            end
+                        };
+                        res.get_mut().truncate(end);
+                        self.timings.response = Some(Instant::now());
                     } else {
                         // NOTE: wait to be polled again; response not ready.
                         return Ok(());
@@ -641,37 +1532,57 @@ where
 
             if response.status() != 101 {
                 if response.status() != 301 && response.status() != 302 {
-                    return Err(Error::new(Kind::Protocol, "Handshake failed."));
-                } else {
-                    return Ok(());
-                }
-            }
-
-            if self.settings.key_strict {
-                let req_key = request.hashed_key()?;
-                let res_key = from_utf8(response.key()?)?;
-                if req_key != res_key {
+                    let body = decode_failure_body(&response, self.in_buffer.get_ref());
                     return Err(Error::new(
                         Kind::Protocol,
                         format!(
-                            "Received incorrect WebSocket Accept key: {} vs {}",
-                            req_key, res_key
+                            "Handshake failed with {} {}: {}",
+                            response.status(),
+                            response.reason(),
+                            body
                         ),
                     ));
+                } else {
+                    return Ok(());
                 }
             }
 
+            if self.settings.key_strict && !request.matches_accept_key(response.key()?)? {
+                return Err(Error::new(
+                    Kind::Protocol,
+                    format!(
+                        "Received incorrect WebSocket Accept key: {} vs {}",
+                        request.hashed_key()?,
+                        String::from_utf8_lossy(response.key()?)
+                    ),
+                ));
+            }
+
+            self.check_early_data()?;
             self.handler.on_response(&response)?;
-            self.handler.on_open(Handshake {
+            let version = request.negotiate_version().unwrap_or(Version::Rfc6455);
+            let connected_url = match self.endpoint {
+                Client(ref url) => Some(url.clone()),
+                Server => None,
+            };
+            let peer_addr = self.socket.peer_addr().ok();
+            self.open(Handshake {
                 request,
                 response,
-                peer_addr: self.socket.peer_addr().ok(),
+                peer_addr,
                 local_addr: self.socket.local_addr().ok(),
+                timings: self.timings,
+                version,
+                connected_url,
+                connected_addr: peer_addr,
+                connection_id: self.connection_id,
+                server_name: None,
             })?;
 
             // check to see if there is anything to read already
             if !self.in_buffer.get_ref().is_empty() {
-                self.read_frames()?;
+                let mut budget = self.read_budget();
+                self.read_frames(&mut budget)?;
             }
 
             self.check_events();
@@ -684,6 +1595,16 @@ where
     }
 
     pub fn read(&mut self) -> Result<()> {
+        if self.writing {
+            trace!("Deferring read until the write to {} completes.", self.peer_addr());
+            return Ok(());
+        }
+        #[cfg(any(feature = "ssl", feature = "nativetls"))]
+        {
+            if self.detect_tls && !self.sniff_tls()? {
+                return Ok(());
+            }
+        }
         if self.socket.is_negotiating() {
             trace!("Performing TLS negotiation on {}.", self.peer_addr());
             self.socket.clear_negotiating()?;
@@ -694,20 +1615,40 @@ where
                 self.read_handshake()
             } else {
                 trace!("Ready to read messages from {}.", self.peer_addr());
-                while let Some(len) = self.buffer_in()? {
-                    self.read_frames()?;
+                let mut budget = self.read_budget();
+                if self.read_pending {
+                    // finish the frames left over from the last read before buffering more
+                    self.read_pending = false;
+                    self.read_frames(&mut budget)?;
+                }
+                while !self.read_pending {
+                    let len = match self.buffer_in()? {
+                        Some(len) => len,
+                        None => break,
+                    };
+                    self.read_frames(&mut budget)?;
+                    if self.read_pending {
+                        // end of stream is handled once the buffered frames are processed
+                        break;
+                    }
                     if len == 0 {
-                        if self.events.is_writable() {
-                            self.events.remove(Ready::readable());
-                        } else {
-                            self.disconnect()
-                        }
+                        self.read_eof()?;
                         break;
                     }
                 }
                 Ok(())
             };
 
+            #[cfg(feature = "ssl")]
+            {
+                if res.is_ok() && self.is_server() && self.socket.is_renegotiating() {
+                    return Err(Error::new(
+                        Kind::TlsRenegotiation,
+                        format!("Client {} attempted to renegotiate TLS.", self.peer_addr()),
+                    ));
+                }
+            }
+
             if self.socket.is_negotiating() && res.is_ok() {
                 self.events.remove(Ready::readable());
                 self.events.insert(Ready::writable());
@@ -716,12 +1657,143 @@ where
         }
     }
 
-    fn read_frames(&mut self) -> Result<()> {
+    // The other endpoint shut down its side of the connection. Anything still buffered is
+    // written before the connection is closed, since the other endpoint may still be reading.
+    fn read_eof(&mut self) -> Result<()> {
+        if !self.read_eof {
+            trace!("Reached the end of the stream from {}.", self.peer_addr());
+            self.read_eof = true;
+            // the end of the stream is expected once a closing handshake has begun
+            if self.state.is_open() {
+                self.handler.on_eof()?;
+            }
+        }
+        if self.events.is_writable() {
+            self.events.remove(Ready::readable());
+        } else {
+            self.disconnect()
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn mask_fn(&self) -> MaskFn {
+        self.settings.mask_fn.unwrap_or_else(frame::default_mask_fn)
+    }
+
+    // The number of frames to process on this read. A budget of 0 would leave the connection
+    // readable without ever making progress, so at least one frame is processed even if the
+    // settings returned by `Factory::settings_for` allow none.
+    #[inline]
+    fn read_budget(&self) -> usize {
+        max(self.settings.max_messages_per_read, 1)
+    }
+
+    /// Whether frames were left unprocessed by the last read because `max_messages_per_read` was
+    /// reached. Pending frames wait while reading is suspended.
+    #[inline]
+    pub fn is_read_pending(&self) -> bool {
+        self.read_pending && !self.is_read_suspended()
+    }
+
+    /// Whether to write before reading when the connection is both readable and writable,
+    /// according to `Settings::event_order`.
+    pub fn writes_first(&mut self) -> bool {
+        match self.settings.event_order {
+            EventOrder::ReadFirst => false,
+            EventOrder::WriteFirst => true,
+            EventOrder::Alternate => {
+                self.wrote_first = !self.wrote_first;
+                self.wrote_first
+            }
+        }
+    }
+
+    // Whether a message that has grown past `max_message_size` is delivered only through
+    // `on_fragment`.
+    fn streams_large_messages(&self) -> bool {
+        self.settings.large_messages == LargeMessages::Stream
+    }
+
+    // Whether a message of the given size is delivered only through `on_fragment`.
+    fn is_large(&self, size: usize) -> bool {
+        self.streams_large_messages() && size > self.settings.max_message_size
+    }
+
+    // Reject a data frame that would take its message past `max_message_size` before the payload
+    // of the frame is buffered.
+    fn check_message_size(&self) -> Result<()> {
+        if self.settings.max_message_size == usize::MAX || self.streams_large_messages() {
+            return Ok(());
+        }
+        let pending = &self.in_buffer.get_ref()[self.in_buffer.position() as usize..];
+        if let Some((opcode, length)) = frame::peek_header(pending) {
+            let size = self.fragments_size as u64 + length;
+            if !opcode.is_control() && size > self.settings.max_message_size as u64 {
+                return Err(Error::new(
+                    Kind::Capacity,
+                    format!(
+                        "Rejected message with length exceeding defined max: {}.",
+                        self.settings.max_message_size
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Apply the limits of `buffer_in` and `Frame::parse` to the bytes that arrived with the
+    // handshake response, so that a server cannot get an oversized frame buffered before the
+    // connection opens.
+    fn check_early_data(&mut self) -> Result<()> {
+        let pending = pool::pending(&self.in_buffer);
+        if !self.settings.in_buffer_grow && pending > self.settings.in_buffer_capacity {
+            return Err(Error::new(
+                Kind::Capacity,
+                "Maxed out input buffer for connection.",
+            ));
+        }
+        let data = &self.in_buffer.get_ref()[self.in_buffer.position() as usize..];
+        if let Some((_, length)) = frame::peek_header(data) {
+            if length > self.settings.max_fragment_size as u64 {
+                return Err(Error::new(
+                    Kind::Capacity,
+                    format!(
+                        "Rejected frame with payload length exceeding defined max: {}.",
+                        self.settings.max_fragment_size
+                    ),
+                ));
+            }
+        }
+        self.in_high_water = max(self.in_high_water, pending);
+        self.account()
+    }
+
+    fn read_frames(&mut self, budget: &mut usize) -> Result<()> {
         let max_size = self.settings.max_fragment_size as u64;
-        while let Some(mut frame) = Frame::parse(&mut self.in_buffer, max_size)? {
+        loop {
+            if *budget == 0 {
+                self.read_pending = true;
+                return Ok(());
+            }
+            if self.state.is_open() && self.handler.should_suspend_read() {
+                self.suspend_read();
+                self.read_pending = true;
+                return Ok(());
+            }
+            self.check_message_size()?;
+            let mut frame = match Frame::parse(&mut self.in_buffer, max_size)? {
+                Some(frame) => frame,
+                None => break,
+            };
+            *budget -= 1;
+
             match self.state {
-                // Ignore data received after receiving close frame
-                RespondingClose | FinishedClose => continue,
+                // The other endpoint must not send data after its close frame
+                RespondingClose | FinishedClose => {
+                    self.read_after_close(frame)?;
+                    continue;
+                }
                 _ => (),
             }
 
@@ -744,231 +1816,321 @@ where
             }
 
             // This is safe whether or not a frame is masked.
-            frame.remove_mask();
-
-            if let Some(frame) = self.handler.on_frame(frame)? {
-                if frame.is_final() {
-                    match frame.opcode() {
-                        // singleton data frames
-                        OpCode::Text => {
-                            trace!("Received text frame {:?}", frame);
-                            // since we are going to handle this, there can't be an ongoing
-                            // message
-                            if !self.fragments.is_empty() {
-                                return Err(Error::new(Kind::Protocol, "Received unfragmented text frame while processing fragmented message."));
-                            }
-                            let msg = Message::text(String::from_utf8(frame.into_data())
-                                .map_err(|err| err.utf8_error())?);
-                            self.handler.on_message(msg)?;
+            frame.remove_mask_with(self.mask_fn());
+            if self.settings.frame_checksums {
+                info!(
+                    "Received {} frame of {} bytes with CRC-32 {:08x} from {}.",
+                    frame.opcode(),
+                    frame.payload().len(),
+                    frame.checksum(),
+                    self.peer_addr()
+                );
+            }
+
+            self.deferred.begin(self.token, self.connection_id);
+            let res = self.receive_frame(frame);
+            self.send_deferred(res)?;
+        }
+        Ok(())
+    }
+
+    fn receive_frame(&mut self, frame: Frame) -> Result<()> {
+        let frame = if self.middleware_frame(&frame)? {
+            self.handler.on_frame(frame)?
+        } else {
+            None
+        };
+
+        if let Some(frame) = frame {
+            self.handle_frame(frame)?;
+        }
+        Ok(())
+    }
+
+    // Write the messages deferred while a frame was handled, unless handling it failed.
+    fn send_deferred(&mut self, res: Result<()>) -> Result<()> {
+        let messages = self.deferred.finish();
+        res?;
+        for msg in messages {
+            self.send_message(msg)?;
+        }
+        Ok(())
+    }
+
+    pub fn inject_frame(&mut self, frame: Frame) -> Result<()> {
+        match self.state {
+            Connecting(..) | RespondingClose | FinishedClose => {
+                trace!("Dropping frame injected into {} {:?}", self.peer_addr(), frame);
+                Ok(())
+            }
+            _ => {
+                self.deferred.begin(self.token, self.connection_id);
+                let res = match self.handler.on_injected_frame(frame) {
+                    Ok(Some(frame)) => self.handle_frame(frame),
+                    Ok(None) => Ok(()),
+                    Err(err) => Err(err),
+                };
+                self.send_deferred(res)
+            }
+        }
+    }
+
+    // Act on a frame that has been passed through the handler.
+    fn handle_frame(&mut self, frame: Frame) -> Result<()> {
+        if frame.is_final() {
+            match frame.opcode() {
+                // singleton data frames
+                OpCode::Text => {
+                    trace!("Received text frame {:?}", frame);
+                    // since we are going to handle this, there can't be an ongoing
+                    // message
+                    if !self.fragments.is_empty() || self.streamed > 0 {
+                        return Err(Error::new(Kind::Protocol, "Received unfragmented text frame while processing fragmented message."));
+                    }
+                    if self.is_large(frame.payload().len()) {
+                        let size = frame.payload().len();
+                        self.handler.on_fragment(&frame, 0, size, true)?;
+                        return Ok(());
+                    }
+                    self.deliver_text(frame.into_data())?;
+                }
+                OpCode::Binary => {
+                    trace!("Received binary frame {:?}", frame);
+                    // since we are going to handle this, there can't be an ongoing
+                    // message
+                    if !self.fragments.is_empty() || self.streamed > 0 {
+                        return Err(Error::new(Kind::Protocol, "Received unfragmented binary frame while processing fragmented message."));
+                    }
+                    if self.is_large(frame.payload().len()) {
+                        let size = frame.payload().len();
+                        self.handler.on_fragment(&frame, 0, size, true)?;
+                        return Ok(());
+                    }
+                    let data = frame.into_data();
+                    self.deliver_binary(data)?;
+                }
+                // control frames
+                OpCode::Close => {
+                    trace!("Received close frame {:?}", frame);
+                    // Closing handshake
+                    if self.state.is_closing() {
+                        if self.is_server() {
+                            // Finished handshake, disconnect server side
+                            self.events = Ready::empty()
+                        } else {
+                            // We are a client, so we wait for the server to close the
+                            // connection
                         }
-                        OpCode::Binary => {
-                            trace!("Received binary frame {:?}", frame);
-                            // since we are going to handle this, there can't be an ongoing
-                            // message
-                            if !self.fragments.is_empty() {
-                                return Err(Error::new(Kind::Protocol, "Received unfragmented binary frame while processing fragmented message."));
-                            }
-                            let data = frame.into_data();
-                            self.handler.on_message(Message::binary(data))?;
+                    } else {
+                        // Starting handshake, will send the responding close frame
+                        self.state = RespondingClose;
+                    }
+
+                    let mut close_code = [0u8; 2];
+                    let mut data = Cursor::new(frame.into_data());
+                    if let 2 = data.read(&mut close_code)? {
+                        let raw_code = frame::decode_close_code(close_code);
+                        trace!(
+                            "Connection to {} received raw close code: {:?}, {:?}",
+                            self.peer_addr(),
+                            raw_code,
+                            close_code
+                        );
+                        let named = CloseCode::from(raw_code);
+                        let registration = named.registration();
+                        if !registration.is_valid()
+                            && !self.handler.accept_close_code(named)
+                        {
+                            return Err(Error::new(
+                                Kind::Protocol,
+                                format!(
+                                    "Received invalid close code from endpoint: {} ({:?})",
+                                    raw_code, registration
+                                ),
+                            ));
                         }
-                        // control frames
-                        OpCode::Close => {
-                            trace!("Received close frame {:?}", frame);
-                            // Closing handshake
-                            if self.state.is_closing() {
-                                if self.is_server() {
-                                    // Finished handshake, disconnect server side
-                                    self.events = Ready::empty()
-                                } else {
-                                    // We are a client, so we wait for the server to close the
-                                    // connection
-                                }
+                        let has_reason = {
+                            if let Ok(reason) = from_utf8(&data.get_ref()[2..]) {
+                                self.closed(named, reason); // note reason may be an empty string
+                                true
                             } else {
-                                // Starting handshake, will send the responding close frame
-                                self.state = RespondingClose;
+                                self.closed_raw(named, &data.get_ref()[2..]);
+                                false
                             }
+                        };
 
-                            let mut close_code = [0u8; 2];
-                            let mut data = Cursor::new(frame.into_data());
-                            if let 2 = data.read(&mut close_code)? {
-                                let raw_code: u16 =
-                                    (u16::from(close_code[0]) << 8) | (u16::from(close_code[1]));
-                                trace!(
-                                    "Connection to {} received raw close code: {:?}, {:?}",
-                                    self.peer_addr(),
-                                    raw_code,
-                                    close_code
-                                );
-                                let named = CloseCode::from(raw_code);
-                                if let CloseCode::Other(code) = named {
-                                    if code < 1000 ||
-                                            code >= 5000 ||
-                                            code == 1004 ||
-                                            code == 1014 ||
-                                            code == 1016 || // these below are here to pass the autobahn test suite
-                                            code == 1100 || // we shouldn't need them later
-                                            code == 2000
-                                        || code == 2999
-                                    {
-                                        return Err(Error::new(
-                                            Kind::Protocol,
-                                            format!(
-                                                "Received invalid close code from endpoint: {}",
-                                                code
-                                            ),
-                                        ));
-                                    }
-                                }
-                                let has_reason = {
-                                    if let Ok(reason) = from_utf8(&data.get_ref()[2..]) {
-                                        self.handler.on_close(named, reason); // note reason may be an empty string
-                                        true
-                                    } else {
-                                        self.handler.on_close(named, "");
-                                        false
-                                    }
-                                };
-
-                                if let CloseCode::Abnormal = named {
-                                    return Err(Error::new(
-                                        Kind::Protocol,
-                                        "Received abnormal close code from endpoint.",
-                                    ));
-                                } else if let CloseCode::Status = named {
-                                    return Err(Error::new(
-                                        Kind::Protocol,
-                                        "Received no status close code from endpoint.",
-                                    ));
-                                } else if let CloseCode::Restart = named {
-                                    return Err(Error::new(
-                                        Kind::Protocol,
-                                        "Restart close code is not supported.",
-                                    ));
-                                } else if let CloseCode::Again = named {
-                                    return Err(Error::new(
-                                        Kind::Protocol,
-                                        "Try again later close code is not supported.",
-                                    ));
-                                } else if let CloseCode::Tls = named {
-                                    return Err(Error::new(
-                                        Kind::Protocol,
-                                        "Received TLS close code outside of TLS handshake.",
-                                    ));
-                                } else {
-                                    if !self.state.is_closing() {
-                                        if has_reason {
-                                            self.send_close(named, "")?; // note this drops any extra close data
-                                        } else {
-                                            self.send_close(CloseCode::Invalid, "")?;
-                                        }
-                                    } else {
-                                        self.state = FinishedClose;
-                                    }
-                                }
+                        if !self.state.is_closing() {
+                            if has_reason {
+                                self.send_close(named, "")?; // note this drops any extra close data
                             } else {
-                                // This is not an error. It is allowed behavior in the
-                                // protocol, so we don't trigger an error.
-                                // "If there is no such data in the Close control frame,
-                                // _The WebSocket Connection Close Reason_ is the empty string."
-                                self.handler.on_close(CloseCode::Status, "");
-                                if !self.state.is_closing() {
-                                    self.send_close(CloseCode::Empty, "")?;
-                                } else {
-                                    self.state = FinishedClose;
-                                }
+                                self.send_close(CloseCode::Invalid, "")?;
                             }
+                        } else {
+                            self.state = FinishedClose;
                         }
-                        OpCode::Ping => {
-                            trace!("Received ping frame {:?}", frame);
-                            self.send_pong(frame.into_data())?;
-                        }
-                        OpCode::Pong => {
-                            trace!("Received pong frame {:?}", frame);
-                            // no ping validation for now
+                    } else {
+                        // This is not an error. It is allowed behavior in the
+                        // protocol, so we don't trigger an error.
+                        // "If there is no such data in the Close control frame,
+                        // _The WebSocket Connection Close Reason_ is the empty string."
+                        self.closed(CloseCode::Status, "");
+                        if !self.state.is_closing() {
+                            self.send_close(CloseCode::Empty, "")?;
+                        } else {
+                            self.state = FinishedClose;
                         }
-                        // last fragment
-                        OpCode::Continue => {
-                            trace!("Received final fragment {:?}", frame);
-                            if let Some(first) = self.fragments.pop_front() {
-                                let size = self.fragments.iter().fold(
-                                    first.payload().len() + frame.payload().len(),
-                                    |len, frame| len + frame.payload().len(),
+                    }
+                }
+                OpCode::Ping => {
+                    trace!("Received ping frame {:?}", frame);
+                    self.send_pong(frame.into_data())?;
+                }
+                OpCode::Pong => {
+                    trace!("Received pong frame {:?}", frame);
+                    // pongs that arrive while closing no longer prove anything
+                    if self.settings.pong_must_match && self.state.is_open() {
+                        self.answer_ping(frame.payload())?;
+                    }
+                }
+                // last fragment
+                OpCode::Continue => {
+                    trace!("Received final fragment {:?}", frame);
+                    if self.streamed > 0 {
+                        // the message was delivered through on_fragment as it arrived
+                        let size = self.fragments_size + frame.payload().len();
+                        let index = self.streamed;
+                        self.fragments_size = 0;
+                        self.streamed = 0;
+                        self.handler.on_fragment(&frame, index, size, true)?;
+                        return Ok(());
+                    }
+                    if !self.fragments.is_empty() {
+                        let size = self.fragments_size + frame.payload().len();
+                        self.fragments_size = 0;
+                        self.account()?;
+                        self.handler
+                            .on_fragment(&frame, self.fragments.len(), size, true)?;
+                    }
+                    if let Some(first) = self.fragments.pop_front() {
+                        let size = self.fragments.iter().fold(
+                            first.payload().len() + frame.payload().len(),
+                            |len, frame| len + frame.payload().len(),
+                        );
+                        match first.opcode() {
+                            OpCode::Text => {
+                                trace!("Constructing text message from fragments: {:?} -> {:?} -> {:?}", first, self.fragments.iter().collect::<Vec<&Frame>>(), frame);
+                                let mut data = Vec::with_capacity(size);
+                                data.extend(first.into_data());
+                                while let Some(frame) = self.fragments.pop_front() {
+                                    data.extend(frame.into_data());
+                                }
+                                data.extend(frame.into_data());
+
+                                trace!(
+                                    "Calling handler with constructed message: {:?}",
+                                    String::from_utf8_lossy(&data)
                                 );
-                                match first.opcode() {
-                                    OpCode::Text => {
-                                        trace!("Constructing text message from fragments: {:?} -> {:?} -> {:?}", first, self.fragments.iter().collect::<Vec<&Frame>>(), frame);
-                                        let mut data = Vec::with_capacity(size);
-                                        data.extend(first.into_data());
-                                        while let Some(frame) = self.fragments.pop_front() {
-                                            data.extend(frame.into_data());
-                                        }
-                                        data.extend(frame.into_data());
-
-                                        let string = String::from_utf8(data)
-                                            .map_err(|err| err.utf8_error())?;
-
-                                        trace!(
-                                            "Calling handler with constructed message: {:?}",
-                                            string
-                                        );
-                                        self.handler.on_message(Message::text(string))?;
-                                    }
-                                    OpCode::Binary => {
-                                        trace!("Constructing binary message from fragments: {:?} -> {:?} -> {:?}", first, self.fragments.iter().collect::<Vec<&Frame>>(), frame);
-                                        let mut data = Vec::with_capacity(size);
-                                        data.extend(first.into_data());
-
-                                        while let Some(frame) = self.fragments.pop_front() {
-                                            data.extend(frame.into_data());
-                                        }
-
-                                        data.extend(frame.into_data());
-
-                                        trace!(
-                                            "Calling handler with constructed message: {:?}",
-                                            data
-                                        );
-                                        self.handler.on_message(Message::binary(data))?;
-                                    }
-                                    _ => {
-                                        return Err(Error::new(
-                                            Kind::Protocol,
-                                            "Encounted fragmented control frame.",
-                                        ))
-                                    }
+                                self.deliver_text(data)?;
+                            }
+                            OpCode::Binary => {
+                                trace!("Constructing binary message from fragments: {:?} -> {:?} -> {:?}", first, self.fragments.iter().collect::<Vec<&Frame>>(), frame);
+                                let mut data = Vec::with_capacity(size);
+                                data.extend(first.into_data());
+
+                                while let Some(frame) = self.fragments.pop_front() {
+                                    data.extend(frame.into_data());
                                 }
-                            } else {
+
+                                data.extend(frame.into_data());
+
+                                trace!(
+                                    "Calling handler with constructed message: {:?}",
+                                    data
+                                );
+                                self.deliver_binary(data)?;
+                            }
+                            _ => {
                                 return Err(Error::new(
                                     Kind::Protocol,
-                                    "Unable to reconstruct fragmented message. No first frame.",
-                                ));
+                                    "Encounted fragmented control frame.",
+                                ))
                             }
                         }
-                        _ => return Err(Error::new(Kind::Protocol, "Encountered invalid opcode.")),
-                    }
-                } else {
-                    if frame.is_control() {
+                    } else {
                         return Err(Error::new(
                             Kind::Protocol,
-                            "Encounted fragmented control frame.",
+                            "Unable to reconstruct fragmented message. No first frame.",
                         ));
+                    }
+                }
+                _ => return Err(Error::new(Kind::Protocol, "Encountered invalid opcode.")),
+            }
+        } else {
+            if frame.is_control() {
+                return Err(Error::new(
+                    Kind::Protocol,
+                    "Encounted fragmented control frame.",
+                ));
+            } else {
+                trace!("Received non-final fragment frame {:?}", frame);
+                if !self.settings.fragments_grow
+                    && self.settings.fragments_capacity == self.fragments.len()
+                {
+                    return Err(Error::new(Kind::Capacity, "Exceeded max fragments."));
+                } else {
+                    self.fragments_size += frame.payload().len();
+                    let index = self.fragments.len() + self.streamed;
+                    self.handler
+                        .on_fragment(&frame, index, self.fragments_size, false)?;
+                    if self.streamed > 0 || self.is_large(self.fragments_size) {
+                        // deliver the rest of the message only through on_fragment
+                        self.streamed = index + 1;
+                        self.fragments.clear();
                     } else {
-                        trace!("Received non-final fragment frame {:?}", frame);
-                        if !self.settings.fragments_grow
-                            && self.settings.fragments_capacity == self.fragments.len()
-                        {
-                            return Err(Error::new(Kind::Capacity, "Exceeded max fragments."));
-                        } else {
-                            self.fragments.push_back(frame)
-                        }
+                        self.fragments.push_back(frame);
                     }
+                    self.account()?;
                 }
             }
         }
         Ok(())
     }
 
+    fn read_after_close(&mut self, mut frame: Frame) -> Result<()> {
+        self.after_close_frames += 1;
+        self.after_close_bytes += frame.payload().len();
+        if self.after_close_bytes > self.settings.max_after_close_bytes {
+            return Err(Error::new(
+                Kind::Capacity,
+                "Exceeded max bytes received after close frame.",
+            ));
+        }
+
+        match self.settings.after_close {
+            AfterClose::Drop => {
+                trace!("Dropping frame received after close frame {:?}", frame);
+            }
+            AfterClose::Abort(max) => {
+                if self.after_close_frames > max {
+                    return Err(Error::new(
+                        Kind::Protocol,
+                        "Received too many frames after close frame.",
+                    ));
+                }
+                trace!("Dropping frame received after close frame {:?}", frame);
+            }
+            AfterClose::Deliver => {
+                frame.remove_mask_with(self.mask_fn());
+                self.handler.on_after_close_frame(frame)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn write(&mut self) -> Result<()> {
+        if self.writing {
+            return Ok(());
+        }
         if self.socket.is_negotiating() {
             trace!("Performing TLS negotiation on {}.", self.peer_addr());
             self.socket.clear_negotiating()?;
@@ -980,28 +2142,15 @@ where
             } else {
                 trace!("Ready to write messages to {}.", self.peer_addr());
 
+                if self.offload_write() {
+                    return Ok(());
+                }
+
                 // Start out assuming that this write will clear the whole buffer
                 self.events.remove(Ready::writable());
 
-                if let Some(len) = self.socket.try_write_buf(&mut self.out_buffer)? {
-                    trace!("Wrote {} bytes to {}", len, self.peer_addr());
-                    let finished = len == 0
-                        || self.out_buffer.position() == self.out_buffer.get_ref().len() as u64;
-                    if finished {
-                        match self.state {
-                            // we are are a server that is closing and just wrote out our confirming
-                            // close frame, let's disconnect
-                            FinishedClose if self.is_server() => {
-                                self.events = Ready::empty();
-                                return Ok(());
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-
-                // Check if there is more to write so that the connection will be rescheduled
-                self.check_events();
+                let len = self.socket.try_write_buf(&mut self.out_buffer)?;
+                self.wrote(len);
                 Ok(())
             };
 
@@ -1013,7 +2162,215 @@ where
         }
     }
 
+    fn wrote(&mut self, len: Option<usize>) {
+        if let Some(len) = len {
+            trace!("Wrote {} bytes to {}", len, self.peer_addr());
+            self.written_bytes += len as u64;
+            while let Some(&(end, meta)) = self.traced.front() {
+                if end > self.written_bytes {
+                    break;
+                }
+                self.traced.pop_front();
+                self.handler.on_message_written(meta);
+            }
+            let finished =
+                len == 0 || self.out_buffer.position() == self.out_buffer.get_ref().len() as u64;
+            if finished {
+                match self.state {
+                    // we are are a server that is closing and just wrote out our confirming
+                    // close frame, let's disconnect
+                    FinishedClose if self.is_server() && !self.socket.wants_write() => {
+                        self.events = Ready::empty();
+                        return;
+                    }
+                    // the other endpoint stopped sending, so the connection is done once the
+                    // last of our data is written
+                    _ if self.read_eof && !self.socket.wants_write() => {
+                        self.disconnect();
+                        self.check_flushed();
+                        return;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        // Check if there is more to write so that the connection will be rescheduled
+        self.check_events();
+        self.check_flushed();
+    }
+
+    // Tell the threads waiting in `Sender::flush` once everything buffered has been written.
+    fn check_flushed(&mut self) {
+        if !self.flushes.is_empty()
+            && !self.writing
+            && self.out_buffer.position() == self.out_buffer.get_ref().len() as u64
+        {
+            for waiter in self.flushes.drain(..) {
+                waiter.flushed();
+            }
+        }
+    }
+
+    // Hand the stream and the outgoing buffer to a writer thread. Frames buffered while the write
+    // is in flight are appended to the returned buffer when the write completes.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn offload_write(&mut self) -> bool {
+        let writer = match self.writer {
+            Some(ref writer) if self.socket.is_tls_established() => writer.clone(),
+            _ => return false,
+        };
+        let buffer = replace(
+            &mut self.out_buffer,
+            Cursor::new(Vec::with_capacity(self.settings.out_buffer_capacity)),
+        );
+        let job = Job {
+            token: self.token,
+            connection_id: self.connection_id,
+            stream: self.socket.take_stream(),
+            buffer,
+        };
+        match writer.submit(job) {
+            Ok(()) => {
+                trace!("Offloaded write to connection {:?}.", self.token);
+                self.writing = true;
+                true
+            }
+            Err(job) => {
+                self.socket = job.stream;
+                self.out_buffer = job.buffer;
+                false
+            }
+        }
+    }
+
+    #[cfg(not(any(feature = "ssl", feature = "nativetls")))]
+    #[inline]
+    fn offload_write(&mut self) -> bool {
+        false
+    }
+
+    /// Take back the stream and buffer from a write performed by a writer thread.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn write_complete(
+        &mut self,
+        stream: Stream,
+        buffer: Cursor<Vec<u8>>,
+        result: ::std::io::Result<Option<usize>>,
+    ) -> Result<()> {
+        self.writing = false;
+        self.socket = stream;
+        let queued = replace(&mut self.out_buffer, buffer);
+        self.out_buffer
+            .get_mut()
+            .extend(&queued.get_ref()[queued.position() as usize..]);
+
+        self.events.remove(Ready::writable());
+        self.wrote(result?);
+
+        if self.socket.is_negotiating() {
+            self.events.remove(Ready::writable());
+            self.events.insert(Ready::readable());
+        }
+        Ok(())
+    }
+
+    /// Release memory held by buffers that grew to handle a large message if they have been
+    /// mostly empty since the last call.
+    pub fn shrink_buffers(&mut self) {
+        if pool::shrink(
+            &mut self.in_buffer,
+            self.in_high_water,
+            self.settings.in_buffer_capacity,
+        ) {
+            trace!("Shrunk incoming buffer for {}.", self.peer_addr());
+        }
+        // The outgoing buffer is owned by a writer thread while a write is in progress
+        if !self.writing
+            && pool::shrink(
+                &mut self.out_buffer,
+                self.out_high_water,
+                self.settings.out_buffer_capacity,
+            ) {
+            trace!("Shrunk outgoing buffer for {}.", self.peer_addr());
+        }
+        // shrinking can only release memory
+        let _ = self.account();
+        self.in_high_water = pool::pending(&self.in_buffer);
+        self.out_high_water = pool::pending(&self.out_buffer);
+    }
+
+    pub fn send_traced(&mut self, msg: Message, meta: MessageMeta) -> Result<()> {
+        if self.state.is_closing() {
+            return self.send_message(msg);
+        }
+        self.send_message(msg)?;
+        self.traced.push_back((self.buffered_bytes, meta));
+        Ok(())
+    }
+
+    pub fn send_tracked(&mut self, msg: Message, token: AckToken) -> Result<()> {
+        self.send_message(ack::wrap(msg, token))?;
+        self.pending_acks.push_back((token, Instant::now()));
+        Ok(())
+    }
+
+    /// When the oldest message awaiting acknowledgment times out, if any does.
+    pub fn next_ack_deadline(&self) -> Option<Instant> {
+        if self.settings.ack_timeout_ms == 0 {
+            return None;
+        }
+        self.pending_acks
+            .front()
+            .map(|&(_, sent)| sent + Duration::from_millis(self.settings.ack_timeout_ms))
+    }
+
+    /// Report the messages whose acknowledgment is overdue to `Handler::on_ack_timeout`.
+    pub fn expire_acks(&mut self) {
+        let now = Instant::now();
+        while let Some(deadline) = self.next_ack_deadline() {
+            if deadline > now {
+                break;
+            }
+            if let Some((token, _)) = self.pending_acks.pop_front() {
+                self.handler.on_ack_timeout(token);
+            }
+        }
+    }
+
+    // Acknowledge a tracked message or pass on an acknowledgment, and return the payload to
+    // deliver, if any.
+    fn unwrap_ack(&mut self, mut data: Vec<u8>, text: bool) -> Result<Option<Vec<u8>>> {
+        if !self.settings.acks {
+            return Ok(Some(data));
+        }
+        match ack::parse(&data, text) {
+            Envelope::Tracked(token, offset) => {
+                self.send_message(ack::ack(token))?;
+                data.drain(..offset);
+                Ok(Some(data))
+            }
+            Envelope::Ack(token) => {
+                if let Some(index) = self.pending_acks.iter().position(|&(t, _)| t == token) {
+                    self.pending_acks.remove(index);
+                }
+                self.handler.on_ack(token);
+                Ok(None)
+            }
+            Envelope::Plain => Ok(Some(data)),
+        }
+    }
+
     pub fn send_message(&mut self, msg: Message) -> Result<()> {
+        let fragment_size = match msg.opcode() {
+            OpCode::Text => self.settings.text_fragment_size,
+            OpCode::Binary => self.settings.binary_fragment_size,
+            _ => None,
+        }.unwrap_or(self.settings.fragment_size);
+        self.send_fragmented(msg, fragment_size)
+    }
+
+    pub fn send_fragmented(&mut self, msg: Message, fragment_size: usize) -> Result<()> {
         if self.state.is_closing() {
             trace!(
                 "Connection is closing. Ignoring request to send message {:?} to {}.",
@@ -1030,12 +2387,12 @@ where
         if let Some(frame) = self.handler
             .on_send_frame(Frame::message(data, opcode, true))?
         {
-            if frame.payload().len() > self.settings.fragment_size {
-                trace!("Chunking at {:?}.", self.settings.fragment_size);
+            if frame.payload().len() > fragment_size {
+                trace!("Chunking at {:?}.", fragment_size);
                 // note this copies the data, so it's actually somewhat expensive to fragment
                 let mut chunks = frame
                     .payload()
-                    .chunks(self.settings.fragment_size)
+                    .chunks(fragment_size)
                     .peekable();
                 let chunk = chunks.next().expect("Unable to get initial chunk!");
 
@@ -1074,6 +2431,29 @@ where
     }
 
     #[inline]
+    // Append a frame that was formatted ahead of time to the outgoing buffer. Frames sent by
+    // clients must be masked, so only open server connections take raw frames.
+    pub fn send_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.is_client() || !self.state.is_open() {
+            trace!(
+                "Ignoring raw frame for {}, which is not an open server connection.",
+                self.peer_addr()
+            );
+            return Ok(());
+        }
+        trace!("Sending raw frame of {} bytes to {}.", bytes.len(), self.peer_addr());
+
+        self.check_buffer_out(bytes.len())?;
+        let pos = self.out_buffer.position();
+        self.out_buffer.seek(SeekFrom::End(0))?;
+        self.out_buffer.write_all(bytes)?;
+        self.buffered_bytes += bytes.len() as u64;
+        self.out_buffer.seek(SeekFrom::Start(pos))?;
+        self.out_high_water = max(self.out_high_water, pool::pending(&self.out_buffer));
+        self.check_events();
+        Ok(())
+    }
+
     pub fn send_ping(&mut self, data: Vec<u8>) -> Result<()> {
         if self.state.is_closing() {
             trace!(
@@ -1086,12 +2466,33 @@ where
         trace!("Sending ping to {}.", self.peer_addr());
 
         if let Some(frame) = self.handler.on_send_frame(Frame::ping(data))? {
+            if self.settings.pong_must_match && frame.opcode() == OpCode::Ping {
+                self.pings.push_back(frame.payload().clone());
+            }
             self.buffer_frame(frame)?;
         }
         self.check_events();
         Ok(())
     }
 
+    // Remove the ping answered by a pong along with any older pings.
+    fn answer_ping(&mut self, payload: &[u8]) -> Result<()> {
+        match self.pings.iter().position(|ping| &ping[..] == payload) {
+            Some(pos) => {
+                self.pings.drain(..=pos);
+                Ok(())
+            }
+            None if self.pings.is_empty() => Err(Error::new(
+                Kind::Protocol,
+                "Received a pong without having sent a ping.",
+            )),
+            None => Err(Error::new(
+                Kind::Protocol,
+                "Received a pong that does not match the payload of an outstanding ping.",
+            )),
+        }
+    }
+
     #[inline]
     pub fn send_pong(&mut self, data: Vec<u8>) -> Result<()> {
         if self.state.is_closing() {
@@ -1159,31 +2560,45 @@ where
 
     fn check_events(&mut self) {
         if !self.state.is_connecting() {
-            self.events.insert(Ready::readable());
-            if self.out_buffer.position() < self.out_buffer.get_ref().len() as u64 {
+            if !self.read_eof {
+                self.events.insert(Ready::readable());
+            }
+            if self.out_buffer.position() < self.out_buffer.get_ref().len() as u64
+                || self.socket.wants_write()
+            {
                 self.events.insert(Ready::writable());
             }
         }
     }
 
     fn buffer_frame(&mut self, mut frame: Frame) -> Result<()> {
-        self.check_buffer_out(&frame)?;
+        self.check_buffer_out(frame.len())?;
 
         if self.is_client() {
             frame.set_mask();
         }
 
         trace!("Buffering frame to {}:\n{}", self.peer_addr(), frame);
+        if self.settings.frame_checksums {
+            info!(
+                "Sending {} frame of {} bytes with CRC-32 {:08x} to {}.",
+                frame.opcode(),
+                frame.payload().len(),
+                frame.checksum(),
+                self.peer_addr()
+            );
+        }
 
-        let pos = self.out_buffer.position();
-        self.out_buffer.seek(SeekFrom::End(0))?;
-        frame.format(&mut self.out_buffer)?;
-        self.out_buffer.seek(SeekFrom::Start(pos))?;
+        let len = self.out_buffer.get_ref().len();
+        let mask_fn = self.mask_fn();
+        frame.format_into(self.out_buffer.get_mut(), mask_fn)?;
+        self.buffered_bytes += (self.out_buffer.get_ref().len() - len) as u64;
+        self.out_high_water = max(self.out_high_water, pool::pending(&self.out_buffer));
         Ok(())
     }
 
-    fn check_buffer_out(&mut self, frame: &Frame) -> Result<()> {
-        if self.out_buffer.get_ref().capacity() <= self.out_buffer.get_ref().len() + frame.len() {
+    fn check_buffer_out(&mut self, len: usize) -> Result<()> {
+        if self.out_buffer.get_ref().capacity() <= self.out_buffer.get_ref().len() + len {
             // extend
             let mut new = Vec::with_capacity(self.out_buffer.get_ref().capacity());
             new.extend(&self.out_buffer.get_ref()[self.out_buffer.position() as usize..]);
@@ -1198,6 +2613,7 @@ where
                 }
             }
             self.out_buffer = Cursor::new(new);
+            self.account()?;
         }
         Ok(())
     }
@@ -1206,6 +2622,7 @@ where
         trace!("Reading buffer for connection to {}.", self.peer_addr());
         if let Some(len) = self.socket.try_read_buf(self.in_buffer.get_mut())? {
             trace!("Buffered {}.", len);
+            self.in_high_water = max(self.in_high_water, pool::pending(&self.in_buffer));
             if self.in_buffer.get_ref().len() == self.in_buffer.get_ref().capacity() {
                 // extend
                 let mut new = Vec::with_capacity(self.in_buffer.get_ref().capacity());
@@ -1221,6 +2638,7 @@ where
                     }
                 }
                 self.in_buffer = Cursor::new(new);
+                self.account()?;
             }
             Ok(Some(len))
         } else {
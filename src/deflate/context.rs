@@ -1,13 +1,47 @@
+// Calls into zlib, so unsafe code is unavoidable here
+#![allow(unsafe_code)]
+
 use std::mem;
+use std::ptr;
 use std::slice;
-use std::mem::MaybeUninit;
 
 use super::ffi;
-use super::libc::{c_char, c_int, c_uint};
+use super::libc::{self, c_char, c_int, c_uint, c_void, size_t};
 
 use result::{Error, Kind, Result};
 
 const ZLIB_VERSION: &'static str = "1.2.8\0";
+// Adding 16 to the window bits instructs zlib to expect a gzip header and trailer
+const GZIP_WINDOW_BITS: c_int = 15 + 16;
+
+unsafe extern "C" fn zalloc(_: *mut c_void, items: c_uint, size: c_uint) -> *mut c_void {
+    libc::calloc(items as size_t, size as size_t)
+}
+
+unsafe extern "C" fn zfree(_: *mut c_void, address: *mut c_void) {
+    libc::free(address)
+}
+
+// zlib expects the allocation functions and opaque pointer to be initialized before the stream
+// is passed to one of the init functions.
+fn new_stream() -> Box<ffi::z_stream> {
+    Box::new(ffi::z_stream {
+        next_in: ptr::null_mut(),
+        avail_in: 0,
+        total_in: 0,
+        next_out: ptr::null_mut(),
+        avail_out: 0,
+        total_out: 0,
+        msg: ptr::null_mut(),
+        state: ptr::null_mut(),
+        zalloc,
+        zfree,
+        opaque: ptr::null_mut(),
+        data_type: 0,
+        adler: 0,
+        reserved: 0,
+    })
+}
 
 trait Context {
     fn stream(&mut self) -> &mut ffi::z_stream;
@@ -60,6 +94,8 @@ pub struct Compressor {
     // Box the z_stream to ensure it isn't moved. Moving the z_stream
     // causes zlib to fail, because it maintains internal pointers.
     stream: Box<ffi::z_stream>,
+    // applied again each time the context is reset
+    dictionary: Option<&'static [u8]>,
 }
 
 impl Compressor {
@@ -68,7 +104,7 @@ impl Compressor {
         debug_assert!(window_bits <= 15, "Received too large window size.");
 
         unsafe {
-            let mut stream: Box<ffi::z_stream> = Box::new(MaybeUninit::uninit().assume_init());
+            let mut stream = new_stream();
             let result = ffi::deflateInit2_(
                 stream.as_mut(),
                 9,
@@ -80,7 +116,10 @@ impl Compressor {
                 mem::size_of::<ffi::z_stream>() as c_int,
             );
             assert!(result == ffi::Z_OK, "Failed to initialize compresser.");
-            Compressor { stream: stream }
+            Compressor {
+                stream,
+                dictionary: None,
+            }
         }
     }
 
@@ -104,15 +143,50 @@ impl Compressor {
 
     pub fn reset(&mut self) -> Result<()> {
         match unsafe { ffi::deflateReset(self.stream.as_mut()) } {
-            ffi::Z_OK => Ok(()),
+            ffi::Z_OK => self.apply_dictionary(),
             code => Err(Error::new(
                 Kind::Protocol,
                 format!("Failed to reset compression context: {}", code),
             )),
         }
     }
+
+    // Use a preset dictionary for the next message and every message after a reset. The context
+    // is reset if the dictionary changes, so it must not be in the middle of a message.
+    pub fn set_dictionary(&mut self, dictionary: Option<&'static [u8]>) -> Result<()> {
+        if self.dictionary == dictionary {
+            return Ok(());
+        }
+        self.dictionary = dictionary;
+        self.reset()
+    }
+
+    fn apply_dictionary(&mut self) -> Result<()> {
+        let dictionary = match self.dictionary {
+            Some(dictionary) => dictionary,
+            None => return Ok(()),
+        };
+        let code = unsafe {
+            ffi::deflateSetDictionary(
+                self.stream.as_mut(),
+                dictionary.as_ptr(),
+                dictionary.len() as c_uint,
+            )
+        };
+        match code {
+            ffi::Z_OK => Ok(()),
+            code => Err(Error::new(
+                Kind::Protocol,
+                format!("Failed to set compression dictionary: {}", code),
+            )),
+        }
+    }
 }
 
+// The z_stream is owned exclusively by its context and zlib keeps no thread-local state, so a
+// context may be handed to another thread, for example through a `DeflatePool`.
+unsafe impl Send for Compressor {}
+
 impl Context for Compressor {
     fn stream(&mut self) -> &mut ffi::z_stream {
         self.stream.as_mut()
@@ -132,6 +206,7 @@ impl Drop for Compressor {
 
 pub struct Decompressor {
     stream: Box<ffi::z_stream>,
+    dictionary: Option<&'static [u8]>,
 }
 
 impl Decompressor {
@@ -139,22 +214,34 @@ impl Decompressor {
         debug_assert!(window_bits >= 8, "Received too small window size.");
         debug_assert!(window_bits <= 15, "Received too large window size.");
 
+        Decompressor::with_raw_window_bits(-window_bits as c_int)
+    }
+
+    pub fn gzip() -> Decompressor {
+        Decompressor::with_raw_window_bits(GZIP_WINDOW_BITS)
+    }
+
+    fn with_raw_window_bits(window_bits: c_int) -> Decompressor {
         unsafe {
-            let mut stream: Box<ffi::z_stream> = Box::new(MaybeUninit::uninit().assume_init());
+            let mut stream = new_stream();
             let result = ffi::inflateInit2_(
                 stream.as_mut(),
-                -window_bits as c_int,
+                window_bits,
                 ZLIB_VERSION.as_ptr() as *const c_char,
                 mem::size_of::<ffi::z_stream>() as c_int,
             );
             assert!(result == ffi::Z_OK, "Failed to initialize decompresser.");
-            Decompressor { stream: stream }
+            Decompressor {
+                stream,
+                dictionary: None,
+            }
         }
     }
 
     pub fn decompress(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<()> {
         self.stream_apply(input, output, |stream| unsafe {
             match ffi::inflate(stream, ffi::Z_SYNC_FLUSH) {
+                ffi::Z_STREAM_END => Some(Ok(())),
                 ffi::Z_OK | ffi::Z_BUF_ERROR => {
                     if stream.avail_in == 0 && stream.avail_out > 0 {
                         Some(Ok(()))
@@ -170,17 +257,74 @@ impl Decompressor {
         })
     }
 
+    // Decompress no more than `limit` bytes of output, so that a small input cannot expand into
+    // an unbounded amount of memory. The rest of the output is discarded.
+    pub fn decompress_limited(&mut self, input: &[u8], limit: usize) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(limit);
+        let stream = self.stream.as_mut();
+        stream.next_in = input.as_ptr() as *mut _;
+        stream.avail_in = input.len() as c_uint;
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = limit.min(c_uint::MAX as usize) as c_uint;
+
+        let before = stream.total_out;
+        let code = unsafe { ffi::inflate(stream, ffi::Z_SYNC_FLUSH) };
+        unsafe {
+            output.set_len((stream.total_out - before) as usize);
+        }
+        match code {
+            ffi::Z_OK | ffi::Z_STREAM_END | ffi::Z_BUF_ERROR => Ok(output),
+            code => Err(Error::new(
+                Kind::Protocol,
+                format!("Failed to perform decompression: {}", code),
+            )),
+        }
+    }
+
     pub fn reset(&mut self) -> Result<()> {
         match unsafe { ffi::inflateReset(self.stream.as_mut()) } {
-            ffi::Z_OK => Ok(()),
+            ffi::Z_OK => self.apply_dictionary(),
             code => Err(Error::new(
                 Kind::Protocol,
                 format!("Failed to reset compression context: {}", code),
             )),
         }
     }
+
+    // Like `Compressor::set_dictionary`. Raw streams carry no dictionary id, so the dictionary
+    // must be the one used to compress the data.
+    pub fn set_dictionary(&mut self, dictionary: Option<&'static [u8]>) -> Result<()> {
+        if self.dictionary == dictionary {
+            return Ok(());
+        }
+        self.dictionary = dictionary;
+        self.reset()
+    }
+
+    fn apply_dictionary(&mut self) -> Result<()> {
+        let dictionary = match self.dictionary {
+            Some(dictionary) => dictionary,
+            None => return Ok(()),
+        };
+        let code = unsafe {
+            ffi::inflateSetDictionary(
+                self.stream.as_mut(),
+                dictionary.as_ptr(),
+                dictionary.len() as c_uint,
+            )
+        };
+        match code {
+            ffi::Z_OK => Ok(()),
+            code => Err(Error::new(
+                Kind::Protocol,
+                format!("Failed to set decompression dictionary: {}", code),
+            )),
+        }
+    }
 }
 
+unsafe impl Send for Decompressor {}
+
 impl Context for Decompressor {
     fn stream(&mut self) -> &mut ffi::z_stream {
         self.stream.as_mut()
@@ -266,4 +410,70 @@ mod test {
         assert!(compressed2 != compressed2_ind);
         assert!(compressed2.len() < compressed2_ind.len());
     }
+
+    #[test]
+    fn dictionary() {
+        static DICTIONARY: &[u8] = br#"{"type":"update","id":,"price":,"volume":}"#;
+        let data = br#"{"type":"update","id":7,"price":12,"volume":3}"#;
+
+        let mut plain = Vec::with_capacity(data.len());
+        Compressor::new(15).compress(data, &mut plain).unwrap();
+
+        let mut com = Compressor::new(15);
+        com.set_dictionary(Some(DICTIONARY)).unwrap();
+        let mut dec = Decompressor::new(15);
+        dec.set_dictionary(Some(DICTIONARY)).unwrap();
+
+        // the dictionary is applied again after each reset
+        for _ in 0..2 {
+            let mut compressed = Vec::with_capacity(data.len());
+            let mut decompressed = Vec::with_capacity(data.len());
+            com.compress(data, &mut compressed).unwrap();
+            dec.decompress(&compressed, &mut decompressed).unwrap();
+            assert_eq!(&data[..], &decompressed[..]);
+            assert!(compressed.len() < plain.len());
+            com.reset().unwrap();
+            dec.reset().unwrap();
+        }
+
+        let mut compressed = Vec::with_capacity(data.len());
+        let mut decompressed = Vec::with_capacity(data.len());
+        com.compress(data, &mut compressed).unwrap();
+        assert!(Decompressor::new(15)
+            .decompress(&compressed, &mut decompressed)
+            .is_err());
+    }
+
+    #[test]
+    fn gzip() {
+        let data = [
+            31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 115, 74, 76, 81, 112, 79, 44, 73, 45, 79, 172, 4, 0,
+            239, 247, 80, 101, 11, 0, 0, 0,
+        ];
+        let mut decompressed = Vec::with_capacity(data.len());
+
+        let mut dec = Decompressor::gzip();
+        dec.decompress(&data, &mut decompressed).unwrap();
+
+        assert_eq!(b"Bad Gateway", &decompressed[..]);
+    }
+
+    #[test]
+    fn decompress_limited() {
+        // 64 KiB of zeros
+        let mut data = vec![
+            31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 237, 193, 1, 1, 0, 0, 0, 128, 144, 254, 175, 238, 8, 10,
+        ];
+        data.extend(vec![0; 63]);
+        data.extend(&[106, 235, 142, 151, 215, 0, 0, 1, 0]);
+
+        let decompressed = Decompressor::gzip().decompress_limited(&data, 100).unwrap();
+        assert_eq!(decompressed, vec![0; 100]);
+        assert!(decompressed.capacity() <= 100);
+
+        let decompressed = Decompressor::gzip()
+            .decompress_limited(&data, 1 << 20)
+            .unwrap();
+        assert_eq!(decompressed.len(), 65_536);
+    }
 }
@@ -1,4 +1,9 @@
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
 use std::mem::replace;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 #[cfg(feature = "ssl")]
 use openssl::ssl::SslStream;
@@ -6,17 +11,22 @@ use openssl::ssl::SslStream;
 use native_tls::TlsStream as SslStream;
 use url;
 
+use communication::{AckToken, MessageMeta, Sender};
+use event::ErrorEvent;
 use frame::Frame;
 use handler::Handler;
 use handshake::{Handshake, Request, Response};
-use message::Message;
+use message::{Message, MessageInfo};
 use protocol::{CloseCode, OpCode};
 use result::{Error, Kind, Result};
 #[cfg(any(feature = "ssl", feature = "nativetls"))]
+use stream::TlsClientOptions;
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
 use util::TcpStream;
 use util::{Timeout, Token};
 
 use super::context::{Compressor, Decompressor};
+use super::pool::{DeflatePool, InflatePool};
 
 /// Deflate Extension Handler Settings
 #[derive(Debug, Clone, Copy)]
@@ -43,6 +53,29 @@ pub struct DeflateSettings {
     /// exceeded. If this is not true, a capacity error will be triggered instead.
     /// Default: true
     pub fragments_grow: bool,
+    /// The compressed size in bytes at which an incoming message is decompressed on a worker
+    /// thread instead of the event loop, so that inflating a large message does not delay every
+    /// other connection. The message is handed back to the connection through the event loop
+    /// queue, and messages received in the meantime are handed back after it so that they are
+    /// delivered in order. Control frames are not delayed, so a close frame that arrives while a
+    /// message is being decompressed closes the connection and the message is dropped.
+    /// Offloading requires the connection's sender, see `DeflateHandler::with_sender`.
+    /// Default: usize::MAX
+    pub offload_threshold: usize,
+    /// The number of received frames that may wait to be decompressed on a worker thread, or to
+    /// be handed back after messages that are. Once half of them are waiting, the connection
+    /// stops reading until all of them have been handed back, and a frame that arrives while the
+    /// queue is full fails the connection with a capacity error.
+    /// Default: 64
+    pub offload_queue_size: usize,
+    /// A preset dictionary of content that messages are likely to contain, such as the keys of a
+    /// JSON protocol, which greatly improves the compression of small messages. The dictionary
+    /// primes both the compressor and the decompressor before each message that starts with an
+    /// empty sliding window. It is not negotiated in the handshake, so both endpoints must agree
+    /// on it out-of-band, and a connection whose peer uses a different dictionary fails to
+    /// decompress messages. Use `include_bytes!` or `Box::leak` to obtain a static dictionary.
+    /// Default: None
+    pub preset_dictionary: Option<&'static [u8]>,
 }
 
 impl Default for DeflateSettings {
@@ -53,15 +86,155 @@ impl Default for DeflateSettings {
             accept_no_context_takeover: true,
             fragments_capacity: 10,
             fragments_grow: true,
+            offload_threshold: usize::MAX,
+            offload_queue_size: 64,
+            preset_dictionary: None,
+        }
+    }
+}
+
+/// The parameters of a single permessage-deflate offer or response, as found in a
+/// `Sec-WebSocket-Extensions` header.
+///
+/// A client may list several offers in order of preference. The server accepts the first one that
+/// it can satisfy and answers with the agreed parameters, which the `DeflateHandler` then makes
+/// available through `DeflateHandler::negotiated`. Handlers wrapped by a `DeflateHandler` can
+/// recover the agreed parameters from the handshake passed to `on_open` with
+/// `DeflateOffer::from_response`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeflateOffer {
+    /// The server resets its sliding window for each message it compresses.
+    pub server_no_context_takeover: bool,
+    /// The client resets its sliding window for each message it compresses.
+    pub client_no_context_takeover: bool,
+    /// The size of the sliding window used by the server, if limited.
+    pub server_max_window_bits: Option<u8>,
+    /// The size of the sliding window used by the client, if limited. A `client_max_window_bits`
+    /// parameter given without a value is recorded as 15.
+    pub client_max_window_bits: Option<u8>,
+}
+
+impl DeflateOffer {
+    /// Parse a single permessage-deflate extension, such as
+    /// `permessage-deflate; client_max_window_bits`.
+    pub fn parse(ext: &str) -> Result<DeflateOffer> {
+        let mut params = ext.split(';').map(str::trim);
+        if params.next() != Some("permessage-deflate") {
+            return Err(Error::new(
+                Kind::Protocol,
+                format!("Not a permessage-deflate extension: {}", ext),
+            ));
+        }
+
+        let mut offer = DeflateOffer::default();
+        let mut s_takeover = false;
+        let mut c_takeover = false;
+        let mut s_max = false;
+        let mut c_max = false;
+
+        for param in params {
+            let mut param_iter = param.splitn(2, '=');
+            // split always yields at least one item
+            let name = param_iter.next().unwrap().trim();
+            let value = param_iter
+                .next()
+                .map(|value| value.trim().trim_matches('"'));
+            let seen = match name {
+                "server_no_context_takeover" if value.is_none() => {
+                    offer.server_no_context_takeover = true;
+                    &mut s_takeover
+                }
+                "client_no_context_takeover" if value.is_none() => {
+                    offer.client_no_context_takeover = true;
+                    &mut c_takeover
+                }
+                "server_max_window_bits" => {
+                    if let Some(value) = value {
+                        offer.server_max_window_bits = Some(parse_window_bits(name, value)?);
+                    }
+                    &mut s_max
+                }
+                "client_max_window_bits" => {
+                    offer.client_max_window_bits = Some(match value {
+                        Some(value) => parse_window_bits(name, value)?,
+                        None => 15,
+                    });
+                    &mut c_max
+                }
+                _ => {
+                    return Err(Error::new(
+                        Kind::Protocol,
+                        format!("Bad extension parameter: {}", param),
+                    ))
+                }
+            };
+            if *seen {
+                return Err(Error::new(
+                    Kind::Protocol,
+                    format!("Duplicate extension parameter {}", name),
+                ));
+            }
+            *seen = true;
         }
+
+        Ok(offer)
+    }
+
+    /// Find the permessage-deflate extension accepted by a handshake response, if any.
+    pub fn from_response(res: &Response) -> Result<Option<DeflateOffer>> {
+        let mut found = None;
+        for ext in res.extensions()? {
+            if ext.split(';').next().map(str::trim) == Some("permessage-deflate") {
+                if found.is_some() {
+                    return Err(Error::new(
+                        Kind::Protocol,
+                        "Duplicate extension name permessage-deflate",
+                    ));
+                }
+                found = Some(DeflateOffer::parse(ext)?);
+            }
+        }
+        Ok(found)
+    }
+}
+
+impl fmt::Display for DeflateOffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "permessage-deflate")?;
+        if self.server_no_context_takeover {
+            write!(f, "; server_no_context_takeover")?;
+        }
+        if self.client_no_context_takeover {
+            write!(f, "; client_no_context_takeover")?;
+        }
+        if let Some(window_bits) = self.server_max_window_bits {
+            write!(f, "; server_max_window_bits={}", window_bits)?;
+        }
+        if let Some(window_bits) = self.client_max_window_bits {
+            write!(f, "; client_max_window_bits={}", window_bits)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_window_bits(name: &str, value: &str) -> Result<u8> {
+    match value.parse() {
+        Ok(window_bits) if (9..=15).contains(&window_bits) => Ok(window_bits),
+        _ => Err(Error::new(
+            Kind::Protocol,
+            format!("Invalid {} parameter: {}", name, value),
+        )),
     }
 }
 
 /// Utility for applying the permessage-deflate extension to a handler with particular deflate
 /// settings.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct DeflateBuilder {
     settings: DeflateSettings,
+    pool: Option<DeflatePool>,
+    inflate: Option<InflatePool>,
+    offers: Vec<DeflateOffer>,
 }
 
 impl DeflateBuilder {
@@ -69,6 +242,9 @@ impl DeflateBuilder {
     pub fn new() -> DeflateBuilder {
         DeflateBuilder {
             settings: DeflateSettings::default(),
+            pool: None,
+            inflate: None,
+            offers: Vec::new(),
         }
     }
 
@@ -78,16 +254,45 @@ impl DeflateBuilder {
         self
     }
 
+    /// Share the deflate contexts of the handlers built from this DeflateBuilder through the given
+    /// pool. See `DeflatePool` for when contexts are taken from and given back to the pool.
+    pub fn with_pool(&mut self, pool: DeflatePool) -> &mut DeflateBuilder {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Decompress the messages that the handlers built from this DeflateBuilder offload on the
+    /// threads of the given pool instead of the shared one. See `InflatePool`.
+    pub fn with_inflate_pool(&mut self, pool: InflatePool) -> &mut DeflateBuilder {
+        self.inflate = Some(pool);
+        self
+    }
+
+    /// Offer the given permessage-deflate parameters, in order of preference, when the handlers
+    /// built from this DeflateBuilder act as clients. See `DeflateHandler::with_offers`.
+    pub fn with_offers(&mut self, offers: Vec<DeflateOffer>) -> &mut DeflateBuilder {
+        self.offers = offers;
+        self
+    }
+
     /// Wrap another handler in with a deflate handler as configured.
     pub fn build<H: Handler>(&self, handler: H) -> DeflateHandler<H> {
         DeflateHandler {
-            com: Compressor::new(self.settings.max_window_bits as i8),
-            dec: Decompressor::new(self.settings.max_window_bits as i8),
+            com: None,
+            dec: None,
+            com_window_bits: self.settings.max_window_bits as i8,
+            dec_window_bits: self.settings.max_window_bits as i8,
+            pool: self.pool.clone(),
+            inflate: self.inflate.clone(),
             fragments: Vec::with_capacity(self.settings.fragments_capacity),
             compress_reset: false,
             decompress_reset: false,
             pass: false,
+            negotiated: None,
+            last_compressed: false,
+            offload: None,
             settings: self.settings,
+            offers: self.offers.clone(),
             inner: handler,
         }
     }
@@ -100,33 +305,318 @@ impl DeflateBuilder {
 /// permessage-deflate specification and pass them to the child handler. Message frames sent from
 /// the child handler will be compressed and sent to the other endpoint using deflate compression.
 pub struct DeflateHandler<H: Handler> {
-    com: Compressor,
-    dec: Decompressor,
+    com: Option<Compressor>,
+    dec: Option<Decompressor>,
+    com_window_bits: i8,
+    dec_window_bits: i8,
+    pool: Option<DeflatePool>,
+    inflate: Option<InflatePool>,
     fragments: Vec<Frame>,
     compress_reset: bool,
     decompress_reset: bool,
     pass: bool,
+    negotiated: Option<DeflateOffer>,
+    last_compressed: bool,
+    offload: Option<Offload>,
     settings: DeflateSettings,
+    // the offers made by a client, or a single offer derived from the settings if empty
+    offers: Vec<DeflateOffer>,
     inner: H,
 }
 
+// Work handed to the decompression worker, which hands each result back in order.
+enum Job {
+    // a compressed message ending with the empty block that the peer stripped, and its opcode
+    Inflate(Vec<u8>, OpCode),
+    // a frame received while earlier messages were being decompressed
+    Pass(Frame),
+}
+
+#[derive(Default)]
+struct Worker {
+    jobs: VecDeque<Job>,
+    busy: bool,
+    // the decompressor while the worker is idle but its results have not all been handed back
+    dec: Option<Decompressor>,
+    error: Option<Error>,
+}
+
+// Decompression of large messages off the event loop, see `DeflateSettings::offload_threshold`.
+struct Offload {
+    out: Sender,
+    worker: Arc<Mutex<Worker>>,
+    threads: InflatePool,
+    // whether each job that has not been handed back yet inflates a message
+    pending: VecDeque<bool>,
+    // whether reading is suspended because too many jobs are pending, see `should_suspend_read`
+    suspended: bool,
+}
+
+impl Offload {
+    fn lock(&self) -> MutexGuard<'_, Worker> {
+        self.worker.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+// Decompress the queued jobs of a connection on a thread of the pool until there are none left.
+fn start_worker(offload: &Offload, mut dec: Decompressor, reset: bool) -> Result<()> {
+    let worker = offload.worker.clone();
+    let out = offload.out.clone();
+    offload.threads.execute(move || loop {
+        let job = worker
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .jobs
+            .pop_front();
+        let frame = match job {
+            Some(Job::Pass(frame)) => Ok(frame),
+            Some(Job::Inflate(compressed, opcode)) => {
+                let mut decompressed = Vec::with_capacity(compressed.len() * 2);
+                dec.decompress(&compressed, &mut decompressed)
+                    .and_then(|()| if reset { dec.reset() } else { Ok(()) })
+                    .map(|()| Frame::message(decompressed, opcode, true))
+            }
+            None => return,
+        };
+
+        let mut state = worker.lock().unwrap_or_else(|err| err.into_inner());
+        let frame = frame.unwrap_or_else(|err| {
+            state.error = Some(err);
+            state.jobs.clear();
+            Frame::message(Vec::new(), OpCode::Binary, true)
+        });
+        if state.jobs.is_empty() {
+            // hand the decompressor back before the last result so that it is there when the
+            // result is handled
+            state.busy = false;
+            state.dec = Some(dec);
+            drop(state);
+            let _ = out.inject_frame(frame);
+            return;
+        }
+        drop(state);
+        if out.inject_frame(frame).is_err() {
+            return;
+        }
+    })
+}
+
 impl<H: Handler> DeflateHandler<H> {
     /// Wrap a child handler to provide the permessage-deflate extension.
     pub fn new(handler: H) -> DeflateHandler<H> {
         trace!("Using permessage-deflate handler.");
         let settings = DeflateSettings::default();
         DeflateHandler {
-            com: Compressor::new(settings.max_window_bits as i8),
-            dec: Decompressor::new(settings.max_window_bits as i8),
+            com: None,
+            dec: None,
+            com_window_bits: settings.max_window_bits as i8,
+            dec_window_bits: settings.max_window_bits as i8,
+            pool: None,
+            inflate: None,
             fragments: Vec::with_capacity(settings.fragments_capacity),
             compress_reset: false,
             decompress_reset: false,
             pass: false,
+            negotiated: None,
+            last_compressed: false,
+            offload: None,
             settings: settings,
+            offers: Vec::new(),
             inner: handler,
         }
     }
 
+    /// Give the handler the sender of its connection so that messages of at least
+    /// `DeflateSettings::offload_threshold` compressed bytes can be decompressed on a worker
+    /// thread and handed back to the connection.
+    pub fn with_sender(mut self, out: Sender) -> DeflateHandler<H> {
+        self.offload = Some(Offload {
+            out,
+            worker: Arc::new(Mutex::new(Worker::default())),
+            threads: self.inflate.clone().unwrap_or_else(InflatePool::shared),
+            pending: VecDeque::new(),
+            suspended: false,
+        });
+        self
+    }
+
+    /// Offer the given permessage-deflate parameters when this handler acts as a client. The
+    /// offers are sent in the given order, which the server treats as the order of preference,
+    /// so a client can ask for a small window or no context takeover and fall back to plain
+    /// compression. Without offers, a single offer is derived from the `DeflateSettings`.
+    pub fn with_offers(mut self, offers: Vec<DeflateOffer>) -> DeflateHandler<H> {
+        self.offers = offers;
+        self
+    }
+
+    /// The permessage-deflate parameters agreed during the opening handshake, or `None` if the
+    /// handshake has not completed or the extension was not negotiated.
+    pub fn negotiated(&self) -> Option<&DeflateOffer> {
+        self.negotiated.as_ref()
+    }
+
+    /// Whether the peer compressed the last message received, or the message currently being
+    /// received if only some of its frames have arrived.
+    pub fn last_message_compressed(&self) -> bool {
+        self.last_compressed
+    }
+
+    /// Work out the parameters to answer a client's offer with, or `None` if these settings
+    /// cannot satisfy it.
+    fn select(&self, offer: &DeflateOffer) -> Option<DeflateOffer> {
+        if offer.server_no_context_takeover && !self.settings.accept_no_context_takeover {
+            return None;
+        }
+
+        let max_window_bits = self.settings.max_window_bits;
+        let client_max_window_bits = match offer.client_max_window_bits {
+            Some(window_bits) => Some(min(window_bits, max_window_bits)),
+            // the client can't be told to use a smaller window
+            None if max_window_bits < 15 => return None,
+            None => None,
+        };
+
+        Some(DeflateOffer {
+            server_no_context_takeover: offer.server_no_context_takeover,
+            client_no_context_takeover: offer.client_no_context_takeover
+                || self.settings.request_no_context_takeover,
+            server_max_window_bits: Some(
+                offer
+                    .server_max_window_bits
+                    .map_or(max_window_bits, |window_bits| {
+                        min(window_bits, max_window_bits)
+                    }),
+            ),
+            client_max_window_bits: client_max_window_bits,
+        })
+    }
+
+    fn accept(&mut self, mut res: Response, agreed: DeflateOffer) -> Response {
+        trace!("Accepted permessage-deflate offer: {}", agreed);
+        self.compress_reset = agreed.server_no_context_takeover;
+        self.decompress_reset = agreed.client_no_context_takeover;
+        if let Some(window_bits) = agreed.server_max_window_bits {
+            if window_bits < self.settings.max_window_bits {
+                self.com_window_bits = window_bits as i8;
+            }
+        }
+        if let Some(window_bits) = agreed.client_max_window_bits {
+            if window_bits < self.settings.max_window_bits {
+                self.dec_window_bits = window_bits as i8;
+            }
+        }
+        res.add_extension(&agreed.to_string());
+        self.negotiated = Some(agreed);
+        res
+    }
+
+    // Contexts are created, or taken from the pool, once the first message needs them.
+    fn compressor(&mut self) -> Result<&mut Compressor> {
+        if self.com.is_none() {
+            let mut com = match self.pool {
+                Some(ref pool) => pool.take_compressor(self.com_window_bits),
+                None => Compressor::new(self.com_window_bits),
+            };
+            // pooled contexts may have been primed by a handler with other settings
+            com.set_dictionary(self.settings.preset_dictionary)?;
+            self.com = Some(com);
+        }
+        // it's safe to unwrap because of the above check for none
+        Ok(self.com.as_mut().unwrap())
+    }
+
+    fn decompressor(&mut self) -> Result<&mut Decompressor> {
+        if self.dec.is_none() {
+            let mut dec = match self.pool {
+                Some(ref pool) => pool.take_decompressor(self.dec_window_bits),
+                None => Decompressor::new(self.dec_window_bits),
+            };
+            dec.set_dictionary(self.settings.preset_dictionary)?;
+            self.dec = Some(dec);
+        }
+        // it's safe to unwrap because of the above check for none
+        Ok(self.dec.as_mut().unwrap())
+    }
+
+    // Reset the compressor after a message, giving it back to the pool if there is one.
+    fn release_compressor(&mut self) -> Result<()> {
+        match (&self.pool, self.com.take()) {
+            (&Some(ref pool), Some(com)) => pool.give_compressor(self.com_window_bits, com),
+            (&None, Some(mut com)) => {
+                com.reset()?;
+                self.com = Some(com);
+            }
+            (_, None) => (),
+        }
+        Ok(())
+    }
+
+    fn release_decompressor(&mut self) -> Result<()> {
+        match (&self.pool, self.dec.take()) {
+            (&Some(ref pool), Some(dec)) => pool.give_decompressor(self.dec_window_bits, dec),
+            (&None, Some(mut dec)) => {
+                dec.reset()?;
+                self.dec = Some(dec);
+            }
+            (_, None) => (),
+        }
+        Ok(())
+    }
+
+    // Whether earlier frames are still with the worker, so that later ones must follow them.
+    fn offloading(&self) -> bool {
+        self.offload
+            .as_ref()
+            .is_some_and(|offload| !offload.pending.is_empty())
+    }
+
+    fn should_offload(&self, compressed: usize) -> bool {
+        self.offload.is_some()
+            && (self.offloading() || compressed >= self.settings.offload_threshold)
+    }
+
+    // Hand a job to the worker, starting it if it is idle.
+    fn submit(&mut self, job: Job) -> Result<()> {
+        let queue_size = self.settings.offload_queue_size.max(1);
+        // the decompressor only stays with this handler while nothing is offloaded
+        let dec = if self.offloading() {
+            None
+        } else {
+            self.decompressor()?;
+            self.dec.take()
+        };
+        let reset = self.decompress_reset;
+        if let Some(ref mut offload) = self.offload {
+            if offload.pending.len() >= queue_size {
+                return Err(Error::new(
+                    Kind::Capacity,
+                    "Too many received frames are waiting to be decompressed.",
+                ));
+            }
+            offload.pending.push_back(match job {
+                Job::Inflate(..) => true,
+                Job::Pass(_) => false,
+            });
+            let mut worker = offload.lock();
+            worker.jobs.push_back(job);
+            if !worker.busy {
+                // it's safe to unwrap because an idle worker holds the decompressor until all
+                // of its results are handed back
+                let dec = dec.or_else(|| worker.dec.take()).unwrap();
+                worker.busy = true;
+                drop(worker);
+                start_worker(offload, dec, reset)?;
+            } else {
+                drop(worker);
+            }
+            if !offload.suspended && offload.pending.len() >= (queue_size / 2).max(1) {
+                trace!("Suspending reads until offloaded frames are handed back.");
+                offload.suspended = true;
+            }
+        }
+        Ok(())
+    }
+
     #[doc(hidden)]
     #[inline]
     fn decline(&mut self, mut res: Response) -> Result<Response> {
@@ -137,9 +627,44 @@ impl<H: Handler> DeflateHandler<H> {
     }
 }
 
+impl<H: Handler> Drop for DeflateHandler<H> {
+    fn drop(&mut self) {
+        if let Some(ref pool) = self.pool {
+            if let Some(com) = self.com.take() {
+                pool.give_compressor(self.com_window_bits, com);
+            }
+            if let Some(dec) = self.dec.take() {
+                pool.give_decompressor(self.dec_window_bits, dec);
+            }
+            if let Some(ref offload) = self.offload {
+                if let Some(dec) = offload.lock().dec.take() {
+                    pool.give_decompressor(self.dec_window_bits, dec);
+                }
+            }
+        }
+    }
+}
+
 impl<H: Handler> Handler for DeflateHandler<H> {
     fn build_request(&mut self, url: &url::Url) -> Result<Request> {
         let mut req = self.inner.build_request(url)?;
+        if !self.offers.is_empty() {
+            for offer in &self.offers {
+                for window_bits in [offer.server_max_window_bits, offer.client_max_window_bits]
+                    .iter()
+                    .filter_map(|window_bits| *window_bits)
+                {
+                    if !(9..=15).contains(&window_bits) {
+                        return Err(Error::new(
+                            Kind::Internal,
+                            format!("Invalid window bits in permessage-deflate offer: {}", offer),
+                        ));
+                    }
+                }
+                req.add_extension(&offer.to_string());
+            }
+            return Ok(req);
+        }
         let mut req_ext = String::with_capacity(100);
         req_ext.push_str("permessage-deflate");
         if self.settings.max_window_bits < 15 {
@@ -158,264 +683,59 @@ impl<H: Handler> Handler for DeflateHandler<H> {
     }
 
     fn on_request(&mut self, req: &Request) -> Result<Response> {
-        let mut res = self.inner.on_request(req)?;
+        let res = self.inner.on_request(req)?;
 
-        'ext: for req_ext in req.extensions()?
+        // The client lists its offers in order of preference, so accept the first one that
+        // parses and that these settings can satisfy.
+        for req_ext in req.extensions()?
             .iter()
-            .filter(|&&ext| ext.contains("permessage-deflate"))
+            .filter(|&&ext| ext.split(';').next().map(str::trim) == Some("permessage-deflate"))
         {
-            let mut res_ext = String::with_capacity(req_ext.len());
-            let mut s_takeover = false;
-            let mut c_takeover = false;
-            let mut s_max = false;
-            let mut c_max = false;
-
-            for param in req_ext.split(';') {
-                match param.trim() {
-                    "permessage-deflate" => res_ext.push_str("permessage-deflate"),
-                    "server_no_context_takeover" => {
-                        if s_takeover {
-                            return self.decline(res);
-                        } else {
-                            s_takeover = true;
-                            if self.settings.accept_no_context_takeover {
-                                self.compress_reset = true;
-                                res_ext.push_str("; server_no_context_takeover");
-                            } else {
-                                continue 'ext;
-                            }
-                        }
-                    }
-                    "client_no_context_takeover" => {
-                        if c_takeover {
-                            return self.decline(res);
-                        } else {
-                            c_takeover = true;
-                            self.decompress_reset = true;
-                            res_ext.push_str("; client_no_context_takeover");
-                        }
-                    }
-                    param if param.starts_with("server_max_window_bits") => {
-                        if s_max {
-                            return self.decline(res);
-                        } else {
-                            s_max = true;
-                            let mut param_iter = param.split('=');
-                            param_iter.next(); // we already know the name
-                            if let Some(window_bits_str) = param_iter.next() {
-                                if let Ok(window_bits) = window_bits_str.trim().parse() {
-                                    if window_bits >= 9 && window_bits <= 15 {
-                                        if window_bits < self.settings.max_window_bits as i8 {
-                                            self.com = Compressor::new(window_bits);
-                                            res_ext.push_str("; ");
-                                            res_ext.push_str(param)
-                                        }
-                                    } else {
-                                        return self.decline(res);
-                                    }
-                                } else {
-                                    return self.decline(res);
-                                }
-                            }
-                        }
-                    }
-                    param if param.starts_with("client_max_window_bits") => {
-                        if c_max {
-                            return self.decline(res);
-                        } else {
-                            c_max = true;
-                            let mut param_iter = param.split('=');
-                            param_iter.next(); // we already know the name
-                            if let Some(window_bits_str) = param_iter.next() {
-                                if let Ok(window_bits) = window_bits_str.trim().parse() {
-                                    if window_bits >= 9 && window_bits <= 15 {
-                                        if window_bits < self.settings.max_window_bits as i8 {
-                                            self.dec = Decompressor::new(window_bits);
-                                            res_ext.push_str("; ");
-                                            res_ext.push_str(param);
-                                            continue;
-                                        }
-                                    } else {
-                                        return self.decline(res);
-                                    }
-                                } else {
-                                    return self.decline(res);
-                                }
-                            }
-                            res_ext.push_str("; ");
-                            res_ext.push_str(&format!(
-                                "client_max_window_bits={}",
-                                self.settings.max_window_bits
-                            ))
-                        }
-                    }
-                    _ => {
-                        // decline all extension offers because we got a bad parameter
-                        return self.decline(res);
-                    }
+            let offer = match DeflateOffer::parse(req_ext) {
+                Ok(offer) => offer,
+                Err(err) => {
+                    trace!("Skipping permessage-deflate offer {:?}: {}", req_ext, err);
+                    continue;
                 }
-            }
+            };
 
-            if !res_ext.contains("client_no_context_takeover")
-                && self.settings.request_no_context_takeover
-            {
-                self.decompress_reset = true;
-                res_ext.push_str("; client_no_context_takeover");
+            if let Some(agreed) = self.select(&offer) {
+                return Ok(self.accept(res, agreed));
             }
-
-            if !res_ext.contains("server_max_window_bits") {
-                res_ext.push_str("; ");
-                res_ext.push_str(&format!(
-                    "server_max_window_bits={}",
-                    self.settings.max_window_bits
-                ))
-            }
-
-            if !res_ext.contains("client_max_window_bits") && self.settings.max_window_bits < 15 {
-                continue;
-            }
-
-            res.add_extension(&res_ext);
-            return Ok(res);
+            trace!(
+                "Skipping unsatisfiable permessage-deflate offer {:?}",
+                req_ext
+            );
         }
         self.decline(res)
     }
 
     fn on_response(&mut self, res: &Response) -> Result<()> {
-        if let Some(res_ext) = res.extensions()?
-            .iter()
-            .find(|&&ext| ext.contains("permessage-deflate"))
-        {
-            let mut name = false;
-            let mut s_takeover = false;
-            let mut c_takeover = false;
-            let mut s_max = false;
-            let mut c_max = false;
-
-            for param in res_ext.split(';') {
-                match param.trim() {
-                    "permessage-deflate" => {
-                        if name {
-                            return Err(Error::new(
-                                Kind::Protocol,
-                                format!("Duplicate extension name permessage-deflate"),
-                            ));
-                        } else {
-                            name = true;
-                        }
-                    }
-                    "server_no_context_takeover" => {
-                        if s_takeover {
-                            return Err(Error::new(
-                                Kind::Protocol,
-                                format!("Duplicate extension parameter server_no_context_takeover"),
-                            ));
-                        } else {
-                            s_takeover = true;
-                            self.decompress_reset = true;
-                        }
-                    }
-                    "client_no_context_takeover" => {
-                        if c_takeover {
-                            return Err(Error::new(
-                                Kind::Protocol,
-                                format!("Duplicate extension parameter client_no_context_takeover"),
-                            ));
-                        } else {
-                            c_takeover = true;
-                            if self.settings.accept_no_context_takeover {
-                                self.compress_reset = true;
-                            } else {
-                                return Err(Error::new(
-                                    Kind::Protocol,
-                                    format!("The client requires context takeover."),
-                                ));
-                            }
-                        }
-                    }
-                    param if param.starts_with("server_max_window_bits") => {
-                        if s_max {
-                            return Err(Error::new(
-                                Kind::Protocol,
-                                format!("Duplicate extension parameter server_max_window_bits"),
-                            ));
-                        } else {
-                            s_max = true;
-                            let mut param_iter = param.split('=');
-                            param_iter.next(); // we already know the name
-                            if let Some(window_bits_str) = param_iter.next() {
-                                if let Ok(window_bits) = window_bits_str.trim().parse() {
-                                    if window_bits >= 9 && window_bits <= 15 {
-                                        if window_bits as u8 != self.settings.max_window_bits {
-                                            self.dec = Decompressor::new(window_bits);
-                                        }
-                                    } else {
-                                        return Err(Error::new(
-                                            Kind::Protocol,
-                                            format!(
-                                                "Invalid server_max_window_bits parameter: {}",
-                                                window_bits
-                                            ),
-                                        ));
-                                    }
-                                } else {
-                                    return Err(Error::new(
-                                        Kind::Protocol,
-                                        format!(
-                                            "Invalid server_max_window_bits parameter: {}",
-                                            window_bits_str
-                                        ),
-                                    ));
-                                }
-                            }
-                        }
-                    }
-                    param if param.starts_with("client_max_window_bits") => {
-                        if c_max {
-                            return Err(Error::new(
-                                Kind::Protocol,
-                                format!("Duplicate extension parameter client_max_window_bits"),
-                            ));
-                        } else {
-                            c_max = true;
-                            let mut param_iter = param.split('=');
-                            param_iter.next(); // we already know the name
-                            if let Some(window_bits_str) = param_iter.next() {
-                                if let Ok(window_bits) = window_bits_str.trim().parse() {
-                                    if window_bits >= 9 && window_bits <= 15 {
-                                        if window_bits as u8 != self.settings.max_window_bits {
-                                            self.com = Compressor::new(window_bits);
-                                        }
-                                    } else {
-                                        return Err(Error::new(
-                                            Kind::Protocol,
-                                            format!(
-                                                "Invalid client_max_window_bits parameter: {}",
-                                                window_bits
-                                            ),
-                                        ));
-                                    }
-                                } else {
-                                    return Err(Error::new(
-                                        Kind::Protocol,
-                                        format!(
-                                            "Invalid client_max_window_bits parameter: {}",
-                                            window_bits_str
-                                        ),
-                                    ));
-                                }
-                            }
-                        }
-                    }
-                    param => {
-                        // fail the connection because we got a bad parameter
-                        return Err(Error::new(
-                            Kind::Protocol,
-                            format!("Bad extension parameter: {}", param),
-                        ));
-                    }
+        if let Some(offer) = DeflateOffer::from_response(res)? {
+            if offer.server_no_context_takeover {
+                self.decompress_reset = true;
+            }
+            if offer.client_no_context_takeover {
+                if self.settings.accept_no_context_takeover {
+                    self.compress_reset = true;
+                } else {
+                    return Err(Error::new(
+                        Kind::Protocol,
+                        format!("The client requires context takeover."),
+                    ));
+                }
+            }
+            if let Some(window_bits) = offer.server_max_window_bits {
+                if window_bits != self.settings.max_window_bits {
+                    self.dec_window_bits = window_bits as i8;
+                }
+            }
+            if let Some(window_bits) = offer.client_max_window_bits {
+                if window_bits != self.settings.max_window_bits {
+                    self.com_window_bits = window_bits as i8;
                 }
             }
+            self.negotiated = Some(offer);
         } else {
             self.pass = true
         }
@@ -425,6 +745,11 @@ impl<H: Handler> Handler for DeflateHandler<H> {
 
     fn on_frame(&mut self, mut frame: Frame) -> Result<Option<Frame>> {
         if !self.pass && !frame.is_control() {
+            let offloading = self.offloading();
+            if frame.opcode() != OpCode::Continue && !offloading {
+                // only the first frame of a message marks whether it is compressed
+                self.last_compressed = frame.has_rsv1();
+            }
             if !self.fragments.is_empty() || frame.has_rsv1() {
                 frame.set_rsv1(false);
 
@@ -432,7 +757,7 @@ impl<H: Handler> Handler for DeflateHandler<H> {
                     self.fragments.push(frame);
                     return Ok(None);
                 } else {
-                    if frame.opcode() == OpCode::Continue {
+                    let (compressed, opcode) = if frame.opcode() == OpCode::Continue {
                         if self.fragments.is_empty() {
                             return Err(Error::new(
                                 Kind::Protocol,
@@ -452,37 +777,80 @@ impl<H: Handler> Handler for DeflateHandler<H> {
                             let size = self.fragments
                                 .iter()
                                 .fold(0, |len, frame| len + frame.payload().len());
-                            let mut compressed = Vec::with_capacity(size);
-                            let mut decompressed = Vec::with_capacity(size * 2);
+                            let mut compressed = Vec::with_capacity(size + 4);
                             for frag in replace(
                                 &mut self.fragments,
                                 Vec::with_capacity(self.settings.fragments_capacity),
                             ) {
                                 compressed.extend(frag.into_data())
                             }
-
-                            compressed.extend(&[0, 0, 255, 255]);
-                            self.dec.decompress(&compressed, &mut decompressed)?;
-                            frame = Frame::message(decompressed, opcode, true);
+                            (compressed, opcode)
                         }
                     } else {
-                        let mut decompressed = Vec::with_capacity(frame.payload().len() * 2);
-                        frame.payload_mut().extend(&[0, 0, 255, 255]);
+                        let opcode = frame.opcode();
+                        (frame.into_data(), opcode)
+                    };
+                    let mut compressed = compressed;
+                    compressed.extend(&[0, 0, 255, 255]);
 
-                        self.dec.decompress(frame.payload(), &mut decompressed)?;
-
-                        *frame.payload_mut() = decompressed;
+                    if self.should_offload(compressed.len()) {
+                        self.submit(Job::Inflate(compressed, opcode))?;
+                        return Ok(None);
                     }
 
+                    let mut decompressed = Vec::with_capacity(compressed.len() * 2);
+                    self.decompressor()?.decompress(&compressed, &mut decompressed)?;
+                    frame = Frame::message(decompressed, opcode, true);
+
                     if self.decompress_reset {
-                        self.dec.reset()?
+                        self.release_decompressor()?
                     }
                 }
+            } else if offloading {
+                self.submit(Job::Pass(frame))?;
+                return Ok(None);
             }
         }
         self.inner.on_frame(frame)
     }
 
+    fn on_injected_frame(&mut self, frame: Frame) -> Result<Option<Frame>> {
+        let (inflated, error, dec) = match self.offload {
+            Some(ref mut offload) => match offload.pending.pop_front() {
+                Some(inflated) => {
+                    let mut worker = offload.lock();
+                    let dec = if offload.pending.is_empty() {
+                        worker.dec.take()
+                    } else {
+                        None
+                    };
+                    let error = worker.error.take();
+                    drop(worker);
+                    if offload.suspended && offload.pending.is_empty() {
+                        offload.out.resume_read()?;
+                        offload.suspended = false;
+                    }
+                    (inflated, error, dec)
+                }
+                None => return self.inner.on_injected_frame(frame),
+            },
+            None => return self.inner.on_injected_frame(frame),
+        };
+        if let Some(err) = error {
+            return Err(err);
+        }
+        if dec.is_some() {
+            self.dec = dec;
+            if self.decompress_reset {
+                self.release_decompressor()?
+            }
+        }
+        if frame.opcode() != OpCode::Continue {
+            self.last_compressed = inflated;
+        }
+        self.inner.on_frame(frame)
+    }
+
     fn on_send_frame(&mut self, frame: Frame) -> Result<Option<Frame>> {
         if let Some(mut frame) = self.inner.on_send_frame(frame)? {
             if !self.pass && !frame.is_control() {
@@ -497,13 +865,13 @@ impl<H: Handler> Handler for DeflateHandler<H> {
 
                 frame.set_rsv1(true);
                 let mut compressed = Vec::with_capacity(frame.payload().len());
-                self.com.compress(frame.payload(), &mut compressed)?;
+                self.compressor()?.compress(frame.payload(), &mut compressed)?;
                 let len = compressed.len();
                 compressed.truncate(len - 4);
                 *frame.payload_mut() = compressed;
 
                 if self.compress_reset {
-                    self.com.reset()?
+                    self.release_compressor()?
                 }
             }
             Ok(Some(frame))
@@ -527,16 +895,118 @@ impl<H: Handler> Handler for DeflateHandler<H> {
         self.inner.on_message(msg)
     }
 
+    #[inline]
+    fn on_message_with_meta(&mut self, msg: Message, mut info: MessageInfo) -> Result<()> {
+        info.compressed = self.last_compressed;
+        self.inner.on_message_with_meta(msg, info)
+    }
+
+    #[inline]
+    fn on_text_borrowed(&mut self, text: &str) -> Result<()> {
+        self.inner.on_text_borrowed(text)
+    }
+
+    #[inline]
+    fn borrows_text(&self) -> bool {
+        self.inner.borrows_text()
+    }
+
+    #[inline]
+    fn should_suspend_read(&self) -> bool {
+        self.offload
+            .as_ref()
+            .is_some_and(|offload| offload.suspended)
+            || self.inner.should_suspend_read()
+    }
+
     #[inline]
     fn on_close(&mut self, code: CloseCode, reason: &str) {
         self.inner.on_close(code, reason)
     }
 
+    fn on_close_raw(&mut self, code: CloseCode, reason: &[u8]) {
+        self.inner.on_close_raw(code, reason)
+    }
+
+    fn on_socket_error(&mut self, err: io::Error) {
+        self.inner.on_socket_error(err)
+    }
+
+    fn on_ack(&mut self, token: AckToken) {
+        self.inner.on_ack(token)
+    }
+
+    fn on_ack_timeout(&mut self, token: AckToken) {
+        self.inner.on_ack_timeout(token)
+    }
+
+    #[inline]
+    fn on_eof(&mut self) -> Result<()> {
+        self.inner.on_eof()
+    }
+
     #[inline]
     fn on_error(&mut self, err: Error) {
         self.inner.on_error(err)
     }
 
+    #[inline]
+    fn on_error_event(&mut self, event: ErrorEvent) {
+        self.inner.on_error_event(event)
+    }
+
+    #[inline]
+    fn on_fragment(
+        &mut self,
+        frame: &Frame,
+        index: usize,
+        accumulated_bytes: usize,
+        is_last: bool,
+    ) -> Result<()> {
+        self.inner
+            .on_fragment(frame, index, accumulated_bytes, is_last)
+    }
+
+    #[inline]
+    fn on_incomplete_message(&mut self, fragments: Vec<Frame>) {
+        self.inner.on_incomplete_message(fragments)
+    }
+
+    #[inline]
+    fn on_after_close_frame(&mut self, frame: Frame) -> Result<()> {
+        self.inner.on_after_close_frame(frame)
+    }
+
+    #[inline]
+    fn on_message_written(&mut self, meta: MessageMeta) {
+        self.inner.on_message_written(meta)
+    }
+
+    #[inline]
+    fn on_wake(&mut self) -> Result<()> {
+        self.inner.on_wake()
+    }
+
+    #[inline]
+    fn accept_close_code(&mut self, code: CloseCode) -> bool {
+        self.inner.accept_close_code(code)
+    }
+
+    #[inline]
+    fn close_code_for_error(&mut self, err: &Error) -> CloseCode {
+        self.inner.close_code_for_error(err)
+    }
+
+    #[inline]
+    fn close_reason_for_error(&mut self, err: &Error) -> String {
+        self.inner.close_reason_for_error(err)
+    }
+
+    #[inline]
+    fn on_connect_result(&mut self, user_token: Token, result: Result<Token>) -> Result<()> {
+        self.inner.on_connect_result(user_token, result)
+    }
+
     #[inline]
     fn on_timeout(&mut self, event: Token) -> Result<()> {
         self.inner.on_timeout(event)
@@ -557,9 +1027,31 @@ impl<H: Handler> Handler for DeflateHandler<H> {
         self.inner.upgrade_ssl_client(stream, url)
     }
 
+    #[inline]
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn upgrade_ssl_client_with_options(
+        &mut self,
+        stream: TcpStream,
+        url: &url::Url,
+        options: &TlsClientOptions,
+    ) -> Result<SslStream<TcpStream>> {
+        self.inner
+            .upgrade_ssl_client_with_options(stream, url, options)
+    }
+
     #[inline]
     #[cfg(any(feature = "ssl", feature = "nativetls"))]
     fn upgrade_ssl_server(&mut self, stream: TcpStream) -> Result<SslStream<TcpStream>> {
         self.inner.upgrade_ssl_server(stream)
     }
+
+    #[inline]
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn upgrade_ssl_server_for(
+        &mut self,
+        stream: TcpStream,
+        server_name: Option<&str>,
+    ) -> Result<SslStream<TcpStream>> {
+        self.inner.upgrade_ssl_server_for(stream, server_name)
+    }
 }
@@ -5,5 +5,15 @@ extern crate libz_sys as ffi;
 
 mod context;
 mod extension;
+mod pool;
 
-pub use self::extension::{DeflateBuilder, DeflateHandler, DeflateSettings};
+pub use self::extension::{DeflateBuilder, DeflateHandler, DeflateOffer, DeflateSettings};
+pub use self::pool::{DeflatePool, InflatePool};
+
+use result::Result;
+
+/// Decompress a complete or partial gzip encoded buffer, such as an HTTP body sent with
+/// `Content-Encoding: gzip`, keeping at most `limit` bytes of the output.
+pub fn gunzip(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    context::Decompressor::gzip().decompress_limited(data, limit)
+}
@@ -0,0 +1,305 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use result::Result;
+
+use super::context::{Compressor, Decompressor};
+
+struct Contexts {
+    capacity: usize,
+    compressors: HashMap<i8, Vec<Compressor>>,
+    decompressors: HashMap<i8, Vec<Decompressor>>,
+}
+
+/// A pool of deflate contexts shared by the `DeflateHandler`s built from the same
+/// `DeflateBuilder`.
+///
+/// A handler using a pool only takes a context from it once the first message that needs one is
+/// sent or received. When the permessage-deflate parameters require a context to be reset after
+/// each message, the handler gives it back as soon as the message is done, so idle connections
+/// hold no contexts at all. Other contexts are given back when the handler is dropped. Servers
+/// with many mostly idle compressed connections should consider asking clients for
+/// `client_no_context_takeover` with `DeflateSettings::request_no_context_takeover`.
+///
+/// Contexts are keyed by their window size, so a handler is only ever handed a context that
+/// matches the negotiated parameters.
+#[derive(Clone)]
+pub struct DeflatePool {
+    contexts: Arc<Mutex<Contexts>>,
+}
+
+impl DeflatePool {
+    /// Create a pool that keeps up to `capacity` idle compressors and `capacity` idle
+    /// decompressors.
+    pub fn new(capacity: usize) -> DeflatePool {
+        DeflatePool {
+            contexts: Arc::new(Mutex::new(Contexts {
+                capacity,
+                compressors: HashMap::new(),
+                decompressors: HashMap::new(),
+            })),
+        }
+    }
+
+    /// The number of idle contexts, compressors and decompressors alike, held by the pool.
+    pub fn len(&self) -> usize {
+        let contexts = self.lock();
+        count(&contexts.compressors) + count(&contexts.decompressors)
+    }
+
+    /// Whether the pool holds no idle contexts.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[doc(hidden)]
+    pub fn take_compressor(&self, window_bits: i8) -> Compressor {
+        self.lock()
+            .compressors
+            .get_mut(&window_bits)
+            .and_then(|pooled| pooled.pop())
+            .unwrap_or_else(|| Compressor::new(window_bits))
+    }
+
+    #[doc(hidden)]
+    pub fn take_decompressor(&self, window_bits: i8) -> Decompressor {
+        self.lock()
+            .decompressors
+            .get_mut(&window_bits)
+            .and_then(|pooled| pooled.pop())
+            .unwrap_or_else(|| Decompressor::new(window_bits))
+    }
+
+    #[doc(hidden)]
+    pub fn give_compressor(&self, window_bits: i8, mut com: Compressor) {
+        let mut contexts = self.lock();
+        if count(&contexts.compressors) < contexts.capacity && com.reset().is_ok() {
+            contexts
+                .compressors
+                .entry(window_bits)
+                .or_default()
+                .push(com);
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn give_decompressor(&self, window_bits: i8, mut dec: Decompressor) {
+        let mut contexts = self.lock();
+        if count(&contexts.decompressors) < contexts.capacity && dec.reset().is_ok() {
+            contexts
+                .decompressors
+                .entry(window_bits)
+                .or_default()
+                .push(dec);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Contexts> {
+        self.contexts.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl fmt::Debug for DeflatePool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DeflatePool {{ idle: {} }}", self.len())
+    }
+}
+
+type Task = Box<dyn FnOnce() + Send>;
+
+struct Tasks {
+    queue: VecDeque<Task>,
+    threads: usize,
+    idle: usize,
+    closed: bool,
+}
+
+struct Threads {
+    max: usize,
+    tasks: Mutex<Tasks>,
+    ready: Condvar,
+}
+
+impl Threads {
+    fn lock(&self) -> MutexGuard<'_, Tasks> {
+        self.tasks.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+// Tells the threads to stop once the last handle to the pool is gone.
+struct Handle {
+    threads: Arc<Threads>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.threads.lock().closed = true;
+        self.threads.ready.notify_all();
+    }
+}
+
+static SHARED_INFLATE_POOL: Mutex<Option<InflatePool>> = Mutex::new(None);
+
+/// A bounded set of threads that decompress large messages for the `DeflateHandler`s that
+/// offload them, see `DeflateSettings::offload_threshold`.
+///
+/// Threads are started as they are needed, up to the number given to `InflatePool::new`. A
+/// connection hands its messages to one thread at a time so that they are decompressed in
+/// order, and the thread moves on to other connections once that connection has nothing left to
+/// decompress. Handlers built without `DeflateBuilder::with_inflate_pool` share a pool of
+/// `InflatePool::DEFAULT_THREADS` threads.
+#[derive(Clone)]
+pub struct InflatePool {
+    handle: Arc<Handle>,
+}
+
+impl InflatePool {
+    /// The number of threads of the pool shared by default.
+    pub const DEFAULT_THREADS: usize = 2;
+
+    /// Create a pool that runs at most `threads` threads, and at least one.
+    pub fn new(threads: usize) -> InflatePool {
+        InflatePool {
+            handle: Arc::new(Handle {
+                threads: Arc::new(Threads {
+                    max: threads.max(1),
+                    tasks: Mutex::new(Tasks {
+                        queue: VecDeque::new(),
+                        threads: 0,
+                        idle: 0,
+                        closed: false,
+                    }),
+                    ready: Condvar::new(),
+                }),
+            }),
+        }
+    }
+
+    /// The pool used by handlers that were not given one.
+    pub fn shared() -> InflatePool {
+        SHARED_INFLATE_POOL
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get_or_insert_with(|| InflatePool::new(InflatePool::DEFAULT_THREADS))
+            .clone()
+    }
+
+    /// The number of threads that have been started.
+    pub fn threads(&self) -> usize {
+        self.handle.threads.lock().threads
+    }
+
+    #[doc(hidden)]
+    pub fn execute<T>(&self, task: T) -> Result<()>
+    where
+        T: FnOnce() + Send + 'static,
+    {
+        let threads = &self.handle.threads;
+        let mut tasks = threads.lock();
+        tasks.queue.push_back(Box::new(task));
+        if tasks.idle > 0 {
+            threads.ready.notify_one();
+        } else if tasks.threads < threads.max {
+            let runner = threads.clone();
+            thread::Builder::new()
+                .name("ws-inflate".into())
+                .spawn(move || run(&runner))?;
+            tasks.threads += 1;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for InflatePool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "InflatePool {{ threads: {}, max: {} }}",
+            self.threads(),
+            self.handle.threads.max
+        )
+    }
+}
+
+fn run(threads: &Threads) {
+    loop {
+        let task = {
+            let mut tasks = threads.lock();
+            loop {
+                if let Some(task) = tasks.queue.pop_front() {
+                    break task;
+                }
+                if tasks.closed {
+                    tasks.threads -= 1;
+                    return;
+                }
+                tasks.idle += 1;
+                tasks = threads
+                    .ready
+                    .wait(tasks)
+                    .unwrap_or_else(|err| err.into_inner());
+                tasks.idle -= 1;
+            }
+        };
+        task();
+    }
+}
+
+fn count<T>(pooled: &HashMap<i8, Vec<T>>) -> usize {
+    pooled.values().map(Vec::len).sum()
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+
+    #[test]
+    fn recycle() {
+        let pool = DeflatePool::new(1);
+        let com = pool.take_compressor(15);
+        let dec = pool.take_decompressor(15);
+        assert!(pool.is_empty());
+
+        pool.give_compressor(15, com);
+        pool.give_decompressor(15, dec);
+        assert_eq!(pool.len(), 2);
+
+        pool.take_compressor(15);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn keyed_by_window_bits() {
+        let pool = DeflatePool::new(1);
+        pool.give_compressor(15, Compressor::new(15));
+
+        pool.take_compressor(9);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn inflate_threads() {
+        use std::sync::mpsc;
+
+        let pool = InflatePool::new(2);
+        let (tx, rx) = mpsc::channel();
+        for i in 0..8 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap()).unwrap();
+        }
+        let mut done = rx.iter().take(8).collect::<Vec<_>>();
+        done.sort();
+        assert_eq!(done, (0..8).collect::<Vec<_>>());
+        assert!(pool.threads() <= 2);
+    }
+
+    #[test]
+    fn bounded() {
+        let pool = DeflatePool::new(1);
+        pool.give_decompressor(15, Decompressor::new(15));
+        pool.give_decompressor(9, Decompressor::new(9));
+        assert_eq!(pool.len(), 1);
+    }
+}
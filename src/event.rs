@@ -0,0 +1,82 @@
+use std::net::SocketAddr;
+
+use mio::Token;
+
+use result::Error;
+
+/// A structured notification about the lifecycle of connections on a WebSocket.
+///
+/// Events are delivered to every receiver obtained from `WebSocket::subscribe_events`. They are
+/// intended for observers such as dashboards or supervisors that need to watch the WebSocket
+/// without wrapping every handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsEvent {
+    /// A new TCP connection was accepted by the listener.
+    Accepted {
+        /// The token assigned to the connection.
+        token: Token,
+        /// The address of the other endpoint.
+        peer_addr: SocketAddr,
+    },
+    /// The opening handshake completed and the connection is open.
+    HandshakeComplete {
+        /// The token of the connection.
+        token: Token,
+        /// The address of the other endpoint, if it is still available.
+        peer_addr: Option<SocketAddr>,
+    },
+    /// The connection was removed from the WebSocket.
+    Closed {
+        /// The token of the connection.
+        token: Token,
+        /// The address of the other endpoint, if it is still available.
+        peer_addr: Option<SocketAddr>,
+    },
+    /// An error was encountered while accepting, establishing or servicing a connection.
+    Error {
+        /// The token of the connection, if the error is associated with one.
+        token: Option<Token>,
+        /// The address of the other endpoint, if known.
+        peer_addr: Option<SocketAddr>,
+        /// A description of the error.
+        details: String,
+    },
+}
+
+/// The part of servicing a connection during which an error was encountered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorPhase {
+    /// Performing the opening handshake, including TLS negotiation and any proxy tunnel.
+    Handshake,
+    /// Reading from the socket or handling the frames that were read.
+    Read,
+    /// Writing to the socket.
+    Write,
+    /// Scheduling or handling a timeout.
+    Timer,
+    /// Handling a command sent through a `Sender`, such as sending a message or closing.
+    Command,
+}
+
+/// Which way the data involved in an error was travelling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Data received from the other endpoint.
+    Inbound,
+    /// Data sent to the other endpoint.
+    Outbound,
+}
+
+/// An error together with where it was encountered, delivered to `Handler::on_error_event`.
+#[derive(Debug)]
+pub struct ErrorEvent {
+    /// The part of servicing the connection during which the error was encountered. Any error
+    /// encountered before the connection opens is attributed to the handshake.
+    pub phase: ErrorPhase,
+    /// Which way the data involved in the error was travelling, if any was.
+    pub direction: Option<Direction>,
+    /// The token of the connection.
+    pub token: Token,
+    /// The error itself.
+    pub error: Error,
+}
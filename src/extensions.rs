@@ -0,0 +1,354 @@
+//! The extensions module parses and formats the value of the `Sec-WebSocket-Extensions` header,
+//! as described by section 9.1 of RFC 6455, so that applications implementing their own
+//! extensions interpret offers and parameters the same way as their peers.
+//!
+//! ```
+//! use ws::extensions::{format_header, parse_header, Offer};
+//!
+//! let offers = parse_header("x-custom; level=\"3\", permessage-deflate").unwrap();
+//! assert_eq!(offers[0].name, "x-custom");
+//! assert_eq!(offers[0].param("level"), Some(Some("3")));
+//!
+//! let mut offer = Offer::new("x-custom");
+//! offer.add_param("level", Some("3"));
+//! assert_eq!(format_header(&[offer]).unwrap(), "x-custom; level=3");
+//! ```
+
+use result::{Error, Kind, Result};
+
+/// A parameter of an extension offer, with a value unless it is given by its name alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    /// The name of the parameter.
+    pub name: String,
+    /// The value of the parameter, with any quoting removed.
+    pub value: Option<String>,
+}
+
+impl Param {
+    /// Create a parameter with a name and an optional value.
+    pub fn new<N, V>(name: N, value: Option<V>) -> Param
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        Param {
+            name: name.into(),
+            value: value.map(Into::into),
+        }
+    }
+}
+
+/// An extension listed in a `Sec-WebSocket-Extensions` header, either offered by a client or
+/// accepted by a server, along with its parameters in the order that they were given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Offer {
+    /// The name of the extension.
+    pub name: String,
+    /// The parameters of the extension.
+    pub params: Vec<Param>,
+}
+
+impl Offer {
+    /// Create an offer for an extension without parameters.
+    pub fn new<N: Into<String>>(name: N) -> Offer {
+        Offer {
+            name: name.into(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Add a parameter to this offer.
+    pub fn add_param<N, V>(&mut self, name: N, value: Option<V>) -> &mut Offer
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.params.push(Param::new(name, value));
+        self
+    }
+
+    /// Get the value of the first parameter with this name. The outer option is `None` when the
+    /// parameter is absent and the inner option is `None` when it is given without a value.
+    pub fn param(&self, name: &str) -> Option<Option<&str>> {
+        self.params
+            .iter()
+            .find(|param| param.name.eq_ignore_ascii_case(name))
+            .map(|param| param.value.as_deref())
+    }
+}
+
+/// Parse the value of a `Sec-WebSocket-Extensions` header into the offers that it lists.
+///
+/// Parameter values may be tokens or quoted strings, and commas and semicolons within quoted
+/// strings do not separate offers or parameters. As required by RFC 6455, a quoted value must
+/// still be a token once unescaped. A malformed value fails with an error of kind `Protocol`.
+pub fn parse_header(value: &str) -> Result<Vec<Offer>> {
+    let mut parser = Parser { value, pos: 0 };
+    let mut offers = Vec::new();
+    loop {
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => return Ok(offers),
+            // empty list elements are allowed and ignored
+            Some(b',') => {
+                parser.pos += 1;
+                continue;
+            }
+            Some(_) => (),
+        }
+        let mut offer = Offer::new(parser.token("extension name")?);
+        loop {
+            parser.skip_whitespace();
+            match parser.peek() {
+                None => break,
+                Some(b',') => {
+                    parser.pos += 1;
+                    break;
+                }
+                Some(b';') => {
+                    parser.pos += 1;
+                    parser.skip_whitespace();
+                    let name = parser.token("parameter name")?;
+                    parser.skip_whitespace();
+                    let value = if parser.peek() == Some(b'=') {
+                        parser.pos += 1;
+                        parser.skip_whitespace();
+                        Some(parser.value()?)
+                    } else {
+                        None
+                    };
+                    offer.params.push(Param { name, value });
+                }
+                Some(_) => return Err(parser.unexpected()),
+            }
+        }
+        offers.push(offer);
+    }
+}
+
+/// Format offers as the value of a `Sec-WebSocket-Extensions` header.
+///
+/// Names and values are written as tokens, since RFC 6455 does not allow any other values even
+/// when quoted. An offer with an empty or invalid name or value fails with an error of kind
+/// `Internal`.
+pub fn format_header(offers: &[Offer]) -> Result<String> {
+    let mut header = String::new();
+    for (i, offer) in offers.iter().enumerate() {
+        if i > 0 {
+            header.push_str(", ");
+        }
+        push_token(&mut header, &offer.name)?;
+        for param in &offer.params {
+            header.push_str("; ");
+            push_token(&mut header, &param.name)?;
+            if let Some(ref value) = param.value {
+                header.push('=');
+                push_token(&mut header, value)?;
+            }
+        }
+    }
+    Ok(header)
+}
+
+/// Split the value of a `Sec-WebSocket-Extensions` header into the trimmed text of each offer,
+/// ignoring commas within quoted strings.
+#[doc(hidden)]
+pub fn split_header(value: &str) -> Vec<&str> {
+    let mut offers = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, b) in value.bytes().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if quoted {
+            match b {
+                b'\\' => escaped = true,
+                b'"' => quoted = false,
+                _ => (),
+            }
+        } else if b == b'"' {
+            quoted = true;
+        } else if b == b',' {
+            offers.push(value[start..i].trim());
+            start = i + 1;
+        }
+    }
+    offers.push(value[start..].trim());
+    offers
+}
+
+/// Whether this string is a token as defined by RFC 7230, and so may be used as an extension
+/// name, parameter name or parameter value.
+pub fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(is_tchar)
+}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn push_token(header: &mut String, token: &str) -> Result<()> {
+    if !is_token(token) {
+        return Err(Error::new(
+            Kind::Internal,
+            format!("Invalid token {:?} in Sec-WebSocket-Extensions header.", token),
+        ));
+    }
+    header.push_str(token);
+    Ok(())
+}
+
+struct Parser<'a> {
+    value: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.value.as_bytes().get(self.pos).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn unexpected(&self) -> Error {
+        let found = match self.value[self.pos..].chars().next() {
+            Some(c) => format!("{:?}", c),
+            None => "the end".into(),
+        };
+        Error::new(
+            Kind::Protocol,
+            format!(
+                "Unexpected {} at position {} of Sec-WebSocket-Extensions header {:?}.",
+                found, self.pos, self.value
+            ),
+        )
+    }
+
+    fn token(&mut self, what: &str) -> Result<String> {
+        let start = self.pos;
+        while self.peek().map_or(false, is_tchar) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(Error::new(
+                Kind::Protocol,
+                format!(
+                    "Expected {} at position {} of Sec-WebSocket-Extensions header {:?}.",
+                    what, start, self.value
+                ),
+            ));
+        }
+        Ok(self.value[start..self.pos].into())
+    }
+
+    fn value(&mut self) -> Result<String> {
+        if self.peek() != Some(b'"') {
+            return self.token("parameter value");
+        }
+        let start = self.pos;
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.unexpected()),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b) if b.is_ascii() => {
+                            value.push(b as char);
+                            self.pos += 1;
+                        }
+                        _ => return Err(self.unexpected()),
+                    }
+                }
+                Some(b) if b.is_ascii() => {
+                    value.push(b as char);
+                    self.pos += 1;
+                }
+                Some(_) => return Err(self.unexpected()),
+            }
+        }
+        if !is_token(&value) {
+            return Err(Error::new(
+                Kind::Protocol,
+                format!(
+                    "The quoted value at position {} of Sec-WebSocket-Extensions header {:?} is \
+                     not a token.",
+                    start, self.value
+                ),
+            ));
+        }
+        Ok(value)
+    }
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+
+    #[test]
+    fn parse_offers() {
+        let offers = parse_header(
+            "permessage-deflate; client_max_window_bits, \
+             permessage-deflate ;server_no_context_takeover; client_max_window_bits = \"10\",,x-y",
+        )
+        .unwrap();
+        assert_eq!(offers.len(), 3);
+        assert_eq!(offers[0].name, "permessage-deflate");
+        assert_eq!(offers[0].param("client_max_window_bits"), Some(None));
+        assert_eq!(offers[1].param("server_no_context_takeover"), Some(None));
+        assert_eq!(offers[1].param("client_max_window_bits"), Some(Some("10")));
+        assert_eq!(offers[1].param("server_max_window_bits"), None);
+        assert_eq!(offers[2], Offer::new("x-y"));
+        assert_eq!(parse_header("").unwrap(), Vec::new());
+        assert_eq!(parse_header("a; b=\"\\c\"").unwrap()[0].param("b"), Some(Some("c")));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse_header("a b").is_err());
+        assert!(parse_header("; a").is_err());
+        assert!(parse_header("a; =1").is_err());
+        assert!(parse_header("a; b=").is_err());
+        assert!(parse_header("a; b=\"1").is_err());
+        // quoted values must be tokens once unescaped
+        assert!(parse_header("a; b=\"1,2\"").is_err());
+        assert!(parse_header("a; b=\"\"").is_err());
+    }
+
+    #[test]
+    fn format_offers() {
+        let mut first = Offer::new("permessage-deflate");
+        first
+            .add_param("client_max_window_bits", None::<String>)
+            .add_param("server_max_window_bits", Some("10"));
+        let offers = vec![first, Offer::new("x-y")];
+        let header = format_header(&offers).unwrap();
+        assert_eq!(
+            header,
+            "permessage-deflate; client_max_window_bits; server_max_window_bits=10, x-y"
+        );
+        assert_eq!(parse_header(&header).unwrap(), offers);
+
+        let mut invalid = Offer::new("a");
+        invalid.add_param("b", Some("1 2"));
+        assert!(format_header(&[invalid]).is_err());
+        assert!(format_header(&[Offer::new("")]).is_err());
+    }
+
+    #[test]
+    fn split_offers() {
+        assert_eq!(split_header("a; b=\"1\", c"), vec!["a; b=\"1\"", "c"]);
+        assert_eq!(split_header("a; b=\"1,\\\"\" ,c"), vec!["a; b=\"1,\\\"\"", "c"]);
+    }
+}
@@ -1,7 +1,17 @@
+use std::net::SocketAddr;
+
+use mio::Token;
+
+use super::Settings;
 use communication::Sender;
 use handler::Handler;
+use result::Error;
 
 /// A trait for creating new WebSocket handlers.
+///
+/// Factories and handlers are only ever used on the thread running the event loop, so they are
+/// not required to be `Send`. Use `Builder::spawn` to create a factory on the event loop thread
+/// when the WebSocket should run in the background.
 pub trait Factory {
     type Handler: Handler;
 
@@ -91,6 +101,52 @@ pub trait Factory {
         self.connection_made(ws)
     }
 
+    /// Called before a TCP connection is set up to choose the settings that it will use. The
+    /// address is that of the peer for server connections and of the server being connected to
+    /// for client connections. The default implementation returns the WebSocket's settings.
+    ///
+    /// Settings that govern the event loop as a whole, such as `max_connections`, `queue_size`
+    /// and `batch_writes`, are always taken from the WebSocket's settings. Connections over Unix
+    /// domain sockets have no address and also use the WebSocket's settings.
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use ws::{Factory, Handler, Sender, Settings};
+    ///
+    /// struct MyHandler {
+    ///     ws: Sender,
+    /// }
+    ///
+    /// impl Handler for MyHandler {}
+    ///
+    /// struct MyFactory;
+    ///
+    /// impl Factory for MyFactory {
+    ///     type Handler = MyHandler;
+    ///
+    ///     fn connection_made(&mut self, ws: Sender) -> MyHandler {
+    ///         MyHandler { ws: ws }
+    ///     }
+    ///
+    ///     fn settings_for(&mut self, addr: &SocketAddr, default: Settings) -> Settings {
+    ///         if addr.ip().is_loopback() {
+    ///             // trust local peers with larger messages
+    ///             Settings {
+    ///                 max_fragment_size: 64 << 20,
+    ///                 ..default
+    ///             }
+    ///         } else {
+    ///             default
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    fn settings_for(&mut self, addr: &SocketAddr, default: Settings) -> Settings {
+        let _ = addr;
+        default
+    }
+
     /// Called when a TCP connection is lost with the handler that was
     /// setup for that connection.
     ///
@@ -99,6 +155,27 @@ pub trait Factory {
     /// state that was not internally tracked by the handler.
     #[inline]
     fn connection_lost(&mut self, _: Self::Handler) {}
+
+    /// Called when the listening socket stops working. This happens when
+    /// `Settings::max_accept_failures` consecutive connections could not be accepted, for
+    /// example because the process ran out of file descriptors. The listener is then closed and
+    /// bound to the same address again after `Settings::listener_rebind_delay_ms`. If that
+    /// fails, this is called again with the error and binding is retried after the same delay.
+    /// Connections that are already open are not affected.
+    ///
+    /// The default implementation does nothing, as the error has already been logged.
+    #[inline]
+    fn on_listener_error(&mut self, _: Error) {}
+
+    /// Called after a message or other signal is broadcast to every connection, with the number
+    /// of connections that it was delivered to and the tokens of those that it could not be
+    /// delivered to. Those connections have already been failed with the error, so this is
+    /// useful to track how many of the connections a broadcast reached. Broadcast messages that
+    /// are handled together by one iteration of the event loop are reported together.
+    ///
+    /// The default implementation does nothing.
+    #[inline]
+    fn on_broadcast_result(&mut self, _sent: usize, _failed: Vec<Token>) {}
 }
 
 impl<F, H> Factory for F
@@ -185,4 +262,30 @@ mod test {
         let m = x.connection_made(Sender::new(mio::Token(0), chn, 0));
         x.connection_lost(m);
     }
+
+    #[test]
+    fn settings_for() {
+        struct X;
+
+        impl Factory for X {
+            type Handler = M;
+            fn connection_made(&mut self, _: Sender) -> M {
+                M
+            }
+            fn settings_for(&mut self, addr: &SocketAddr, default: Settings) -> Settings {
+                Settings {
+                    in_buffer_capacity: addr.port() as usize,
+                    ..default
+                }
+            }
+        }
+
+        let addr = "127.0.0.1:4096".parse().unwrap();
+        assert_eq!(X.settings_for(&addr, Settings::default()).in_buffer_capacity, 4096);
+        let mut factory = |_| |_| Ok(());
+        assert_eq!(
+            factory.settings_for(&addr, Settings::default()).in_buffer_capacity,
+            Settings::default().in_buffer_capacity
+        );
+    }
 }
@@ -1,17 +1,33 @@
+use std::io;
+
 use log::Level::Error as ErrorLevel;
 #[cfg(feature = "nativetls")]
 use native_tls::{TlsConnector, TlsStream as SslStream};
 #[cfg(feature = "ssl")]
-use openssl::ssl::{SslConnector, SslMethod, SslStream};
+use openssl::error::ErrorStack;
+#[cfg(feature = "ssl")]
+use openssl::hash::MessageDigest;
+#[cfg(feature = "ssl")]
+use openssl::ocsp::{OcspCertId, OcspCertStatus, OcspFlag, OcspResponse, OcspResponseStatus};
+#[cfg(feature = "ssl")]
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslRef, SslStream, StatusType};
+#[cfg(feature = "ssl")]
+use openssl::x509::store::X509Lookup;
+#[cfg(feature = "ssl")]
+use openssl::x509::verify::X509VerifyFlags;
 use url;
 
+use communication::{AckToken, MessageMeta};
+use event::ErrorEvent;
 use frame::Frame;
 use handshake::{Handshake, Request, Response};
-use message::Message;
+use message::{Message, MessageInfo};
 use protocol::CloseCode;
 use result::{Error, Kind, Result};
 use util::{Timeout, Token};
 
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+use stream::TlsClientOptions;
 #[cfg(any(feature = "ssl", feature = "nativetls"))]
 use util::TcpStream;
 
@@ -43,6 +59,35 @@ pub trait Handler {
         Ok(())
     }
 
+    /// Called on incoming messages when `Settings::message_info` is enabled, along with the
+    /// receive-order sequence number of the message and the time it was received. By default,
+    /// the info is discarded and the message is passed to `on_message`.
+    #[inline]
+    fn on_message_with_meta(&mut self, msg: Message, info: MessageInfo) -> Result<()> {
+        trace!("Received message {} at {:?}", info.sequence, info.received);
+        self.on_message(msg)
+    }
+
+    /// Called on incoming text messages with a validated view over the received payload, when
+    /// `borrows_text` returns true.
+    ///
+    /// Override this method to process text without taking ownership of the message, for example
+    /// when parsing JSON that will be dropped immediately afterwards. By default the text is
+    /// converted into a `Message` and passed to `on_message`.
+    #[inline]
+    fn on_text_borrowed(&mut self, text: &str) -> Result<()> {
+        self.on_message(Message::text(text))
+    }
+
+    /// Whether incoming text messages are passed to `on_text_borrowed` rather than moved into a
+    /// `Message` for `on_message`. Return true when overriding `on_text_borrowed`. The default
+    /// is false, which delivers the payload without copying it. Text is never borrowed when
+    /// `Settings::message_info` is enabled.
+    #[inline]
+    fn borrows_text(&self) -> bool {
+        false
+    }
+
     /// Called any time this endpoint receives a close control frame.
     /// This may be because the other endpoint is initiating a closing handshake,
     /// or it may be the other endpoint confirming the handshake initiated by this endpoint.
@@ -50,6 +95,61 @@ pub trait Handler {
         debug!("Connection closing due to ({:?}) {}", code, reason);
     }
 
+    /// Called instead of `on_close` when the reason in the close frame is not valid UTF-8, with
+    /// the raw bytes of the reason, so that the data sent by a misbehaving peer can be captured.
+    /// The connection answers such a frame with an Invalid close code. By default, the reason is
+    /// converted lossily and passed to `on_close`.
+    #[inline]
+    fn on_close_raw(&mut self, code: CloseCode, reason: &[u8]) {
+        self.on_close(code, &String::from_utf8_lossy(reason))
+    }
+
+    /// Called when the event loop reports an error or hang up on the socket and the socket has a
+    /// pending error, with that error as reported by `SO_ERROR`, such as `ConnectionRefused` or
+    /// `ConnectionReset`. This gives the precise cause of a failure that reading or writing would
+    /// otherwise report less clearly, for example as `NotConnected` during the handshake. The
+    /// connection then fails with the same error, which is passed to `on_error`, unless a client
+    /// goes on to connect to the next address of its url.
+    #[inline]
+    fn on_socket_error(&mut self, err: io::Error) {
+        debug!("Socket error: {}", err);
+    }
+
+    /// Called when the other endpoint acknowledges a message sent with `Sender::send_tracked`.
+    #[inline]
+    fn on_ack(&mut self, token: AckToken) {
+        debug!("Message {} was acknowledged.", token);
+    }
+
+    /// Called when a message sent with `Sender::send_tracked` has not been acknowledged within
+    /// `Settings::ack_timeout_ms`. The message may still be acknowledged later.
+    #[inline]
+    fn on_ack_timeout(&mut self, token: AckToken) {
+        debug!("Message {} was not acknowledged in time.", token);
+    }
+
+    /// Called when the other endpoint shuts down its side of the connection without a closing
+    /// handshake, so that nothing more will be read from it. Messages that are already buffered
+    /// are still written to the other endpoint, which may continue to read, and the connection is
+    /// then closed with `CloseCode::Abnormal`. Messages sent from this method through a `Sender`
+    /// are not guaranteed to be written.
+    #[inline]
+    fn on_eof(&mut self) -> Result<()> {
+        debug!("The other endpoint stopped sending without a closing handshake.");
+        Ok(())
+    }
+
+    /// Called when the other endpoint sends a close code that is not valid according to the IANA
+    /// WebSocket Close Code Number Registry, such as an unassigned code in the range reserved for
+    /// the protocol. Return true to accept the code anyway and continue the closing handshake, for
+    /// example to interoperate with a peer that uses custom codes outside of the private range.
+    /// By default, such codes are rejected as a protocol error.
+    #[inline]
+    fn accept_close_code(&mut self, code: CloseCode) -> bool {
+        debug!("Rejecting close code {:?} ({:?})", code, code.registration());
+        false
+    }
+
     /// Called when an error occurs on the WebSocket.
     fn on_error(&mut self, err: Error) {
         // Ignore connection reset errors by default, but allow library clients to see them by
@@ -69,6 +169,76 @@ pub trait Handler {
         }
     }
 
+    /// Choose the close code sent to the other endpoint when an error of kind `Internal`,
+    /// `Capacity`, `Protocol` or `Encoding` closes an open connection. Override this method to
+    /// change the code reported for some failures, for example to report capacity errors as
+    /// `CloseCode::Policy`. By default, these kinds map to `CloseCode::Error`, `CloseCode::Size`,
+    /// `CloseCode::Protocol` and `CloseCode::Invalid` respectively.
+    #[inline]
+    fn close_code_for_error(&mut self, err: &Error) -> CloseCode {
+        match err.kind {
+            Kind::Capacity => CloseCode::Size,
+            Kind::Protocol => CloseCode::Protocol,
+            Kind::Encoding(_) => CloseCode::Invalid,
+            _ => CloseCode::Error,
+        }
+    }
+
+    /// Choose the reason sent along with the code returned by `close_code_for_error`. Override
+    /// this method to avoid revealing the details of internal failures to the other endpoint. By
+    /// default, the reason is the description of the error.
+    #[inline]
+    fn close_reason_for_error(&mut self, err: &Error) -> String {
+        err.to_string()
+    }
+
+    /// Called when an error occurs on the WebSocket, along with the phase of servicing the
+    /// connection during which it occurred and the direction of the data involved. Override this
+    /// method to count errors by category. By default, the error is passed on to `on_error`.
+    #[inline]
+    fn on_error_event(&mut self, event: ErrorEvent) {
+        self.on_error(event.error)
+    }
+
+    /// Called when the final byte of a message sent with `Sender::send_traced` has been written
+    /// to the socket. `meta.id` is the identifier returned by `send_traced` and `meta.enqueued`
+    /// is when the message was queued, so the difference from the current time is the latency of
+    /// the send pipeline. Messages that are discarded, for example because the connection closes
+    /// before they are written, are not reported.
+    #[inline]
+    fn on_message_written(&mut self, meta: MessageMeta) {
+        trace!("Message {} written", meta.id);
+    }
+
+    /// Called when the event loop processes a signal sent with `Sender::wake`. This runs on the
+    /// event loop thread, so it can be used to hand work from other threads to the connection.
+    #[inline]
+    fn on_wake(&mut self) -> Result<()> {
+        trace!("Handler received wake signal.");
+        Ok(())
+    }
+
+    /// Called with the outcome of a connection requested through `Sender::connect_with_token`.
+    ///
+    /// `user_token` is the token passed to `connect_with_token`. On success the result holds the
+    /// token assigned to the new connection. By default errors are passed to `on_error`.
+    #[inline]
+    fn on_connect_result(&mut self, user_token: Token, result: Result<Token>) -> Result<()> {
+        match result {
+            Ok(token) => {
+                debug!(
+                    "Connection requested with {:?} was assigned {:?}.",
+                    user_token, token
+                );
+                Ok(())
+            }
+            Err(err) => {
+                self.on_error(err);
+                Ok(())
+            }
+        }
+    }
+
     // handshake events
 
     /// A method for handling the low-level workings of the request portion of the WebSocket
@@ -228,6 +398,81 @@ pub trait Handler {
         }
     }
 
+    /// Called for each data frame of a fragmented message after it has passed through `on_frame`.
+    ///
+    /// `index` is the position of the frame within the message, starting at 0 for the frame
+    /// carrying the opcode, and `accumulated_bytes` is the length of the payloads received for the
+    /// message so far, including this frame. `is_last` is true for the final frame, after which
+    /// the reassembled message is passed to `on_message`. When `Settings::large_messages` is
+    /// `LargeMessages::Stream`, messages longer than `Settings::max_message_size` are not
+    /// reassembled and are only delivered here, including unfragmented ones as a single last
+    /// frame.
+    ///
+    /// This allows streaming consumers to process a message incrementally or to enforce their own
+    /// limits by returning an error. This is a noop by default.
+    #[inline]
+    fn on_fragment(
+        &mut self,
+        frame: &Frame,
+        index: usize,
+        accumulated_bytes: usize,
+        is_last: bool,
+    ) -> Result<()> {
+        trace!(
+            "Handler received fragment {} ({} bytes so far, last: {}): {}",
+            index,
+            accumulated_bytes,
+            is_last,
+            frame
+        );
+        Ok(())
+    }
+
+    /// Called when the connection closes or is lost while a fragmented message is incomplete,
+    /// with the frames of the message received so far, starting with the frame that carries the
+    /// opcode. This is called before `on_close`.
+    ///
+    /// Applications that send large payloads as fragmented messages, such as file uploads, can
+    /// use this to resume a transfer or to record how much of it arrived. By default the
+    /// fragments are discarded.
+    #[inline]
+    fn on_incomplete_message(&mut self, fragments: Vec<Frame>) {
+        debug!(
+            "Handler discarding {} fragments of an incomplete message.",
+            fragments.len()
+        );
+    }
+
+    /// Called for frames received after the other endpoint has sent a close frame when
+    /// `Settings::after_close` is `AfterClose::Deliver`. Such frames violate the protocol and
+    /// are not passed to `on_frame` or `on_message`. This is a noop by default.
+    #[inline]
+    fn on_after_close_frame(&mut self, frame: Frame) -> Result<()> {
+        debug!("Handler received frame after close: {}", frame);
+        Ok(())
+    }
+
+    /// Called with a frame injected with `Sender::inject_frame`, before the frame is handled as
+    /// if it had been received and returned by `on_frame`. Extensions that process received
+    /// frames off the event loop use this to hand the results back to the connection.
+    ///
+    /// Returning `Ok(None)` will cause the connection to forget about the frame. By default the
+    /// frame is handled unchanged.
+    #[inline]
+    fn on_injected_frame(&mut self, frame: Frame) -> Result<Option<Frame>> {
+        trace!("Handler received injected frame: {}", frame);
+        Ok(Some(frame))
+    }
+
+    /// Checked before each received frame is read while the connection is open. Returning true
+    /// suspends reading at once, as if `Sender::suspend_read` had been called, so that frames
+    /// already buffered wait until `Sender::resume_read` is called. Extensions that queue
+    /// received frames use this to stop reading before their queue overflows.
+    #[inline]
+    fn should_suspend_read(&self) -> bool {
+        false
+    }
+
     /// A method for handling outgoing frames.
     ///
     /// This method provides very low-level access to the details of the WebSocket protocol. It may
@@ -240,7 +485,8 @@ pub trait Handler {
     ///
     /// For messages, this method will be called with a single complete, final frame before any
     /// fragmentation is performed. Automatic fragmentation will be performed on the returned
-    /// frame, if any, based on the `fragment_size` setting.
+    /// frame, if any, based on the `fragment_size` setting or the size given to
+    /// `Sender::send_with_fragment_size`.
     ///
     /// By default this method simply ensures that no reserved bits are set.
     #[inline]
@@ -327,6 +573,94 @@ pub trait Handler {
 
         connector.connect(domain, stream).map_err(Error::from)
     }
+
+    /// A method for wrapping a client TcpStream with Ssl Authentication machinery using the TLS
+    /// options configured on the `Builder`.
+    ///
+    /// When no options are configured this calls `upgrade_ssl_client`, so handlers that override
+    /// that method continue to work. Override this method to take control of the options.
+    #[inline]
+    #[cfg(feature = "ssl")]
+    fn upgrade_ssl_client_with_options(
+        &mut self,
+        stream: TcpStream,
+        url: &url::Url,
+        options: &TlsClientOptions,
+    ) -> Result<SslStream<TcpStream>> {
+        if *options == TlsClientOptions::default() {
+            return self.upgrade_ssl_client(stream, url);
+        }
+        let domain = options.domain(url)?;
+        let internal = |e: ErrorStack| {
+            Error::new(
+                Kind::Internal,
+                format!("Failed to upgrade client to SSL: {}", e),
+            )
+        };
+        let mut builder = SslConnector::builder(SslMethod::tls()).map_err(internal)?;
+        if !options.crl_files.is_empty() {
+            let lookup = builder
+                .cert_store_mut()
+                .add_lookup(X509Lookup::file())
+                .map_err(internal)?;
+            for path in &options.crl_files {
+                lookup.load_crl_file(path, SslFiletype::PEM).map_err(|e| {
+                    Error::new(
+                        Kind::Internal,
+                        format!("Failed to load CRL file {}: {}", path.display(), e),
+                    )
+                })?;
+            }
+            builder
+                .verify_param_mut()
+                .set_flags(X509VerifyFlags::CRL_CHECK | X509VerifyFlags::CRL_CHECK_ALL)
+                .map_err(internal)?;
+        }
+        if options.require_ocsp_stapling {
+            builder
+                .set_status_callback(check_ocsp_staple)
+                .map_err(internal)?;
+        }
+        let mut config = builder.build().configure().map_err(internal)?;
+        if options.require_ocsp_stapling {
+            config.set_status_type(StatusType::OCSP).map_err(internal)?;
+        }
+        config.set_verify_hostname(!options.accept_invalid_hostnames);
+        config.connect(domain, stream).map_err(Error::from)
+    }
+
+    #[inline]
+    #[cfg(feature = "nativetls")]
+    fn upgrade_ssl_client_with_options(
+        &mut self,
+        stream: TcpStream,
+        url: &url::Url,
+        options: &TlsClientOptions,
+    ) -> Result<SslStream<TcpStream>> {
+        if *options == TlsClientOptions::default() {
+            return self.upgrade_ssl_client(stream, url);
+        }
+        if options.require_ocsp_stapling || !options.crl_files.is_empty() {
+            return Err(Error::new(
+                Kind::Internal,
+                "Revocation checking requires the ssl feature instead of nativetls.",
+            ));
+        }
+        let domain = options.domain(url)?;
+
+        let connector = TlsConnector::builder()
+            .danger_accept_invalid_hostnames(options.accept_invalid_hostnames)
+            .build()
+            .map_err(|e| {
+                Error::new(
+                    Kind::Internal,
+                    format!("Failed to upgrade client to SSL: {}", e),
+                )
+            })?;
+
+        connector.connect(domain, stream).map_err(Error::from)
+    }
+
     /// A method for wrapping a server TcpStream with Ssl Authentication machinery
     ///
     /// Override this method to customize how the connection is encrypted. By default
@@ -336,6 +670,66 @@ pub trait Handler {
     fn upgrade_ssl_server(&mut self, _: TcpStream) -> Result<SslStream<TcpStream>> {
         unimplemented!()
     }
+
+    /// A method for wrapping a server TcpStream with Ssl Authentication machinery, given the
+    /// server name that the client asked for in its TLS ClientHello. The name is only read when
+    /// `Settings::sniff_server_name` is set, and it is None when the client did not send one.
+    ///
+    /// Override this method to choose the certificate or tenant by hostname. By default this
+    /// method calls `upgrade_ssl_server`.
+    #[inline]
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn upgrade_ssl_server_for(
+        &mut self,
+        stream: TcpStream,
+        server_name: Option<&str>,
+    ) -> Result<SslStream<TcpStream>> {
+        let _ = server_name;
+        self.upgrade_ssl_server(stream)
+    }
+}
+
+// Whether the OCSP response stapled by the server shows that its certificate is good.
+#[cfg(feature = "ssl")]
+fn check_ocsp_staple(ssl: &mut SslRef) -> ::std::result::Result<bool, ErrorStack> {
+    let response = match ssl.ocsp_status() {
+        Some(der) => OcspResponse::from_der(der)?,
+        None => {
+            debug!("Server did not staple an OCSP response.");
+            return Ok(false);
+        }
+    };
+    if response.status() != OcspResponseStatus::SUCCESSFUL {
+        debug!("Stapled OCSP response was not successful.");
+        return Ok(false);
+    }
+    let basic = response.basic()?;
+    let chain = match ssl.peer_cert_chain() {
+        Some(chain) => chain,
+        None => return Ok(false),
+    };
+    let (cert, issuer) = match (chain.get(0), chain.get(1)) {
+        (Some(cert), Some(issuer)) => (cert, issuer),
+        _ => {
+            debug!("Server did not send the issuer of its certificate for OCSP.");
+            return Ok(false);
+        }
+    };
+    if let Err(err) = basic.verify(chain, ssl.ssl_context().cert_store(), OcspFlag::empty()) {
+        debug!("Stapled OCSP response failed verification: {}", err);
+        return Ok(false);
+    }
+    let id = OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer)?;
+    match basic.find_status(&id) {
+        // allow five minutes of clock skew
+        Some(ref status) if status.status == OcspCertStatus::GOOD => {
+            Ok(status.check_validity(300, None).is_ok())
+        }
+        _ => {
+            debug!("Stapled OCSP response does not show the certificate as good.");
+            Ok(false)
+        }
+    }
 }
 
 impl<F> Handler for F
@@ -351,7 +745,7 @@ mod test {
     #![allow(unused_imports, unused_variables, dead_code)]
     use super::*;
     use frame;
-    use handshake::{Handshake, Request, Response};
+    use handshake::{Handshake, HandshakeTimings, Request, Response, Version};
     use message;
     use mio;
     use protocol::CloseCode;
@@ -403,12 +797,39 @@ mod test {
             response: res,
             peer_addr: None,
             local_addr: None,
+            timings: HandshakeTimings::default(),
+            version: Version::Rfc6455,
+            connected_url: Some(url.clone()),
+            connected_addr: None,
+            connection_id: 0,
+            server_name: None,
         }).unwrap();
         h.on_message(message::Message::Text("testme".to_owned()))
             .unwrap();
         h.on_close(CloseCode::Normal, "");
     }
 
+    #[test]
+    fn text_borrowed_handler() {
+        struct H {
+            received: Option<message::Message>,
+        }
+
+        impl Handler for H {
+            fn on_message(&mut self, msg: message::Message) -> Result<()> {
+                self.received = Some(msg);
+                Ok(())
+            }
+        }
+
+        let mut h = H { received: None };
+        h.on_text_borrowed("testme").unwrap();
+        assert_eq!(
+            h.received,
+            Some(message::Message::Text(String::from("testme")))
+        );
+    }
+
     #[test]
     fn closure_handler() {
         let mut close = |msg| {
@@ -1,24 +1,68 @@
+use std::cmp::Ordering;
 use std::fmt;
 use std::io::Write;
 use std::net::SocketAddr;
 use std::str::from_utf8;
+use std::time::{Duration, Instant};
 
 use httparse;
 use rand;
 use sha1::{self, Digest};
 use url;
 
+use extensions::{self, Offer};
+use limits::MAX_HEADERS;
 use result::{Error, Kind, Result};
 
 static WS_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 static BASE64: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-const MAX_HEADERS: usize = 124;
 
 fn generate_key() -> String {
     let key: [u8; 16] = rand::random();
     encode_base64(&key)
 }
 
+// Normalize the percent-encoding of a path or query as described by section 6.2.2 of RFC 3986,
+// since some servers compare paths byte for byte: escaped unreserved characters are decoded,
+// the hexadecimal digits of other escapes are capitalized, and a percent sign that does not
+// start an escape is itself escaped.
+fn normalize_percent_encoding(value: &str) -> String {
+    fn hex(b: u8) -> Option<u8> {
+        (b as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let bytes = value.as_bytes();
+    let mut normalized = String::with_capacity(value.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            let len = value[i..].chars().next().map_or(1, char::len_utf8);
+            normalized.push_str(&value[i..i + len]);
+            i += len;
+            continue;
+        }
+        match (
+            bytes.get(i + 1).cloned().and_then(hex),
+            bytes.get(i + 2).cloned().and_then(hex),
+        ) {
+            (Some(high), Some(low)) => {
+                let b = high << 4 | low;
+                if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                    normalized.push(b as char);
+                } else {
+                    normalized.push_str(&format!("%{:02X}", b));
+                }
+                i += 3;
+            }
+            _ => {
+                normalized.push_str("%25");
+                i += 1;
+            }
+        }
+    }
+    normalized
+}
+
 pub fn hash_key(key: &[u8]) -> String {
     let mut hasher = sha1::Sha1::new();
 
@@ -29,7 +73,7 @@ pub fn hash_key(key: &[u8]) -> String {
 }
 
 // This code is based on rustc_serialize base64 STANDARD
-fn encode_base64(data: &[u8]) -> String {
+pub fn encode_base64(data: &[u8]) -> String {
     let len = data.len();
     let mod_len = len % 3;
 
@@ -70,6 +114,49 @@ fn encode_base64(data: &[u8]) -> String {
     String::from_utf8(encoded).unwrap()
 }
 
+/// Whether a Sec-WebSocket-Key is the base64 encoding of 16 bytes, as required by RFC 6455.
+fn is_valid_key(key: &[u8]) -> bool {
+    key.len() == 24
+        && key[..22].iter().all(|byte| BASE64.contains(byte))
+        && &key[22..] == b"=="
+}
+
+/// Compare two byte strings in time that depends only on their lengths, so that the position of
+/// the first difference is not revealed.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The protocol versions understood by this library, in order of preference.
+pub const SUPPORTED_VERSIONS: &[Version] = &[Version::Rfc6455];
+
+/// A revision of the WebSocket protocol as identified by the Sec-WebSocket-Version header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// The protocol specified by RFC 6455, version 13.
+    Rfc6455,
+}
+
+impl Version {
+    /// Get the version identified by a single Sec-WebSocket-Version value.
+    pub fn from_header(value: &str) -> Option<Version> {
+        match value.trim() {
+            "13" => Some(Version::Rfc6455),
+            _ => None,
+        }
+    }
+
+    /// The value used to identify this version in the Sec-WebSocket-Version header.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Version::Rfc6455 => "13",
+        }
+    }
+}
+
 /// A struct representing the two halves of the WebSocket handshake.
 #[derive(Debug)]
 pub struct Handshake {
@@ -82,9 +169,56 @@ pub struct Handshake {
     pub peer_addr: Option<SocketAddr>,
     /// The socket address of this endpoint.
     pub local_addr: Option<SocketAddr>,
+    /// The times at which each phase of the handshake completed.
+    pub timings: HandshakeTimings,
+    /// The protocol version used by the connection. Servers that do not set
+    /// `Settings::version_strict` assume RFC 6455 when the client does not request a supported
+    /// version.
+    pub version: Version,
+    /// The URL that a client connection was opened to. None for server connections.
+    pub connected_url: Option<url::Url>,
+    /// The address that a client connection was established with, once the resolved addresses
+    /// that failed have been skipped. This is the address of the proxy when the connection is
+    /// tunnelled. None for server connections.
+    pub connected_addr: Option<SocketAddr>,
+    /// The identifier of the connection, the same as `Sender::connection_id`. Unlike its token,
+    /// it is never reused for another connection by the same WebSocket, so it can correlate the
+    /// events of a connection, including the log messages of this crate, after it has closed.
+    pub connection_id: u64,
+    /// The server name that the client asked for with the Server Name Indication extension of
+    /// TLS. It is read from the ClientHello when `Settings::sniff_server_name` is set, and from
+    /// the TLS session of connections encrypted with rustls. None for client connections and for
+    /// connections without a server name.
+    pub server_name: Option<String>,
 }
 
 impl Handshake {
+    /// Get the times at which each phase of the handshake completed.
+    #[inline]
+    pub fn timings(&self) -> &HandshakeTimings {
+        &self.timings
+    }
+
+    /// When the connection was accepted by a server or initiated by a client. This is the same
+    /// instant as `timings().started`.
+    #[inline]
+    pub fn connected_at(&self) -> Instant {
+        self.timings.started
+    }
+
+    /// The URL that this client connection was opened to, or None for a server connection.
+    #[inline]
+    pub fn connected_url(&self) -> Option<&url::Url> {
+        self.connected_url.as_ref()
+    }
+
+    /// The address that this client connection was finally established with, after falling back
+    /// through the addresses that the host resolved to. None for a server connection.
+    #[inline]
+    pub fn connected_addr(&self) -> Option<SocketAddr> {
+        self.connected_addr
+    }
+
     /// Get the IP address of the remote connection.
     ///
     /// This is the preferred method of obtaining the client's IP address.
@@ -109,6 +243,41 @@ impl Handshake {
     }
 }
 
+/// Timestamps recorded as the opening handshake of a connection progressed.
+///
+/// Comparing these timestamps helps to tell slow TLS negotiation apart from slow clients or slow
+/// handlers. Phases that did not occur are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeTimings {
+    /// When the connection was accepted by a server or initiated by a client.
+    pub started: Instant,
+    /// When TLS negotiation completed, if the connection is encrypted.
+    pub tls_established: Option<Instant>,
+    /// For servers, when the request was parsed. For clients, when the request was written.
+    pub request: Option<Instant>,
+    /// For servers, when the response was written. For clients, when the response was parsed.
+    pub response: Option<Instant>,
+}
+
+impl HandshakeTimings {
+    /// The time taken from the start of the connection until the handshake completed.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.response
+            .map(|response| response.duration_since(self.started))
+    }
+}
+
+impl Default for HandshakeTimings {
+    fn default() -> HandshakeTimings {
+        HandshakeTimings {
+            started: Instant::now(),
+            tls_established: None,
+            request: None,
+            response: None,
+        }
+    }
+}
+
 /// The handshake request.
 #[derive(Debug)]
 pub struct Request {
@@ -169,8 +338,69 @@ impl Request {
         Ok(hash_key(self.key()?))
     }
 
+    /// Check that the request carries a well formed WebSocket key: 16 bytes encoded as base64.
+    pub fn validate_key(&self) -> Result<()> {
+        let key = self.key()?;
+        if is_valid_key(key) {
+            Ok(())
+        } else {
+            Err(Error::new(
+                Kind::Protocol,
+                format!("Received malformed WebSocket key: {}", String::from_utf8_lossy(key)),
+            ))
+        }
+    }
+
+    /// Check that a request sent by a browser comes from a page served by the host it was sent
+    /// to. The Origin header must name the same host and port as the Host header, with `secure`
+    /// indicating whether the request arrived over TLS: a secure connection (wss) requires an
+    /// https origin and an insecure one (ws) an http origin, and ports missing from either header
+    /// take the default port of that scheme. Requests without an Origin header do not come from
+    /// a browser and pass the check.
+    pub fn validate_same_origin(&self, secure: bool) -> Result<()> {
+        let origin = match self.origin()? {
+            Some(origin) => origin,
+            None => return Ok(()),
+        };
+        let host = match self.header("host") {
+            Some(host) => from_utf8(host)?,
+            None => {
+                return Err(Error::new(
+                    Kind::Protocol,
+                    "The Host header is missing.",
+                ))
+            }
+        };
+
+        let scheme = if secure { "https" } else { "http" };
+        let same = match (
+            url::Url::parse(origin),
+            url::Url::parse(&format!("{}://{}", scheme, host)),
+        ) {
+            (Ok(origin), Ok(target)) => {
+                origin.scheme() == scheme
+                    && origin.host() == target.host()
+                    && origin.port_or_known_default() == target.port_or_known_default()
+            }
+            _ => false,
+        };
+        if same {
+            Ok(())
+        } else {
+            Err(Error::new(
+                Kind::Protocol,
+                format!("Origin {} does not match host {}.", origin, host),
+            ))
+        }
+    }
+
+    /// Check whether the accept key in a response matches this request. The keys are compared in
+    /// constant time.
+    pub fn matches_accept_key(&self, accept_key: &[u8]) -> Result<bool> {
+        Ok(constant_time_eq(self.hashed_key()?.as_bytes(), accept_key))
+    }
+
     /// Get the WebSocket protocol version from the request (should be 13).
-    #[allow(dead_code)]
     pub fn version(&self) -> Result<&str> {
         if let Some(version) = self.header("sec-websocket-version") {
             from_utf8(version).map_err(Error::from)
@@ -182,6 +412,18 @@ impl Request {
         }
     }
 
+    /// Select the protocol version to use for this request. The first version offered by the
+    /// client that is also supported is chosen, and None is returned if the header is missing or
+    /// none of the offered versions are supported.
+    pub fn negotiate_version(&self) -> Option<Version> {
+        self.version().ok().and_then(|versions| {
+            versions
+                .split(',')
+                .filter_map(Version::from_header)
+                .find(|version| SUPPORTED_VERSIONS.contains(version))
+        })
+    }
+
     /// Get the request method.
     #[inline]
     pub fn method(&self) -> &str {
@@ -195,17 +437,66 @@ impl Request {
         &self.path
     }
 
-    /// Get the possible protocols for the WebSocket connection.
+    /// Get the possible protocols for the WebSocket connection in the order they were offered.
+    /// Protocols from every `Sec-WebSocket-Protocol` header are merged, and parameters such as a
+    /// `q` weight are removed.
     #[allow(dead_code)]
     pub fn protocols(&self) -> Result<Vec<&str>> {
-        if let Some(protos) = self.header("sec-websocket-protocol") {
-            Ok(from_utf8(protos)?
-                .split(',')
-                .map(|proto| proto.trim())
-                .collect())
-        } else {
-            Ok(Vec::new())
+        Ok(self.offered_protocols()?
+            .into_iter()
+            .map(|(proto, _)| proto)
+            .collect())
+    }
+
+    /// Get the possible protocols for the WebSocket connection with their weights, most preferred
+    /// first. A protocol may carry a weight between 0 and 1 as in `chat;q=0.5`, and defaults to a
+    /// weight of 1. Protocols with equal weights keep the order they were offered in, and
+    /// protocols with a weight of 0 are not acceptable to the client, so they are left out.
+    #[allow(dead_code)]
+    pub fn weighted_protocols(&self) -> Result<Vec<(&str, f32)>> {
+        let mut weighted = Vec::new();
+        for (proto, params) in self.offered_protocols()? {
+            let mut weight = 1.0;
+            for param in params.split(';') {
+                let mut parts = param.splitn(2, '=');
+                if parts.next().map(str::trim) != Some("q") {
+                    continue;
+                }
+                weight = match parts.next().map(|q| q.trim().parse::<f32>()) {
+                    Some(Ok(q)) if (0.0..=1.0).contains(&q) => q,
+                    _ => {
+                        return Err(Error::new(
+                            Kind::Protocol,
+                            format!("Invalid weight for protocol {}: {}", proto, param.trim()),
+                        ))
+                    }
+                };
+            }
+            if weight > 0.0 {
+                weighted.push((proto, weight));
+            }
         }
+        // sorting is stable, so equal weights keep their order
+        weighted.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        Ok(weighted)
+    }
+
+    // Every protocol in the Sec-WebSocket-Protocol headers along with its parameters.
+    fn offered_protocols(&self) -> Result<Vec<(&str, &str)>> {
+        let mut protos = Vec::new();
+        for (key, val) in &self.headers {
+            if !key.eq_ignore_ascii_case("sec-websocket-protocol") {
+                continue;
+            }
+            for proto in from_utf8(val)?.split(',') {
+                let mut parts = proto.splitn(2, ';');
+                let name = parts.next().unwrap_or("").trim();
+                if !name.is_empty() {
+                    protos.push((name, parts.next().unwrap_or("")));
+                }
+            }
+        }
+        Ok(protos)
     }
 
     /// Add a possible protocol to this request.
@@ -245,7 +536,17 @@ impl Request {
     #[allow(dead_code)]
     pub fn extensions(&self) -> Result<Vec<&str>> {
         if let Some(exts) = self.header("sec-websocket-extensions") {
-            Ok(from_utf8(exts)?.split(',').map(|ext| ext.trim()).collect())
+            Ok(extensions::split_header(from_utf8(exts)?))
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Get the possible extensions for the WebSocket connection, parsed into their names and
+    /// parameters. Fails with an error of kind `Protocol` if the header is malformed.
+    pub fn extension_offers(&self) -> Result<Vec<Offer>> {
+        if let Some(exts) = self.header("sec-websocket-extensions") {
+            extensions::parse_header(from_utf8(exts)?)
         } else {
             Ok(Vec::new())
         }
@@ -346,25 +647,32 @@ impl Request {
     /// Construct a new WebSocket handshake HTTP request from a url.
     pub fn from_url(url: &url::Url) -> Result<Request> {
         let query = if let Some(q) = url.query() {
-            format!("?{}", q)
+            format!("?{}", normalize_percent_encoding(q))
         } else {
             "".into()
         };
 
+        let host = url.host_str().ok_or_else(|| {
+            Error::new(Kind::Internal, "No host passed for WebSocket connection.")
+        })?;
+        // Some servers and gateways reject a Host header that names the default port of the
+        // scheme, which the url has already left out.
+        let host = match (url.host(), url.port()) {
+            (Some(url::Host::Ipv6(_)), port) if !host.starts_with('[') => match port {
+                Some(port) => format!("[{}]:{}", host, port),
+                None => format!("[{}]", host),
+            },
+            (_, Some(port)) => format!("{}:{}", host, port),
+            (_, None) => host.to_owned(),
+        };
+
         let mut headers = vec![
             ("Connection".into(), "Upgrade".into()),
+            ("Host".into(), host.into()),
             (
-                "Host".into(),
-                format!(
-                    "{}:{}",
-                    url.host_str().ok_or_else(|| Error::new(
-                        Kind::Internal,
-                        "No host passed for WebSocket connection.",
-                    ))?,
-                    url.port_or_known_default().unwrap_or(80)
-                ).into(),
+                "Sec-WebSocket-Version".into(),
+                SUPPORTED_VERSIONS[0].as_str().into(),
             ),
-            ("Sec-WebSocket-Version".into(), "13".into()),
             ("Sec-WebSocket-Key".into(), generate_key().into()),
             ("Upgrade".into(), "websocket".into()),
         ];
@@ -375,7 +683,7 @@ impl Request {
         }
 
         let req = Request {
-            path: format!("{}{}", url.path(), query),
+            path: format!("{}{}", normalize_percent_encoding(url.path()), query),
             method: "GET".to_owned(),
             headers: headers,
         };
@@ -426,6 +734,8 @@ pub struct Response {
     reason: String,
     headers: Vec<(String, Vec<u8>)>,
     body: Vec<u8>,
+    // The protocols offered by the request this responds to, if known
+    offered: Option<Vec<String>>,
 }
 
 impl Response {
@@ -441,6 +751,7 @@ impl Response {
             reason: reason.into(),
             headers: vec![("Content-Length".into(), body.len().to_string().into())],
             body,
+            offered: None,
         }
     }
 
@@ -451,7 +762,7 @@ impl Response {
     }
 
     /// Get the value of the first instance of an HTTP header.
-    fn header(&self, header: &str) -> Option<&Vec<u8>> {
+    pub fn header(&self, header: &str) -> Option<&Vec<u8>> {
         self.headers
             .iter()
             .find(|&&(ref key, _)| key.to_lowercase() == header.to_lowercase())
@@ -527,14 +838,31 @@ impl Response {
     }
 
     /// Set the protocol that the server has decided to use.
+    ///
+    /// When the response was built from a request with `from_request`, the protocol must be one
+    /// of the protocols offered by the request, otherwise an error of kind `Protocol` is returned
+    /// and the response is left unchanged.
     #[allow(dead_code)]
-    pub fn set_protocol(&mut self, protocol: &str) {
+    pub fn set_protocol(&mut self, protocol: &str) -> Result<()> {
+        if let Some(ref offered) = self.offered {
+            if !offered.iter().any(|proto| proto == protocol) {
+                return Err(Error::new(
+                    Kind::Protocol,
+                    format!(
+                        "The protocol {} was not offered by the client, which offered: {}",
+                        protocol,
+                        offered.join(", ")
+                    ),
+                ));
+            }
+        }
         if let Some(proto) = self.header_mut("sec-websocket-protocol") {
             *proto = protocol.into();
-            return;
+            return Ok(());
         }
         self.headers_mut()
-            .push(("Sec-WebSocket-Protocol".into(), protocol.into()))
+            .push(("Sec-WebSocket-Protocol".into(), protocol.into()));
+        Ok(())
     }
 
     /// Get the extensions that the server has decided to use. If these are unacceptable, it is
@@ -542,10 +870,17 @@ impl Response {
     #[allow(dead_code)]
     pub fn extensions(&self) -> Result<Vec<&str>> {
         if let Some(exts) = self.header("sec-websocket-extensions") {
-            Ok(from_utf8(exts)?
-                .split(',')
-                .map(|proto| proto.trim())
-                .collect())
+            Ok(extensions::split_header(from_utf8(exts)?))
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Get the extensions that the server has decided to use, parsed into their names and
+    /// parameters. Fails with an error of kind `Protocol` if the header is malformed.
+    pub fn extension_offers(&self) -> Result<Vec<Offer>> {
+        if let Some(exts) = self.header("sec-websocket-extensions") {
+            extensions::parse_header(from_utf8(exts)?)
         } else {
             Ok(Vec::new())
         }
@@ -601,6 +936,7 @@ impl Response {
                     .map(|h| (h.name.into(), h.value.into()))
                     .collect(),
                 body: Vec::new(),
+                offered: None,
             }))
         } else {
             Ok(None)
@@ -620,12 +956,33 @@ impl Response {
                 ("Upgrade".into(), "websocket".into()),
             ],
             body: Vec::new(),
+            offered: req.protocols()
+                .ok()
+                .map(|protos| protos.into_iter().map(String::from).collect()),
         };
 
         debug!("Built response from request:\n{}", res);
         Ok(res)
     }
 
+    /// Construct a 426 Upgrade Required response listing the supported protocol versions. This is
+    /// the response sent to clients that request an unsupported version of the protocol.
+    pub fn upgrade_required() -> Response {
+        let versions = SUPPORTED_VERSIONS
+            .iter()
+            .map(|version| version.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut res = Response::new(
+            426,
+            "Upgrade Required",
+            b"Unsupported WebSocket version".to_vec(),
+        );
+        res.headers
+            .push(("Sec-WebSocket-Version".into(), versions.into()));
+        res
+    }
+
     /// Write a response out to a buffer
     pub fn format<W>(&self, w: &mut W) -> Result<()>
     where
@@ -661,6 +1018,247 @@ impl fmt::Display for Response {
     }
 }
 
+/// A builder for handshake responses that checks the status line and headers before they are
+/// written to the connection.
+///
+/// By default the builder produces a `101 Switching Protocols` response. For such responses the
+/// `Connection`, `Upgrade` and `Sec-WebSocket-Accept` headers are added automatically, the latter
+/// computed from the client key given with `with_key` or taken from the request passed to
+/// `from_request`.
+#[derive(Debug, Clone)]
+pub struct ResponseBuilder {
+    status: u16,
+    reason: Option<String>,
+    headers: Vec<(String, Vec<u8>)>,
+    protocol: Option<String>,
+    extensions: Vec<String>,
+    key: Option<Vec<u8>>,
+    body: Vec<u8>,
+    offered: Option<Vec<String>>,
+}
+
+impl ResponseBuilder {
+    /// Create a builder for a `101 Switching Protocols` response.
+    pub fn new() -> ResponseBuilder {
+        ResponseBuilder::default()
+    }
+
+    /// Create a builder for a response to a request. The key of the request is used to compute
+    /// the `Sec-WebSocket-Accept` header and the protocol chosen with `with_protocol` must be one
+    /// of the protocols offered by the request.
+    pub fn from_request(req: &Request) -> ResponseBuilder {
+        ResponseBuilder {
+            key: req.key().ok().cloned(),
+            offered: req.protocols()
+                .ok()
+                .map(|protos| protos.into_iter().map(String::from).collect()),
+            ..ResponseBuilder::default()
+        }
+    }
+
+    /// Set the HTTP status code. Default: 101.
+    pub fn with_status(&mut self, status: u16) -> &mut ResponseBuilder {
+        self.status = status;
+        self
+    }
+
+    /// Set the HTTP status reason. When no reason is given, the canonical reason of the status
+    /// code is used.
+    pub fn with_reason<R>(&mut self, reason: R) -> &mut ResponseBuilder
+    where
+        R: Into<String>,
+    {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    /// Add an HTTP header to the response.
+    pub fn with_header<K, V>(&mut self, key: K, value: V) -> &mut ResponseBuilder
+    where
+        K: Into<String>,
+        V: Into<Vec<u8>>,
+    {
+        self.headers.push((key.into(), value.into()));
+        self
+    }
+
+    /// Set the protocol that the server has decided to use.
+    pub fn with_protocol<P>(&mut self, protocol: P) -> &mut ResponseBuilder
+    where
+        P: Into<String>,
+    {
+        self.protocol = Some(protocol.into());
+        self
+    }
+
+    /// Add an extension that the server has decided to use.
+    pub fn with_extension<E>(&mut self, ext: E) -> &mut ResponseBuilder
+    where
+        E: Into<String>,
+    {
+        self.extensions.push(ext.into());
+        self
+    }
+
+    /// Set the Sec-WebSocket-Key sent by the client, from which the Sec-WebSocket-Accept header
+    /// is computed.
+    pub fn with_key<K>(&mut self, key: K) -> &mut ResponseBuilder
+    where
+        K: Into<Vec<u8>>,
+    {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Set the response body. A `Content-Length` header is added for responses that do not
+    /// switch protocols.
+    pub fn with_body(&mut self, body: Vec<u8>) -> &mut ResponseBuilder {
+        self.body = body;
+        self
+    }
+
+    /// Validate the configuration and build the response.
+    ///
+    /// An error of kind `Protocol` is returned when the status code does not have three digits,
+    /// when the reason or a header is not valid HTTP, when the protocol was not offered by the
+    /// request or when a `101` response has no client key to answer.
+    pub fn build(&self) -> Result<Response> {
+        if self.status < 100 || self.status > 999 {
+            return Err(invalid(format!("Invalid status code {}.", self.status)));
+        }
+        let reason = match self.reason {
+            Some(ref reason) => reason.clone(),
+            None => canonical_reason(self.status).into(),
+        };
+        if !reason.bytes().all(is_field_byte) {
+            return Err(invalid(format!("Invalid status reason {:?}.", reason)));
+        }
+
+        let mut headers = Vec::with_capacity(self.headers.len() + 5);
+        if self.status == 101 {
+            let key = self.key.as_ref().ok_or_else(|| {
+                invalid("Unable to compute Sec-WebSocket-Accept without a client key.")
+            })?;
+            headers.push(("Connection".into(), "Upgrade".into()));
+            headers.push(("Sec-WebSocket-Accept".into(), hash_key(key).into()));
+            headers.push(("Upgrade".into(), "websocket".into()));
+        } else {
+            headers.push(("Content-Length".into(), self.body.len().to_string().into()));
+        }
+
+        if let Some(ref protocol) = self.protocol {
+            if let Some(ref offered) = self.offered {
+                if !offered.iter().any(|proto| proto == protocol) {
+                    return Err(invalid(format!(
+                        "The protocol {} was not offered by the client, which offered: {}",
+                        protocol,
+                        offered.join(", ")
+                    )));
+                }
+            }
+            if !is_token(protocol) {
+                return Err(invalid(format!("Invalid protocol {:?}.", protocol)));
+            }
+            headers.push(("Sec-WebSocket-Protocol".into(), protocol.clone().into()));
+        }
+
+        if !self.extensions.is_empty() {
+            if self.extensions.iter().any(|ext| {
+                ext.trim().is_empty() || !ext.bytes().all(is_field_byte)
+            }) {
+                return Err(invalid(format!(
+                    "Invalid extensions {:?}.",
+                    self.extensions
+                )));
+            }
+            headers.push((
+                "Sec-WebSocket-Extensions".into(),
+                self.extensions.join(", ").into(),
+            ));
+        }
+
+        for &(ref key, ref val) in &self.headers {
+            if !is_token(key) {
+                return Err(invalid(format!("Invalid header name {:?}.", key)));
+            }
+            if !val.iter().cloned().all(is_field_byte) {
+                return Err(invalid(format!(
+                    "Invalid value for header {}: {:?}.",
+                    key,
+                    String::from_utf8_lossy(val)
+                )));
+            }
+            headers.push((key.clone(), val.clone()));
+        }
+
+        let res = Response {
+            status: self.status,
+            reason,
+            headers,
+            body: self.body.clone(),
+            offered: self.offered.clone(),
+        };
+        debug!("Built response:\n{}", res);
+        Ok(res)
+    }
+}
+
+impl Default for ResponseBuilder {
+    fn default() -> ResponseBuilder {
+        ResponseBuilder {
+            status: 101,
+            reason: None,
+            headers: Vec::new(),
+            protocol: None,
+            extensions: Vec::new(),
+            key: None,
+            body: Vec::new(),
+            offered: None,
+        }
+    }
+}
+
+fn invalid<M>(msg: M) -> Error
+where
+    M: Into<::std::borrow::Cow<'static, str>>,
+{
+    Error::new(Kind::Protocol, msg)
+}
+
+/// Whether a string is an HTTP token as defined by RFC 7230, as required of header names and
+/// protocol identifiers.
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|byte| match byte {
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
+        | b'`' | b'|' | b'~' => true,
+        _ => byte.is_ascii_alphanumeric(),
+    })
+}
+
+/// Whether a byte may appear in a header value or status reason, which excludes control
+/// characters such as the CR and LF that would end the line.
+fn is_field_byte(byte: u8) -> bool {
+    byte == b'\t' || (byte >= b' ' && byte != 0x7f)
+}
+
+fn canonical_reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        301 => "Moved Permanently",
+        302 => "Found",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
 mod test {
     #![allow(unused_imports, unused_variables, dead_code)]
     use super::*;
@@ -668,6 +1266,122 @@ mod test {
     use std::net::SocketAddr;
     use std::str::FromStr;
 
+    #[test]
+    fn validate_key() {
+        let request = |key: &str| {
+            let mut buf = Vec::new();
+            write!(
+                &mut buf,
+                "GET / HTTP/1.1\r\n\
+                 Connection: Upgrade\r\n\
+                 Upgrade: websocket\r\n\
+                 Sec-WebSocket-Version: 13\r\n\
+                 Sec-WebSocket-Key: {}\r\n\r\n",
+                key
+            ).unwrap();
+            Request::parse(&buf).unwrap().unwrap()
+        };
+
+        assert!(request("q16eN37NCfVwUChPvBdk4g==").validate_key().is_ok());
+        assert!(request("q16eN37NCfVwUChPvBdk4g").validate_key().is_err());
+        assert!(request("q16eN37NCfVwUChPvBdk4g=A").validate_key().is_err());
+        assert!(request("q16eN37NCfVw*ChPvBdk4g==").validate_key().is_err());
+        assert!(request("dGhlIHNhbXBsZSBub25jZSBub25jZQ==").validate_key().is_err());
+    }
+
+    #[test]
+    fn same_origin() {
+        let request = |headers: &str| {
+            let mut buf = Vec::new();
+            write!(
+                &mut buf,
+                "GET / HTTP/1.1\r\n\
+                 Connection: Upgrade\r\n\
+                 Upgrade: websocket\r\n\
+                 Sec-WebSocket-Version: 13\r\n\
+                 Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\
+                 {}\r\n",
+                headers
+            ).unwrap();
+            Request::parse(&buf).unwrap().unwrap()
+        };
+        let check = |origin: &str, host: &str, secure: bool| {
+            request(&format!("Origin: {}\r\nHost: {}\r\n", origin, host))
+                .validate_same_origin(secure)
+                .is_ok()
+        };
+
+        assert!(request("Host: example.com\r\n").validate_same_origin(false).is_ok());
+        assert!(request("Origin: http://example.com\r\n").validate_same_origin(false).is_err());
+
+        assert!(check("http://example.com", "example.com", false));
+        assert!(check("http://Example.com", "example.com:80", false));
+        assert!(check("https://example.com", "example.com", true));
+        assert!(check("https://example.com:443", "example.com", true));
+        assert!(check("http://example.com:8080", "example.com:8080", false));
+
+        assert!(!check("http://example.com", "example.com", true));
+        assert!(!check("https://example.com", "example.com", false));
+        assert!(!check("http://example.com:8080", "example.com", false));
+        assert!(!check("http://evil.com", "example.com", false));
+        assert!(!check("http://example.com.evil.com", "example.com", false));
+        assert!(!check("null", "example.com", false));
+    }
+
+    #[test]
+    fn protocols() {
+        let buf = b"GET / HTTP/1.1\r\n\
+                    Connection: Upgrade\r\n\
+                    Upgrade: websocket\r\n\
+                    Sec-WebSocket-Version: 13\r\n\
+                    Sec-WebSocket-Protocol: chat;q=0.5, superchat\r\n\
+                    Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\
+                    sec-websocket-protocol: json ; q=0.8,,legacy;q=0\r\n\r\n";
+        let req = Request::parse(buf).unwrap().unwrap();
+        assert_eq!(
+            req.protocols().unwrap(),
+            vec!["chat", "superchat", "json", "legacy"]
+        );
+        assert_eq!(
+            req.weighted_protocols().unwrap(),
+            vec![("superchat", 1.0), ("json", 0.8), ("chat", 0.5)]
+        );
+
+        let mut res = Response::from_request(&req).unwrap();
+        assert!(res.set_protocol("mqtt").is_err());
+        assert_eq!(res.protocol().unwrap(), None);
+        res.set_protocol("json").unwrap();
+        assert_eq!(res.protocol().unwrap(), Some("json"));
+
+        let mut req = req;
+        req.headers_mut()
+            .push(("Sec-WebSocket-Protocol".into(), b"chat;q=2".to_vec()));
+        assert!(req.weighted_protocols().is_err());
+
+        // responses that do not answer a request accept any protocol
+        let mut res = Response::new(101, "Switching Protocols", vec![]);
+        res.set_protocol("mqtt").unwrap();
+    }
+
+    #[test]
+    fn accept_key() {
+        let mut buf = Vec::new();
+        write!(
+            &mut buf,
+            "GET / HTTP/1.1\r\n\
+             Connection: Upgrade\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+        ).unwrap();
+        let req = Request::parse(&buf).unwrap().unwrap();
+
+        // the example from RFC 6455
+        assert!(req.matches_accept_key(b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=").unwrap());
+        assert!(!req.matches_accept_key(b"s3pPLMBiTxaQ9kYGzzhZRbK+xOp=").unwrap());
+        assert!(!req.matches_accept_key(b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo").unwrap());
+    }
+
     #[test]
     fn remote_addr() {
         let mut buf = Vec::with_capacity(2048);
@@ -687,6 +1401,12 @@ mod test {
             response: res,
             peer_addr: Some(SocketAddr::from_str("127.0.0.1:8888").unwrap()),
             local_addr: None,
+            timings: HandshakeTimings::default(),
+            version: Version::Rfc6455,
+            connected_url: None,
+            connected_addr: None,
+            connection_id: 0,
+            server_name: None,
         };
         assert_eq!(shake.remote_addr().unwrap().unwrap(), "127.0.0.1");
     }
@@ -711,6 +1431,12 @@ mod test {
             response: res,
             peer_addr: None,
             local_addr: None,
+            timings: HandshakeTimings::default(),
+            version: Version::Rfc6455,
+            connected_url: None,
+            connected_addr: None,
+            connection_id: 0,
+            server_name: None,
         };
         assert_eq!(shake.remote_addr().unwrap().unwrap(), "192.168.1.1");
     }
@@ -734,7 +1460,155 @@ mod test {
             response: res,
             peer_addr: None,
             local_addr: None,
+            timings: HandshakeTimings::default(),
+            version: Version::Rfc6455,
+            connected_url: None,
+            connected_addr: None,
+            connection_id: 0,
+            server_name: None,
         };
         assert_eq!(shake.remote_addr().unwrap().unwrap(), "192.0.2.43");
     }
+
+    #[test]
+    fn negotiate_version() {
+        let parse = |version: &str| {
+            let mut buf = Vec::with_capacity(2048);
+            write!(
+                &mut buf,
+                "GET / HTTP/1.1\r\n\
+                 Connection: Upgrade\r\n\
+                 Upgrade: websocket\r\n\
+                 {}\
+                 Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+                version
+            ).unwrap();
+            Request::parse(&buf).unwrap().unwrap()
+        };
+
+        let req = parse("Sec-WebSocket-Version: 13\r\n");
+        assert_eq!(req.negotiate_version(), Some(Version::Rfc6455));
+
+        let req = parse("Sec-WebSocket-Version: 8, 13\r\n");
+        assert_eq!(req.negotiate_version(), Some(Version::Rfc6455));
+
+        let req = parse("Sec-WebSocket-Version: 8\r\n");
+        assert_eq!(req.negotiate_version(), None);
+
+        let req = parse("");
+        assert_eq!(req.negotiate_version(), None);
+    }
+
+    #[test]
+    fn request_from_url() {
+        let host = |url: &str| {
+            let req = Request::from_url(&url::Url::parse(url).unwrap()).unwrap();
+            String::from_utf8(req.header("host").unwrap().clone()).unwrap()
+        };
+        // default ports are left out, since some gateways reject them
+        assert_eq!(host("ws://example.com"), "example.com");
+        assert_eq!(host("ws://example.com:80/chat"), "example.com");
+        assert_eq!(host("wss://example.com:443"), "example.com");
+        assert_eq!(host("wss://example.com:80"), "example.com:80");
+        assert_eq!(host("ws://example.com:8080"), "example.com:8080");
+        assert_eq!(host("ws://[::1]:9001"), "[::1]:9001");
+        assert_eq!(host("wss://[::1]"), "[::1]");
+
+        let path = |url: &str| {
+            Request::from_url(&url::Url::parse(url).unwrap())
+                .unwrap()
+                .resource()
+                .to_owned()
+        };
+        assert_eq!(path("ws://example.com"), "/");
+        assert_eq!(path("ws://example.com/a%2fb%7e%41"), "/a%2Fb~A");
+        assert_eq!(path("ws://example.com/a b?q=%7e%zz&r=a b"), "/a%20b?q=~%25zz&r=a%20b");
+        assert_eq!(path("ws://example.com/caf\u{e9}"), "/caf%C3%A9");
+        // the fragment is never sent
+        assert_eq!(path("ws://example.com/chat#top"), "/chat");
+    }
+
+    #[test]
+    fn extension_offers() {
+        let mut buf = Vec::with_capacity(2048);
+        write!(
+            &mut buf,
+            "GET / HTTP/1.1\r\n\
+             Connection: Upgrade\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Extensions: x-custom; tag=\"a,b\", permessage-deflate\r\n\
+             Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n"
+        ).unwrap();
+        let req = Request::parse(&buf).unwrap().unwrap();
+        // the comma in the quoted string does not separate extensions
+        assert_eq!(
+            req.extensions().unwrap(),
+            vec!["x-custom; tag=\"a,b\"", "permessage-deflate"]
+        );
+        // but it is not a valid parameter value
+        assert!(req.extension_offers().is_err());
+    }
+
+    #[test]
+    fn upgrade_required() {
+        let res = Response::upgrade_required();
+        assert_eq!(res.status(), 426);
+        assert_eq!(res.header("sec-websocket-version").unwrap(), b"13");
+    }
+
+    #[test]
+    fn response_builder() {
+        let mut buf = Vec::new();
+        write!(
+            &mut buf,
+            "GET / HTTP/1.1\r\n\
+             Connection: Upgrade\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Protocol: chat, superchat\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+        ).unwrap();
+        let req = Request::parse(&buf).unwrap().unwrap();
+
+        let res = ResponseBuilder::from_request(&req)
+            .with_protocol("chat")
+            .with_extension("permessage-deflate")
+            .with_header("X-Server", "ws-rs")
+            .build()
+            .unwrap();
+        assert_eq!(res.status(), 101);
+        assert_eq!(res.reason(), "Switching Protocols");
+        assert_eq!(res.key().unwrap(), b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(res.protocol().unwrap(), Some("chat"));
+        assert_eq!(res.extensions().unwrap(), vec!["permessage-deflate"]);
+        assert_eq!(res.header("x-server").unwrap(), b"ws-rs");
+
+        // the protocol must have been offered
+        assert!(ResponseBuilder::from_request(&req)
+            .with_protocol("mqtt")
+            .build()
+            .is_err());
+        // a switching response needs a key to answer
+        assert!(ResponseBuilder::new().build().is_err());
+        // headers must not be able to break out of their line
+        assert!(ResponseBuilder::from_request(&req)
+            .with_header("X-Server", "ws-rs\r\nX-Injected: true")
+            .build()
+            .is_err());
+        assert!(ResponseBuilder::from_request(&req)
+            .with_header("X Server", "ws-rs")
+            .build()
+            .is_err());
+        assert!(ResponseBuilder::new().with_status(42).build().is_err());
+
+        let res = ResponseBuilder::new()
+            .with_status(403)
+            .with_body(b"Forbidden".to_vec())
+            .build()
+            .unwrap();
+        assert_eq!(res.reason(), "Forbidden");
+        assert_eq!(res.header("content-length").unwrap(), b"9");
+        assert!(res.key().is_err());
+    }
 }
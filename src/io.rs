@@ -1,37 +1,77 @@
-use std::borrow::Borrow;
-use std::io::{Error as IoError, ErrorKind};
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::time::Duration;
+use std::mem;
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind, Write};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::mpsc;
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::usize;
 
 use mio;
 use mio::tcp::{TcpListener, TcpStream};
 use mio::{Poll, PollOpt, Ready, Token};
+#[cfg(unix)]
+use mio::unix::UnixReady;
 use mio_extras;
+use mio_extras::timer::Timeout as TimerTimeout;
 
 use url::Url;
 
 #[cfg(feature = "native_tls")]
 use native_tls::Error as SslError;
+#[cfg(feature = "rustls")]
+use rustls::ServerConfig;
 
-use super::Settings;
-use communication::{Command, Sender, Signal};
+use super::{ListenerTls, Settings};
+use communication::{
+    Command, ConnectTarget, HandshakeStats, Producers, ProducerStats, Sender, Signal,
+};
 use connection::Connection;
+use event::{ErrorPhase, WsEvent};
 use factory::Factory;
+use message::Message;
+use middleware::Chain;
+use pool::{BufferPool, Memory};
+use protocol::CloseCode;
+use proxy::Proxy;
 use slab::Slab;
+use stream::connect_tcp;
+#[cfg(unix)]
+use stream::connect_unix;
+use stream::Stream;
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+use stream::TlsClientOptions;
 use result::{Error, Kind, Result};
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+use writer::{Completion, Writer, WriterPool};
 
 
 const QUEUE: Token = Token(usize::MAX - 3);
 const TIMER: Token = Token(usize::MAX - 4);
 pub const ALL: Token = Token(usize::MAX - 5);
 const SYSTEM: Token = Token(usize::MAX - 6);
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+const WRITER: Token = Token(usize::MAX - 7);
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+const TLS_HANDSHAKE: Token = Token(usize::MAX - 8);
+const HANDSHAKE: Token = Token(usize::MAX - 9);
+const ACKS: Token = Token(usize::MAX - 10);
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+const CLIENT_HELLO: Token = Token(usize::MAX - 11);
+// the tokens of listeners added with `add_listener` count down from here
+const FIRST_LISTENER: usize = usize::MAX - 1024;
+
+// System timeout events
+const SHRINK_BUFFERS: Token = Token(0);
+const REBIND_LISTENER: Token = Token(1);
 
 type Conn<F> = Connection<<F as Factory>::Handler>;
 
 const MAX_EVENTS: usize = 1024;
 const MESSAGES_PER_TICK: usize = 256;
-const TIMER_TICK_MILLIS: u64 = 100;
 const TIMER_WHEEL_SIZE: usize = 1024;
 const TIMER_CAPACITY: usize = 65_536;
 
@@ -40,6 +80,18 @@ const CONNECTION_REFUSED: i32 = 111;
 #[cfg(windows)]
 const CONNECTION_REFUSED: i32 = 61;
 
+// Whether the event loop reported an error or hang up on a socket.
+#[cfg(unix)]
+fn reports_error(events: Ready) -> bool {
+    let events = UnixReady::from(events);
+    events.is_error() || events.is_hup()
+}
+
+#[cfg(not(unix))]
+fn reports_error(_: Ready) -> bool {
+    false
+}
+
 fn url_to_addrs(url: &Url) -> Result<Vec<SocketAddr>> {
     let host = url.host_str();
     if host.is_none() || (url.scheme() != "ws" && url.scheme() != "wss") {
@@ -58,6 +110,54 @@ fn url_to_addrs(url: &Url) -> Result<Vec<SocketAddr>> {
     Ok(addrs)
 }
 
+// Client connections through a proxy connect to the proxy rather than the server.
+fn connect_addrs(url: &Url, proxy: Option<&Proxy>) -> Result<Vec<SocketAddr>> {
+    match proxy {
+        Some(proxy) => {
+            if url.host_str().is_none() || (url.scheme() != "ws" && url.scheme() != "wss") {
+                return Err(Error::new(
+                    Kind::Internal,
+                    format!("Not a valid websocket url: {}", url),
+                ));
+            }
+            Ok(proxy.addrs().to_vec())
+        }
+        None => url_to_addrs(url),
+    }
+}
+
+/// Split a `ws+unix` url into the path of the socket and the url to use for the handshake. The
+/// socket path is separated from the requested resource by the first colon, as in
+/// `ws+unix:///tmp/app.sock:/chat`. Without a colon the resource defaults to `/`.
+#[cfg(unix)]
+fn unix_url_to_path(url: &Url) -> Result<(PathBuf, Url)> {
+    let (socket, resource) = match url.path().find(':') {
+        Some(idx) => (&url.path()[..idx], &url.path()[idx + 1..]),
+        None => (url.path(), "/"),
+    };
+    if url.scheme() != "ws+unix" || socket.is_empty() {
+        return Err(Error::new(
+            Kind::Internal,
+            format!("Not a valid websocket unix socket url: {}", url),
+        ));
+    }
+
+    let resource = if resource.starts_with('/') {
+        resource.to_owned()
+    } else {
+        format!("/{}", resource)
+    };
+    let mut handshake_url = Url::parse(&format!("ws://localhost{}", resource)).map_err(|err| {
+        Error::new(
+            Kind::Internal,
+            format!("Not a valid websocket unix socket url: {} ({})", url, err),
+        )
+    })?;
+    handshake_url.set_query(url.query());
+
+    Ok((PathBuf::from(socket), handshake_url))
+}
+
 enum State {
     Active,
     Inactive,
@@ -76,6 +176,34 @@ impl State {
 pub struct Timeout {
     connection: Token,
     event: Token,
+    // identifies timeouts scheduled by handlers while they are pending, 0 for internal timeouts
+    id: u64,
+}
+
+fn new_timer(settings: &Settings) -> mio_extras::timer::Timer<Timeout> {
+    mio_extras::timer::Builder::default()
+        .tick_duration(Duration::from_millis(settings.timer_tick_ms))
+        .num_slots(TIMER_WHEEL_SIZE)
+        .capacity(TIMER_CAPACITY)
+        .build()
+}
+
+// A connection attempt requested with `Sender::connect_with_token`, so that the requester can be
+// told if the attempt is cancelled.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectRequest {
+    pub requester: Token,
+    pub connection_id: u64,
+    pub user_token: Token,
+}
+
+// A listener added with `Handler::add_listener`.
+struct Listener {
+    socket: TcpListener,
+    // how the connections accepted by this listener are encrypted
+    tls: ListenerTls,
+    // whether the listener may have connections left to accept without becoming readable again
+    pending: bool,
 }
 
 pub struct Handler<F>
@@ -83,14 +211,55 @@ where
     F: Factory,
 {
     listener: Option<TcpListener>,
+    // listeners added with `add_listener`, by token
+    listeners: HashMap<Token, Listener>,
+    // the number of listeners added so far, which gives the token of the next one
+    listeners_added: usize,
+    accept_paused: bool,
+    // the address that the listener is bound to, kept so that it can be bound again
+    listen_addr: Option<SocketAddr>,
+    // consecutive failures to accept a connection
+    accept_failures: usize,
+    // whether the listener was closed after failing and is waiting to be bound again
+    rebinding: bool,
+    // whether the listener may have connections left to accept without becoming readable again
+    accept_pending: bool,
     connections: Slab<Conn<F>>,
     factory: F,
     settings: Settings,
     state: State,
     queue_tx: mio::channel::SyncSender<Command>,
     queue_rx: mio::channel::Receiver<Command>,
+    // the queue can only be registered once, so later runs reregister it
+    queue_registered: bool,
     timer: mio_extras::timer::Timer<Timeout>,
-    next_connection_id: u32,
+    next_connection_id: u64,
+    observers: Vec<mpsc::SyncSender<WsEvent>>,
+    pool: BufferPool,
+    memory: Memory,
+    // the pending timeouts scheduled by the handler of each connection, by id
+    timeouts: HashMap<Token, HashMap<u64, TimerTimeout>>,
+    next_timeout_id: u64,
+    peer_ips: HashMap<Token, IpAddr>,
+    pending_reads: Vec<Token>,
+    pending_writes: Vec<Token>,
+    connections_per_ip: HashMap<IpAddr, usize>,
+    // accepted connections performing the opening handshake and when they were accepted
+    pending_handshakes: HashMap<Token, Instant>,
+    // connections with a check for overdue acknowledgments scheduled, by the id of the connection
+    // that the check was last scheduled for
+    ack_checks: HashMap<Token, u64>,
+    // client connections requested with a user token, by the token of the new connection
+    connect_requests: HashMap<Token, ConnectRequest>,
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    tls_client: TlsClientOptions,
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    writers: Option<WriterPool>,
+    #[cfg(feature = "rustls")]
+    rustls_server: Option<Arc<ServerConfig>>,
+    middleware: Chain,
+    producers: Producers,
+    proxy: Option<Proxy>,
 }
 
 impl<F> Handler<F>
@@ -99,52 +268,720 @@ where
 {
     pub fn new(factory: F, settings: Settings) -> Handler<F> {
         let (tx, rx) = mio::channel::sync_channel(settings.max_connections * settings.queue_size);
-        let timer = mio_extras::timer::Builder::default()
-            .tick_duration(Duration::from_millis(TIMER_TICK_MILLIS))
-            .num_slots(TIMER_WHEEL_SIZE)
-            .capacity(TIMER_CAPACITY)
-            .build();
         Handler {
             listener: None,
+            listeners: HashMap::new(),
+            listeners_added: 0,
+            accept_paused: false,
+            listen_addr: None,
+            accept_failures: 0,
+            rebinding: false,
+            accept_pending: false,
             connections: Slab::with_capacity(settings.max_connections),
             factory,
             settings,
             state: State::Inactive,
             queue_tx: tx,
             queue_rx: rx,
-            timer,
+            queue_registered: false,
+            timer: new_timer(&settings),
             next_connection_id: 0,
+            observers: Vec::new(),
+            pool: BufferPool::new(&settings),
+            memory: Memory::new(settings.max_total_buffer_memory),
+            timeouts: HashMap::new(),
+            next_timeout_id: 1,
+            peer_ips: HashMap::new(),
+            pending_reads: Vec::new(),
+            pending_writes: Vec::new(),
+            connections_per_ip: HashMap::new(),
+            pending_handshakes: HashMap::new(),
+            ack_checks: HashMap::new(),
+            connect_requests: HashMap::new(),
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            tls_client: TlsClientOptions::default(),
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            writers: None,
+            #[cfg(feature = "rustls")]
+            rustls_server: None,
+            middleware: Chain::default(),
+            producers: Producers::default(),
+            proxy: None,
+        }
+    }
+
+    pub fn with_proxy(mut self, proxy: Option<Proxy>) -> Handler<F> {
+        self.proxy = proxy;
+        self
+    }
+
+    pub fn with_middleware(mut self, middleware: Chain) -> Handler<F> {
+        self.middleware = middleware;
+        self
+    }
+
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn with_tls_client_options(mut self, options: TlsClientOptions) -> Handler<F> {
+        self.tls_client = options;
+        self
+    }
+
+    #[cfg(feature = "rustls")]
+    pub fn with_rustls_server_config(mut self, config: Option<Arc<ServerConfig>>) -> Handler<F> {
+        self.rustls_server = config;
+        self
+    }
+
+    // Whether accepted connections are encrypted with rustls rather than by the handler.
+    #[inline]
+    fn uses_rustls(&self) -> bool {
+        #[cfg(feature = "rustls")]
+        {
+            self.rustls_server.is_some()
+        }
+        #[cfg(not(feature = "rustls"))]
+        {
+            false
+        }
+    }
+
+    // The stream for a socket accepted by the given listener, encrypted with rustls if it is
+    // configured and the listener is not a plaintext one.
+    #[cfg_attr(not(feature = "rustls"), allow(unused_variables))]
+    fn server_stream(&self, sock: TcpStream, listener: Option<Token>) -> Result<Stream> {
+        #[cfg(feature = "rustls")]
+        {
+            if let Some(ref config) = self.rustls_server {
+                if self.listener_tls(listener) != Some(ListenerTls::Plain) {
+                    return Stream::rustls(sock, config.clone());
+                }
+            }
+        }
+        Ok(Stream::tcp(sock))
+    }
+
+    // How the connections accepted by a listener added with `add_listener` are encrypted.
+    fn listener_tls(&self, listener: Option<Token>) -> Option<ListenerTls> {
+        listener
+            .and_then(|token| self.listeners.get(&token))
+            .map(|listener| listener.tls)
+    }
+
+    // The settings of a connection accepted by the given listener, which decides whether the
+    // connection is encrypted in place of `encrypt_server` and `tls_auto_detect`.
+    fn listener_settings(&self, listener: Option<Token>, mut settings: Settings) -> Settings {
+        if let Some(tls) = self.listener_tls(listener) {
+            settings.encrypt_server = tls == ListenerTls::Encrypted;
+            settings.tls_auto_detect = tls == ListenerTls::AutoDetect;
+        }
+        settings
+    }
+
+    // Get a handle to the TLS writer threads, starting them on first use.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn writer(&mut self, poll: &mut Poll) -> Result<Option<Writer>> {
+        if self.settings.tls_write_threads == 0 {
+            return Ok(None);
+        }
+        if self.writers.is_none() {
+            let pool = WriterPool::new(self.settings.tls_write_threads)?;
+            poll.register(
+                pool.completions(),
+                WRITER,
+                Ready::readable(),
+                PollOpt::edge() | PollOpt::oneshot(),
+            )?;
+            self.writers = Some(pool);
+        }
+        Ok(self.writers.as_ref().map(WriterPool::writer))
+    }
+
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn attach_writer(&mut self, poll: &mut Poll, tok: Token) {
+        match self.writer(poll) {
+            Ok(Some(writer)) => self.connections[tok.into()].set_writer(writer),
+            Ok(None) => (),
+            Err(err) => error!("Unable to start TLS writer threads: {}", err),
+        }
+    }
+
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn schedule_tls_timeout(&mut self, tok: Token) {
+        if self.settings.tls_handshake_timeout_ms > 0 {
+            self.timer.set_timeout(
+                Duration::from_millis(self.settings.tls_handshake_timeout_ms),
+                Timeout {
+                    connection: TLS_HANDSHAKE,
+                    event: tok,
+                    id: 0,
+                },
+            );
+        }
+    }
+
+    // Retry reading the ClientHello of a connection after a short delay, since the event loop is
+    // not notified of the rest of it while the part already received remains unread.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn schedule_client_hello(&mut self, tok: Token) {
+        if self
+            .connections
+            .get(tok.into())
+            .map_or(false, |conn| conn.awaits_client_hello())
+        {
+            self.timer.set_timeout(
+                Duration::from_millis(self.settings.timer_tick_ms),
+                Timeout {
+                    connection: CLIENT_HELLO,
+                    event: tok,
+                    id: 0,
+                },
+            );
         }
     }
 
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn retry_client_hello(&mut self, poll: &mut Poll, tok: Token) {
+        if !self
+            .connections
+            .get(tok.into())
+            .map_or(false, |conn| conn.awaits_client_hello())
+        {
+            return;
+        }
+        if !self.read_connection(poll, tok) {
+            return;
+        }
+        let active = {
+            let conn = &self.connections[tok.into()];
+            conn.events().is_readable() || conn.events().is_writable()
+        };
+        self.check_active(poll, active, tok, ErrorPhase::Read);
+        self.schedule_client_hello(tok);
+    }
+
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn check_tls_timeout(&mut self, poll: &mut Poll, tok: Token) {
+        let timeout = Duration::from_millis(self.settings.tls_handshake_timeout_ms);
+        let active = {
+            if let Some(conn) = self.connections.get_mut(tok.into()) {
+                let deadline = match conn.tls_handshake_deadline(timeout) {
+                    Some(deadline) => deadline,
+                    None => return,
+                };
+                let now = Instant::now();
+                if deadline > now {
+                    // the token was reused by a newer connection or the timer fired early
+                    self.timer.set_timeout(
+                        deadline - now,
+                        Timeout {
+                            connection: TLS_HANDSHAKE,
+                            event: tok,
+                            id: 0,
+                        },
+                    );
+                    return;
+                }
+                conn.error(ErrorPhase::Timer, Error::new(
+                    Kind::TlsTimeout,
+                    format!(
+                        "The TLS handshake did not complete within {}ms.",
+                        self.settings.tls_handshake_timeout_ms
+                    ),
+                ));
+                conn.events().is_readable() || conn.events().is_writable()
+            } else {
+                return;
+            }
+        };
+        self.check_active(poll, active, tok, ErrorPhase::Timer);
+    }
+
     pub fn sender(&self) -> Sender {
         Sender::new(ALL, self.queue_tx.clone(), 0)
+            .with_producers(self.producers.clone())
+            .with_settings(&self.settings)
     }
 
-    pub fn listen(&mut self, poll: &mut Poll, addr: &SocketAddr) -> Result<&mut Handler<F>> {
-        debug_assert!(
-            self.listener.is_none(),
-            "Attempted to listen for connections from two addresses on the same websocket."
+    pub fn producer_stats(&self) -> Vec<ProducerStats> {
+        self.producers.stats()
+    }
+
+    pub fn handshake_stats(&self) -> HandshakeStats {
+        self.producers.handshake_stats()
+    }
+
+    pub fn subscribe_events(&mut self) -> mpsc::Receiver<WsEvent> {
+        let (tx, rx) = mpsc::sync_channel(self.settings.event_queue_size);
+        self.observers.push(tx);
+        rx
+    }
+
+    #[inline]
+    fn emit(&mut self, event: WsEvent) {
+        if !self.observers.is_empty() {
+            // Drop any observers that have hung up, and the event for those that are full
+            self.observers.retain(|tx| match tx.try_send(event.clone()) {
+                Err(mpsc::TrySendError::Disconnected(_)) => false,
+                Err(mpsc::TrySendError::Full(_)) => {
+                    trace!("Dropping event for a full observer: {:?}", event);
+                    true
+                }
+                Ok(()) => true,
+            });
+        }
+    }
+
+    #[inline]
+    fn emit_error(&mut self, token: Option<Token>, peer_addr: Option<SocketAddr>, err: &Error) {
+        self.emit(WsEvent::Error {
+            token,
+            peer_addr,
+            details: err.to_string(),
+        });
+    }
+
+    fn remove_connection(&mut self, token: Token) {
+        let conn = self.connections.remove(token.into());
+        let peer_addr = conn.peer_socket_addr();
+        self.emit(WsEvent::Closed { token, peer_addr });
+        self.release_ip(token);
+        self.pending_handshakes.remove(&token);
+        self.connect_requests.remove(&token);
+        self.cancel_timeouts(token);
+        let (handler, buffers) = conn.recycle();
+        self.pool.give(&self.settings, buffers);
+        self.factory.connection_lost(handler);
+    }
+
+    // Whether another connection from this address is within the budget set by the
+    // `max_connections_per_ip` setting.
+    fn ip_has_capacity(&self, ip: &IpAddr) -> bool {
+        self.connections_per_ip.get(ip).cloned().unwrap_or(0) < self.settings.max_connections_per_ip
+    }
+
+    fn track_ip(&mut self, token: Token, ip: IpAddr) {
+        if self.settings.max_connections_per_ip != usize::MAX {
+            *self.connections_per_ip.entry(ip).or_insert(0) += 1;
+            self.peer_ips.insert(token, ip);
+        }
+    }
+
+    fn release_ip(&mut self, token: Token) {
+        if let Some(ip) = self.peer_ips.remove(&token) {
+            let remaining = self.connections_per_ip.get_mut(&ip).map(|count| {
+                *count -= 1;
+                *count
+            });
+            if remaining == Some(0) {
+                self.connections_per_ip.remove(&ip);
+            }
+        }
+    }
+
+    // Whether another accepted connection may start the opening handshake within the budget set
+    // by the `max_pending_handshakes` setting.
+    fn handshake_has_capacity(&mut self) -> bool {
+        if self.settings.max_pending_handshakes == usize::MAX {
+            return true;
+        }
+        let connections = &self.connections;
+        self.pending_handshakes.retain(|tok, _| match connections.get(tok.0) {
+            Some(conn) => conn.is_connecting(),
+            None => false,
+        });
+        self.pending_handshakes.len() < self.settings.max_pending_handshakes
+    }
+
+    fn track_handshake(&mut self, tok: Token) {
+        if self.settings.max_pending_handshakes != usize::MAX
+            || self.settings.handshake_timeout_ms > 0
+        {
+            self.pending_handshakes.insert(tok, Instant::now());
+        }
+        if self.settings.handshake_timeout_ms > 0 {
+            self.timer.set_timeout(
+                Duration::from_millis(self.settings.handshake_timeout_ms),
+                Timeout {
+                    connection: HANDSHAKE,
+                    event: tok,
+                    id: 0,
+                },
+            );
+        }
+    }
+
+    // Schedule a check for overdue acknowledgments on a connection, unless one is already
+    // scheduled. A check left behind by a previous connection with the same token serves the new
+    // connection instead.
+    fn schedule_ack_check(&mut self, tok: Token) {
+        let (deadline, connection_id) = match self.connections.get(tok.into()) {
+            Some(conn) => match conn.next_ack_deadline() {
+                Some(deadline) => (deadline, conn.connection_id()),
+                None => return,
+            },
+            None => return,
+        };
+        if self.ack_checks.insert(tok, connection_id).is_some() {
+            return;
+        }
+        self.timer.set_timeout(
+            deadline.saturating_duration_since(Instant::now()),
+            Timeout {
+                connection: ACKS,
+                event: tok,
+                id: 0,
+            },
         );
+    }
+
+    fn check_acks(&mut self, tok: Token) {
+        let connection_id = match self.ack_checks.remove(&tok) {
+            Some(id) => id,
+            None => return,
+        };
+        match self.connections.get_mut(tok.into()) {
+            Some(conn) if conn.connection_id() == connection_id => conn.expire_acks(),
+            _ => return,
+        }
+        self.schedule_ack_check(tok);
+    }
+
+    fn check_handshake_timeout(&mut self, poll: &mut Poll, tok: Token) {
+        let timeout = Duration::from_millis(self.settings.handshake_timeout_ms);
+        let active = {
+            let deadline = match self.pending_handshakes.get(&tok) {
+                Some(&accepted) => accepted + timeout,
+                None => return,
+            };
+            let now = Instant::now();
+            if deadline > now {
+                // the token was reused by a newer connection or the timer fired early
+                self.timer.set_timeout(
+                    deadline - now,
+                    Timeout {
+                        connection: HANDSHAKE,
+                        event: tok,
+                        id: 0,
+                    },
+                );
+                return;
+            }
+            self.pending_handshakes.remove(&tok);
+            match self.connections.get_mut(tok.into()) {
+                Some(ref mut conn) if conn.is_connecting() => {
+                    conn.error(ErrorPhase::Timer, Error::new(
+                        Kind::HandshakeTimeout,
+                        format!(
+                            "The opening handshake did not complete within {}ms.",
+                            self.settings.handshake_timeout_ms
+                        ),
+                    ));
+                    conn.events().is_readable() || conn.events().is_writable()
+                }
+                _ => return,
+            }
+        };
+        self.check_active(poll, active, tok, ErrorPhase::Timer);
+    }
+
+    pub fn listen(&mut self, poll: &mut Poll, addr: &SocketAddr) -> Result<&mut Handler<F>> {
+        if let Some(ref listener) = self.listener {
+            return Err(Error::new(
+                Kind::Internal,
+                format!(
+                    "Attempted to listen on {} while the WebSocket is already listening on {}.",
+                    addr,
+                    listener.local_addr()?
+                ),
+            ));
+        }
 
         let tcp = TcpListener::bind(addr)?;
         // TODO: consider net2 in order to set reuse_addr
-        poll.register(&tcp, ALL, Ready::readable(), PollOpt::level())?;
+        poll.register(&tcp, ALL, Ready::readable(), PollOpt::edge())?;
+        self.listen_addr = Some(tcp.local_addr()?);
         self.listener = Some(tcp);
         Ok(self)
     }
 
+    // Listen on another address, encrypting the connections accepted there as `tls` says.
+    pub fn add_listener(
+        &mut self,
+        poll: &mut Poll,
+        addr: &SocketAddr,
+        tls: ListenerTls,
+    ) -> Result<Token> {
+        let tcp = TcpListener::bind(addr)?;
+        let token = Token(FIRST_LISTENER - self.listeners_added);
+        poll.register(&tcp, token, Ready::readable(), PollOpt::edge())?;
+        self.listeners_added += 1;
+        self.listeners.insert(
+            token,
+            Listener {
+                socket: tcp,
+                tls,
+                pending: false,
+            },
+        );
+        Ok(token)
+    }
+
+    pub fn listener_addr(&self, token: Token) -> ::std::io::Result<SocketAddr> {
+        match self.listeners.get(&token) {
+            Some(listener) => listener.socket.local_addr(),
+            None => Err(IoError::new(ErrorKind::NotFound, "Not a listener")),
+        }
+    }
+
+    // Count a failure to accept a connection, and close the listener to bind it again later once
+    // `Settings::max_accept_failures` consecutive accepts have failed.
+    fn accept_failed(&mut self, poll: &mut Poll, err: IoError) {
+        error!("Encountered an error {:?} while accepting tcp connection.", err);
+        self.accept_failures += 1;
+        let max = self.settings.max_accept_failures;
+        if max == 0 || self.accept_failures < max {
+            return;
+        }
+        let err = Error::new(
+            Kind::Io(err),
+            format!(
+                "The listening socket failed to accept {} connections in a row and will be bound \
+                 again in {} ms.",
+                self.accept_failures, self.settings.listener_rebind_delay_ms
+            ),
+        );
+        warn!("{}", err);
+        if let Some(listener) = self.listener.take() {
+            if !self.accept_paused {
+                if let Err(err) = poll.deregister(&listener) {
+                    error!("Unable to deregister the listening socket: {}", err);
+                }
+            }
+        }
+        self.rebinding = true;
+        self.emit_error(None, None, &err);
+        self.factory.on_listener_error(err);
+        self.schedule_rebind();
+    }
+
+    // Accept connections until the listener would block. At most
+    // `Settings::max_accepts_per_event` are accepted at once; the rest are accepted on the next
+    // iteration of the event loop, since the edge-triggered listener will not report them again.
+    fn accept_connections(&mut self, poll: &mut Poll) {
+        for _ in 0..self.settings.max_accepts_per_event {
+            let accepted = match self.listener {
+                // the listener is not registered, so it will become readable again once resumed
+                Some(_) if self.accept_paused => return,
+                Some(ref listener) => listener.accept(),
+                // the listener failed and was closed after this event was polled
+                None => return,
+            };
+            match accepted {
+                Ok((sock, addr)) => {
+                    self.accept_failures = 0;
+                    self.accepted(poll, sock, addr, None);
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(err) => {
+                    self.accept_failed(poll, err);
+                    // the connection that failed is still in the backlog, so try again on the
+                    // next iteration rather than waiting for another connection to arrive
+                    self.accept_pending = self.listener.is_some();
+                    return;
+                }
+            }
+        }
+        trace!(
+            "Accepted {} connections, deferring the rest.",
+            self.settings.max_accepts_per_event
+        );
+        self.accept_pending = true;
+    }
+
+    // Accept connections from a listener added with `add_listener`, as `accept_connections` does.
+    // Failures are reported without closing the listener, which becomes readable again with the
+    // next incoming connection.
+    fn accept_from(&mut self, poll: &mut Poll, token: Token) {
+        for _ in 0..self.settings.max_accepts_per_event {
+            let accepted = match self.listeners.get(&token) {
+                Some(_) if self.accept_paused => return,
+                Some(listener) => listener.socket.accept(),
+                None => return,
+            };
+            match accepted {
+                Ok((sock, addr)) => self.accepted(poll, sock, addr, Some(token)),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(err) => {
+                    error!("Encountered an error {:?} while accepting tcp connection.", err);
+                    let err = Error::new(Kind::Io(err), "Unable to accept a connection.");
+                    self.emit_error(None, None, &err);
+                    return;
+                }
+            }
+        }
+        trace!(
+            "Accepted {} connections, deferring the rest.",
+            self.settings.max_accepts_per_event
+        );
+        if let Some(listener) = self.listeners.get_mut(&token) {
+            listener.pending = true;
+        }
+    }
+
+    fn accepted(
+        &mut self,
+        poll: &mut Poll,
+        mut sock: TcpStream,
+        addr: SocketAddr,
+        listener: Option<Token>,
+    ) {
+        info!("Accepted a new tcp connection from {}.", addr);
+        if !self.ip_has_capacity(&addr.ip()) {
+            warn!("Rejecting connection from {}, too many connections.", addr);
+            self.producers.handshakes().responded(429);
+            let settings = self.listener_settings(listener, self.settings);
+            // A TLS client would not understand a plaintext response, so it
+            // only sees the socket close.
+            if !settings.encrypt_server && !settings.tls_auto_detect {
+                // The socket was just accepted, so the response will fit in
+                // the send buffer. Failure to write it is of no consequence.
+                let _ =
+                    sock.write(b"HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\n\r\n");
+            }
+            self.emit_error(
+                None,
+                Some(addr),
+                &Error::new(Kind::Capacity, "Too many connections from the same address."),
+            );
+        } else if let Err(err) = self.accept(poll, sock, listener) {
+            self.emit_error(None, Some(addr), &err);
+            error!("Unable to build WebSocket connection {:?}", err);
+            if self.settings.panic_on_new_connection {
+                panic!("Unable to build WebSocket connection {:?}", err);
+            }
+        }
+    }
+
+    fn schedule_rebind(&mut self) {
+        self.timer.set_timeout(
+            Duration::from_millis(self.settings.listener_rebind_delay_ms),
+            Timeout {
+                connection: SYSTEM,
+                event: REBIND_LISTENER,
+                id: 0,
+            },
+        );
+    }
+
+    fn rebind_listener(&mut self, poll: &mut Poll) {
+        let addr = match self.listen_addr {
+            Some(addr) if self.rebinding => addr,
+            _ => return,
+        };
+        let result = TcpListener::bind(&addr).and_then(|tcp| {
+            if !self.accept_paused {
+                poll.register(&tcp, ALL, Ready::readable(), PollOpt::edge())?;
+            }
+            Ok(tcp)
+        });
+        match result {
+            Ok(tcp) => {
+                info!("Listening on {} again.", addr);
+                self.listener = Some(tcp);
+                self.rebinding = false;
+                self.accept_failures = 0;
+            }
+            Err(err) => {
+                let err = Error::new(
+                    Kind::Io(err),
+                    format!("Unable to bind the listening socket to {} again.", addr),
+                );
+                warn!("{}", err);
+                self.emit_error(None, None, &err);
+                self.factory.on_listener_error(err);
+                self.schedule_rebind();
+            }
+        }
+    }
+
+    fn pause_accepting(&mut self, poll: &mut Poll) {
+        let paused = self.accept_paused;
+        if self.rebinding {
+            self.accept_paused = true;
+        }
+        if let Some(ref listener) = self.listener {
+            if !self.accept_paused {
+                debug!("Pausing the acceptance of new connections.");
+                if let Err(err) = poll.deregister(listener) {
+                    error!("Unable to pause the acceptance of new connections: {}", err);
+                    return;
+                }
+                self.accept_paused = true;
+            }
+        }
+        if !paused && !self.listeners.is_empty() {
+            for listener in self.listeners.values() {
+                if let Err(err) = poll.deregister(&listener.socket) {
+                    error!("Unable to pause the acceptance of new connections: {}", err);
+                }
+            }
+            self.accept_paused = true;
+        }
+    }
+
+    fn resume_accepting(&mut self, poll: &mut Poll) {
+        let paused = self.accept_paused;
+        if self.rebinding {
+            self.accept_paused = false;
+        }
+        if let Some(ref listener) = self.listener {
+            if self.accept_paused {
+                debug!("Resuming the acceptance of new connections.");
+                if let Err(err) = poll.register(listener, ALL, Ready::readable(), PollOpt::edge()) {
+                    error!("Unable to resume the acceptance of new connections: {}", err);
+                    return;
+                }
+                self.accept_paused = false;
+            }
+        }
+        if paused && !self.listeners.is_empty() {
+            for (&token, listener) in &self.listeners {
+                if let Err(err) =
+                    poll.register(&listener.socket, token, Ready::readable(), PollOpt::edge())
+                {
+                    error!("Unable to resume the acceptance of new connections: {}", err);
+                }
+            }
+            self.accept_paused = false;
+        }
+    }
+
     pub fn local_addr(&self) -> ::std::io::Result<SocketAddr> {
         if let Some(ref listener) = self.listener {
             listener.local_addr()
+        } else if let (true, Some(addr)) = (self.rebinding, self.listen_addr) {
+            Ok(addr)
         } else {
             Err(IoError::new(ErrorKind::NotFound, "Not a listening socket"))
         }
     }
 
     #[cfg(any(feature = "ssl", feature = "nativetls"))]
-    pub fn connect(&mut self, poll: &mut Poll, url: Url) -> Result<()> {
+    pub fn connect(
+        &mut self,
+        poll: &mut Poll,
+        url: Url,
+        local_addr: Option<SocketAddr>,
+    ) -> Result<Token> {
+        #[cfg(unix)]
+        {
+            if url.scheme() == "ws+unix" {
+                return self.connect_unix(poll, url);
+            }
+        }
+
         let settings = self.settings;
+        let local_addr = local_addr.or(settings.local_bind);
 
         let (tok, addresses) = {
             let (tok, entry, connection_id, handler) =
@@ -157,11 +994,11 @@ where
                         tok,
                         entry,
                         connection_id,
-                        self.factory.client_connected(Sender::new(
-                            tok,
-                            self.queue_tx.clone(),
-                            connection_id,
-                        )),
+                        self.factory.client_connected(
+                            Sender::new(tok, self.queue_tx.clone(), connection_id)
+                                .with_producers(self.producers.clone())
+                                .with_settings(&self.settings),
+                        ),
                     )
                 } else {
                     return Err(Error::new(
@@ -170,7 +1007,7 @@ where
                     ));
                 };
 
-            let mut addresses = match url_to_addrs(&url) {
+            let mut addresses = match connect_addrs(&url, self.proxy.as_ref()) {
                 Ok(addresses) => addresses,
                 Err(err) => {
                     self.factory.connection_lost(handler);
@@ -180,12 +1017,29 @@ where
 
             loop {
                 if let Some(addr) = addresses.pop() {
-                    if let Ok(sock) = TcpStream::connect(&addr) {
+                    if let Ok(sock) = connect_tcp(&addr, local_addr, settings.local_interface) {
+                        let settings = self.factory.settings_for(&addr, self.settings);
                         if settings.tcp_nodelay {
                             sock.set_nodelay(true)?
                         }
                         addresses.push(addr); // Replace the first addr in case ssl fails and we fallback
-                        entry.insert(Connection::new(tok, sock, handler, settings, connection_id));
+                        let buffers = self.pool.take(&settings);
+                        entry.insert(Connection::new(
+                            tok,
+                            Stream::tcp(sock),
+                            handler,
+                            settings,
+                            connection_id,
+                            buffers,
+                        )
+                        .with_middleware(
+                            self.middleware.clone(),
+                            Sender::new(tok, self.queue_tx.clone(), connection_id)
+                                .with_producers(self.producers.clone())
+                                .with_settings(&self.settings),
+                        )
+                        .with_memory(self.memory.clone())
+                        .with_deferred(self.producers.deferred()));
                         break;
                     }
                 } else {
@@ -202,13 +1056,25 @@ where
 
         let will_encrypt = url.scheme() == "wss";
 
-        if let Err(error) = self.connections[tok.into()].as_client(url, addresses) {
-            let handler = self.connections.remove(tok.into()).consume();
-            self.factory.connection_lost(handler);
+        let client = {
+            let proxy = self.proxy.clone();
+            let conn = &mut self.connections[tok.into()];
+            conn.as_client(url, addresses, local_addr)
+                .and_then(|_| proxy.map_or(Ok(()), |proxy| conn.set_proxy(proxy)))
+        };
+        if let Err(error) = client {
+            self.remove_connection(tok);
             return Err(error);
         }
 
+        self.connections[tok.into()].set_tls_client_options(self.tls_client.clone());
+
         if will_encrypt {
+            self.attach_writer(poll, tok);
+        }
+
+        // Connections through a proxy are encrypted once the tunnel is established
+        if will_encrypt && self.proxy.is_none() {
             while let Err(ssl_error) = self.connections[tok.into()].encrypt() {
                 match ssl_error.kind {
                     #[cfg(feature = "ssl")]
@@ -241,10 +1107,12 @@ where
                     }
                     _ => (),
                 }
-                self.connections[tok.into()].error(ssl_error);
-                // Allow socket to be registered anyway to await hangup
-                break;
+                // The socket was consumed by the failed upgrade, so the connection is discarded
+                self.producers.handshakes().tls_failed();
+                self.remove_connection(tok);
+                return Err(ssl_error);
             }
+            self.schedule_tls_timeout(tok);
         }
 
         poll.register(
@@ -258,15 +1126,28 @@ where
                     "Encountered error while trying to build WebSocket connection: {}",
                     err
                 );
-                let handler = self.connections.remove(tok.into()).consume();
-                self.factory.connection_lost(handler);
+                self.remove_connection(tok);
                 Err(err)
             })
+            .map(|_| tok)
     }
 
     #[cfg(not(any(feature = "ssl", feature = "nativetls")))]
-    pub fn connect(&mut self, poll: &mut Poll, url: Url) -> Result<()> {
+    pub fn connect(
+        &mut self,
+        poll: &mut Poll,
+        url: Url,
+        local_addr: Option<SocketAddr>,
+    ) -> Result<Token> {
+        #[cfg(unix)]
+        {
+            if url.scheme() == "ws+unix" {
+                return self.connect_unix(poll, url);
+            }
+        }
+
         let settings = self.settings;
+        let local_addr = local_addr.or(settings.local_bind);
 
         let (tok, addresses) = {
             let (tok, entry, connection_id, handler) =
@@ -279,11 +1160,11 @@ where
                         tok,
                         entry,
                         connection_id,
-                        self.factory.client_connected(Sender::new(
-                            tok,
-                            self.queue_tx.clone(),
-                            connection_id,
-                        )),
+                        self.factory.client_connected(
+                            Sender::new(tok, self.queue_tx.clone(), connection_id)
+                                .with_producers(self.producers.clone())
+                                .with_settings(&self.settings),
+                        ),
                     )
                 } else {
                     return Err(Error::new(
@@ -292,7 +1173,7 @@ where
                     ));
                 };
 
-            let mut addresses = match url_to_addrs(&url) {
+            let mut addresses = match connect_addrs(&url, self.proxy.as_ref()) {
                 Ok(addresses) => addresses,
                 Err(err) => {
                     self.factory.connection_lost(handler);
@@ -302,11 +1183,28 @@ where
 
             loop {
                 if let Some(addr) = addresses.pop() {
-                    if let Ok(sock) = TcpStream::connect(&addr) {
+                    if let Ok(sock) = connect_tcp(&addr, local_addr, settings.local_interface) {
+                        let settings = self.factory.settings_for(&addr, self.settings);
                         if settings.tcp_nodelay {
                             sock.set_nodelay(true)?
                         }
-                        entry.insert(Connection::new(tok, sock, handler, settings, connection_id));
+                        let buffers = self.pool.take(&settings);
+                        entry.insert(Connection::new(
+                            tok,
+                            Stream::tcp(sock),
+                            handler,
+                            settings,
+                            connection_id,
+                            buffers,
+                        )
+                        .with_middleware(
+                            self.middleware.clone(),
+                            Sender::new(tok, self.queue_tx.clone(), connection_id)
+                                .with_producers(self.producers.clone())
+                                .with_settings(&self.settings),
+                        )
+                        .with_memory(self.memory.clone())
+                        .with_deferred(self.producers.deferred()));
                         break;
                     }
                 } else {
@@ -326,14 +1224,18 @@ where
                 Kind::Protocol,
                 "The ssl feature is not enabled. Please enable it to use wss urls.",
             );
-            let handler = self.connections.remove(tok.into()).consume();
-            self.factory.connection_lost(handler);
+            self.remove_connection(tok);
             return Err(error);
         }
 
-        if let Err(error) = self.connections[tok.into()].as_client(url, addresses) {
-            let handler = self.connections.remove(tok.into()).consume();
-            self.factory.connection_lost(handler);
+        let client = {
+            let proxy = self.proxy.clone();
+            let conn = &mut self.connections[tok.into()];
+            conn.as_client(url, addresses, local_addr)
+                .and_then(|_| proxy.map_or(Ok(()), |proxy| conn.set_proxy(proxy)))
+        };
+        if let Err(error) = client {
+            self.remove_connection(tok);
             return Err(error);
         }
 
@@ -348,34 +1250,56 @@ where
                     "Encountered error while trying to build WebSocket connection: {}",
                     err
                 );
-                let handler = self.connections.remove(tok.into()).consume();
-                self.factory.connection_lost(handler);
+                self.remove_connection(tok);
                 Err(err)
             })
+            .map(|_| tok)
     }
 
-    #[cfg(any(feature = "ssl", feature = "nativetls"))]
-    pub fn accept(&mut self, poll: &mut Poll, sock: TcpStream) -> Result<()> {
-        let factory = &mut self.factory;
+    #[cfg(unix)]
+    fn connect_unix(&mut self, poll: &mut Poll, url: Url) -> Result<Token> {
         let settings = self.settings;
 
-        if settings.tcp_nodelay {
-            sock.set_nodelay(true)?
-        }
-
         let tok = {
             if self.connections.len() < settings.max_connections {
                 let entry = self.connections.vacant_entry();
                 let tok = Token(entry.key());
                 let connection_id = self.next_connection_id;
                 self.next_connection_id = self.next_connection_id.wrapping_add(1);
-                let handler = factory.server_connected(Sender::new(
-                    tok,
-                    self.queue_tx.clone(),
-                    connection_id,
-                ));
-                entry.insert(Connection::new(tok, sock, handler, settings, connection_id));
-                tok
+                let handler = self.factory.client_connected(
+                    Sender::new(tok, self.queue_tx.clone(), connection_id)
+                        .with_producers(self.producers.clone())
+                        .with_settings(&self.settings),
+                );
+
+                let sock = match unix_url_to_path(&url)
+                    .and_then(|(path, _)| connect_unix(&path).map_err(Error::from))
+                {
+                    Ok(sock) => sock,
+                    Err(err) => {
+                        self.factory.connection_lost(handler);
+                        return Err(err);
+                    }
+                };
+
+                let buffers = self.pool.take(&settings);
+                entry.insert(Connection::new(
+                    tok,
+                    Stream::unix(sock),
+                    handler,
+                    settings,
+                    connection_id,
+                    buffers,
+                )
+                .with_middleware(
+                    self.middleware.clone(),
+                    Sender::new(tok, self.queue_tx.clone(), connection_id)
+                        .with_producers(self.producers.clone())
+                        .with_settings(&self.settings),
+                )
+                .with_memory(self.memory.clone())
+                .with_deferred(self.producers.deferred()));
+                tok
             } else {
                 return Err(Error::new(
                     Kind::Capacity,
@@ -384,12 +1308,139 @@ where
             }
         };
 
-        let conn = &mut self.connections[tok.into()];
+        let result = unix_url_to_path(&url)
+            .and_then(|(_, url)| self.connections[tok.into()].as_client(url, Vec::new(), None));
+        if let Err(error) = result {
+            self.remove_connection(tok);
+            return Err(error);
+        }
 
-        conn.as_server()?;
-        if settings.encrypt_server {
-            conn.encrypt()?
+        let conn = &self.connections[tok.into()];
+        if let Err(err) = poll.register(
+            conn.socket(),
+            conn.token(),
+            conn.events(),
+            PollOpt::edge() | PollOpt::oneshot(),
+        ) {
+            error!(
+                "Encountered error while trying to build WebSocket connection: {}",
+                err
+            );
+            self.remove_connection(tok);
+            return Err(err.into());
+        }
+        Ok(tok)
+    }
+
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn accept(
+        &mut self,
+        poll: &mut Poll,
+        sock: TcpStream,
+        listener: Option<Token>,
+    ) -> Result<()> {
+        let settings = match sock.peer_addr() {
+            Ok(addr) => self.factory.settings_for(&addr, self.settings),
+            Err(_) => self.settings,
+        };
+        let settings = self.listener_settings(listener, settings);
+        if !self.handshake_has_capacity() {
+            return Err(Error::new(
+                Kind::Capacity,
+                format!(
+                    "Unable to accept another connection while {} opening handshakes are pending.",
+                    self.pending_handshakes.len()
+                ),
+            ));
+        }
+        if !self
+            .memory
+            .has_room(settings.in_buffer_capacity + settings.out_buffer_capacity)
+        {
+            return Err(Error::new(
+                Kind::Capacity,
+                format!(
+                    "Unable to accept another connection while connection buffers hold {} bytes.",
+                    self.memory.used()
+                ),
+            ));
         }
+        if settings.tcp_nodelay {
+            sock.set_nodelay(true)?
+        }
+        let stream = self.server_stream(sock, listener)?;
+        let factory = &mut self.factory;
+
+        let tok = {
+            if self.connections.len() < self.settings.max_connections {
+                let entry = self.connections.vacant_entry();
+                let tok = Token(entry.key());
+                let connection_id = self.next_connection_id;
+                self.next_connection_id = self.next_connection_id.wrapping_add(1);
+                let handler = factory.server_connected(
+                    Sender::new(tok, self.queue_tx.clone(), connection_id)
+                        .with_producers(self.producers.clone())
+                        .with_settings(&self.settings)
+                        .with_listener(listener),
+                );
+                let buffers = self.pool.take(&settings);
+                entry.insert(Connection::new(
+                    tok,
+                    stream,
+                    handler,
+                    settings,
+                    connection_id,
+                    buffers,
+                )
+                .with_middleware(
+                    self.middleware.clone(),
+                    Sender::new(tok, self.queue_tx.clone(), connection_id)
+                        .with_producers(self.producers.clone())
+                        .with_settings(&self.settings)
+                        .with_listener(listener),
+                )
+                .with_memory(self.memory.clone())
+                .with_deferred(self.producers.deferred())
+                .with_handshakes(self.producers.handshakes()));
+                tok
+            } else {
+                return Err(Error::new(
+                    Kind::Capacity,
+                    "Unable to add another connection to the event loop.",
+                ));
+            }
+        };
+
+        if let Some(peer_addr) = self.connections[tok.into()].peer_socket_addr() {
+            self.track_ip(tok, peer_addr.ip());
+            self.emit(WsEvent::Accepted {
+                token: tok,
+                peer_addr,
+            });
+        }
+        self.track_handshake(tok);
+
+        self.connections[tok.into()].as_server()?;
+        if settings.encrypt_server && settings.sniff_server_name && !self.uses_rustls() {
+            // the connection is encrypted once the server name is read from the ClientHello
+            self.attach_writer(poll, tok);
+            self.connections[tok.into()].detect_tls();
+            self.schedule_tls_timeout(tok);
+        } else if settings.encrypt_server && !self.uses_rustls() {
+            self.attach_writer(poll, tok);
+            if let Err(err) = self.connections[tok.into()].encrypt() {
+                // The socket was consumed by the failed upgrade, so the connection is discarded
+                self.remove_connection(tok);
+                return Err(err);
+            }
+            self.schedule_tls_timeout(tok);
+        } else if settings.tls_auto_detect && !self.uses_rustls() {
+            self.attach_writer(poll, tok);
+            self.connections[tok.into()].detect_tls();
+            self.schedule_tls_timeout(tok);
+        }
+
+        let conn = &mut self.connections[tok.into()];
 
         poll.register(
             conn.socket(),
@@ -402,7 +1453,7 @@ where
                     "Encountered error while trying to build WebSocket connection: {}",
                     err
                 );
-                conn.error(err);
+                conn.error(ErrorPhase::Handshake, err);
                 if settings.panic_on_new_connection {
                     panic!("Encountered error while trying to build WebSocket connection.");
                 }
@@ -411,26 +1462,75 @@ where
     }
 
     #[cfg(not(any(feature = "ssl", feature = "nativetls")))]
-    pub fn accept(&mut self, poll: &mut Poll, sock: TcpStream) -> Result<()> {
-        let factory = &mut self.factory;
-        let settings = self.settings;
-
+    pub fn accept(
+        &mut self,
+        poll: &mut Poll,
+        sock: TcpStream,
+        listener: Option<Token>,
+    ) -> Result<()> {
+        let settings = match sock.peer_addr() {
+            Ok(addr) => self.factory.settings_for(&addr, self.settings),
+            Err(_) => self.settings,
+        };
+        let settings = self.listener_settings(listener, settings);
+        if !self.handshake_has_capacity() {
+            return Err(Error::new(
+                Kind::Capacity,
+                format!(
+                    "Unable to accept another connection while {} opening handshakes are pending.",
+                    self.pending_handshakes.len()
+                ),
+            ));
+        }
+        if !self
+            .memory
+            .has_room(settings.in_buffer_capacity + settings.out_buffer_capacity)
+        {
+            return Err(Error::new(
+                Kind::Capacity,
+                format!(
+                    "Unable to accept another connection while connection buffers hold {} bytes.",
+                    self.memory.used()
+                ),
+            ));
+        }
         if settings.tcp_nodelay {
             sock.set_nodelay(true)?
         }
+        let stream = self.server_stream(sock, listener)?;
+        let factory = &mut self.factory;
 
         let tok = {
-            if self.connections.len() < settings.max_connections {
+            if self.connections.len() < self.settings.max_connections {
                 let entry = self.connections.vacant_entry();
                 let tok = Token(entry.key());
                 let connection_id = self.next_connection_id;
                 self.next_connection_id = self.next_connection_id.wrapping_add(1);
-                let handler = factory.server_connected(Sender::new(
+                let handler = factory.server_connected(
+                    Sender::new(tok, self.queue_tx.clone(), connection_id)
+                        .with_producers(self.producers.clone())
+                        .with_settings(&self.settings)
+                        .with_listener(listener),
+                );
+                let buffers = self.pool.take(&settings);
+                entry.insert(Connection::new(
                     tok,
-                    self.queue_tx.clone(),
+                    stream,
+                    handler,
+                    settings,
                     connection_id,
-                ));
-                entry.insert(Connection::new(tok, sock, handler, settings, connection_id));
+                    buffers,
+                )
+                .with_middleware(
+                    self.middleware.clone(),
+                    Sender::new(tok, self.queue_tx.clone(), connection_id)
+                        .with_producers(self.producers.clone())
+                        .with_settings(&self.settings)
+                        .with_listener(listener),
+                )
+                .with_memory(self.memory.clone())
+                .with_deferred(self.producers.deferred())
+                .with_handshakes(self.producers.handshakes()));
                 tok
             } else {
                 return Err(Error::new(
@@ -440,10 +1540,20 @@ where
             }
         };
 
+        if let Some(peer_addr) = self.connections[tok.into()].peer_socket_addr() {
+            self.track_ip(tok, peer_addr.ip());
+            self.emit(WsEvent::Accepted {
+                token: tok,
+                peer_addr,
+            });
+        }
+        self.track_handshake(tok);
+
+        let uses_rustls = self.uses_rustls();
         let conn = &mut self.connections[tok.into()];
 
         conn.as_server()?;
-        if settings.encrypt_server {
+        if settings.encrypt_server && !uses_rustls {
             return Err(Error::new(
                 Kind::Protocol,
                 "The ssl feature is not enabled. Please enable it to use wss urls.",
@@ -461,7 +1571,7 @@ where
                     "Encountered error while trying to build WebSocket connection: {}",
                     err
                 );
-                conn.error(err);
+                conn.error(ErrorPhase::Handshake, err);
                 if settings.panic_on_new_connection {
                     panic!("Encountered error while trying to build WebSocket connection.");
                 }
@@ -471,21 +1581,82 @@ where
 
     pub fn run(&mut self, poll: &mut Poll) -> Result<()> {
         trace!("Running event loop");
-        poll.register(
-            &self.queue_rx,
-            QUEUE,
-            Ready::readable(),
-            PollOpt::edge() | PollOpt::oneshot(),
-        )?;
+        if self.queue_registered {
+            poll.reregister(
+                &self.queue_rx,
+                QUEUE,
+                Ready::readable(),
+                PollOpt::edge() | PollOpt::oneshot(),
+            )?;
+        } else {
+            poll.register(
+                &self.queue_rx,
+                QUEUE,
+                Ready::readable(),
+                PollOpt::edge() | PollOpt::oneshot(),
+            )?;
+            self.queue_registered = true;
+        }
         poll.register(&self.timer, TIMER, Ready::readable(), PollOpt::edge())?;
+        self.schedule_shrink();
 
         self.state = State::Active;
         let result = self.event_loop(poll);
         self.state = State::Inactive;
 
-        result
+        let result = result
             .and(poll.deregister(&self.timer).map_err(Error::from))
-            .and(poll.deregister(&self.queue_rx).map_err(Error::from))
+            .and(poll.deregister(&self.queue_rx).map_err(Error::from));
+        self.reset(poll);
+        result
+    }
+
+    // Forget everything that belongs to a run of the event loop, so that the WebSocket can listen
+    // and run again. Commands queued after the loop stopped are discarded, but connections queued
+    // before the next run are kept.
+    fn reset(&mut self, poll: &mut Poll) {
+        let tokens = self.tokens();
+        if !tokens.is_empty() {
+            debug!("Dropping {} connections after the event loop stopped.", tokens.len());
+        }
+        for token in tokens {
+            self.remove_connection(token);
+        }
+        if let Some(listener) = self.listener.take() {
+            if !self.accept_paused {
+                if let Err(err) = poll.deregister(&listener) {
+                    error!("Unable to deregister the listening socket: {}", err);
+                }
+            }
+        }
+        for (_, listener) in self.listeners.drain() {
+            if !self.accept_paused {
+                if let Err(err) = poll.deregister(&listener.socket) {
+                    error!("Unable to deregister the listening socket: {}", err);
+                }
+            }
+        }
+        self.accept_paused = false;
+        self.listen_addr = None;
+        self.accept_failures = 0;
+        self.rebinding = false;
+        self.accept_pending = false;
+        #[cfg(any(feature = "ssl", feature = "nativetls"))]
+        {
+            if let Some(pool) = self.writers.take() {
+                if let Err(err) = poll.deregister(pool.completions()) {
+                    error!("Unable to deregister the TLS writer threads: {}", err);
+                }
+            }
+        }
+        while self.queue_rx.try_recv().is_ok() {
+            self.producers.dequeued();
+        }
+        self.timer = new_timer(&self.settings);
+        self.timeouts.clear();
+        self.pending_reads.clear();
+        self.pending_writes.clear();
+        self.ack_checks.clear();
     }
 
     #[inline]
@@ -493,7 +1664,14 @@ where
         let mut events = mio::Events::with_capacity(MAX_EVENTS);
         while self.state.is_active() {
             trace!("Waiting for event");
-            let nevents = match poll.poll(&mut events, None) {
+            // Connections with unprocessed frames, and connections left in the backlog of the
+            // edge-triggered listener, must not wait for new socket activity
+            let timeout = if self.pending_reads.is_empty() && !self.accepts_pending() {
+                None
+            } else {
+                Some(Duration::from_millis(0))
+            };
+            let nevents = match poll.poll(&mut events, timeout) {
                 Ok(nevents) => nevents,
                 Err(err) => {
                     if err.kind() == ErrorKind::Interrupted {
@@ -513,33 +1691,112 @@ where
 
             for i in 0..nevents {
                 let evt = events.get(i).unwrap();
-                self.handle_event(poll, evt.token(), evt.kind());
+                let kind = self.defer_write(evt.token(), evt.kind());
+                if !kind.is_empty() {
+                    self.handle_event(poll, evt.token(), kind);
+                }
+            }
+
+            if mem::replace(&mut self.accept_pending, false) {
+                self.accept_connections(poll);
+            }
+            let pending: Vec<Token> = self
+                .listeners
+                .iter_mut()
+                .filter_map(|(&token, listener)| {
+                    if mem::replace(&mut listener.pending, false) {
+                        Some(token)
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            for token in pending {
+                self.accept_from(poll, token);
+            }
+
+            for token in mem::take(&mut self.pending_reads) {
+                if self.connections.contains(token.into())
+                    && self.connections[token.into()].is_read_pending()
+                {
+                    self.handle_event(poll, token, Ready::readable());
+                }
             }
 
+            self.flush_writes(poll);
+
             self.check_count();
         }
         Ok(())
     }
 
+    // Whether any listener may have connections left to accept.
+    fn accepts_pending(&self) -> bool {
+        self.accept_pending || self.listeners.values().any(|listener| listener.pending)
+    }
+
     #[inline]
     fn schedule(&self, poll: &mut Poll, conn: &Conn<F>) -> Result<()> {
+        if conn.is_writing() {
+            // The connection is rescheduled once the writer thread returns its stream
+            return Ok(());
+        }
         trace!(
-            "Scheduling connection to {} as {:?}",
-            conn.socket()
-                .peer_addr()
+            "Scheduling connection {} to {} as {:?}",
+            conn.connection_id(),
+            conn.peer_socket_addr()
                 .map(|addr| addr.to_string())
-                .unwrap_or_else(|_| "UNKNOWN".into()),
-            conn.events()
+                .unwrap_or_else(|| "UNKNOWN".into()),
+            conn.interest()
         );
-        poll.reregister(
-            conn.socket(),
-            conn.token(),
-            conn.events(),
-            PollOpt::edge() | PollOpt::oneshot(),
-        )?;
+        if conn.take_new_socket() {
+            // the connection replaced its socket, for instance to retry a proxy request
+            poll.register(
+                conn.socket(),
+                conn.token(),
+                conn.interest(),
+                PollOpt::edge() | PollOpt::oneshot(),
+            )?;
+        } else {
+            poll.reregister(
+                conn.socket(),
+                conn.token(),
+                conn.interest(),
+                PollOpt::edge() | PollOpt::oneshot(),
+            )?;
+        }
         Ok(())
     }
 
+    // When writes are batched, hold back the writable readiness of a connection until the end of
+    // the iteration so that everything queued for it in the meantime goes out in one write.
+    fn defer_write(&mut self, token: Token, mut events: Ready) -> Ready {
+        if self.settings.batch_writes
+            && events.is_writable()
+            && self.connections.contains(token.into())
+        {
+            self.batch_write(token);
+            events.remove(Ready::writable());
+        }
+        events
+    }
+
+    fn batch_write(&mut self, token: Token) {
+        if self.settings.batch_writes && !self.pending_writes.contains(&token) {
+            self.pending_writes.push(token);
+        }
+    }
+
+    fn flush_writes(&mut self, poll: &mut Poll) {
+        for token in mem::take(&mut self.pending_writes) {
+            if self.connections.contains(token.into())
+                && self.connections[token.into()].events().is_writable()
+            {
+                self.handle_event(poll, token, Ready::writable());
+            }
+        }
+    }
+
     fn shutdown(&mut self) {
         debug!("Received shutdown signal. WebSocket is attempting to shut down.");
         for (_, conn) in self.connections.iter_mut() {
@@ -553,34 +1810,32 @@ where
     }
 
     #[inline]
-    fn check_active(&mut self, poll: &mut Poll, active: bool, token: Token) {
+    fn check_active(&mut self, poll: &mut Poll, active: bool, token: Token, phase: ErrorPhase) {
         // NOTE: Closing state only applies after a ws connection was successfully
         // established. It's possible that we may go inactive while in a connecting
         // state if the handshake fails.
         if !active {
-            if let Ok(addr) = self.connections[token.into()].socket().peer_addr() {
-                debug!("WebSocket connection to {} disconnected.", addr);
+            let connection_id = self.connections[token.into()].connection_id();
+            if let Some(addr) = self.connections[token.into()].peer_socket_addr() {
+                debug!("WebSocket connection {} to {} disconnected.", connection_id, addr);
             } else {
-                trace!("WebSocket connection to token={:?} disconnected.", token);
+                trace!(
+                    "WebSocket connection {} with token={:?} disconnected.",
+                    connection_id,
+                    token
+                );
             }
-            let handler = self.connections.remove(token.into()).consume();
-            self.factory.connection_lost(handler);
-        } else {
-            self.schedule(poll, &self.connections[token.into()])
-                .or_else(|err| {
-                    // This will be an io error, so disconnect will already be called
-                    self.connections[token.into()].error(err);
-                    let handler = self.connections.remove(token.into()).consume();
-                    self.factory.connection_lost(handler);
-                    Ok::<(), Error>(())
-                })
-                .unwrap()
+            self.remove_connection(token);
+        } else if let Err(err) = self.schedule(poll, &self.connections[token.into()]) {
+            // This will be an io error, so disconnect will already be called
+            self.connections[token.into()].error(phase, err);
+            self.remove_connection(token);
         }
     }
 
     #[inline]
     fn is_client(&self) -> bool {
-        self.listener.is_none()
+        self.listener.is_none() && !self.rebinding && self.listeners.is_empty()
     }
 
     #[inline]
@@ -605,37 +1860,39 @@ where
             }
             ALL => {
                 if events.is_readable() {
-                    match self.listener
-                        .as_ref()
-                        .expect("No listener provided for server websocket connections")
-                        .accept()
-                    {
-                        Ok((sock, addr)) => {
-                            info!("Accepted a new tcp connection from {}.", addr);
-                            if let Err(err) = self.accept(poll, sock) {
-                                error!("Unable to build WebSocket connection {:?}", err);
-                                if self.settings.panic_on_new_connection {
-                                    panic!("Unable to build WebSocket connection {:?}", err);
-                                }
-                            }
-                        }
-                        Err(err) => error!(
-                            "Encountered an error {:?} while accepting tcp connection.",
-                            err
-                        ),
-                    }
+                    self.accept_connections(poll);
                 }
             }
             TIMER => while let Some(t) = self.timer.poll() {
                 self.handle_timeout(poll, t);
             },
             QUEUE => {
+                // Consecutive broadcast messages are coalesced so that each connection is written
+                // to and rescheduled once per tick rather than once per message.
+                let mut broadcast = Vec::new();
                 for _ in 0..MESSAGES_PER_TICK {
                     match self.queue_rx.try_recv() {
-                        Ok(cmd) => self.handle_queue(poll, cmd),
+                        Ok(cmd) => {
+                            self.producers.dequeued();
+                            if cmd.token() == ALL {
+                                if let Signal::Message(_) = *cmd.signal() {
+                                    if let Signal::Message(msg) = cmd.into_signal() {
+                                        broadcast.push(msg);
+                                    }
+                                    continue;
+                                }
+                            }
+                            if !broadcast.is_empty() {
+                                self.broadcast(poll, mem::take(&mut broadcast));
+                            }
+                            self.handle_queue(poll, cmd)
+                        }
                         _ => break,
                     }
                 }
+                if !broadcast.is_empty() {
+                    self.broadcast(poll, broadcast);
+                }
                 let _ = poll.reregister(
                     &self.queue_rx,
                     QUEUE,
@@ -643,83 +1900,53 @@ where
                     PollOpt::edge() | PollOpt::oneshot(),
                 );
             }
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            WRITER => self.handle_writes(poll),
+            _ if self.listeners.contains_key(&token) => {
+                if events.is_readable() {
+                    self.accept_from(poll, token);
+                }
+            }
             _ => {
+                let was_connecting = self.connections[token.into()].is_connecting();
+                // The cause of a failure is taken from the socket rather than left for reading or
+                // writing to report.
+                let socket_error = if reports_error(events) {
+                    self.connections[token.into()].socket_error()
+                } else {
+                    None
+                };
+                let interest = self.connections[token.into()].interest();
+                let write_first = (events & interest).is_readable()
+                    && (events & interest).is_writable()
+                    && self.connections[token.into()].writes_first();
                 let active = {
-                    let conn_events = self.connections[token.into()].events();
-
-                    if (events & conn_events).is_readable() {
-                        if let Err(err) = self.connections[token.into()].read() {
-                            trace!("Encountered error while reading: {}", err);
-                            if let Kind::Io(ref err) = err.kind {
-                                if let Some(errno) = err.raw_os_error() {
-                                    if errno == CONNECTION_REFUSED {
-                                        match self.connections[token.into()].reset() {
-                                            Ok(_) => {
-                                                poll.register(
-                                                    self.connections[token.into()].socket(),
-                                                    self.connections[token.into()].token(),
-                                                    self.connections[token.into()].events(),
-                                                    PollOpt::edge() | PollOpt::oneshot(),
-                                                ).or_else(|err| {
-                                                        self.connections[token.into()]
-                                                            .error(Error::from(err));
-                                                        let handler = self.connections
-                                                            .remove(token.into())
-                                                            .consume();
-                                                        self.factory.connection_lost(handler);
-                                                        Ok::<(), Error>(())
-                                                    })
-                                                    .unwrap();
-                                                return;
-                                            }
-                                            Err(err) => {
-                                                trace!("Encountered error while trying to reset connection: {:?}", err);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            // This will trigger disconnect if the connection is open
-                            self.connections[token.into()].error(err)
+                    if let Some(err) = socket_error {
+                        trace!("Encountered socket error: {}", err);
+                        let err = Error::from(err);
+                        if !self.connection_failed(poll, token, ErrorPhase::Read, err) {
+                            return;
+                        }
+                    } else {
+                        if write_first && !self.write_connection(poll, token) {
+                            return;
                         }
-                    }
 
-                    let conn_events = self.connections[token.into()].events();
-
-                    if (events & conn_events).is_writable() {
-                        if let Err(err) = self.connections[token.into()].write() {
-                            trace!("Encountered error while writing: {}", err);
-                            if let Kind::Io(ref err) = err.kind {
-                                if let Some(errno) = err.raw_os_error() {
-                                    if errno == CONNECTION_REFUSED {
-                                        match self.connections[token.into()].reset() {
-                                            Ok(_) => {
-                                                poll.register(
-                                                    self.connections[token.into()].socket(),
-                                                    self.connections[token.into()].token(),
-                                                    self.connections[token.into()].events(),
-                                                    PollOpt::edge() | PollOpt::oneshot(),
-                                                ).or_else(|err| {
-                                                        self.connections[token.into()]
-                                                            .error(Error::from(err));
-                                                        let handler = self.connections
-                                                            .remove(token.into())
-                                                            .consume();
-                                                        self.factory.connection_lost(handler);
-                                                        Ok::<(), Error>(())
-                                                    })
-                                                    .unwrap();
-                                                return;
-                                            }
-                                            Err(err) => {
-                                                trace!("Encountered error while trying to reset connection: {:?}", err);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            // This will trigger disconnect if the connection is open
-                            self.connections[token.into()].error(err)
+                        let conn_events = self.connections[token.into()].interest();
+
+                        if (events & conn_events).is_readable()
+                            && !self.read_connection(poll, token)
+                        {
+                            return;
+                        }
+
+                        let conn_events = self.connections[token.into()].interest();
+
+                        if !write_first
+                            && (events & conn_events).is_writable()
+                            && !self.write_connection(poll, token)
+                        {
+                            return;
                         }
                     }
 
@@ -728,11 +1955,190 @@ where
                         || self.connections[token.into()].events().is_writable()
                 };
 
-                self.check_active(poll, active, token)
+                if was_connecting && active && self.connections[token.into()].is_open() {
+                    let peer_addr = self.connections[token.into()].peer_socket_addr();
+                    self.emit(WsEvent::HandshakeComplete { token, peer_addr });
+                }
+
+                // registration follows reading and writing, so attribute failures to the last of them
+                let phase = if events.is_writable() && !write_first {
+                    ErrorPhase::Write
+                } else {
+                    ErrorPhase::Read
+                };
+                self.check_active(poll, active, token, phase);
+                #[cfg(any(feature = "ssl", feature = "nativetls"))]
+                self.schedule_client_hello(token);
+
+                if self.connections.contains(token.into())
+                    && self.connections[token.into()].is_read_pending()
+                    && !self.pending_reads.contains(&token)
+                {
+                    self.pending_reads.push(token);
+                }
             }
         }
     }
 
+    // Read from a connection. Returns false if the connection was reset in order to connect to
+    // the next address of its url, so that nothing more is to be done for this event.
+    fn read_connection(&mut self, poll: &mut Poll, token: Token) -> bool {
+        if let Err(err) = self.connections[token.into()].read() {
+            trace!("Encountered error while reading: {}", err);
+            return self.connection_failed(poll, token, ErrorPhase::Read, err);
+        }
+        true
+    }
+
+    // Write to a connection. Returns false if the connection was reset in order to connect to the
+    // next address of its url, so that nothing more is to be done for this event.
+    fn write_connection(&mut self, poll: &mut Poll, token: Token) -> bool {
+        if let Err(err) = self.connections[token.into()].write() {
+            trace!("Encountered error while writing: {}", err);
+            return self.connection_failed(poll, token, ErrorPhase::Write, err);
+        }
+        true
+    }
+
+    // Fail a connection, unless it is a client that was refused and can connect to the next
+    // address of its url instead, in which case false is returned.
+    fn connection_failed(
+        &mut self,
+        poll: &mut Poll,
+        token: Token,
+        phase: ErrorPhase,
+        err: Error,
+    ) -> bool {
+        if let Kind::Io(ref err) = err.kind {
+            if let Some(errno) = err.raw_os_error() {
+                if errno == CONNECTION_REFUSED {
+                    match self.connections[token.into()].reset() {
+                        Ok(_) => {
+                            poll.register(
+                                self.connections[token.into()].socket(),
+                                self.connections[token.into()].token(),
+                                self.connections[token.into()].events(),
+                                PollOpt::edge() | PollOpt::oneshot(),
+                            ).or_else(|err| {
+                                    self.connections[token.into()]
+                                        .error(phase, Error::from(err));
+                                    self.remove_connection(token);
+                                    Ok::<(), Error>(())
+                                })
+                                .unwrap();
+                            return false;
+                        }
+                        Err(err) => {
+                            trace!("Encountered error while trying to reset connection: {:?}", err);
+                        }
+                    }
+                }
+            }
+        }
+        let peer_addr = self.connections[token.into()].peer_socket_addr();
+        self.emit_error(Some(token), peer_addr, &err);
+        // This will trigger disconnect if the connection is open
+        self.connections[token.into()].error(phase, err);
+        true
+    }
+
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn handle_writes(&mut self, poll: &mut Poll) {
+        let mut completions = Vec::new();
+        if let Some(ref pool) = self.writers {
+            while let Ok(completion) = pool.completions().try_recv() {
+                completions.push(completion);
+            }
+        }
+
+        for Completion {
+            token,
+            connection_id,
+            stream,
+            buffer,
+            result,
+        } in completions
+        {
+            let waiting = self.connections
+                .get(token.into())
+                .map(|conn| conn.connection_id() == connection_id && conn.is_writing())
+                .unwrap_or(false);
+            if !waiting {
+                trace!("Connection disconnected while a write was in progress.");
+                continue;
+            }
+
+            let active = {
+                let conn = &mut self.connections[token.into()];
+                if let Err(err) = conn.write_complete(stream, buffer, result) {
+                    trace!("Encountered error while writing: {}", err);
+                    conn.error(ErrorPhase::Write, err)
+                }
+                conn.events().is_readable() || conn.events().is_writable()
+            };
+            self.check_active(poll, active, token, ErrorPhase::Write);
+
+            if self.connections.contains(token.into())
+                && self.connections[token.into()].is_read_pending()
+                && !self.pending_reads.contains(&token)
+            {
+                self.pending_reads.push(token);
+            }
+        }
+
+        if let Some(ref pool) = self.writers {
+            let _ = poll.reregister(
+                pool.completions(),
+                WRITER,
+                Ready::readable(),
+                PollOpt::edge() | PollOpt::oneshot(),
+            );
+        }
+    }
+
+    fn tokens(&self) -> Vec<Token> {
+        self.connections.iter().map(|(_, conn)| conn.token()).collect()
+    }
+
+    fn broadcast(&mut self, poll: &mut Poll, messages: Vec<Message>) {
+        trace!("Broadcasting {} messages", messages.len());
+        let mut dead = Vec::with_capacity(self.connections.len());
+
+        for (_, conn) in self.connections.iter_mut() {
+            for msg in &messages {
+                if let Err(err) = conn.send_message(msg.clone()) {
+                    dead.push((conn.token(), err));
+                    break;
+                }
+            }
+        }
+        for (_, conn) in self.connections.iter() {
+            if let Err(err) = self.schedule(poll, conn) {
+                dead.push((conn.token(), err))
+            }
+        }
+        self.finish_broadcast(dead);
+        if self.settings.batch_writes {
+            for token in self.tokens() {
+                self.batch_write(token);
+            }
+        }
+    }
+
+    // Fail the connections that a broadcast could not be delivered to, then report the result of
+    // the broadcast to the factory.
+    fn finish_broadcast(&mut self, dead: Vec<(Token, Error)>) {
+        let mut failed = dead.iter().map(|&(token, _)| token).collect::<Vec<_>>();
+        failed.sort();
+        failed.dedup();
+        let sent = self.connections.len() - failed.len();
+        for (token, err) in dead {
+            // note the same connection may be called twice
+            self.connections[token.into()].error(ErrorPhase::Command, err)
+        }
+        self.factory.on_broadcast_result(sent, failed);
+    }
+
     fn handle_queue(&mut self, poll: &mut Poll, cmd: Command) {
         match cmd.token() {
             SYSTEM => {
@@ -740,42 +2146,24 @@ where
             }
             ALL => {
                 let mut dead = Vec::with_capacity(self.connections.len());
+                // whether the signal was broadcast to the connections, rather than handled by the
+                // event loop itself
+                let mut report = false;
 
                 match cmd.into_signal() {
                     Signal::Message(msg) => {
                         trace!("Broadcasting message: {:?}", msg);
-                        for (_, conn) in self.connections.iter_mut() {
-                            if let Err(err) = conn.send_message(msg.clone()) {
-                                dead.push((conn.token(), err))
-                            }
-                        }
-                    }
-                    Signal::Close(code, reason) => {
-                        trace!("Broadcasting close: {:?} - {}", code, reason);
-                        for (_, conn) in self.connections.iter_mut() {
-                            if let Err(err) = conn.send_close(code, reason.borrow()) {
-                                dead.push((conn.token(), err))
-                            }
-                        }
-                    }
-                    Signal::Ping(data) => {
-                        trace!("Broadcasting ping");
-                        for (_, conn) in self.connections.iter_mut() {
-                            if let Err(err) = conn.send_ping(data.clone()) {
-                                dead.push((conn.token(), err))
-                            }
-                        }
+                        self.broadcast(poll, vec![msg]);
+                        return;
                     }
-                    Signal::Pong(data) => {
-                        trace!("Broadcasting pong");
-                        for (_, conn) in self.connections.iter_mut() {
-                            if let Err(err) = conn.send_pong(data.clone()) {
-                                dead.push((conn.token(), err))
-                            }
-                        }
+                    Signal::CloseTokens(tokens, code, reason) => {
+                        self.close_tokens(poll, tokens, code, &reason);
+                        return;
                     }
-                    Signal::Connect(url) => {
-                        if let Err(err) = self.connect(poll, url.clone()) {
+                    // broadcasters cannot request a result, see `Sender::connect_with_token`
+                    Signal::Connect(url, local_addr, _) => {
+                        if let Err(err) = self.connect(poll, url.clone(), local_addr) {
+                            self.emit_error(None, None, &err);
                             if self.settings.panic_on_new_connection {
                                 panic!("Unable to establish connection to {}: {:?}", url, err);
                             }
@@ -783,29 +2171,60 @@ where
                         }
                         return;
                     }
+                    Signal::CancelConnect(target) => {
+                        self.cancel_connect(poll, &target);
+                        return;
+                    }
                     Signal::Shutdown => self.shutdown(),
                     Signal::Timeout {
                         delay,
                         token: event,
                     } => {
-                        let timeout = self.timer.set_timeout(
-                            Duration::from_millis(delay),
-                            Timeout {
-                                connection: ALL,
-                                event,
-                            },
-                        );
+                        let timeout = self.set_timeout(ALL, event, delay);
                         for (_, conn) in self.connections.iter_mut() {
                             if let Err(err) = conn.new_timeout(event, timeout.clone()) {
-                                conn.error(err);
+                                conn.error(ErrorPhase::Timer, err);
                             }
                         }
                         return;
                     }
                     Signal::Cancel(timeout) => {
-                        self.timer.cancel_timeout(&timeout);
+                        if let Some(timeout) = self.timer.cancel_timeout(&timeout) {
+                            self.untrack_timeout(timeout);
+                        }
+                        return;
+                    }
+                    Signal::CancelAllTimeouts => {
+                        self.cancel_timeouts(ALL);
                         return;
                     }
+                    Signal::Tokens(reply) => {
+                        // the requester may have stopped waiting, which is not an error
+                        let _ = reply.send(self.tokens());
+                        return;
+                    }
+                    Signal::PauseAccept => {
+                        self.pause_accepting(poll);
+                        return;
+                    }
+                    Signal::ResumeAccept => {
+                        self.resume_accepting(poll);
+                        return;
+                    }
+                    // received frames belong to one connection, see `Sender::inject_frame`
+                    Signal::Inject(_) => return,
+                    signal => {
+                        trace!("Broadcasting signal: {:?}", signal);
+                        for (_, conn) in self.connections.iter_mut() {
+                            if let Ok(Err(err)) = conn.deliver(signal.clone()) {
+                                dead.push((conn.token(), err))
+                            }
+                            if conn.is_read_pending() && !self.pending_reads.contains(&conn.token()) {
+                                self.pending_reads.push(conn.token());
+                            }
+                        }
+                        report = true;
+                    }
                 }
 
                 for (_, conn) in self.connections.iter() {
@@ -813,122 +2232,321 @@ where
                         dead.push((conn.token(), err))
                     }
                 }
-                for (token, err) in dead {
-                    // note the same connection may be called twice
-                    self.connections[token.into()].error(err)
+                if report {
+                    self.finish_broadcast(dead);
+                } else {
+                    for (token, err) in dead {
+                        // note the same connection may be called twice
+                        self.connections[token.into()].error(ErrorPhase::Command, err)
+                    }
+                }
+                if self.settings.batch_writes {
+                    for token in self.tokens() {
+                        self.batch_write(token);
+                    }
                 }
             }
             token => {
                 let connection_id = cmd.connection_id();
                 match cmd.into_signal() {
-                    Signal::Message(msg) => {
-                        if let Some(conn) = self.connections.get_mut(token.into()) {
-                            if conn.connection_id() == connection_id {
-                                if let Err(err) = conn.send_message(msg) {
-                                    conn.error(err)
-                                }
-                            } else {
-                                trace!("Connection disconnected while a message was waiting in the queue.")
-                            }
-                        } else {
-                            trace!(
-                                "Connection disconnected while a message was waiting in the queue."
-                            )
-                        }
+                    Signal::CloseTokens(tokens, code, reason) => {
+                        self.close_tokens(poll, tokens, code, &reason);
+                        return;
                     }
-                    Signal::Close(code, reason) => {
-                        if let Some(conn) = self.connections.get_mut(token.into()) {
-                            if conn.connection_id() == connection_id {
-                                if let Err(err) = conn.send_close(code, reason) {
-                                    conn.error(err)
-                                }
-                            } else {
-                                trace!("Connection disconnected while close signal was waiting in the queue.")
-                            }
-                        } else {
-                            trace!("Connection disconnected while close signal was waiting in the queue.")
+                    Signal::Connect(url, local_addr, user_token) => {
+                        let result = self.connect(poll, url.clone(), local_addr);
+                        if let Err(ref err) = result {
+                            self.emit_error(None, None, err);
                         }
-                    }
-                    Signal::Ping(data) => {
-                        if let Some(conn) = self.connections.get_mut(token.into()) {
-                            if conn.connection_id() == connection_id {
-                                if let Err(err) = conn.send_ping(data) {
-                                    conn.error(err)
+                        // a sender for a connection that has since been replaced is ignored
+                        let requester = self
+                            .connections
+                            .get_mut(token.into())
+                            .filter(|conn| conn.connection_id() == connection_id);
+                        match (requester, user_token) {
+                            (Some(conn), Some(user_token)) => {
+                                if let Ok(new_token) = result {
+                                    self.connect_requests.insert(
+                                        new_token,
+                                        ConnectRequest {
+                                            requester: token,
+                                            connection_id: conn.connection_id(),
+                                            user_token,
+                                        },
+                                    );
+                                }
+                                // The handler asked to be told about the result, so let it
+                                // respond and schedule any messages it sends
+                                if let Err(err) = conn.connect_result(user_token, result) {
+                                    conn.error(ErrorPhase::Command, err)
                                 }
-                            } else {
-                                trace!("Connection disconnected while ping signal was waiting in the queue.")
                             }
-                        } else {
-                            trace!("Connection disconnected while ping signal was waiting in the queue.")
-                        }
-                    }
-                    Signal::Pong(data) => {
-                        if let Some(conn) = self.connections.get_mut(token.into()) {
-                            if conn.connection_id() == connection_id {
-                                if let Err(err) = conn.send_pong(data) {
-                                    conn.error(err)
+                            (Some(conn), None) => {
+                                if let Err(err) = result {
+                                    conn.error(ErrorPhase::Command, err)
                                 }
-                            } else {
-                                trace!("Connection disconnected while pong signal was waiting in the queue.")
+                                return;
                             }
-                        } else {
-                            trace!("Connection disconnected while pong signal was waiting in the queue.")
-                        }
-                    }
-                    Signal::Connect(url) => {
-                        if let Err(err) = self.connect(poll, url.clone()) {
-                            if let Some(conn) = self.connections.get_mut(token.into()) {
-                                conn.error(err)
-                            } else {
-                                if self.settings.panic_on_new_connection {
-                                    panic!("Unable to establish connection to {}: {:?}", url, err);
+                            (None, _) => {
+                                if let Err(err) = result {
+                                    if self.settings.panic_on_new_connection {
+                                        panic!(
+                                            "Unable to establish connection to {}: {:?}",
+                                            url, err
+                                        );
+                                    }
+                                    error!("Unable to establish connection to {}: {:?}", url, err);
                                 }
-                                error!("Unable to establish connection to {}: {:?}", url, err);
+                                return;
                             }
                         }
-                        return;
                     }
+                    Signal::CancelConnect(target) => self.cancel_connect(poll, &target),
                     Signal::Shutdown => self.shutdown(),
                     Signal::Timeout {
                         delay,
                         token: event,
                     } => {
-                        let timeout = self.timer.set_timeout(
-                            Duration::from_millis(delay),
-                            Timeout {
-                                connection: token,
-                                event,
-                            },
-                        );
-                        if let Some(conn) = self.connections.get_mut(token.into()) {
-                            if let Err(err) = conn.new_timeout(event, timeout) {
-                                conn.error(err)
+                        match self.connections.get(token.into()) {
+                            Some(conn) if conn.connection_id() == connection_id => (),
+                            _ => {
+                                trace!("Connection disconnected while timeout signal was waiting in the queue.");
+                                return;
                             }
-                        } else {
-                            trace!("Connection disconnected while pong signal was waiting in the queue.")
+                        }
+                        let timeout = self.set_timeout(token, event, delay);
+                        let conn = &mut self.connections[token.into()];
+                        if let Err(err) = conn.new_timeout(event, timeout) {
+                            conn.error(ErrorPhase::Timer, err)
                         }
                         return;
                     }
                     Signal::Cancel(timeout) => {
-                        self.timer.cancel_timeout(&timeout);
+                        if let Some(timeout) = self.timer.cancel_timeout(&timeout) {
+                            self.untrack_timeout(timeout);
+                        }
+                        return;
+                    }
+                    Signal::CancelAllTimeouts => {
+                        match self.connections.get(token.into()) {
+                            Some(conn) if conn.connection_id() == connection_id => {
+                                self.cancel_timeouts(token)
+                            }
+                            _ => trace!("Connection disconnected while timeout signal was waiting in the queue."),
+                        }
+                        return;
+                    }
+                    Signal::Tokens(reply) => {
+                        // the requester may have stopped waiting, which is not an error
+                        let _ = reply.send(self.tokens());
+                        return;
+                    }
+                    Signal::PauseAccept => {
+                        self.pause_accepting(poll);
                         return;
                     }
+                    Signal::ResumeAccept => {
+                        self.resume_accepting(poll);
+                        return;
+                    }
+                    signal => match self.connections.get_mut(token.into()) {
+                        Some(ref mut conn) if conn.connection_id() == connection_id => {
+                            let tracked = matches!(signal, Signal::Tracked(..));
+                            if let Ok(Err(err)) = conn.deliver(signal) {
+                                conn.error(ErrorPhase::Command, err)
+                            }
+                            if conn.is_read_pending() && !self.pending_reads.contains(&token) {
+                                self.pending_reads.push(token);
+                            }
+                            if tracked {
+                                self.schedule_ack_check(token);
+                            }
+                        }
+                        _ => trace!(
+                            "Connection disconnected while a signal was waiting in the queue."
+                        ),
+                    },
                 }
 
                 if self.connections.get(token.into()).is_some() {
                     if let Err(err) = self.schedule(poll, &self.connections[token.into()]) {
-                        self.connections[token.into()].error(err)
+                        self.connections[token.into()].error(ErrorPhase::Command, err)
                     }
+                    self.batch_write(token);
+                }
+            }
+        }
+    }
+
+    // Disconnect the client connections matching the target that have not completed their
+    // opening handshake, and tell whoever requested them with a user token.
+    fn cancel_connect(&mut self, poll: &mut Poll, target: &ConnectTarget) {
+        let cancelled = self
+            .connections
+            .iter()
+            .filter(|&(_, conn)| conn.is_client() && conn.is_connecting())
+            .filter(|&(key, conn)| match *target {
+                ConnectTarget::Url(ref url) => conn.client_url() == Some(url),
+                ConnectTarget::Token(tok) => Token(key) == tok,
+            })
+            .map(|(key, _)| Token(key))
+            .collect::<Vec<_>>();
+        if cancelled.is_empty() {
+            trace!("No pending connection attempt matched {:?}.", target);
+        }
+
+        for tok in cancelled {
+            debug!("Cancelling connection attempt {:?}.", tok);
+            let request = self.connect_requests.get(&tok).cloned();
+            self.connections[tok.into()].error(
+                ErrorPhase::Handshake,
+                Error::new(Kind::Cancelled, "The connection attempt was cancelled."),
+            );
+            self.remove_connection(tok);
+
+            let request = match request {
+                Some(request) => request,
+                None => continue,
+            };
+            let active = match self.connections.get_mut(request.requester.into()) {
+                Some(conn) if conn.connection_id() == request.connection_id => {
+                    let err = Error::new(Kind::Cancelled, "The connection attempt was cancelled.");
+                    if let Err(err) = conn.connect_result(request.user_token, Err(err)) {
+                        conn.error(ErrorPhase::Command, err)
+                    }
+                    conn.events().is_readable() || conn.events().is_writable()
+                }
+                _ => continue,
+            };
+            self.check_active(poll, active, request.requester, ErrorPhase::Command);
+        }
+    }
+
+    fn close_tokens(
+        &mut self,
+        poll: &mut Poll,
+        tokens: Vec<Token>,
+        code: CloseCode,
+        reason: &str,
+    ) {
+        trace!("Closing {} connections: {:?} - {}", tokens.len(), code, reason);
+        for token in tokens {
+            if let Some(conn) = self.connections.get_mut(token.into()) {
+                if let Err(err) = conn.send_close(code, reason) {
+                    conn.error(ErrorPhase::Command, err)
                 }
+            } else {
+                trace!("Connection {:?} disconnected before it could be closed.", token);
+                continue;
+            }
+            if let Err(err) = self.schedule(poll, &self.connections[token.into()]) {
+                self.connections[token.into()].error(ErrorPhase::Command, err)
+            }
+            self.batch_write(token);
+        }
+    }
+
+    fn schedule_shrink(&mut self) {
+        if self.settings.buffer_shrink_interval_ms > 0 {
+            self.timer.set_timeout(
+                Duration::from_millis(self.settings.buffer_shrink_interval_ms),
+                Timeout {
+                    connection: SYSTEM,
+                    event: SHRINK_BUFFERS,
+                    id: 0,
+                },
+            );
+        }
+    }
+
+    // Schedule a timeout requested by a handler, tracking it so that it can be cancelled when
+    // the connection is removed.
+    fn set_timeout(&mut self, connection: Token, event: Token, delay: u64) -> TimerTimeout {
+        let id = self.next_timeout_id;
+        self.next_timeout_id += 1;
+        let timeout = self.timer.set_timeout(
+            Duration::from_millis(delay),
+            Timeout {
+                connection,
+                event,
+                id,
+            },
+        );
+        self.timeouts
+            .entry(connection)
+            .or_default()
+            .insert(id, timeout.clone());
+        timeout
+    }
+
+    fn untrack_timeout(&mut self, timeout: Timeout) {
+        let empty = match self.timeouts.get_mut(&timeout.connection) {
+            Some(pending) => {
+                pending.remove(&timeout.id);
+                pending.is_empty()
             }
+            None => false,
+        };
+        if empty {
+            self.timeouts.remove(&timeout.connection);
         }
     }
 
-    fn handle_timeout(&mut self, poll: &mut Poll, Timeout { connection, event }: Timeout) {
+    // Cancel the pending timeouts scheduled by the handler of a connection, or by the handlers of
+    // all connections and the broadcaster when the token is ALL.
+    fn cancel_timeouts(&mut self, token: Token) {
+        let pending: Vec<_> = if token == ALL {
+            self.timeouts.drain().flat_map(|(_, pending)| pending).collect()
+        } else {
+            self.timeouts
+                .remove(&token)
+                .map(|pending| pending.into_iter().collect())
+                .unwrap_or_default()
+        };
+        for (_, timeout) in pending {
+            self.timer.cancel_timeout(&timeout);
+        }
+    }
+
+    fn handle_timeout(&mut self, poll: &mut Poll, timeout: Timeout) {
+        if timeout.id != 0 {
+            self.untrack_timeout(timeout);
+        }
+        let Timeout { connection, event, .. } = timeout;
+        if connection == SYSTEM {
+            if event == SHRINK_BUFFERS {
+                for (_, conn) in self.connections.iter_mut() {
+                    conn.shrink_buffers();
+                }
+                self.schedule_shrink();
+            } else if event == REBIND_LISTENER {
+                self.rebind_listener(poll);
+            }
+            return;
+        }
+        if connection == HANDSHAKE {
+            self.check_handshake_timeout(poll, event);
+            return;
+        }
+        if connection == ACKS {
+            self.check_acks(event);
+            return;
+        }
+        #[cfg(any(feature = "ssl", feature = "nativetls"))]
+        {
+            if connection == TLS_HANDSHAKE {
+                self.check_tls_timeout(poll, event);
+                return;
+            }
+            if connection == CLIENT_HELLO {
+                self.retry_client_hello(poll, event);
+                return;
+            }
+        }
         let active = {
             if let Some(conn) = self.connections.get_mut(connection.into()) {
                 if let Err(err) = conn.timeout_triggered(event) {
-                    conn.error(err)
+                    conn.error(ErrorPhase::Timer, err)
                 }
 
                 conn.events().is_readable() || conn.events().is_writable()
@@ -937,7 +2555,7 @@ where
                 return;
             }
         };
-        self.check_active(poll, active, connection);
+        self.check_active(poll, active, connection, ErrorPhase::Timer);
     }
 }
 
@@ -982,4 +2600,31 @@ mod test {
         }
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_unix_url_to_path() {
+        let url = Url::from_str("ws+unix:///tmp/app.sock:/chat?room=1").unwrap();
+        let (path, handshake_url) = unix_url_to_path(&url).unwrap();
+        assert_eq!(path, PathBuf::from("/tmp/app.sock"));
+        assert_eq!(handshake_url.as_str(), "ws://localhost/chat?room=1");
+
+        let url = Url::from_str("ws+unix:///tmp/app.sock").unwrap();
+        let (path, handshake_url) = unix_url_to_path(&url).unwrap();
+        assert_eq!(path, PathBuf::from("/tmp/app.sock"));
+        assert_eq!(handshake_url.path(), "/");
+
+        let url = Url::from_str("ws+unix:///tmp/app.sock:chat").unwrap();
+        let (_, handshake_url) = unix_url_to_path(&url).unwrap();
+        assert_eq!(handshake_url.path(), "/chat");
+
+        match unix_url_to_path(&Url::from_str("ws+unix::/chat").unwrap()) {
+            Ok(_) => panic!("unix_url_to_path accepts urls without a socket path."),
+            Err(Error {
+                kind: Kind::Internal,
+                details: _,
+            }) => (), // pass
+            err => panic!("{:?}", err),
+        }
+    }
+
 }
@@ -2,57 +2,121 @@
 #![allow(deprecated)]
 #![deny(missing_copy_implementations, trivial_casts, trivial_numeric_casts, unstable_features,
         unused_import_braces)]
+// Unsafe code is confined to the items that allow it. The `safe` feature removes them.
+#![cfg_attr(not(feature = "safe"), deny(unsafe_code))]
+#![cfg_attr(feature = "safe", forbid(unsafe_code))]
+
+#[cfg(all(feature = "safe", feature = "permessage-deflate"))]
+compile_error!("The `permessage-deflate` feature binds to zlib and cannot be used with `safe`.");
 
 extern crate byteorder;
 extern crate bytes;
 extern crate httparse;
+#[cfg(target_os = "linux")]
+extern crate libc;
 extern crate mio;
 extern crate mio_extras;
+extern crate net2;
+extern crate percent_encoding;
 #[cfg(feature = "ssl")]
 extern crate openssl;
 #[cfg(feature = "nativetls")]
 extern crate native_tls;
+#[cfg(feature = "rustls")]
+extern crate rustls;
 extern crate rand;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 extern crate sha1;
 extern crate slab;
+#[cfg(all(feature = "system-proxy", target_os = "macos"))]
+extern crate system_configuration;
 extern crate url;
+#[cfg(all(feature = "system-proxy", windows))]
+extern crate winreg;
 #[macro_use]
 extern crate log;
 
+mod ack;
+mod adapter;
+mod broadcaster;
 mod communication;
 mod connection;
+mod event;
 mod factory;
 mod frame;
 mod handler;
 mod handshake;
 mod io;
 mod message;
+mod middleware;
+mod pool;
 mod protocol;
 mod result;
+mod sni;
 mod stream;
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+mod writer;
 
 #[cfg(feature = "permessage-deflate")]
 pub mod deflate;
 
+#[cfg(feature = "bin-tools")]
+pub mod bin_tools;
+
+#[cfg(feature = "cli")]
+pub mod cli;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+
+#[doc(hidden)]
+pub mod bench;
+pub mod extensions;
+pub mod limits;
+pub mod proxy;
 pub mod util;
 
+pub use adapter::{channel_adapter, ChannelAdapter, ChannelHandler, Channels};
+pub use broadcaster::PersistentBroadcaster;
 pub use factory::Factory;
 pub use handler::Handler;
 
-pub use communication::Sender;
-pub use frame::Frame;
-pub use handshake::{Handshake, Request, Response};
-pub use message::Message;
-pub use protocol::{CloseCode, OpCode};
+pub use communication::{
+    AckToken, ConnectTarget, HandshakeStats, MessageId, MessageMeta, ProducerStats, Sender,
+};
+pub use event::{Direction, ErrorEvent, ErrorPhase, WsEvent};
+pub use frame::{apply_mask_fast, default_mask_fn, Frame, MaskFn};
+pub use handshake::{
+    Handshake, HandshakeTimings, Request, Response, ResponseBuilder, Version, SUPPORTED_VERSIONS,
+};
+pub use message::{Message, MessageInfo};
+pub use middleware::Middleware;
+pub use protocol::{CloseCode, OpCode, Registration};
+pub use proxy::{Proxy, ProxyAuth};
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+pub use stream::TlsClientOptions;
 pub use result::Kind as ErrorKind;
 pub use result::{Error, Result};
 
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
+use std::cmp;
 use std::default::Default;
 use std::fmt;
 use std::net::{SocketAddr, ToSocketAddrs};
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+use std::path::PathBuf;
+use std::str;
+use std::sync::mpsc;
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+use std::thread;
 
-use mio::Poll;
+use middleware::Chain;
+use mio::{Poll, Token};
 
 /// A utility function for setting up a WebSocket server.
 ///
@@ -125,8 +189,251 @@ where
     Ok(())
 }
 
+/// A utility function for connecting a WebSocket client on a new thread.
+///
+/// The event loop runs on the new thread, and this function blocks until the opening handshake
+/// completes. It returns a sender for the connection along with a handle to join once the
+/// connection is closed. If the connection cannot be established, the error that failed it is
+/// returned instead. Messages received from the server are discarded, so use `connect` with a
+/// handler to receive them.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ws::{connect_blocking, CloseCode};
+///
+/// let (out, handle) = connect_blocking("ws://127.0.0.1:3012").unwrap();
+/// out.send("Hello WebSocket").unwrap();
+/// out.close(CloseCode::Normal).unwrap();
+/// handle.join().unwrap().unwrap();
+/// ```
+///
+pub fn connect_blocking<U>(url: U) -> Result<(Sender, thread::JoinHandle<Result<()>>)>
+where
+    U: Borrow<str>,
+{
+    let parsed = url::Url::parse(url.borrow()).map_err(|err| {
+        Error::new(
+            ErrorKind::Internal,
+            format!("Unable to parse {} as url due to {:?}", url.borrow(), err),
+        )
+    })?;
+    let (tx, rx) = mpsc::channel();
+
+    let handle = thread::spawn(move || {
+        let opened = tx.clone();
+        let queued = WebSocket::new(move |out| Blocking {
+            out,
+            opened: Some(opened.clone()),
+        })
+        .and_then(|mut ws| {
+            ws.connect(parsed)?;
+            Ok(ws)
+        });
+        match queued {
+            Ok(ws) => ws.run().map(|_| ()),
+            Err(err) => {
+                // The error is reported to the connecting thread
+                let _ = tx.send(Err(err));
+                Ok(())
+            }
+        }
+    });
+
+    match rx.recv() {
+        Ok(Ok(out)) => Ok((out, handle)),
+        Ok(Err(err)) => {
+            let _ = handle.join();
+            Err(err)
+        }
+        Err(_) => {
+            let joined = handle.join().map_err(|_| {
+                Error::new(ErrorKind::Internal, "The WebSocket thread panicked.")
+            })?;
+            joined?;
+            Err(Error::new(
+                ErrorKind::Internal,
+                "The connection closed before the handshake completed.",
+            ))
+        }
+    }
+}
+
+// The handler of a connection made by `connect_blocking`, which reports the outcome of the
+// opening handshake to the connecting thread.
+struct Blocking {
+    out: Sender,
+    opened: Option<mpsc::Sender<Result<Sender>>>,
+}
+
+impl Handler for Blocking {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        if let Some(opened) = self.opened.take() {
+            let _ = opened.send(Ok(self.out.clone()));
+        }
+        Ok(())
+    }
+
+    fn on_error(&mut self, err: Error) {
+        match self.opened.take() {
+            Some(opened) => {
+                let _ = opened.send(Err(err));
+            }
+            None => error!("{:?}", err),
+        }
+    }
+}
+
+/// How a connection treats frames that the other endpoint sends after its close frame.
+///
+/// The protocol forbids sending data after a close frame, so these frames are never passed to
+/// `on_message`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AfterClose {
+    /// Silently drop the frames.
+    Drop,
+    /// Drop up to the given number of frames, then fail the connection with a Protocol error.
+    Abort(usize),
+    /// Pass the frames to `Handler::on_after_close_frame`.
+    Deliver,
+}
+
+/// How a server treats a body sent with a handshake request.
+///
+/// WebSocket clients do not send a body with the opening handshake, so a body is usually a sign of
+/// a misbehaving or malicious client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HandshakeBody {
+    /// Respond with 413 Payload Too Large and close the connection.
+    Reject,
+    /// Wait for a body announced with Content-Length and discard it before responding. Bodies
+    /// that would exceed `max_handshake_size` and chunked bodies are still rejected.
+    Ignore,
+}
+
+/// How incoming messages longer than `Settings::max_message_size` are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LargeMessages {
+    /// Fail the connection with a Capacity error, which closes it with `CloseCode::Size`. Frames
+    /// are rejected as soon as their header is read, before their payload is buffered.
+    Reject,
+    /// Deliver the message only through `Handler::on_fragment`, without reassembling it, so that
+    /// it is never held in memory as a whole. Text is not validated as UTF-8. Each frame is still
+    /// buffered whole, so `max_fragment_size` should be set to bound the size of a single frame.
+    Stream,
+}
+
+/// How `Sender::close_with_reason` and `Sender::close_tokens` treat reasons longer than
+/// `Settings::max_close_reason`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LongCloseReasons {
+    /// Cut the reason to the longest prefix that fits, ending on a character boundary.
+    Truncate,
+    /// Return an error of kind `Internal` and send nothing.
+    Reject,
+}
+
+/// The order in which a connection is read from and written to when its socket is both readable
+/// and writable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EventOrder {
+    /// Read before writing, so that the responses to incoming messages go out with the same
+    /// write.
+    ReadFirst,
+    /// Write before reading, so that outgoing data makes progress however busy the other
+    /// endpoint keeps the socket.
+    WriteFirst,
+    /// Take turns between reading first and writing first, for each connection.
+    Alternate,
+}
+
+/// How the connections accepted by a listener added with `WebSocket::add_listener` are
+/// encrypted. The listener of `WebSocket::bind` follows `Settings::encrypt_server` and
+/// `Settings::tls_auto_detect` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ListenerTls {
+    /// Connections are not encrypted, even if the `Builder` has a rustls configuration.
+    Plain,
+    /// Connections are encrypted with `Handler::upgrade_ssl_server`, or with the rustls
+    /// configuration of the `Builder`. `Settings::sniff_server_name` still applies.
+    Encrypted,
+    /// Connections are encrypted if they begin with a TLS ClientHello, as with
+    /// `Settings::tls_auto_detect`.
+    AutoDetect,
+}
+
+/// The name of a network interface, used by `Settings::local_interface` to bind outgoing client
+/// connections to that interface. The name is held inline so that settings remain `Copy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interface {
+    name: [u8; Interface::MAX_LEN],
+    len: u8,
+}
+
+impl Interface {
+    /// The maximum length of an interface name in bytes.
+    pub const MAX_LEN: usize = 15;
+
+    /// Create an interface from its name, such as `eth0`. Fails if the name is empty, longer than
+    /// `MAX_LEN` bytes or contains a nul byte.
+    pub fn new(name: &str) -> Result<Interface> {
+        if name.is_empty() || name.len() > Interface::MAX_LEN || name.contains('\0') {
+            return Err(Error::new(
+                ErrorKind::Internal,
+                format!("Invalid network interface name {:?}.", name),
+            ));
+        }
+        let mut interface = Interface {
+            name: [0; Interface::MAX_LEN],
+            len: name.len() as u8,
+        };
+        interface.name[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(interface)
+    }
+
+    /// The name of the interface.
+    pub fn name(&self) -> &str {
+        let len = cmp::min(self.len as usize, Interface::MAX_LEN);
+        str::from_utf8(&self.name[..len]).expect("interface names are copied from a str")
+    }
+}
+
+// Interfaces are serialized as their name, and names from configuration files are checked in the
+// same way as those given to `Interface::new`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Interface {
+    fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Interface {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Interface, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+        Interface::new(&name).map_err(serde::de::Error::custom)
+    }
+}
+
 /// WebSocket settings
+///
+/// With the `serde` feature enabled, settings can be loaded from configuration files. Fields
+/// missing from the configuration take their default values.
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Settings {
     /// The maximum number of connections that this WebSocket will support.
     /// The default setting is low and should be increased when expecting more
@@ -134,9 +441,17 @@ pub struct Settings {
     /// this limit can be made until an old connection is dropped.
     /// Default: 100
     pub max_connections: usize,
+    /// The maximum number of connections accepted from a single IP address. Connections beyond
+    /// this limit receive a 429 Too Many Requests response and are closed immediately. When
+    /// `encrypt_server` or `tls_auto_detect` is set, they are closed without a response, since
+    /// the response could only be sent after a TLS handshake. This contains misbehaving clients
+    /// that open many sockets without affecting other clients.
+    /// Default: unlimited
+    pub max_connections_per_ip: usize,
     /// The number of events anticipated per connection. The event loop queue size will
-    /// be `queue_size` * `max_connections`. In order to avoid an overflow error,
-    /// `queue_size` * `max_connections` must be less than or equal to `usize::max_value()`.
+    /// be `queue_size` * `max_connections`, which must be less than or equal to
+    /// `usize::max_value()`; building a WebSocket with a larger queue fails with an error of kind
+    /// `Internal`, as does a `queue_size` of 0.
     /// The queue is shared between connections, which means that a connection may schedule
     /// more events than `queue_size` provided that another connection is using less than
     /// `queue_size`. However, if the queue is maxed out a Queue error will occur.
@@ -155,20 +470,61 @@ pub struct Settings {
     /// a Capacity error will be triggered instead.
     /// Default: true
     pub fragments_grow: bool,
-    /// The maximum length of outgoing frames. Messages longer than this will be fragmented.
+    /// The maximum length of outgoing frames. Messages longer than this will be fragmented. Must
+    /// be at least 1, as must `text_fragment_size` and `binary_fragment_size` when given.
     /// Default: 65,535
     pub fragment_size: usize,
+    /// The maximum length of outgoing text frames, overriding `fragment_size` for text messages.
+    /// Default: None
+    pub text_fragment_size: Option<usize>,
+    /// The maximum length of outgoing binary frames, overriding `fragment_size` for binary
+    /// messages.
+    /// Default: None
+    pub binary_fragment_size: Option<usize>,
     /// The maximum length of acceptable incoming frames. Messages longer than this will be rejected.
     /// Default: unlimited
     pub max_fragment_size: usize,
+    /// The maximum length of incoming messages, counting the payloads of all of their frames.
+    /// Longer messages are treated according to `large_messages`.
+    /// Default: unlimited
+    pub max_message_size: usize,
+    /// How to treat incoming messages longer than `max_message_size`.
+    /// Default: LargeMessages::Reject
+    pub large_messages: LargeMessages,
     /// The size of the incoming buffer. A larger buffer uses more memory but will allow for fewer
-    /// reallocations.
+    /// reallocations. Must be at least `limits::MAX_FRAME_HEADER`, so that any frame header fits.
     /// Default: 2048
     pub in_buffer_capacity: usize,
     /// Whether to reallocate the incoming buffer when `in_buffer_capacity` is reached. If this is
     /// false, a Capacity error will be triggered instead.
     /// Default: true
     pub in_buffer_grow: bool,
+    /// The maximum number of frames to process for a connection each time it becomes readable.
+    /// Once the limit is reached the connection yields to the other connections on the event loop
+    /// and the remaining frames are processed on a later iteration, so that a single busy peer
+    /// cannot monopolize the loop. Must be at least 1; building a WebSocket with 0 fails with an
+    /// error of kind `Internal`.
+    /// Default: unlimited
+    pub max_messages_per_read: usize,
+    /// Whether to defer writes until the end of each event loop iteration. When this is true,
+    /// messages queued for a connection during an iteration are written together with a single
+    /// system call once all events and queued commands have been processed, which improves
+    /// throughput when many small messages are sent to many connections.
+    /// Default: false
+    pub batch_writes: bool,
+    /// The order in which a connection is read from and written to when both are possible. A
+    /// connection that is read first can starve its writes while the other endpoint keeps its
+    /// socket readable, for example when it streams data while waiting for a reply.
+    /// Default: EventOrder::ReadFirst
+    pub event_order: EventOrder,
+    /// How to treat frames received after the other endpoint has sent a close frame.
+    /// Default: AfterClose::Drop
+    pub after_close: AfterClose,
+    /// The maximum number of payload bytes accepted after the other endpoint has sent a close
+    /// frame. Exceeding this limit triggers a Capacity error regardless of `after_close`, which
+    /// stops a peer from streaming data into a closing connection.
+    /// Default: 65,536
+    pub max_after_close_bytes: usize,
     /// The size of the outgoing buffer. A larger buffer uses more memory but will allow for fewer
     /// reallocations.
     /// Default: 2048
@@ -177,6 +533,13 @@ pub struct Settings {
     /// false, a Capacity error will be triggered instead.
     /// Default: true
     pub out_buffer_grow: bool,
+    /// How often, in milliseconds, to check whether buffers that grew past `in_buffer_capacity`
+    /// or `out_buffer_capacity` can be shrunk. A buffer is reallocated toward its configured
+    /// capacity when no more than half of it was used since the previous check, so that a
+    /// long-lived connection that once handled a large message does not hold on to the memory.
+    /// Set to 0 to disable shrinking.
+    /// Default: 0
+    pub buffer_shrink_interval_ms: u64,
     /// Whether to panic when an Internal error is encountered. Internal errors should generally
     /// not occur, so this setting defaults to true as a debug measure, whereas production
     /// applications should consider setting it to false.
@@ -215,12 +578,46 @@ pub struct Settings {
     /// fail late if a protocol error occurs. Change this setting to enable key verification.
     /// Default: false
     pub key_strict: bool,
+    /// The WebSocket protocol requires clients to send a key made of 16 random bytes encoded as
+    /// base64. WS-RS accepts any key by default and simply hashes it. Set this to true to reject
+    /// handshake requests with a missing or malformed key with a 400 Bad Request response.
+    /// Default: false
+    pub request_key_strict: bool,
+    /// Set this to true to answer handshake requests without a supported Sec-WebSocket-Version
+    /// with a 426 Upgrade Required response listing the supported versions. By default such
+    /// requests are accepted and the connection uses RFC 6455.
+    /// Default: false
+    pub version_strict: bool,
+    /// Browsers send the Origin of the page that opened a WebSocket, but unlike other requests,
+    /// WebSocket handshakes are not subject to the same-origin policy, so any page can connect to
+    /// a server using the cookies of its users. Set this to true to reject handshake requests
+    /// whose Origin does not name the host given in the Host header with a 403 Forbidden
+    /// response, as described in `Request::validate_same_origin`. Requests without an Origin
+    /// header, such as those from non-browser clients, are accepted.
+    /// Default: false
+    pub same_origin_only: bool,
     /// The WebSocket protocol requires clients to perform an opening handshake using the HTTP
     /// GET method for the request. However, since only WebSockets are supported on the connection,
     /// verifying the method of handshake requests is not always necessary. To enforce the
     /// requirement that handshakes begin with a GET method, set this to true.
     /// Default: false
     pub method_strict: bool,
+    /// The WebSocket protocol allows an endpoint to send pongs that were not requested by a ping,
+    /// and a pong only needs to answer the most recent ping. Deployments that treat the echo of a
+    /// ping payload as proof of liveness can set this to true to track the payloads of the pings
+    /// sent on each connection and fail the connection with a protocol error when a pong does not
+    /// echo the payload of an outstanding ping. A matching pong also answers the pings sent
+    /// before it.
+    /// Default: false
+    pub pong_must_match: bool,
+    /// The maximum number of bytes buffered for a handshake request, including any body. Requests
+    /// with larger headers are answered with 431 Request Header Fields Too Large and requests
+    /// with larger bodies with 413 Payload Too Large.
+    /// Default: 16,384
+    pub max_handshake_size: usize,
+    /// How to treat a body sent with a handshake request.
+    /// Default: HandshakeBody::Reject
+    pub handshake_body: HandshakeBody,
     /// Indicate whether server connections should use ssl encryption when accepting connections.
     /// Setting this to true means that clients should use the `wss` scheme to connect to this
     /// server. Note that using this flag will in general necessitate overriding the
@@ -230,29 +627,295 @@ pub struct Settings {
     ///
     /// Default: false
     pub encrypt_server: bool,
+    /// Serve both `ws` and `wss` connections on the same port. When this is true, the first byte
+    /// sent by each accepted client decides whether its connection is encrypted: connections that
+    /// begin with a TLS handshake record are upgraded with `Handler::upgrade_ssl_server` as if
+    /// `encrypt_server` were set, and all others are served without encryption. This is useful
+    /// for local development and while migrating clients to `wss`. This setting has no effect
+    /// unless the `ssl` or `nativetls` feature is enabled, and `encrypt_server` takes precedence.
+    ///
+    /// Default: false
+    pub tls_auto_detect: bool,
+    /// Read the server name that each client asks for with the Server Name Indication extension
+    /// of its TLS ClientHello before the connection is encrypted. The name is passed to
+    /// `Handler::upgrade_ssl_server_for` so that the certificate or tenant can be chosen by
+    /// hostname before any HTTP is exchanged, and it is available as `Handshake::server_name`
+    /// once the connection opens. Encryption is delayed until the whole ClientHello arrives. This
+    /// setting only applies to connections encrypted because of `encrypt_server` or
+    /// `tls_auto_detect`, and it has no effect unless the `ssl` or `nativetls` feature is enabled.
+    ///
+    /// Default: false
+    pub sniff_server_name: bool,
     /// Disables Nagle's algorithm.
     /// Usually tcp socket tries to accumulate packets to send them all together (every 200ms).
     /// When enabled socket will try to send packet as fast as possible.
     ///
     /// Default: false
     pub tcp_nodelay: bool,
+    /// The local address to bind outgoing client connections to before connecting. This is useful
+    /// on multi-homed hosts or when firewall rules depend on the source address. Use port 0 to let
+    /// the operating system select a port. Remote addresses of a different IP family than this
+    /// address will be skipped.
+    ///
+    /// Default: None
+    pub local_bind: Option<SocketAddr>,
+    /// The network interface to bind outgoing client connections to before connecting, so that
+    /// they leave through that interface whatever the routing table says. This is only supported
+    /// on Linux, where it may require the `CAP_NET_RAW` capability; elsewhere, or with the `safe`
+    /// feature, connecting fails. It can be combined with `local_bind`.
+    ///
+    /// Default: None
+    pub local_interface: Option<Interface>,
+    /// The number of sets of connection buffers (incoming and outgoing buffers, the fragment
+    /// queue and the handshake buffers) to keep for reuse. Buffers are allocated up front and
+    /// buffers from closed connections are returned to the pool, which reduces allocator churn for
+    /// servers with many short-lived connections. Set to 0 to disable pooling.
+    ///
+    /// Default: 0
+    pub connection_pool_size: usize,
+    /// The number of threads used to write to TLS connections. When this is greater than 0,
+    /// writes to established TLS connections are performed on a pool of worker threads and the
+    /// event loop is notified when they complete, so that a slow TLS peer or a large write does
+    /// not add latency to every other connection. This setting has no effect unless the `ssl` or
+    /// `nativetls` feature is enabled.
+    ///
+    /// Default: 0
+    pub tls_write_threads: usize,
+    /// The maximum number of milliseconds that a connection may spend negotiating TLS. A peer that
+    /// has not completed the TLS handshake within this time is disconnected and the handler
+    /// receives an error of kind `Kind::TlsTimeout`. Set to 0 to wait indefinitely. This setting
+    /// has no effect unless the `ssl` or `nativetls` feature is enabled.
+    ///
+    /// Default: 0
+    pub tls_handshake_timeout_ms: u64,
+    /// The maximum number of accepted connections that may be performing the opening handshake
+    /// at the same time. Further connections are refused until a pending handshake completes or
+    /// fails, which keeps a flood of peers that never upgrade from using up `max_connections`.
+    ///
+    /// Default: usize::MAX
+    pub max_pending_handshakes: usize,
+    /// The maximum number of milliseconds that an accepted connection may spend in the opening
+    /// handshake, including TLS negotiation. A peer that has not completed the handshake within
+    /// this time is disconnected and the handler receives an error of kind
+    /// `Kind::HandshakeTimeout`. Set to 0 to wait indefinitely.
+    ///
+    /// Default: 0
+    pub handshake_timeout_ms: u64,
+    /// Whether to deliver incoming messages to `Handler::on_message_with_meta` along with their
+    /// receive-order sequence number and the time they were received. When this is true, text
+    /// messages are delivered as owned messages rather than through `Handler::on_text_borrowed`.
+    /// Default: false
+    pub message_info: bool,
+    /// The maximum number of bytes that the buffers of all connections on the event loop may hold
+    /// together, counting the incoming and outgoing buffers of each connection and the fragments
+    /// of partially received messages. New connections are refused while there is no room for
+    /// their initial buffers, and a connection whose buffers grow past the limit fails with an
+    /// error of kind `Capacity`, so that a few peers sending or receiving huge messages cannot
+    /// exhaust the memory of the process.
+    /// Default: usize::MAX
+    pub max_total_buffer_memory: usize,
+    /// The number of events buffered for each receiver returned by
+    /// `WebSocket::subscribe_events`. Events emitted while a receiver's buffer is full are dropped
+    /// for that receiver, so a slow observer cannot make the WebSocket run out of memory.
+    /// Default: 1024
+    pub event_queue_size: usize,
+    /// Whether to log the CRC-32 checksum of the payload of every frame sent and received, to
+    /// help find where payloads are corrupted, for example by a proxy between the endpoints.
+    /// Checksums are computed over the unmasked payload as it is sent on the wire, so after
+    /// compression by extensions, and can be compared with `Frame::checksum` on the other side.
+    /// They are logged at the info level by the `ws::connection` module. This is a debugging aid
+    /// that costs a pass over every payload.
+    /// Default: false
+    pub frame_checksums: bool,
+    /// The length in milliseconds of one tick of the timer that fires timeouts set with
+    /// `Sender::timeout`, along with the internal timeouts for handshakes and buffer shrinking.
+    /// Timeouts fire on tick boundaries, so a timeout fires within about one tick of its deadline
+    /// and timeouts shorter than a tick are not reliable. Shorter ticks suit heartbeats and round-trip time
+    /// measurements, at the cost of waking the event loop once per tick while any timeout is
+    /// pending. The timer has 1024 slots, so timeouts longer than 1024 ticks take more than one
+    /// turn of the timer to fire. Must be at least 1; building a WebSocket with 0 fails with an
+    /// error of kind `Internal`.
+    /// Default: 100
+    pub timer_tick_ms: u64,
+    /// The number of consecutive failures to accept a connection after which the listening
+    /// socket is considered broken. It is then closed, `Factory::on_listener_error` is called,
+    /// and it is bound to the same address again after `listener_rebind_delay_ms`. A value of 0
+    /// keeps the listener however many accepts fail.
+    /// Default: 100
+    pub max_accept_failures: usize,
+    /// The maximum number of connections to accept each time the listening socket becomes
+    /// readable. The listener is registered edge-triggered, so connections are accepted until the
+    /// socket would block; once the limit is reached the event loop serves the other connections
+    /// and accepts the rest of a burst on its next iteration, without waiting for new activity.
+    /// Must be at least 1; building a WebSocket with 0 fails with an error of kind `Internal`.
+    /// Default: unlimited
+    pub max_accepts_per_event: usize,
+    /// The delay in milliseconds before a listening socket that was closed after
+    /// `max_accept_failures` is bound again, and between further attempts while binding fails.
+    /// Default: 1000
+    pub listener_rebind_delay_ms: u64,
+    /// The maximum length in bytes of the reason given to `Sender::close_with_reason` and
+    /// `Sender::close_tokens`. Longer reasons are treated according to `long_close_reasons`, so
+    /// that they never produce a close frame longer than a control frame may be. Must be at most
+    /// `limits::MAX_CLOSE_REASON`; building a WebSocket with a larger value fails with an error of
+    /// kind `Internal`.
+    /// Default: 123
+    pub max_close_reason: usize,
+    /// How reasons longer than `max_close_reason` are treated.
+    /// Default: LongCloseReasons::Truncate
+    pub long_close_reasons: LongCloseReasons,
+    /// Whether to support the acknowledgment of messages sent with `Sender::send_tracked`. When
+    /// this is true, a received message whose payload starts with `\0ack:<id>:`, where `<id>` is a
+    /// decimal number, is acknowledged with a text message `\0ack!<id>` and delivered without
+    /// that prefix, and a received text message `\0ack!<id>` is passed to `Handler::on_ack`
+    /// rather than delivered. Other endpoints can support acknowledgments by following the same
+    /// format. Messages delivered through `Handler::on_fragment` are never acknowledged.
+    /// Default: false
+    pub acks: bool,
+    /// The time in milliseconds to wait for the acknowledgment of a message sent with
+    /// `Sender::send_tracked` before calling `Handler::on_ack_timeout`. Acknowledgments that
+    /// arrive later are still passed to `Handler::on_ack`. A value of 0 waits indefinitely.
+    /// Default: 30,000
+    pub ack_timeout_ms: u64,
+    /// The function used to mask and unmask frame payloads on this WebSocket, for platforms with
+    /// special requirements. It must produce the same output as `apply_mask_fast` for every input.
+    /// When this is None, the function returned by `default_mask_fn` is used. This setting is not
+    /// loaded from configuration files.
+    /// Default: None
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub mask_fn: Option<MaskFn>,
+}
+
+impl Settings {
+    /// Settings for applications that exchange small messages and care most about how soon each
+    /// one arrives. Nagle's algorithm is disabled and writes are not batched, outgoing messages
+    /// are split into small frames so that a large message does not hold up a small one behind
+    /// it, and each connection yields the event loop after a few frames so that a busy peer does
+    /// not delay the others. Timeouts, such as those used for heartbeats, fire within 10 ms.
+    pub fn for_low_latency() -> Settings {
+        Settings {
+            tcp_nodelay: true,
+            timer_tick_ms: 10,
+            batch_writes: false,
+            fragment_size: 4096,
+            max_messages_per_read: 16,
+            ..Settings::default()
+        }
+    }
+
+    /// Settings for applications that move large volumes of data over a few busy connections.
+    /// Writes are batched, the event loop queue and the buffers are sized so that large messages
+    /// need fewer reallocations and system calls, and each connection reads as many frames as are
+    /// available.
+    pub fn for_high_throughput() -> Settings {
+        Settings {
+            queue_size: 50,
+            batch_writes: true,
+            fragments_capacity: 64,
+            in_buffer_capacity: 65_536,
+            out_buffer_capacity: 65_536,
+            max_messages_per_read: usize::MAX,
+            ..Settings::default()
+        }
+    }
+
+    /// Settings for devices with little memory to spare. The buffers and the event loop queue
+    /// start small and are shrunk again after a large message, outgoing messages are split into
+    /// small frames, and incoming frames and messages larger than 1 MiB are rejected with a
+    /// Capacity error.
+    pub fn for_constrained_memory() -> Settings {
+        Settings {
+            queue_size: 2,
+            fragments_capacity: 2,
+            fragment_size: 4096,
+            max_fragment_size: 1 << 20,
+            max_message_size: 1 << 20,
+            in_buffer_capacity: 512,
+            out_buffer_capacity: 512,
+            buffer_shrink_interval_ms: 10_000,
+            event_queue_size: 64,
+            ..Settings::default()
+        }
+    }
+
+    /// Check that these settings can be used together, returning an error of kind `Internal`
+    /// that names the first inconsistent setting otherwise. `Builder::build` calls this, so
+    /// invalid settings are reported when a WebSocket is built rather than as obscure failures
+    /// once it runs. It is also useful to check settings loaded from a configuration file.
+    pub fn validate(&self) -> Result<()> {
+        fn invalid<M: Into<Cow<'static, str>>>(msg: M) -> Result<()> {
+            Err(Error::new(ErrorKind::Internal, msg))
+        }
+
+        if self.queue_size == 0 {
+            return invalid("Settings::queue_size must be at least 1.");
+        }
+        if self.max_connections.checked_mul(self.queue_size).is_none() {
+            return invalid(format!(
+                "Settings::queue_size * Settings::max_connections overflows ({} * {}).",
+                self.queue_size, self.max_connections
+            ));
+        }
+        if self.fragment_size == 0 {
+            return invalid("Settings::fragment_size must be at least 1.");
+        }
+        if self.text_fragment_size == Some(0) {
+            return invalid("Settings::text_fragment_size must be at least 1.");
+        }
+        if self.binary_fragment_size == Some(0) {
+            return invalid("Settings::binary_fragment_size must be at least 1.");
+        }
+        if self.in_buffer_capacity < limits::MAX_FRAME_HEADER {
+            return invalid(format!(
+                "Settings::in_buffer_capacity must be at least {} to hold a frame header.",
+                limits::MAX_FRAME_HEADER
+            ));
+        }
+        if self.max_messages_per_read == 0 {
+            return invalid("Settings::max_messages_per_read must be at least 1.");
+        }
+        if self.max_accepts_per_event == 0 {
+            return invalid("Settings::max_accepts_per_event must be at least 1.");
+        }
+        if self.timer_tick_ms == 0 {
+            return invalid("Settings::timer_tick_ms must be at least 1.");
+        }
+        if self.max_close_reason > limits::MAX_CLOSE_REASON {
+            return invalid(format!(
+                "Settings::max_close_reason must be at most {}.",
+                limits::MAX_CLOSE_REASON
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl Default for Settings {
     fn default() -> Settings {
         Settings {
             max_connections: 100,
+            max_connections_per_ip: usize::MAX,
             queue_size: 5,
             panic_on_new_connection: false,
             panic_on_shutdown: false,
             fragments_capacity: 10,
             fragments_grow: true,
             fragment_size: u16::max_value() as usize,
+            text_fragment_size: None,
+            binary_fragment_size: None,
             max_fragment_size: usize::max_value(),
+            max_message_size: usize::MAX,
+            large_messages: LargeMessages::Reject,
             in_buffer_capacity: 2048,
             in_buffer_grow: true,
+            max_messages_per_read: usize::MAX,
+            batch_writes: false,
+            event_order: EventOrder::ReadFirst,
+            after_close: AfterClose::Drop,
+            max_after_close_bytes: 65_536,
             out_buffer_capacity: 2048,
             out_buffer_grow: true,
+            buffer_shrink_interval_ms: 0,
             panic_on_internal: true,
             panic_on_capacity: false,
             panic_on_protocol: false,
@@ -263,9 +926,37 @@ impl Default for Settings {
             shutdown_on_interrupt: true,
             masking_strict: false,
             key_strict: false,
+            request_key_strict: false,
+            version_strict: false,
+            same_origin_only: false,
             method_strict: false,
+            pong_must_match: false,
+            max_handshake_size: limits::DEFAULT_MAX_HANDSHAKE_SIZE,
+            handshake_body: HandshakeBody::Reject,
             encrypt_server: false,
+            tls_auto_detect: false,
+            sniff_server_name: false,
             tcp_nodelay: false,
+            local_bind: None,
+            local_interface: None,
+            connection_pool_size: 0,
+            tls_write_threads: 0,
+            tls_handshake_timeout_ms: 0,
+            max_pending_handshakes: usize::MAX,
+            handshake_timeout_ms: 0,
+            message_info: false,
+            max_total_buffer_memory: usize::MAX,
+            event_queue_size: 1024,
+            frame_checksums: false,
+            timer_tick_ms: 100,
+            max_accept_failures: 100,
+            max_accepts_per_event: usize::MAX,
+            listener_rebind_delay_ms: 1000,
+            max_close_reason: limits::MAX_CLOSE_REASON,
+            long_close_reasons: LongCloseReasons::Truncate,
+            acks: false,
+            ack_timeout_ms: 30_000,
+            mask_fn: None,
         }
     }
 }
@@ -277,6 +968,8 @@ where
 {
     poll: Poll,
     handler: io::Handler<F>,
+    // the persistent broadcaster attached to this WebSocket and the generation of the attachment
+    persistent: Option<(PersistentBroadcaster, u64)>,
 }
 
 impl<F> WebSocket<F>
@@ -313,6 +1006,51 @@ where
         Err(last_error)
     }
 
+    /// Consume the WebSocket and bind to an unused port on the loopback interface. Returns the
+    /// assigned address along with the bound WebSocket, which should then be started with `run`.
+    ///
+    /// Because the port is known before the event loop runs, clients can connect as soon as this
+    /// returns without racing the server. This is intended for tests; see `Builder::spawn_local`
+    /// to run the server on its own thread.
+    pub fn listen_local(self) -> Result<(SocketAddr, WebSocket<F>)> {
+        let ws = self.bind("127.0.0.1:0")?;
+        let addr = ws.local_addr()?;
+        Ok((addr, ws))
+    }
+
+    /// Listen for new connections on another address as well as the one given to `bind`, for
+    /// example to serve plaintext on port 80 and TLS on port 443 from the same event loop. Returns
+    /// the token of the new listener, which `Sender::listener` reports for the connections that
+    /// it accepts, so that handlers can pick a certificate for each listener in
+    /// `Handler::upgrade_ssl_server`.
+    ///
+    /// Like the listener of `bind`, the listener is closed when the event loop stops.
+    pub fn add_listener<A>(&mut self, addr_spec: A, tls: ListenerTls) -> Result<Token>
+    where
+        A: ToSocketAddrs,
+    {
+        let mut last_error = Error::new(ErrorKind::Internal, "No address given");
+
+        for addr in addr_spec.to_socket_addrs()? {
+            match self.handler.add_listener(&mut self.poll, &addr, tls) {
+                Ok(token) => {
+                    let actual_addr = self.handler.listener_addr(token).unwrap_or(addr);
+                    info!(
+                        "Listening for new connections on {} with {:?}.",
+                        actual_addr, tls
+                    );
+                    return Ok(token);
+                }
+                Err(e) => {
+                    error!("Unable to listen on {}", addr);
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
     /// Consume the WebSocket and listen for new connections on the specified address.
     ///
     /// # Safety
@@ -327,6 +1065,9 @@ where
 
     /// Queue an outgoing connection on this WebSocket. This method may be called multiple times,
     /// but the actual connections will not be established until `run` is called.
+    ///
+    /// On unix platforms, a `ws+unix` url such as `ws+unix:///tmp/app.sock:/chat` connects to the
+    /// unix domain socket at `/tmp/app.sock` and requests the `/chat` resource.
     pub fn connect(&mut self, url: url::Url) -> Result<&mut WebSocket<F>> {
         let sender = self.handler.sender();
         info!("Queuing connection to {}", url);
@@ -334,13 +1075,54 @@ where
         Ok(self)
     }
 
+    /// Queue an outgoing connection on this WebSocket bound to the given local address. This
+    /// overrides the `local_bind` setting for this connection only.
+    pub fn connect_from(&mut self, url: url::Url, local_addr: SocketAddr) -> Result<&mut WebSocket<F>> {
+        let sender = self.handler.sender();
+        info!("Queuing connection to {} from {}", url, local_addr);
+        sender.connect_from(url, local_addr)?;
+        Ok(self)
+    }
+
     /// Run the WebSocket. This will run the encapsulated event loop blocking the calling thread until
     /// the WebSocket is shutdown.
+    ///
+    /// A WebSocket can be run again after it stops. When the event loop stops, its connections
+    /// are dropped, the listening sockets are closed so that `bind`, `listen` or `add_listener`
+    /// may be called again, and pending timeouts and commands are discarded. Connections queued
+    /// with `connect` before the next run are kept, and the senders of connections from an
+    /// earlier run do not reach the connections of later runs.
     pub fn run(mut self) -> Result<WebSocket<F>> {
-        self.handler.run(&mut self.poll)?;
+        self.run_in_place()?;
         Ok(self)
     }
 
+    /// Run the WebSocket like `run` without consuming it, so that it can be run again even if the
+    /// event loop stops with an error. This is intended for supervisors that restart a WebSocket
+    /// with the same factory.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let mut ws = ws::WebSocket::new(|out: ws::Sender| move |msg| out.send(msg)).unwrap();
+    /// loop {
+    ///     ws = ws.bind("127.0.0.1:3012").unwrap();
+    ///     if let Err(err) = ws.run_in_place() {
+    ///         println!("Restarting after error: {}", err);
+    ///     }
+    /// }
+    /// ```
+    pub fn run_in_place(&mut self) -> Result<()> {
+        if let Some((ref broadcaster, ref mut generation)) = self.persistent {
+            *generation = broadcaster.attach_generation(self.handler.sender());
+        }
+        let result = self.handler.run(&mut self.poll);
+        if let Some((ref broadcaster, generation)) = self.persistent {
+            broadcaster.detach_generation(generation);
+        }
+        result
+    }
+
     /// Get a Sender that can be used to send messages on all connections.
     /// Calling `send` on this Sender is equivalent to calling `broadcast`.
     /// Calling `shutdown` on this Sender will shutdown the WebSocket even if no connections have
@@ -356,12 +1138,53 @@ where
     pub fn local_addr(&self) -> ::std::io::Result<SocketAddr> {
         self.handler.local_addr()
     }
+
+    /// Get the local socket address of a listener added with `add_listener`. Will return a
+    /// `NotFound` error if the token does not belong to a listener.
+    pub fn listener_addr(&self, token: Token) -> ::std::io::Result<SocketAddr> {
+        self.handler.listener_addr(token)
+    }
+
+    /// Subscribe to lifecycle events for the connections on this WebSocket.
+    ///
+    /// Each call returns a new receiver that will observe every event emitted after the
+    /// subscription is made. Up to `Settings::event_queue_size` events are buffered until they
+    /// are received and later events are dropped, so observers should drain the receiver
+    /// regularly. Dropping the receiver unsubscribes it.
+    pub fn subscribe_events(&mut self) -> mpsc::Receiver<WsEvent> {
+        self.handler.subscribe_events()
+    }
+
+    /// Enqueue statistics for every producer registered with `Sender::register_producer`, which
+    /// help to size `Settings::queue_size` and to find the component that floods the queue. While
+    /// the WebSocket is running, use `Sender::producer_stats` instead.
+    pub fn producer_stats(&self) -> Vec<ProducerStats> {
+        self.handler.producer_stats()
+    }
+
+    /// The number of handshakes of server connections that ended with each outcome, such as
+    /// acceptance, rejection with a given status or a failed TLS handshake. While the WebSocket is
+    /// running, use `Sender::handshake_stats` instead.
+    pub fn handshake_stats(&self) -> HandshakeStats {
+        self.handler.handshake_stats()
+    }
 }
 
 /// Utility for constructing a WebSocket from various settings.
-#[derive(Debug, Default, Clone, Copy)]
+///
+/// A builder can be cloned to reuse its configuration, but it is not `Copy`.
+#[derive(Debug, Default, Clone)]
 pub struct Builder {
     settings: Settings,
+    middleware: Chain,
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    tls_client: TlsClientOptions,
+    #[cfg(feature = "rustls")]
+    rustls_server: Option<Arc<rustls::ServerConfig>>,
+    proxy: Option<Proxy>,
+    proxy_auth: Option<ProxyAuth>,
+    system_proxy: bool,
+    persistent: Option<PersistentBroadcaster>,
 }
 
 // TODO: add convenience methods for each setting
@@ -377,9 +1200,35 @@ impl Builder {
     where
         F: Factory,
     {
+        self.settings.validate()?;
+        let proxy = match self.proxy.clone() {
+            None if self.system_proxy => Proxy::from_system()?,
+            proxy => proxy,
+        };
+        let proxy = match proxy {
+            Some(proxy) => Some(
+                match self.proxy_auth {
+                    Some(ref auth) => proxy.with_auth(auth.clone()),
+                    None => proxy,
+                }.resolve()?,
+            ),
+            None => None,
+        };
+        let handler = io::Handler::new(factory, self.settings)
+            .with_middleware(self.middleware.clone())
+            .with_proxy(proxy);
+        #[cfg(any(feature = "ssl", feature = "nativetls"))]
+        let handler = handler.with_tls_client_options(self.tls_client.clone());
+        #[cfg(feature = "rustls")]
+        let handler = handler.with_rustls_server_config(self.rustls_server.clone());
+        let persistent = self.persistent.clone().map(|broadcaster| {
+            let generation = broadcaster.attach_generation(handler.sender());
+            (broadcaster, generation)
+        });
         Ok(WebSocket {
             poll: Poll::new()?,
-            handler: io::Handler::new(factory, self.settings),
+            handler,
+            persistent,
         })
     }
 
@@ -388,4 +1237,309 @@ impl Builder {
         self.settings = settings;
         self
     }
+
+    /// Pass the events of every connection through the given middleware before they reach the
+    /// handler. Middleware runs in the order given, and replaces any middleware set previously.
+    pub fn with_middleware(&mut self, middleware: Vec<Box<dyn Middleware>>) -> &mut Builder {
+        self.middleware = Chain::new(middleware);
+        self
+    }
+
+    /// Build a WebSocket on a new thread, bind it to the given address and run its event loop on
+    /// that thread.
+    ///
+    /// The factory is created by `make_factory` on the event loop thread, so neither the factory
+    /// nor the handlers it creates need to be `Send`. This allows handlers to share application
+    /// state using types such as `Rc<RefCell<_>>`. Once the WebSocket is listening, this returns
+    /// a broadcaster for it along with a handle to the event loop thread.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    ///
+    /// let (broadcaster, handle) = ws::Builder::new()
+    ///     .spawn("127.0.0.1:3012", || {
+    ///         let connections = Rc::new(Cell::new(0));
+    ///         move |_| {
+    ///             connections.set(connections.get() + 1);
+    ///             |_| Ok(())
+    ///         }
+    ///     })
+    ///     .unwrap();
+    ///
+    /// broadcaster.shutdown().unwrap();
+    /// handle.join().unwrap().unwrap();
+    /// ```
+    pub fn spawn<A, M, F>(
+        &self,
+        addr_spec: A,
+        make_factory: M,
+    ) -> Result<(Sender, thread::JoinHandle<Result<()>>)>
+    where
+        A: ToSocketAddrs + Send + 'static,
+        M: FnOnce() -> F + Send + 'static,
+        F: Factory,
+    {
+        self.spawn_bound(addr_spec, make_factory)
+            .map(|(broadcaster, _, handle)| (broadcaster, handle))
+    }
+
+    /// Build a WebSocket on a new thread bound to an unused port on the loopback interface, like
+    /// `spawn`. The returned guard knows the assigned address, so tests can connect without
+    /// guessing ports or sleeping, and it stops the server when it is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let server = ws::Builder::new()
+    ///     .spawn_local(|| |out: ws::Sender| move |msg| out.send(msg))
+    ///     .unwrap();
+    ///
+    /// ws::connect(server.url().as_str(), |out| {
+    ///     out.send("Hello").unwrap();
+    ///     move |msg| {
+    ///         assert_eq!(msg, ws::Message::text("Hello"));
+    ///         out.close(ws::CloseCode::Normal)
+    ///     }
+    /// }).unwrap();
+    ///
+    /// server.stop().unwrap();
+    /// ```
+    pub fn spawn_local<M, F>(&self, make_factory: M) -> Result<LocalServer>
+    where
+        M: FnOnce() -> F + Send + 'static,
+        F: Factory,
+    {
+        let (broadcaster, addr, handle) = self.spawn_bound("127.0.0.1:0", make_factory)?;
+        Ok(LocalServer {
+            addr,
+            broadcaster,
+            handle: Some(handle),
+        })
+    }
+
+    fn spawn_bound<A, M, F>(
+        &self,
+        addr_spec: A,
+        make_factory: M,
+    ) -> Result<(Sender, SocketAddr, thread::JoinHandle<Result<()>>)>
+    where
+        A: ToSocketAddrs + Send + 'static,
+        M: FnOnce() -> F + Send + 'static,
+        F: Factory,
+    {
+        let builder = Builder::clone(self);
+        let (tx, rx) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let bound = builder
+                .build(make_factory())
+                .and_then(|ws| ws.bind(addr_spec))
+                .and_then(|ws| Ok((ws.local_addr()?, ws)));
+            let (addr, ws) = match bound {
+                Ok(bound) => bound,
+                Err(err) => {
+                    // The error is reported to the spawning thread
+                    let _ = tx.send(Err(err));
+                    return Ok(());
+                }
+            };
+            if tx.send(Ok((ws.broadcaster(), addr))).is_err() {
+                return Ok(());
+            }
+            ws.run().map(|_| ())
+        });
+
+        match rx.recv() {
+            Ok(Ok((broadcaster, addr))) => Ok((broadcaster, addr, handle)),
+            Ok(Err(err)) => {
+                let _ = handle.join();
+                Err(err)
+            }
+            Err(_) => Err(Error::new(
+                ErrorKind::Internal,
+                "The WebSocket thread exited before it started listening.",
+            )),
+        }
+    }
+
+    /// Attach the given broadcaster to every WebSocket built by this builder. It sends to the most
+    /// recently built or started WebSocket until that WebSocket's event loop ends, so the same
+    /// broadcaster keeps working when the application builds a new WebSocket to replace a
+    /// stopped one or runs the stopped one again.
+    pub fn with_persistent_broadcaster(
+        &mut self,
+        broadcaster: PersistentBroadcaster,
+    ) -> &mut Builder {
+        self.persistent = Some(broadcaster);
+        self
+    }
+
+    /// Bind outgoing client connections to the given local address before connecting.
+    /// This is equivalent to setting `Settings::local_bind`.
+    pub fn with_local_bind(&mut self, addr: SocketAddr) -> &mut Builder {
+        self.settings.local_bind = Some(addr);
+        self
+    }
+
+    /// Bind outgoing client connections to the given network interface before connecting. This
+    /// is equivalent to setting `Settings::local_interface`.
+    pub fn with_local_interface(&mut self, interface: Interface) -> &mut Builder {
+        self.settings.local_interface = Some(interface);
+        self
+    }
+
+    /// Tunnel outgoing client connections through the given HTTP proxy. Connections to `ws+unix`
+    /// urls do not use the proxy. The host of the proxy is resolved when the WebSocket is built,
+    /// and `build` fails if it cannot be resolved.
+    pub fn with_proxy(&mut self, proxy: Proxy) -> &mut Builder {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Tunnel outgoing client connections through the proxy configured in the settings of the
+    /// operating system, as browsers do, unless a proxy is given with `with_proxy`. The settings
+    /// are read when the WebSocket is built, see `Proxy::from_system`. This requires the
+    /// `system-proxy` feature and is supported on Windows and macOS; elsewhere, connections are
+    /// made directly.
+    pub fn use_system_proxy(&mut self, enable: bool) -> &mut Builder {
+        self.system_proxy = enable;
+        self
+    }
+
+    /// Answer authentication challenges from the proxy with the given credentials, replacing any
+    /// credentials of the proxy itself. When the credentials are rejected, connections fail with
+    /// an error of kind `ProxyAuthentication` and the builder can be used again with new ones.
+    pub fn with_proxy_auth(&mut self, auth: ProxyAuth) -> &mut Builder {
+        self.proxy_auth = Some(auth);
+        self
+    }
+
+    /// Use the given name for Server Name Indication and certificate verification when
+    /// encrypting client connections, instead of the host of the URL being connected to.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn with_tls_server_name<S>(&mut self, name: S) -> &mut Builder
+    where
+        S: Into<String>,
+    {
+        self.tls_client.server_name = Some(name.into());
+        self
+    }
+
+    /// Whether client connections should accept certificates that are not valid for the server
+    /// name.
+    ///
+    /// # Warning
+    /// This makes connections vulnerable to man-in-the-middle attacks.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn danger_accept_invalid_hostnames(&mut self, accept: bool) -> &mut Builder {
+        self.tls_client.accept_invalid_hostnames = accept;
+        self
+    }
+
+    /// Require client connections to receive an OCSP response stapled to the server's certificate
+    /// that shows the certificate has not been revoked. Handshakes with servers that do not staple
+    /// a valid response fail.
+    ///
+    /// Revocation checking is only supported with the `ssl` feature. With `nativetls`, client
+    /// connections fail with an error of kind `Internal` while it is enabled.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn require_tls_ocsp_stapling(&mut self, require: bool) -> &mut Builder {
+        self.tls_client.require_ocsp_stapling = require;
+        self
+    }
+
+    /// Check the certificates presented to client connections against the certificate revocation
+    /// list in the given PEM file. This may be called more than once to load several lists, and
+    /// every certificate in the chain must then be covered by one of them.
+    ///
+    /// Revocation checking is only supported with the `ssl` feature. With `nativetls`, client
+    /// connections fail with an error of kind `Internal` while a list is configured.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn with_tls_crl_file<P>(&mut self, path: P) -> &mut Builder
+    where
+        P: Into<PathBuf>,
+    {
+        self.tls_client.crl_files.push(path.into());
+        self
+    }
+
+    /// Encrypt every accepted connection with rustls using the given server configuration. The
+    /// configuration can be shared with other servers in the application, such as an HTTPS
+    /// server, so that certificates are only loaded once.
+    ///
+    /// This takes the place of `Settings::encrypt_server` and `Handler::upgrade_ssl_server`, and
+    /// `Settings::tls_auto_detect` is ignored. Client connections are not affected. The
+    /// configuration must come from the same version of rustls as the one used by this crate.
+    #[cfg(feature = "rustls")]
+    pub fn with_rustls_server_config(
+        &mut self,
+        config: Arc<rustls::ServerConfig>,
+    ) -> &mut Builder {
+        self.rustls_server = Some(config);
+        self
+    }
+}
+
+/// A WebSocket server running on its own thread, created by `Builder::spawn_local`.
+///
+/// Dropping the guard shuts the server down and waits for its thread to finish. Use `stop` to
+/// observe the result of the event loop instead.
+pub struct LocalServer {
+    addr: SocketAddr,
+    broadcaster: Sender,
+    handle: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl LocalServer {
+    /// The address that the server is listening on.
+    #[inline]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// A `ws` url for the server, such as `ws://127.0.0.1:41234/`.
+    pub fn url(&self) -> url::Url {
+        url::Url::parse(&format!("ws://{}/", self.addr)).expect("socket addresses are valid urls")
+    }
+
+    /// A sender that broadcasts to every connection of the server.
+    #[inline]
+    pub fn broadcaster(&self) -> Sender {
+        self.broadcaster.clone()
+    }
+
+    /// Shut the server down and wait for its event loop to finish.
+    pub fn stop(mut self) -> Result<()> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self.handle.take() {
+            Some(handle) => {
+                // the event loop may already have stopped, for instance by a handler
+                let _ = self.broadcaster.shutdown();
+                handle.join().map_err(|_| {
+                    Error::new(ErrorKind::Internal, "The WebSocket thread panicked.")
+                })?
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for LocalServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LocalServer {{ addr: {} }}", self.addr)
+    }
+}
+
+impl Drop for LocalServer {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            error!("Error while stopping local server: {}", err);
+        }
+    }
 }
@@ -0,0 +1,91 @@
+//! The limits module exposes the limits set by the WebSocket protocol and by this library, so
+//! that applications can check values against them rather than repeating the numbers.
+
+use std::ops::RangeInclusive;
+
+use protocol::CloseCode;
+
+/// The maximum payload length of a control frame (close, ping and pong), as set by RFC 6455.
+pub const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// The maximum length in bytes of the reason in a close frame, which shares the control frame
+/// payload with the two byte close code.
+pub const MAX_CLOSE_REASON: usize = MAX_CONTROL_PAYLOAD - 2;
+
+/// The maximum length of a frame header: two bytes, an eight byte extended payload length and a
+/// four byte masking key.
+pub const MAX_FRAME_HEADER: usize = 14;
+
+/// The maximum number of HTTP headers parsed from a handshake request or response.
+pub const MAX_HEADERS: usize = 124;
+
+/// The default for `Settings::max_handshake_size`, the maximum size of a handshake request.
+pub const DEFAULT_MAX_HANDSHAKE_SIZE: usize = 16_384;
+
+/// The close codes reserved for the protocol and its extensions.
+pub const PROTOCOL_CLOSE_CODES: RangeInclusive<u16> = 1000..=2999;
+
+/// The close codes registered for use by libraries, frameworks and applications.
+pub const LIBRARY_CLOSE_CODES: RangeInclusive<u16> = 3000..=3999;
+
+/// The close codes reserved for private use.
+pub const PRIVATE_CLOSE_CODES: RangeInclusive<u16> = 4000..=4999;
+
+/// Whether a payload of this length may be sent in a control frame.
+#[inline]
+pub fn is_valid_control_payload(len: usize) -> bool {
+    len <= MAX_CONTROL_PAYLOAD
+}
+
+/// Whether this reason fits in a close frame.
+#[inline]
+pub fn is_valid_close_reason(reason: &str) -> bool {
+    reason.len() <= MAX_CLOSE_REASON
+}
+
+/// The longest prefix of `reason` that is at most `max` bytes long and ends on a character
+/// boundary.
+#[inline]
+pub fn truncate_close_reason(reason: &str, max: usize) -> &str {
+    if reason.len() <= max {
+        return reason;
+    }
+    let mut end = max;
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    &reason[..end]
+}
+
+/// Whether this code may be sent in a close frame. This is the same as
+/// `code.registration().is_valid()`.
+#[inline]
+pub fn is_valid_close_code(code: CloseCode) -> bool {
+    code.registration().is_valid()
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+
+    #[test]
+    fn close_frames() {
+        assert!(is_valid_control_payload(125));
+        assert!(!is_valid_control_payload(126));
+        assert!(is_valid_close_reason(&"a".repeat(123)));
+        assert!(!is_valid_close_reason(&"a".repeat(124)));
+        assert_eq!(truncate_close_reason("short", MAX_CLOSE_REASON), "short");
+        assert_eq!(truncate_close_reason("abcdef", 4), "abcd");
+        // the two byte character would be split at 4 bytes
+        assert_eq!(truncate_close_reason("abcé", 4), "abc");
+
+        assert!(is_valid_close_code(CloseCode::Normal));
+        assert!(!is_valid_close_code(CloseCode::Status));
+        for &code in &[*LIBRARY_CLOSE_CODES.start(), *PRIVATE_CLOSE_CODES.end()] {
+            assert!(is_valid_close_code(CloseCode::from(code)));
+        }
+        assert!(!is_valid_close_code(CloseCode::from(
+            *PROTOCOL_CLOSE_CODES.end()
+        )));
+    }
+}
@@ -2,12 +2,26 @@ use std::convert::{From, Into};
 use std::fmt;
 use std::result::Result as StdResult;
 use std::str::from_utf8;
+use std::time::Instant;
 
 use protocol::OpCode;
 use result::Result;
 
 use self::Message::*;
 
+/// Information about a received message, passed to `Handler::on_message_with_meta`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageInfo {
+    /// The position of the message among the data messages received on the connection, starting
+    /// at 0. Messages dropped by middleware still take up a sequence number.
+    pub sequence: u64,
+    /// When the final frame of the message was read.
+    pub received: Instant,
+    /// Whether the peer compressed the message with the permessage-deflate extension. This is
+    /// only ever true for handlers wrapped in a `DeflateHandler`.
+    pub compressed: bool,
+}
+
 /// An enum representing the various forms of a WebSocket message.
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Message {
@@ -0,0 +1,166 @@
+use std::fmt;
+use std::sync::Arc;
+
+use communication::Sender;
+use frame::Frame;
+use handshake::Handshake;
+use message::Message;
+use protocol::CloseCode;
+use result::Result;
+
+/// A reusable layer that observes the events of every connection before they reach the handler.
+///
+/// Middleware is added with `Builder::with_middleware` and is shared by all of the handlers that
+/// the factory produces, so it must be `Send` and `Sync`. State for individual connections can be
+/// kept by keying on `Sender::token` or `Sender::connection_id`. The `Sender` passed to each
+/// method belongs to the connection that produced the event and may be used to send messages or
+/// close the connection, which makes middleware suitable for logging, authentication, metrics
+/// and rate limiting.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ws::{Builder, Message, Middleware, Result, Sender};
+///
+/// struct Logger;
+///
+/// impl Middleware for Logger {
+///     fn on_message(&self, out: &Sender, msg: &Message) -> Result<bool> {
+///         println!("Connection {} received {}", out.connection_id(), msg);
+///         Ok(true)
+///     }
+/// }
+///
+/// Builder::new()
+///     .with_middleware(vec![Box::new(Logger)])
+///     .build(|out: Sender| move |msg| out.send(msg))
+///     .unwrap()
+///     .listen("127.0.0.1:3012")
+///     .unwrap();
+/// ```
+pub trait Middleware: Send + Sync {
+    /// Called when the WebSocket handshake completes, before the handler's `on_open`. Returning
+    /// an error fails the connection in the same way as an error returned from `on_open`.
+    #[inline]
+    fn on_open(&self, out: &Sender, shake: &Handshake) -> Result<()> {
+        let _ = (out, shake);
+        Ok(())
+    }
+
+    /// Called with each complete message before the handler's `on_message`. Return `Ok(false)` to
+    /// drop the message so that neither later middleware nor the handler receives it.
+    #[inline]
+    fn on_message(&self, out: &Sender, msg: &Message) -> Result<bool> {
+        let _ = (out, msg);
+        Ok(true)
+    }
+
+    /// Called with each frame before the handler's `on_frame`. Return `Ok(false)` to drop the
+    /// frame.
+    #[inline]
+    fn on_frame(&self, out: &Sender, frame: &Frame) -> Result<bool> {
+        let _ = (out, frame);
+        Ok(true)
+    }
+
+    /// Called when the connection closes, before the handler's `on_close`.
+    #[inline]
+    fn on_close(&self, out: &Sender, code: CloseCode, reason: &str) {
+        let _ = (out, code, reason);
+    }
+}
+
+/// An ordered list of middleware. Events pass through the middleware in the order it was added.
+#[derive(Clone, Default)]
+pub struct Chain {
+    layers: Arc<Vec<Box<dyn Middleware>>>,
+}
+
+impl Chain {
+    pub fn new(layers: Vec<Box<dyn Middleware>>) -> Chain {
+        Chain {
+            layers: Arc::new(layers),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn on_open(&self, out: &Sender, shake: &Handshake) -> Result<()> {
+        for layer in self.layers.iter() {
+            layer.on_open(out, shake)?;
+        }
+        Ok(())
+    }
+
+    pub fn on_message(&self, out: &Sender, msg: &Message) -> Result<bool> {
+        for layer in self.layers.iter() {
+            if !layer.on_message(out, msg)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn on_frame(&self, out: &Sender, frame: &Frame) -> Result<bool> {
+        for layer in self.layers.iter() {
+            if !layer.on_frame(out, frame)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn on_close(&self, out: &Sender, code: CloseCode, reason: &str) {
+        for layer in self.layers.iter() {
+            layer.on_close(out, code, reason);
+        }
+    }
+}
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Chain({} middleware)", self.layers.len())
+    }
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+    use mio;
+    use std::sync::Mutex;
+
+    struct Record {
+        name: &'static str,
+        pass: bool,
+        seen: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Middleware for Record {
+        fn on_message(&self, out: &Sender, msg: &Message) -> Result<bool> {
+            self.seen.lock().unwrap().push(self.name);
+            Ok(self.pass)
+        }
+    }
+
+    #[test]
+    fn chain_order() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let layer = |name, pass| -> Box<dyn Middleware> {
+            Box::new(Record {
+                name,
+                pass,
+                seen: seen.clone(),
+            })
+        };
+        let chain = Chain::new(vec![layer("a", true), layer("b", false), layer("c", true)]);
+
+        let (chn, _) = mio::channel::sync_channel(42);
+        let out = Sender::new(mio::Token(0), chn, 0);
+        assert!(!chain.on_message(&out, &Message::text("hi")).unwrap());
+        assert_eq!(*seen.lock().unwrap(), vec!["a", "b"]);
+        assert!(Chain::default().on_message(&out, &Message::text("hi")).unwrap());
+    }
+}
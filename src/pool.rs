@@ -0,0 +1,340 @@
+use std::cmp::max;
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use frame::Frame;
+use result::{Error, Kind, Result};
+
+use super::Settings;
+
+const HANDSHAKE_CAPACITY: usize = 2048;
+
+/// The buffers owned by a single connection.
+pub struct Buffers {
+    pub request: Vec<u8>,
+    pub response: Vec<u8>,
+    pub fragments: VecDeque<Frame>,
+    pub in_buffer: Vec<u8>,
+    pub out_buffer: Vec<u8>,
+}
+
+impl Buffers {
+    pub fn new(settings: &Settings) -> Buffers {
+        Buffers {
+            request: Vec::with_capacity(HANDSHAKE_CAPACITY),
+            response: Vec::with_capacity(HANDSHAKE_CAPACITY),
+            fragments: VecDeque::with_capacity(settings.fragments_capacity),
+            in_buffer: Vec::with_capacity(settings.in_buffer_capacity),
+            out_buffer: Vec::with_capacity(settings.out_buffer_capacity),
+        }
+    }
+
+    // Empty the buffers so that they can be handed to a new connection. Buffers that have grown
+    // past their configured size are replaced so that the pool doesn't pin large allocations,
+    // and the handshake buffers, which are dropped once a connection opens, are restored.
+    fn clear(&mut self, key: &Key) {
+        reset(&mut self.request, HANDSHAKE_CAPACITY);
+        reset(&mut self.response, HANDSHAKE_CAPACITY);
+        reset(&mut self.in_buffer, key.in_buffer);
+        reset(&mut self.out_buffer, key.out_buffer);
+        self.fragments.clear();
+        if self.fragments.capacity() > key.fragments {
+            self.fragments = VecDeque::with_capacity(key.fragments);
+        }
+    }
+}
+
+fn reset(buf: &mut Vec<u8>, capacity: usize) {
+    buf.clear();
+    if buf.capacity() > capacity {
+        *buf = Vec::with_capacity(capacity);
+    } else {
+        buf.reserve_exact(capacity);
+    }
+}
+
+/// The number of bytes in the buffer that have not been consumed.
+pub fn pending(buffer: &Cursor<Vec<u8>>) -> usize {
+    buffer.get_ref().len() - buffer.position() as usize
+}
+
+/// Reallocate a buffer that has grown past `capacity` if no more than half of it was used, as
+/// given by the `high_water` mark of pending bytes. Returns whether the buffer was reallocated.
+pub fn shrink(buffer: &mut Cursor<Vec<u8>>, high_water: usize, capacity: usize) -> bool {
+    let current = buffer.get_ref().capacity();
+    if current <= capacity || high_water * 2 > current {
+        return false;
+    }
+    let mut new = Vec::with_capacity(max(max(capacity, high_water), pending(buffer)));
+    new.extend(&buffer.get_ref()[buffer.position() as usize..]);
+    *buffer = Cursor::new(new);
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    fragments: usize,
+    in_buffer: usize,
+    out_buffer: usize,
+}
+
+impl<'s> From<&'s Settings> for Key {
+    fn from(settings: &'s Settings) -> Key {
+        Key {
+            fragments: settings.fragments_capacity,
+            in_buffer: settings.in_buffer_capacity,
+            out_buffer: settings.out_buffer_capacity,
+        }
+    }
+}
+
+/// A pool of connection buffers recycled from closed connections.
+///
+/// Buffers are keyed by the sizes configured in the `Settings` of the connection that owned them,
+/// so that a connection is only ever handed buffers allocated for its own configuration.
+pub struct BufferPool {
+    capacity: usize,
+    len: usize,
+    buffers: HashMap<Key, Vec<Buffers>>,
+}
+
+impl BufferPool {
+    /// Create a pool holding up to `settings.connection_pool_size` sets of buffers and fill it
+    /// with buffers sized for `settings`.
+    pub fn new(settings: &Settings) -> BufferPool {
+        let capacity = settings.connection_pool_size;
+        let mut buffers = HashMap::new();
+        if capacity > 0 {
+            buffers.insert(
+                Key::from(settings),
+                (0..capacity).map(|_| Buffers::new(settings)).collect(),
+            );
+        }
+        BufferPool {
+            capacity,
+            len: capacity,
+            buffers,
+        }
+    }
+
+    /// Take buffers suitable for a connection with the given settings, allocating new ones if
+    /// none are available.
+    pub fn take(&mut self, settings: &Settings) -> Buffers {
+        if let Some(buffers) = self.buffers
+            .get_mut(&Key::from(settings))
+            .and_then(|pooled| pooled.pop())
+        {
+            self.len -= 1;
+            return buffers;
+        }
+        Buffers::new(settings)
+    }
+
+    /// Return buffers from a closed connection to the pool.
+    pub fn give(&mut self, settings: &Settings, mut buffers: Buffers) {
+        if self.len >= self.capacity {
+            return;
+        }
+        let key = Key::from(settings);
+        buffers.clear(&key);
+        self.buffers.entry(key).or_default().push(buffers);
+        self.len += 1;
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// The memory held by the buffers of all connections on an event loop, measured against the
+/// ceiling set by `Settings::max_total_buffer_memory`.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    used: Arc<AtomicUsize>,
+    limit: usize,
+}
+
+impl Memory {
+    pub fn new(limit: usize) -> Memory {
+        Memory {
+            used: Arc::new(AtomicUsize::new(0)),
+            limit,
+        }
+    }
+
+    /// The number of bytes held by the buffers of all connections.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Whether `bytes` more can be held without going past the ceiling.
+    pub fn has_room(&self, bytes: usize) -> bool {
+        self.used().saturating_add(bytes) <= self.limit
+    }
+}
+
+/// The share of the event loop memory held by a single connection. The share is returned when
+/// the reservation is dropped.
+#[derive(Debug)]
+pub struct Reservation {
+    memory: Memory,
+    bytes: usize,
+}
+
+impl Reservation {
+    pub fn new(memory: Memory) -> Reservation {
+        Reservation { memory, bytes: 0 }
+    }
+
+    /// The number of bytes held by the connection.
+    #[allow(dead_code)]
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Record that the connection now holds `bytes`. Growth that takes the total past the ceiling
+    /// is still recorded, since the memory has been allocated, but results in a Capacity error so
+    /// that the connection can be closed.
+    pub fn update(&mut self, bytes: usize) -> Result<()> {
+        if bytes < self.bytes {
+            self.memory
+                .used
+                .fetch_sub(self.bytes - bytes, Ordering::Relaxed);
+            self.bytes = bytes;
+            return Ok(());
+        }
+        let growth = bytes - self.bytes;
+        let total = self.memory.used.fetch_add(growth, Ordering::Relaxed) + growth;
+        self.bytes = bytes;
+        if total > self.memory.limit {
+            return Err(Error::new(
+                Kind::Capacity,
+                format!(
+                    "Buffer memory of all connections ({} bytes) exceeded the limit of {} bytes.",
+                    total, self.memory.limit
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.memory.used.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+
+    #[test]
+    fn recycle() {
+        let settings = Settings {
+            connection_pool_size: 1,
+            ..Settings::default()
+        };
+        let mut pool = BufferPool::new(&settings);
+
+        let mut buffers = pool.take(&settings);
+        buffers.in_buffer.extend(b"data");
+        let ptr = buffers.in_buffer.as_ptr();
+        pool.give(&settings, buffers);
+        assert_eq!(pool.len(), 1);
+
+        let buffers = pool.take(&settings);
+        assert_eq!(pool.len(), 0);
+        assert!(buffers.in_buffer.is_empty());
+        assert_eq!(buffers.in_buffer.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn disabled() {
+        let settings = Settings::default();
+        let mut pool = BufferPool::new(&settings);
+
+        let buffers = pool.take(&settings);
+        pool.give(&settings, buffers);
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn keyed_by_settings() {
+        let settings = Settings {
+            connection_pool_size: 1,
+            ..Settings::default()
+        };
+        let other = Settings {
+            in_buffer_capacity: 4096,
+            ..settings
+        };
+        let mut pool = BufferPool::new(&settings);
+
+        let buffers = pool.take(&other);
+        assert_eq!(pool.len(), 1);
+        assert!(buffers.in_buffer.capacity() >= 4096);
+    }
+
+    #[test]
+    fn shrink_unused() {
+        let mut buffer = Cursor::new(Vec::with_capacity(8192));
+        buffer.get_mut().extend(b"consumed data");
+        buffer.set_position(9);
+
+        assert!(shrink(&mut buffer, 100, 2048));
+        assert_eq!(buffer.position(), 0);
+        assert_eq!(buffer.get_ref(), b"data");
+        assert!(buffer.get_ref().capacity() >= 2048);
+        assert!(buffer.get_ref().capacity() < 8192);
+    }
+
+    #[test]
+    fn shrink_busy() {
+        let mut buffer = Cursor::new(Vec::<u8>::with_capacity(8192));
+        assert!(!shrink(&mut buffer, 5000, 2048));
+        assert!(buffer.get_ref().capacity() >= 8192);
+
+        let mut buffer = Cursor::new(Vec::<u8>::with_capacity(2048));
+        assert!(!shrink(&mut buffer, 0, 2048));
+    }
+
+    #[test]
+    fn memory() {
+        let memory = Memory::new(100);
+        let mut first = Reservation::new(memory.clone());
+        let mut second = Reservation::new(memory.clone());
+
+        assert!(first.update(60).is_ok());
+        assert!(memory.has_room(40));
+        assert!(!memory.has_room(41));
+
+        assert!(second.update(50).is_err());
+        assert_eq!(memory.used(), 110);
+        assert!(second.update(40).is_ok());
+        assert_eq!(memory.used(), 100);
+
+        drop(first);
+        assert_eq!(memory.used(), 40);
+        assert_eq!(second.bytes(), 40);
+    }
+
+    #[test]
+    fn bounded() {
+        let settings = Settings {
+            connection_pool_size: 1,
+            ..Settings::default()
+        };
+        let mut pool = BufferPool::new(&settings);
+        assert_eq!(pool.len(), 1);
+
+        let first = pool.take(&settings);
+        let second = pool.take(&settings);
+        pool.give(&settings, first);
+        pool.give(&settings, second);
+        assert_eq!(pool.len(), 1);
+    }
+}
@@ -1,9 +1,13 @@
 use std::convert::{From, Into};
 use std::fmt;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 use self::OpCode::*;
 /// Operation codes as part of rfc6455.
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum OpCode {
     /// Indicates a continuation frame of a fragmented message.
     Continue,
@@ -81,7 +85,11 @@ impl From<u8> for OpCode {
 
 use self::CloseCode::*;
 /// Status code used to indicate why an endpoint is closing the WebSocket connection.
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+///
+/// Close codes convert to and from their numeric value with `From<u16>` and `Into<u16>`, in host
+/// byte order. Codes are compared by their numeric value, so a code constructed as `Other(1000)`
+/// is equal to `Normal`.
+#[derive(Debug, Eq, Clone, Copy)]
 pub enum CloseCode {
     /// Indicates a normal closure, meaning that the purpose for
     /// which the connection was established has been fulfilled.
@@ -145,12 +153,14 @@ pub enum CloseCode {
     Tls,
     #[doc(hidden)]
     Empty,
-    #[doc(hidden)]
+    /// Any other close code, such as a code in the ranges registered for libraries (3000-3999) or
+    /// for private use (4000-4999).
     Other(u16),
 }
 
-impl Into<u16> for CloseCode {
-    fn into(self) -> u16 {
+impl CloseCode {
+    /// The numeric value of this close code, as sent in a close frame.
+    pub fn raw(self) -> u16 {
         match self {
             Normal => 1000,
             Away => 1001,
@@ -172,6 +182,18 @@ impl Into<u16> for CloseCode {
     }
 }
 
+impl PartialEq for CloseCode {
+    fn eq(&self, other: &CloseCode) -> bool {
+        self.raw() == other.raw()
+    }
+}
+
+impl Into<u16> for CloseCode {
+    fn into(self) -> u16 {
+        self.raw()
+    }
+}
+
 impl From<u16> for CloseCode {
     fn from(code: u16) -> CloseCode {
         match code {
@@ -195,6 +217,85 @@ impl From<u16> for CloseCode {
     }
 }
 
+/// The standing of a close code in the IANA WebSocket Close Code Number Registry.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Registration {
+    /// Codes below 1000, which are not used.
+    Unused,
+    /// A code registered for use by the protocol that may be sent in a close frame.
+    Protocol,
+    /// A registered code that must not be sent in a close frame, such as 1005 (no status) or 1006
+    /// (abnormal closure), or a code reserved for future use.
+    Reserved,
+    /// A code in the range reserved for the protocol and its extensions (1000-2999) that has not
+    /// been assigned.
+    Unassigned,
+    /// A code in the range registered for use by libraries, frameworks and applications
+    /// (3000-3999).
+    Library,
+    /// A code in the range reserved for private use (4000-4999).
+    Private,
+    /// Codes of 5000 and above, which are outside of the range of close codes.
+    OutOfRange,
+}
+
+impl Registration {
+    /// Whether a close frame carrying a code with this registration is valid.
+    pub fn is_valid(self) -> bool {
+        match self {
+            Registration::Protocol | Registration::Library | Registration::Private => true,
+            _ => false,
+        }
+    }
+}
+
+// Ranges of close codes in ascending order, as maintained in the IANA registry.
+const REGISTRY: [(u16, u16, Registration); 9] = [
+    (0, 999, Registration::Unused),
+    (1000, 1003, Registration::Protocol),
+    (1004, 1006, Registration::Reserved),
+    (1007, 1014, Registration::Protocol),
+    (1015, 1015, Registration::Reserved),
+    (1016, 2999, Registration::Unassigned),
+    (3000, 3999, Registration::Library),
+    (4000, 4999, Registration::Private),
+    (5000, 65535, Registration::OutOfRange),
+];
+
+impl CloseCode {
+    /// Look up the registration of this close code.
+    pub fn registration(self) -> Registration {
+        let code = self.raw();
+        REGISTRY
+            .iter()
+            .find(|&&(start, end, _)| start <= code && code <= end)
+            .map(|&(_, _, registration)| registration)
+            .unwrap_or(Registration::OutOfRange)
+    }
+}
+
+// Close codes are serialized as their numeric value so that they round-trip through structured
+// logs and configuration files, including codes represented by `Other`.
+#[cfg(feature = "serde")]
+impl Serialize for CloseCode {
+    fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u16(self.raw())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for CloseCode {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<CloseCode, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        u16::deserialize(deserializer).map(CloseCode::from)
+    }
+}
+
 mod test {
     #![allow(unused_imports, unused_variables, dead_code)]
     use super::*;
@@ -218,10 +319,41 @@ mod test {
         assert_eq!(CloseCode::from(byte), CloseCode::Policy);
     }
 
+    #[test]
+    fn closecode_registration() {
+        assert_eq!(CloseCode::from(0).registration(), Registration::Unused);
+        assert_eq!(CloseCode::from(999).registration(), Registration::Unused);
+        assert_eq!(CloseCode::Normal.registration(), Registration::Protocol);
+        assert_eq!(CloseCode::Unsupported.registration(), Registration::Protocol);
+        assert_eq!(CloseCode::from(1004).registration(), Registration::Reserved);
+        assert_eq!(CloseCode::Status.registration(), Registration::Reserved);
+        assert_eq!(CloseCode::Abnormal.registration(), Registration::Reserved);
+        assert_eq!(CloseCode::Again.registration(), Registration::Protocol);
+        assert_eq!(CloseCode::from(1014).registration(), Registration::Protocol);
+        assert_eq!(CloseCode::Tls.registration(), Registration::Reserved);
+        assert_eq!(CloseCode::from(1016).registration(), Registration::Unassigned);
+        assert_eq!(CloseCode::from(2999).registration(), Registration::Unassigned);
+        assert_eq!(CloseCode::from(3000).registration(), Registration::Library);
+        assert_eq!(CloseCode::from(4999).registration(), Registration::Private);
+        assert_eq!(CloseCode::from(5000).registration(), Registration::OutOfRange);
+        assert_eq!(CloseCode::from(65535).registration(), Registration::OutOfRange);
+    }
+
     #[test]
     fn closecode_into_u16() {
         let text = CloseCode::Away;
         let byte: u16 = text.into();
         assert_eq!(byte, 1001u16);
     }
+
+    #[test]
+    fn closecode_round_trip() {
+        for &code in &[0u16, 1000, 1005, 1015, 1016, 3000, 4000, 4999, 65535] {
+            assert_eq!(CloseCode::from(code).raw(), code);
+            assert_eq!(CloseCode::Other(code).raw(), code);
+            assert_eq!(CloseCode::from(code), CloseCode::Other(code));
+        }
+        assert_eq!(CloseCode::Other(1000), CloseCode::Normal);
+        assert_ne!(CloseCode::Other(1001), CloseCode::Normal);
+    }
 }
@@ -0,0 +1,311 @@
+//! Relaying server connections to an upstream WebSocket server.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+use std::net::ToSocketAddrs;
+use std::rc::Rc;
+
+use mio::Token;
+use url;
+
+use communication::Sender;
+use factory::Factory;
+use handler::Handler;
+use handshake::Handshake;
+use message::Message;
+use protocol::CloseCode;
+use result::{Error, Kind, Result};
+use WebSocket;
+
+const UPSTREAM: Token = Token(0);
+
+/// Accept connections on `addr` and relay each of them to the WebSocket server at `upstream`.
+///
+/// # Safety
+///
+/// This function blocks until the event loop finishes running. Avoid calling this method within
+/// another WebSocket handler.
+///
+/// # Examples
+///
+/// ```no_run
+/// ws::proxy::listen("127.0.0.1:3012", "ws://127.0.0.1:3013").unwrap()
+/// ```
+pub fn listen<A>(addr: A, upstream: &str) -> Result<()>
+where
+    A: ToSocketAddrs + fmt::Debug,
+{
+    let url = url::Url::parse(upstream).map_err(|err| {
+        Error::new(
+            Kind::Internal,
+            format!("Unable to parse {} as url due to {:?}", upstream, err),
+        )
+    })?;
+    let ws = WebSocket::new(ReverseProxy::new(url))?;
+    ws.listen(addr)?;
+    Ok(())
+}
+
+/// The state shared by an accepted connection and the upstream connection opened for it.
+struct Link {
+    downstream: Option<Sender>,
+    upstream: Option<Sender>,
+    upstream_open: bool,
+    // messages received from downstream before the upstream handshake completed
+    buffered: Vec<Message>,
+    // the close that ended the link, once either side has closed
+    closed: Option<(CloseCode, String)>,
+}
+
+impl Link {
+    fn peer(&self, side: Side) -> Option<&Sender> {
+        match side {
+            Side::Downstream if self.upstream_open => self.upstream.as_ref(),
+            Side::Downstream => None,
+            Side::Upstream => self.downstream.as_ref(),
+        }
+    }
+}
+
+/// The side of the proxy that a `ProxyHandler` manages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    /// A connection accepted by the proxy.
+    Downstream,
+    /// A connection from the proxy to the upstream server.
+    Upstream,
+}
+
+/// A factory that relays every connection that it accepts to an upstream WebSocket server.
+///
+/// It opens one client connection to the upstream URL for every connection that it
+/// accepts. Messages are relayed in both directions, and a close code received on either side is
+/// sent on to the other side, so neither endpoint needs to know that the proxy exists.
+///
+/// ```
+/// // an upstream echo server
+/// let upstream = ws::Builder::new()
+///     .spawn_local(|| |out: ws::Sender| move |msg| out.send(msg))
+///     .unwrap();
+///
+/// let url = upstream.url();
+/// let proxy = ws::Builder::new()
+///     .spawn_local(move || ws::proxy::ReverseProxy::new(url))
+///     .unwrap();
+///
+/// ws::connect(proxy.url().as_str(), |out| {
+///     out.send("Hello").unwrap();
+///     move |msg| {
+///         assert_eq!(msg, ws::Message::text("Hello"));
+///         out.close(ws::CloseCode::Normal)
+///     }
+/// }).unwrap();
+///
+/// proxy.stop().unwrap();
+/// upstream.stop().unwrap();
+/// ```
+///
+/// The factory must be used by a WebSocket that can accept connections, because it opens the
+/// upstream connections on the same event loop. Client connections that the proxy did not open
+/// itself are closed with `CloseCode::Policy`.
+pub struct ReverseProxy {
+    upstream: url::Url,
+    // accepted connections waiting for their upstream connection to be created, in the order in
+    // which they requested it
+    pending: Rc<RefCell<VecDeque<Rc<RefCell<Link>>>>>,
+}
+
+impl ReverseProxy {
+    /// Create a factory that relays connections to `upstream`.
+    pub fn new(upstream: url::Url) -> ReverseProxy {
+        ReverseProxy {
+            upstream,
+            pending: Rc::new(RefCell::new(VecDeque::new())),
+        }
+    }
+
+    /// The URL of the upstream server.
+    pub fn upstream(&self) -> &url::Url {
+        &self.upstream
+    }
+
+    fn handler(&self, side: Side, link: Link) -> ProxyHandler {
+        ProxyHandler {
+            side,
+            link: Rc::new(RefCell::new(link)),
+            upstream: self.upstream.clone(),
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl Factory for ReverseProxy {
+    type Handler = ProxyHandler;
+
+    fn connection_made(&mut self, out: Sender) -> ProxyHandler {
+        self.handler(
+            Side::Downstream,
+            Link {
+                downstream: Some(out),
+                upstream: None,
+                upstream_open: false,
+                buffered: Vec::new(),
+                closed: None,
+            },
+        )
+    }
+
+    fn client_connected(&mut self, out: Sender) -> ProxyHandler {
+        // Connections are created in the order they were requested, so the next pending link
+        // is the one this connection was opened for
+        if let Some(link) = self.pending.borrow_mut().pop_front() {
+            link.borrow_mut().upstream = Some(out);
+            return ProxyHandler {
+                side: Side::Upstream,
+                link,
+                upstream: self.upstream.clone(),
+                pending: self.pending.clone(),
+            };
+        }
+        self.handler(
+            Side::Upstream,
+            Link {
+                downstream: None,
+                upstream: Some(out),
+                upstream_open: false,
+                buffered: Vec::new(),
+                closed: None,
+            },
+        )
+    }
+
+    fn connection_lost(&mut self, handler: ProxyHandler) {
+        handler.lost()
+    }
+}
+
+/// The handler for one side of a connection relayed by a `ReverseProxy`.
+pub struct ProxyHandler {
+    side: Side,
+    link: Rc<RefCell<Link>>,
+    upstream: url::Url,
+    pending: Rc<RefCell<VecDeque<Rc<RefCell<Link>>>>>,
+}
+
+impl ProxyHandler {
+    /// Whether this handler manages a connection accepted by the proxy, rather than one to the
+    /// upstream server.
+    pub fn is_downstream(&self) -> bool {
+        self.side == Side::Downstream
+    }
+
+    // Close the other side of the link with the close code of this side, unless the link has
+    // already been closed.
+    fn forward_close(&self, code: CloseCode, reason: &str) -> Result<()> {
+        let mut link = self.link.borrow_mut();
+        if link.closed.is_some() {
+            return Ok(());
+        }
+        link.closed = Some((forwarded(code), reason.into()));
+        link.buffered.clear();
+        match link.peer(self.side) {
+            Some(peer) => peer.close_with_reason(forwarded(code), reason.to_owned()),
+            None => Ok(()),
+        }
+    }
+
+    fn lost(self) {
+        // The link was closed through `on_close` unless the connection failed before it opened
+        // or ended without a closing handshake
+        let code = match self.side {
+            Side::Upstream if !self.link.borrow().upstream_open => CloseCode::Error,
+            _ => CloseCode::Away,
+        };
+        if let Err(err) = self.forward_close(code, "") {
+            debug!(
+                "Unable to close the other side of a proxied connection: {}",
+                err
+            );
+        }
+    }
+}
+
+impl Handler for ProxyHandler {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        match self.side {
+            Side::Downstream => {
+                self.pending.borrow_mut().push_back(self.link.clone());
+                let link = self.link.borrow();
+                let out = link
+                    .downstream
+                    .as_ref()
+                    .expect("accepted connection without a sender");
+                out.connect_with_token(self.upstream.clone(), UPSTREAM)
+            }
+            Side::Upstream => {
+                let mut link = self.link.borrow_mut();
+                let out = link
+                    .upstream
+                    .clone()
+                    .expect("upstream connection without a sender");
+                if link.downstream.is_none() {
+                    return out.close_with_reason(CloseCode::Policy, "Not opened by the proxy.");
+                }
+                link.upstream_open = true;
+                if let Some((code, ref reason)) = link.closed {
+                    return out.close_with_reason(code, reason.clone());
+                }
+                for msg in mem::take(&mut link.buffered) {
+                    out.send(msg)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        let mut link = self.link.borrow_mut();
+        if link.closed.is_some() {
+            return Ok(());
+        }
+        if let Some(peer) = link.peer(self.side) {
+            return peer.send(msg);
+        }
+        link.buffered.push(msg);
+        Ok(())
+    }
+
+    fn on_close(&mut self, code: CloseCode, reason: &str) {
+        if let Err(err) = self.forward_close(code, reason) {
+            debug!(
+                "Unable to close the other side of a proxied connection: {}",
+                err
+            );
+        }
+    }
+
+    fn on_connect_result(&mut self, _: Token, result: Result<Token>) -> Result<()> {
+        if let Err(err) = result {
+            error!("Unable to connect to upstream {}: {}", self.upstream, err);
+            // The upstream connection was never created, so no one else will take this link
+            self.pending
+                .borrow_mut()
+                .retain(|link| !Rc::ptr_eq(link, &self.link));
+            let link = self.link.borrow();
+            if let Some(ref out) = link.downstream {
+                return out.close_with_reason(CloseCode::Error, "Upstream is unavailable.");
+            }
+        }
+        Ok(())
+    }
+}
+
+// Close codes that cannot be sent in a close frame are replaced by the closest code that can.
+fn forwarded(code: CloseCode) -> CloseCode {
+    match code {
+        CloseCode::Status => CloseCode::Empty,
+        CloseCode::Abnormal | CloseCode::Tls => CloseCode::Away,
+        code => code,
+    }
+}
@@ -0,0 +1,163 @@
+// Reading the proxy configured in the settings of the operating system, see `Proxy::from_system`.
+
+use super::Proxy;
+use result::Result;
+
+#[cfg(all(feature = "system-proxy", windows))]
+pub fn proxy() -> Result<Option<Proxy>> {
+    use std::io;
+
+    use winreg::enums::HKEY_CURRENT_USER;
+    use winreg::RegKey;
+
+    // The settings shown by the Internet Options of Windows, which WinHTTP also imports
+    let settings = match RegKey::predef(HKEY_CURRENT_USER)
+        .open_subkey("Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings")
+    {
+        Ok(settings) => settings,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let enabled: u32 = settings.get_value("ProxyEnable").unwrap_or(0);
+    if enabled == 0 {
+        return Ok(None);
+    }
+    let server: String = match settings.get_value("ProxyServer") {
+        Ok(server) => server,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(parse_proxy_server(&server))
+}
+
+#[cfg(all(feature = "system-proxy", target_os = "macos"))]
+pub fn proxy() -> Result<Option<Proxy>> {
+    use system_configuration::core_foundation::base::CFType;
+    use system_configuration::core_foundation::dictionary::CFDictionary;
+    use system_configuration::core_foundation::number::CFNumber;
+    use system_configuration::core_foundation::string::CFString;
+    use system_configuration::dynamic_store::SCDynamicStoreBuilder;
+
+    fn number(proxies: &CFDictionary<CFString, CFType>, key: &'static str) -> Option<i32> {
+        proxies
+            .find(CFString::from_static_string(key))
+            .and_then(|value| value.downcast::<CFNumber>())
+            .and_then(|value| value.to_i32())
+    }
+
+    fn string(proxies: &CFDictionary<CFString, CFType>, key: &'static str) -> Option<String> {
+        proxies
+            .find(CFString::from_static_string(key))
+            .and_then(|value| value.downcast::<CFString>())
+            .map(|value| value.to_string())
+    }
+
+    // The settings shown by the Network preferences, as used by Safari
+    let proxies = match SCDynamicStoreBuilder::new("ws").build().get_proxies() {
+        Some(proxies) => proxies,
+        None => return Ok(None),
+    };
+    let kinds = [
+        ("HTTPSEnable", "HTTPSProxy", "HTTPSPort"),
+        ("HTTPEnable", "HTTPProxy", "HTTPPort"),
+    ];
+    for &(enable, host, port) in &kinds {
+        if number(&proxies, enable) != Some(1) {
+            continue;
+        }
+        match (string(&proxies, host), number(&proxies, port)) {
+            (Some(ref host), Some(port)) if !host.is_empty() && port > 0 && port < 65_536 => {
+                return Ok(Some(Proxy::new(host.clone(), port as u16)));
+            }
+            _ => debug!("Ignoring incomplete {} system proxy settings.", host),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(not(all(feature = "system-proxy", any(windows, target_os = "macos"))))]
+pub fn proxy() -> Result<Option<Proxy>> {
+    debug!("Reading the system proxy settings is not supported by this build.");
+    Ok(None)
+}
+
+// Parse the `ProxyServer` value of the Windows proxy settings. It is either the address of a
+// proxy for every protocol, such as `proxy:3128`, or a list of addresses by protocol, such as
+// `http=proxy:3128;https=secure-proxy:3129;socks=socks-proxy:1080`. The proxy used for HTTPS is
+// preferred since WebSocket connections are tunnelled like HTTPS requests.
+#[cfg_attr(not(all(feature = "system-proxy", windows)), allow(dead_code))]
+fn parse_proxy_server(server: &str) -> Option<Proxy> {
+    let mut all = None;
+    let mut http = None;
+    for entry in server
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (protocol, addr) = match entry.find('=') {
+            Some(i) => (&entry[..i], &entry[i + 1..]),
+            None => ("", entry),
+        };
+        let proxy = match parse_addr(addr.trim()) {
+            Some(proxy) => proxy,
+            None => continue,
+        };
+        match &protocol.trim().to_ascii_lowercase()[..] {
+            "https" => return Some(proxy),
+            "http" => http = http.or(Some(proxy)),
+            "" => all = all.or(Some(proxy)),
+            _ => (),
+        }
+    }
+    all.or(http)
+}
+
+// Parse a proxy address such as `proxy:3128`, `http://proxy:3128` or `[::1]:3128`. The port
+// defaults to 80, as it does for Windows.
+fn parse_addr(addr: &str) -> Option<Proxy> {
+    let addr = match addr.find("://") {
+        Some(i) => &addr[i + 3..],
+        None => addr,
+    };
+    let addr = addr.trim_end_matches('/');
+    let (host, port) = match addr.rfind(':') {
+        // a colon within brackets belongs to an IPv6 address
+        Some(i) if !addr[i..].contains(']') => (&addr[..i], addr[i + 1..].parse().ok()?),
+        _ => (addr, 80),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some(Proxy::new(host, port))
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+
+    #[test]
+    fn proxy_server() {
+        assert_eq!(
+            parse_proxy_server("proxy:3128"),
+            Some(Proxy::new("proxy", 3128))
+        );
+        assert_eq!(
+            parse_proxy_server("http=proxy:3128;https=secure:3129;socks=socks:1080"),
+            Some(Proxy::new("secure", 3129))
+        );
+        assert_eq!(
+            parse_proxy_server("socks=socks:1080; http=http://proxy:3128/"),
+            Some(Proxy::new("proxy", 3128))
+        );
+        assert_eq!(parse_proxy_server("proxy"), Some(Proxy::new("proxy", 80)));
+        assert_eq!(
+            parse_proxy_server("[::1]:3128"),
+            Some(Proxy::new("::1", 3128))
+        );
+        assert_eq!(parse_proxy_server("[::1]"), Some(Proxy::new("::1", 80)));
+        assert_eq!(parse_proxy_server("socks=socks:1080"), None);
+        assert_eq!(parse_proxy_server("proxy:port"), None);
+        assert_eq!(parse_proxy_server(""), None);
+    }
+}
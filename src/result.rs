@@ -58,6 +58,25 @@ pub enum Kind {
     /// Indicates a failure to perform SSL encryption.
     #[cfg(any(feature = "ssl", feature = "nativetls"))]
     SslHandshake(HandshakeError),
+    /// Indicates that a peer did not complete the TLS handshake within
+    /// `Settings::tls_handshake_timeout_ms`. The connection will be disconnected.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    TlsTimeout,
+    /// Indicates that a peer did not complete the opening handshake within
+    /// `Settings::handshake_timeout_ms`. The connection will be disconnected.
+    HandshakeTimeout,
+    /// Indicates that a client attempted to renegotiate an established TLS session with a server.
+    /// Renegotiation is always rejected and the connection will be disconnected. This is only
+    /// detected when the `ssl` feature is enabled.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    TlsRenegotiation,
+    /// Indicates that an HTTP proxy required authentication and the configured credentials were
+    /// missing, of a scheme that the proxy does not accept, or rejected. The connection will be
+    /// closed, and the application may connect again with new credentials.
+    ProxyAuthentication,
+    /// Indicates that a connection attempt was cancelled with `Sender::cancel_connect` before
+    /// the opening handshake completed.
+    Cancelled,
     /// A custom error kind for use by applications. This error kind involves extra overhead
     /// because it will allocate the memory on the heap. The WebSocket ignores such errors by
     /// default, simply passing them to the Connection Handler.
@@ -122,7 +141,14 @@ impl StdError for Error {
             Kind::Ssl(ref err) => err.description(),
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
             Kind::SslHandshake(ref err) => err.description(),
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            Kind::TlsTimeout => "TLS handshake timed out",
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            Kind::TlsRenegotiation => "TLS renegotiation rejected",
+            Kind::HandshakeTimeout => "Opening handshake timed out",
             Kind::Queue(_) => "Unable to send signal on event loop",
+            Kind::ProxyAuthentication => "Proxy authentication failed",
+            Kind::Cancelled => "Connection attempt cancelled",
             Kind::Custom(ref err) => err.description(),
         }
     }
@@ -0,0 +1,213 @@
+// Reading the server name that a client asks for with the Server Name Indication extension of
+// its TLS ClientHello, see `Settings::sniff_server_name`. Only the plaintext records that begin a
+// TLS stream are parsed, so that the name is known before the stream is encrypted.
+#![cfg_attr(not(any(feature = "ssl", feature = "nativetls")), allow(dead_code))]
+
+// The content type of a TLS handshake record.
+const HANDSHAKE_RECORD: u8 = 0x16;
+// The type of a ClientHello handshake message.
+const CLIENT_HELLO: u8 = 0x01;
+// The type of the server_name extension and of a host name within it, as set by RFC 6066.
+const SERVER_NAME_EXTENSION: u16 = 0;
+const HOST_NAME: u8 = 0;
+
+/// The number of bytes of a TLS stream read ahead to find the server name. This fits a
+/// ClientHello sent in a single record of the largest size allowed.
+pub const MAX_CLIENT_HELLO: usize = 5 + 16_384;
+
+/// The server name found at the start of a TLS stream.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ServerName {
+    /// The ClientHello has not been received completely.
+    Incomplete,
+    /// The client asked for this host name.
+    Found(String),
+    /// The client did not send a host name, or the stream does not begin with a valid
+    /// ClientHello.
+    Missing,
+}
+
+/// Look for the server name in the ClientHello at the start of `buf`, which may be split across
+/// several handshake records.
+pub fn parse(buf: &[u8]) -> ServerName {
+    // the handshake messages carried by the records received so far
+    let mut message = Vec::new();
+    let mut pos = 0;
+    loop {
+        if message.len() >= 4 {
+            let len = read_u24(&message[1..4]);
+            if message[0] != CLIENT_HELLO {
+                return ServerName::Missing;
+            }
+            if message.len() >= 4 + len {
+                return parse_client_hello(&message[4..4 + len])
+                    .map_or(ServerName::Missing, ServerName::Found);
+            }
+        }
+        if buf.len() < pos + 5 {
+            return ServerName::Incomplete;
+        }
+        if buf[pos] != HANDSHAKE_RECORD {
+            return ServerName::Missing;
+        }
+        let len = read_u16(&buf[pos + 3..pos + 5]) as usize;
+        if buf.len() < pos + 5 + len {
+            return ServerName::Incomplete;
+        }
+        message.extend_from_slice(&buf[pos + 5..pos + 5 + len]);
+        pos += 5 + len;
+    }
+}
+
+// Find the host name in the body of a ClientHello.
+fn parse_client_hello(body: &[u8]) -> Option<String> {
+    let mut reader = Reader { buf: body };
+    // the legacy version and the random bytes
+    reader.take(2 + 32)?;
+    let session_id = reader.u8()? as usize;
+    reader.take(session_id)?;
+    let cipher_suites = reader.u16()? as usize;
+    reader.take(cipher_suites)?;
+    let compression_methods = reader.u8()? as usize;
+    reader.take(compression_methods)?;
+    let extensions = reader.u16()? as usize;
+    let mut extensions = Reader {
+        buf: reader.take(extensions)?,
+    };
+    while !extensions.buf.is_empty() {
+        let kind = extensions.u16()?;
+        let len = extensions.u16()? as usize;
+        let data = extensions.take(len)?;
+        if kind == SERVER_NAME_EXTENSION {
+            return parse_server_name_list(data);
+        }
+    }
+    None
+}
+
+fn parse_server_name_list(data: &[u8]) -> Option<String> {
+    let mut reader = Reader { buf: data };
+    let len = reader.u16()? as usize;
+    let mut names = Reader {
+        buf: reader.take(len)?,
+    };
+    while !names.buf.is_empty() {
+        let kind = names.u8()?;
+        let len = names.u16()? as usize;
+        let name = names.take(len)?;
+        if kind == HOST_NAME {
+            return match ::std::str::from_utf8(name) {
+                Ok(name) if !name.is_empty() && name.is_ascii() => Some(name.into()),
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
+fn read_u16(buf: &[u8]) -> u16 {
+    u16::from(buf[0]) << 8 | u16::from(buf[1])
+}
+
+fn read_u24(buf: &[u8]) -> usize {
+    (buf[0] as usize) << 16 | (buf[1] as usize) << 8 | buf[2] as usize
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|buf| buf[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(read_u16)
+    }
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+
+    // Build a ClientHello with the given extensions, split into records of at most `record`
+    // bytes.
+    fn client_hello(extensions: &[(u16, Vec<u8>)], record: usize) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[7; 32]);
+        body.extend_from_slice(&[0, 0, 2, 0x13, 0x01, 1, 0]);
+        let mut encoded = Vec::new();
+        for &(kind, ref data) in extensions {
+            encoded.extend_from_slice(&[(kind >> 8) as u8, kind as u8]);
+            encoded.extend_from_slice(&[(data.len() >> 8) as u8, data.len() as u8]);
+            encoded.extend_from_slice(data);
+        }
+        body.extend_from_slice(&[(encoded.len() >> 8) as u8, encoded.len() as u8]);
+        body.extend_from_slice(&encoded);
+
+        let mut message = vec![CLIENT_HELLO, 0, (body.len() >> 8) as u8, body.len() as u8];
+        message.extend_from_slice(&body);
+
+        let mut stream = Vec::new();
+        for chunk in message.chunks(record) {
+            stream.extend_from_slice(&[HANDSHAKE_RECORD, 0x03, 0x01]);
+            stream.extend_from_slice(&[(chunk.len() >> 8) as u8, chunk.len() as u8]);
+            stream.extend_from_slice(chunk);
+        }
+        stream
+    }
+
+    fn server_name(name: &[u8]) -> (u16, Vec<u8>) {
+        let mut data = vec![0, (name.len() + 3) as u8, HOST_NAME, 0, name.len() as u8];
+        data.extend_from_slice(name);
+        (SERVER_NAME_EXTENSION, data)
+    }
+
+    #[test]
+    fn finds_server_name() {
+        let hello = client_hello(
+            &[(10, vec![0, 2, 0, 29]), server_name(b"example.com")],
+            1024,
+        );
+        assert_eq!(parse(&hello), ServerName::Found("example.com".into()));
+    }
+
+    #[test]
+    fn finds_server_name_across_records() {
+        let hello = client_hello(&[(10, vec![0; 300]), server_name(b"example.com")], 100);
+        assert_eq!(parse(&hello), ServerName::Found("example.com".into()));
+        for len in 0..hello.len() {
+            assert_eq!(parse(&hello[..len]), ServerName::Incomplete);
+        }
+    }
+
+    #[test]
+    fn missing_server_name() {
+        assert_eq!(
+            parse(&client_hello(&[(10, vec![0, 2, 0, 29])], 1024)),
+            ServerName::Missing
+        );
+        assert_eq!(
+            parse(&client_hello(&[server_name(b"")], 1024)),
+            ServerName::Missing
+        );
+        assert_eq!(parse(b"GET / HTTP/1.1\r\n"), ServerName::Missing);
+        // a truncated extension
+        let mut hello = client_hello(&[server_name(b"example.com")], 1024);
+        hello[4] -= 2;
+        hello[8] -= 2;
+        let len = hello.len();
+        hello.truncate(len - 2);
+        assert_eq!(parse(&hello), ServerName::Missing);
+    }
+}
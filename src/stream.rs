@@ -1,19 +1,48 @@
+#[cfg(feature = "testing")]
+use std::collections::VecDeque;
 use std::io;
 use std::io::ErrorKind::WouldBlock;
 #[cfg(any(feature = "ssl", feature = "nativetls"))]
 use std::mem::replace;
 use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+use std::path::PathBuf;
+#[cfg(any(feature = "rustls", feature = "testing"))]
+use std::sync::Arc;
+#[cfg(feature = "testing")]
+use std::sync::{Mutex, MutexGuard};
 
-use bytes::{Buf, BufMut};
+use bytes::Buf;
+#[cfg(not(feature = "safe"))]
+use bytes::BufMut;
+#[cfg(all(target_os = "linux", not(feature = "safe")))]
+use libc;
 use mio::tcp::TcpStream;
+#[cfg(unix)]
+use mio::unix::EventedFd;
+use mio::{Evented, Poll, PollOpt, Ready, Token};
+use net2::TcpBuilder;
 #[cfg(feature = "nativetls")]
 use native_tls::{
     HandshakeError, MidHandshakeTlsStream as MidHandshakeSslStream, TlsStream as SslStream,
 };
 #[cfg(feature = "ssl")]
 use openssl::ssl::{ErrorCode as SslErrorCode, HandshakeError, MidHandshakeSslStream, SslStream};
+#[cfg(feature = "rustls")]
+use rustls::{ServerConfig, ServerConnection};
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+use url;
 
+#[cfg(feature = "chaos")]
+use chaos;
 use result::{Error, Kind, Result};
+use Interface;
 
 fn map_non_block<T>(res: io::Result<T>) -> io::Result<Option<T>> {
     match res {
@@ -28,16 +57,150 @@ fn map_non_block<T>(res: io::Result<T>) -> io::Result<Option<T>> {
     }
 }
 
+/// Options used when encrypting client connections.
+///
+/// These options are configured with the `Builder` and are passed to
+/// `Handler::upgrade_ssl_client_with_options`.
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TlsClientOptions {
+    /// The name to use for Server Name Indication and certificate verification instead of the
+    /// host of the URL. This is useful when connecting to an IP address that presents a
+    /// certificate for a DNS name.
+    pub server_name: Option<String>,
+    /// Whether to accept certificates that are not valid for the server name. This makes the
+    /// connection vulnerable to man-in-the-middle attacks and should be used with caution.
+    pub accept_invalid_hostnames: bool,
+    /// Whether to ask the server to staple an OCSP response to its certificate and fail the
+    /// handshake unless the response is signed by a trusted responder, is current, and reports the
+    /// certificate as good. Only supported with the `ssl` feature.
+    pub require_ocsp_stapling: bool,
+    /// PEM files of certificate revocation lists to check every certificate in the server's chain
+    /// against. When any are given, the handshake fails for a certificate that is revoked or whose
+    /// issuer has no list. Only supported with the `ssl` feature.
+    pub crl_files: Vec<PathBuf>,
+}
+
+#[cfg(any(feature = "ssl", feature = "nativetls"))]
+impl TlsClientOptions {
+    /// Get the name that the server is expected to present a certificate for.
+    pub fn domain<'a>(&'a self, url: &'a url::Url) -> Result<&'a str> {
+        if let Some(ref name) = self.server_name {
+            return Ok(name);
+        }
+        let host = if self.accept_invalid_hostnames {
+            url.host_str()
+        } else {
+            url.domain()
+        };
+        host.ok_or_else(|| {
+            Error::new(
+                Kind::Protocol,
+                format!("Unable to parse domain from {}. Needed for SSL.", url),
+            )
+        })
+    }
+}
+
+/// Open a non-blocking TCP connection to `addr`, optionally binding the socket to `local` and to
+/// the network interface `interface` first.
+pub fn connect_tcp(
+    addr: &SocketAddr,
+    local: Option<SocketAddr>,
+    interface: Option<Interface>,
+) -> io::Result<TcpStream> {
+    if local.is_none() && interface.is_none() {
+        return TcpStream::connect(addr);
+    }
+    let builder = if addr.is_ipv4() {
+        TcpBuilder::new_v4()?
+    } else {
+        TcpBuilder::new_v6()?
+    };
+    if let Some(interface) = interface {
+        bind_device(&builder, interface)?;
+    }
+    if let Some(local) = local {
+        if local.is_ipv4() != addr.is_ipv4() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unable to connect to {} from {}", addr, local),
+            ));
+        }
+        builder.bind(local)?;
+    }
+    TcpStream::connect_stream(builder.to_tcp_stream()?, addr)
+}
+
+#[cfg(all(target_os = "linux", not(feature = "safe")))]
+#[allow(unsafe_code)]
+fn bind_device(builder: &TcpBuilder, interface: Interface) -> io::Result<()> {
+    let name = interface.name();
+    // an empty name would remove the binding instead
+    if name.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Unable to bind to an interface without a name.",
+        ));
+    }
+    let res = unsafe {
+        libc::setsockopt(
+            builder.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            name.as_ptr() as *const libc::c_void,
+            name.len() as libc::socklen_t,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_: &TcpBuilder, interface: Interface) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        format!(
+            "Unable to bind to interface {}. Binding to an interface is only supported on Linux.",
+            interface.name()
+        ),
+    ))
+}
+
+#[cfg(all(target_os = "linux", feature = "safe"))]
+fn bind_device(_: &TcpBuilder, interface: Interface) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        format!(
+            "Unable to bind to interface {}. Binding to an interface requires unsafe code, which \
+             the `safe` feature disables.",
+            interface.name()
+        ),
+    ))
+}
+
 pub trait TryReadBuf: io::Read {
-    fn try_read_buf<B: BufMut>(&mut self, buf: &mut B) -> io::Result<Option<usize>>
+    fn try_read(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>>
+    where
+        Self: Sized,
+    {
+        map_non_block(self.read(buf))
+    }
+
+    // Reads into the spare capacity of buf, reserving some first if there is none.
+    // This is not guaranteed to consume an entire datagram or segment.
+    // If your protocol is msg based (instead of continuous stream) you should
+    // ensure that your buffer is large enough to hold an entire segment (1532 bytes if not jumbo
+    // frames)
+    #[cfg(not(feature = "safe"))]
+    #[allow(unsafe_code)]
+    fn try_read_buf(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<usize>>
     where
         Self: Sized,
     {
-        // Reads the length of the slice supplied by buf.mut_bytes into the buffer
-        // This is not guaranteed to consume an entire datagram or segment.
-        // If your protocol is msg based (instead of continuous stream) you should
-        // ensure that your buffer is large enough to hold an entire segment (1532 bytes if not jumbo
-        // frames)
         let res = map_non_block(self.read(unsafe { buf.bytes_mut() }));
 
         if let Ok(Some(cnt)) = res {
@@ -48,6 +211,30 @@ pub trait TryReadBuf: io::Read {
 
         res
     }
+
+    // The spare capacity is zeroed before it is read into, which costs a pass over it per read.
+    #[cfg(feature = "safe")]
+    fn try_read_buf(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<usize>>
+    where
+        Self: Sized,
+    {
+        if buf.len() == buf.capacity() {
+            buf.reserve(64);
+        }
+        let start = buf.len();
+        let end = buf.capacity();
+        buf.resize(end, 0);
+
+        let res = map_non_block(self.read(&mut buf[start..]));
+
+        let cnt = match res {
+            Ok(Some(cnt)) => cnt,
+            _ => 0,
+        };
+        buf.truncate(start + cnt);
+
+        res
+    }
 }
 
 pub trait TryWriteBuf: io::Write {
@@ -68,11 +255,186 @@ pub trait TryWriteBuf: io::Write {
 impl<T: io::Read> TryReadBuf for T {}
 impl<T: io::Write> TryWriteBuf for T {}
 
+/// Connect to a unix domain socket for use with the event loop.
+#[cfg(unix)]
+pub fn connect_unix(path: &Path) -> io::Result<UnixSocket> {
+    let sock = UnixStream::connect(path)?;
+    sock.set_nonblocking(true)?;
+    Ok(UnixSocket(sock))
+}
+
+/// A nonblocking unix domain socket that can be registered with the event loop.
+#[cfg(unix)]
+pub struct UnixSocket(UnixStream);
+
+#[cfg(unix)]
+impl Evented for UnixSocket {
+    fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+#[cfg(unix)]
+fn no_socket_addr() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AddrNotAvailable,
+        "Unix domain sockets do not have a socket address.",
+    )
+}
+
+#[cfg(feature = "testing")]
+#[derive(Default)]
+struct Pipe {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+#[cfg(feature = "testing")]
+fn lock(pipe: &Mutex<Pipe>) -> MutexGuard<'_, Pipe> {
+    // a pipe holds plain bytes, so it remains usable after a panic
+    pipe.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The receiving half of a `MemoryStream`, which can be checked for input without the stream.
+#[cfg(feature = "testing")]
+#[derive(Clone)]
+pub struct MemoryInput(Arc<Mutex<Pipe>>);
+
+#[cfg(feature = "testing")]
+impl MemoryInput {
+    /// Whether a read would return data or the end of the stream rather than block.
+    pub fn is_ready(&self) -> bool {
+        let pipe = lock(&self.0);
+        pipe.closed || !pipe.data.is_empty()
+    }
+}
+
+/// One end of an in-memory connection. Dropping either end closes the connection.
+#[cfg(feature = "testing")]
+pub struct MemoryStream {
+    incoming: Arc<Mutex<Pipe>>,
+    outgoing: Arc<Mutex<Pipe>>,
+}
+
+#[cfg(feature = "testing")]
+impl MemoryStream {
+    /// Create both ends of an in-memory connection.
+    pub fn pair() -> (MemoryStream, MemoryStream) {
+        let forward = Arc::new(Mutex::new(Pipe::default()));
+        let backward = Arc::new(Mutex::new(Pipe::default()));
+        (
+            MemoryStream {
+                incoming: backward.clone(),
+                outgoing: forward.clone(),
+            },
+            MemoryStream {
+                incoming: forward,
+                outgoing: backward,
+            },
+        )
+    }
+
+    /// A handle to the data waiting to be read from this end.
+    pub fn input(&self) -> MemoryInput {
+        MemoryInput(self.incoming.clone())
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        lock(&self.incoming).closed = true;
+        lock(&self.outgoing).closed = true;
+    }
+}
+
+#[cfg(feature = "testing")]
+impl io::Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = lock(&self.incoming);
+        if pipe.data.is_empty() {
+            return if pipe.closed {
+                Ok(0)
+            } else {
+                Err(io::Error::new(WouldBlock, "No data in the in-memory stream."))
+            };
+        }
+        let len = buf.len().min(pipe.data.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.data.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "testing")]
+impl io::Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = lock(&self.outgoing);
+        if pipe.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The in-memory stream is closed.",
+            ));
+        }
+        pipe.data.extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// In-memory streams are driven directly rather than polled, so registration does nothing.
+#[cfg(feature = "testing")]
+impl Evented for MemoryStream {
+    fn register(&self, _: &Poll, _: Token, _: Ready, _: PollOpt) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reregister(&self, _: &Poll, _: Token, _: Ready, _: PollOpt) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn deregister(&self, _: &Poll) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "testing")]
+fn no_memory_addr() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AddrNotAvailable,
+        "In-memory streams do not have a socket address.",
+    )
+}
+
 use self::Stream::*;
 pub enum Stream {
     Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixSocket),
+    #[cfg(feature = "testing")]
+    Memory(MemoryStream),
     #[cfg(any(feature = "ssl", feature = "nativetls"))]
     Tls(TlsStream),
+    #[cfg(feature = "rustls")]
+    Rustls(Box<RustlsStream>),
 }
 
 impl Stream {
@@ -80,6 +442,29 @@ impl Stream {
         Tcp(stream)
     }
 
+    #[cfg(unix)]
+    pub fn unix(stream: UnixSocket) -> Stream {
+        Unix(stream)
+    }
+
+    #[cfg(feature = "testing")]
+    pub fn memory(stream: MemoryStream) -> Stream {
+        Memory(stream)
+    }
+
+    /// Encrypt an accepted TCP stream with rustls. The TLS handshake is performed as the stream
+    /// is read.
+    #[cfg(feature = "rustls")]
+    pub fn rustls(sock: TcpStream, config: Arc<ServerConfig>) -> Result<Stream> {
+        let conn = ServerConnection::new(config).map_err(|err| {
+            Error::new(
+                Kind::Internal,
+                format!("Failed to encrypt server connection with rustls: {}", err),
+            )
+        })?;
+        Ok(Rustls(Box::new(RustlsStream { conn, sock })))
+    }
+
     #[cfg(any(feature = "ssl", feature = "nativetls"))]
     pub fn tls(stream: MidHandshakeSslStream<TcpStream>) -> Stream {
         Tls(TlsStream::Handshake {
@@ -93,27 +478,139 @@ impl Stream {
         Tls(TlsStream::Live(stream))
     }
 
+    /// Take ownership of the TCP stream so that it can be wrapped with TLS. The stream is left in
+    /// an upgrading state until it is replaced with the encrypted stream.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn take_tcp(&mut self) -> Option<TcpStream> {
+        match replace(self, Tls(TlsStream::Upgrading)) {
+            Tcp(sock) => Some(sock),
+            stream => {
+                *self = stream;
+                None
+            }
+        }
+    }
+
+    /// Take ownership of the whole stream so that it can be written to from another thread. The
+    /// stream is left in an upgrading state until it is returned.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn take_stream(&mut self) -> Stream {
+        replace(self, Tls(TlsStream::Upgrading))
+    }
+
     #[cfg(any(feature = "ssl", feature = "nativetls"))]
     pub fn is_tls(&self) -> bool {
         match *self {
             Tcp(_) => false,
+            #[cfg(unix)]
+            Unix(_) => false,
+            #[cfg(feature = "testing")]
+            Memory(_) => false,
             Tls(_) => true,
+            #[cfg(feature = "rustls")]
+            Rustls(_) => false,
         }
     }
 
-    pub fn evented(&self) -> &TcpStream {
+    /// Whether the stream is encrypted, with any of the enabled TLS implementations.
+    pub fn is_encrypted(&self) -> bool {
+        match *self {
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            Tls(_) => true,
+            #[cfg(feature = "rustls")]
+            Rustls(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the stream is encrypted but its TLS handshake has not completed.
+    pub fn is_tls_handshaking(&self) -> bool {
+        match *self {
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            Tls(TlsStream::Live(_)) => false,
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            Tls(_) => true,
+            #[cfg(feature = "rustls")]
+            Rustls(ref stream) => stream.conn.is_handshaking(),
+            _ => false,
+        }
+    }
+
+    /// The server name that the client asked for in the TLS handshake, as known to the TLS
+    /// implementation.
+    pub fn server_name(&self) -> Option<String> {
+        match *self {
+            #[cfg(feature = "rustls")]
+            Rustls(ref stream) => stream.conn.server_name().map(Into::into),
+            _ => None,
+        }
+    }
+
+    /// Whether encrypted data is waiting to be written even though the stream accepted every
+    /// byte written to it.
+    pub fn wants_write(&self) -> bool {
+        match *self {
+            #[cfg(feature = "rustls")]
+            Rustls(ref stream) => stream.conn.wants_write(),
+            _ => false,
+        }
+    }
+
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn is_tls_established(&self) -> bool {
+        match *self {
+            Tls(TlsStream::Live(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the peer has started renegotiating an established TLS session.
+    #[cfg(feature = "ssl")]
+    pub fn is_renegotiating(&self) -> bool {
+        match *self {
+            Tls(TlsStream::Live(ref sock)) => !sock.ssl().is_init_finished(),
+            _ => false,
+        }
+    }
+
+    /// Look at the next bytes of a TCP stream without consuming them.
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Tcp(ref sock) => sock.peek(buf),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only TCP streams can be inspected before they are read.",
+            )),
+        }
+    }
+
+    pub fn evented(&self) -> &dyn Evented {
         match *self {
             Tcp(ref sock) => sock,
+            #[cfg(unix)]
+            Unix(ref sock) => sock,
+            #[cfg(feature = "testing")]
+            Memory(ref sock) => sock,
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
             Tls(ref inner) => inner.evented(),
+            #[cfg(feature = "rustls")]
+            Rustls(ref stream) => &stream.sock,
         }
     }
 
     pub fn is_negotiating(&self) -> bool {
         match *self {
             Tcp(_) => false,
+            #[cfg(unix)]
+            Unix(_) => false,
+            #[cfg(feature = "testing")]
+            Memory(_) => false,
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
             Tls(ref inner) => inner.is_negotiating(),
+            // rustls answers handshake messages as they are read
+            #[cfg(feature = "rustls")]
+            Rustls(_) => false,
         }
     }
 
@@ -123,32 +620,86 @@ impl Stream {
                 Kind::Internal,
                 "Attempted to clear negotiating flag on non ssl connection.",
             )),
+            #[cfg(unix)]
+            Unix(_) => Err(Error::new(
+                Kind::Internal,
+                "Attempted to clear negotiating flag on non ssl connection.",
+            )),
+            #[cfg(feature = "testing")]
+            Memory(_) => Err(Error::new(
+                Kind::Internal,
+                "Attempted to clear negotiating flag on non ssl connection.",
+            )),
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
             Tls(ref mut inner) => inner.clear_negotiating(),
+            #[cfg(feature = "rustls")]
+            Rustls(_) => Err(Error::new(
+                Kind::Internal,
+                "Attempted to clear negotiating flag on rustls connection.",
+            )),
         }
     }
 
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
         match *self {
             Tcp(ref sock) => sock.peer_addr(),
+            #[cfg(unix)]
+            Unix(_) => Err(no_socket_addr()),
+            #[cfg(feature = "testing")]
+            Memory(_) => Err(no_memory_addr()),
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
             Tls(ref inner) => inner.peer_addr(),
+            #[cfg(feature = "rustls")]
+            Rustls(ref stream) => stream.sock.peer_addr(),
+        }
+    }
+
+    /// Take the pending error of the socket, as reported by `SO_ERROR`.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        match *self {
+            Tcp(ref sock) => sock.take_error(),
+            #[cfg(unix)]
+            Unix(ref sock) => sock.0.take_error(),
+            #[cfg(feature = "testing")]
+            Memory(_) => Ok(None),
+            #[cfg(any(feature = "ssl", feature = "nativetls"))]
+            Tls(ref inner) => inner.take_error(),
+            #[cfg(feature = "rustls")]
+            Rustls(ref stream) => stream.sock.take_error(),
         }
     }
 
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
         match *self {
             Tcp(ref sock) => sock.local_addr(),
+            #[cfg(unix)]
+            Unix(_) => Err(no_socket_addr()),
+            #[cfg(feature = "testing")]
+            Memory(_) => Err(no_memory_addr()),
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
             Tls(ref inner) => inner.local_addr(),
+            #[cfg(feature = "rustls")]
+            Rustls(ref stream) => stream.sock.local_addr(),
         }
     }
 }
 
 impl io::Read for Stream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "chaos")]
+        {
+            if let Some(err) = chaos::before_read() {
+                return Err(err);
+            }
+        }
         match *self {
             Tcp(ref mut sock) => sock.read(buf),
+            #[cfg(unix)]
+            Unix(ref mut sock) => sock.0.read(buf),
+            #[cfg(feature = "testing")]
+            Memory(ref mut sock) => sock.read(buf),
+            #[cfg(feature = "rustls")]
+            Rustls(ref mut stream) => stream.read(buf),
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
             Tls(TlsStream::Live(ref mut sock)) => sock.read(buf),
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
@@ -215,8 +766,20 @@ impl io::Read for Stream {
 
 impl io::Write for Stream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(feature = "chaos")]
+        {
+            if let Some(err) = chaos::before_write() {
+                return Err(err);
+            }
+        }
         match *self {
             Tcp(ref mut sock) => sock.write(buf),
+            #[cfg(unix)]
+            Unix(ref mut sock) => sock.0.write(buf),
+            #[cfg(feature = "testing")]
+            Memory(ref mut sock) => sock.write(buf),
+            #[cfg(feature = "rustls")]
+            Rustls(ref mut stream) => stream.write(buf),
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
             Tls(TlsStream::Live(ref mut sock)) => sock.write(buf),
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
@@ -285,6 +848,12 @@ impl io::Write for Stream {
     fn flush(&mut self) -> io::Result<()> {
         match *self {
             Tcp(ref mut sock) => sock.flush(),
+            #[cfg(unix)]
+            Unix(ref mut sock) => sock.0.flush(),
+            #[cfg(feature = "testing")]
+            Memory(ref mut sock) => sock.flush(),
+            #[cfg(feature = "rustls")]
+            Rustls(ref mut stream) => stream.flush(),
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
             Tls(TlsStream::Live(ref mut sock)) => sock.flush(),
             #[cfg(any(feature = "ssl", feature = "nativetls"))]
@@ -295,6 +864,71 @@ impl io::Write for Stream {
     }
 }
 
+/// A server connection encrypted with rustls.
+#[cfg(feature = "rustls")]
+pub struct RustlsStream {
+    conn: ServerConnection,
+    sock: TcpStream,
+}
+
+#[cfg(feature = "rustls")]
+impl RustlsStream {
+    // Write encrypted data until rustls has nothing left to send or the socket would block.
+    fn write_tls(&mut self) -> io::Result<()> {
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.sock)?;
+        }
+        Ok(())
+    }
+
+    // Send what rustls produced in response to the records just read. Anything left over is sent
+    // once the connection is writable, as reported by `Stream::wants_write`.
+    fn answer(&mut self) -> io::Result<()> {
+        match self.write_tls() {
+            Err(ref err) if err.kind() == WouldBlock => Ok(()),
+            res => res,
+        }
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl io::Read for RustlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.conn.reader().read(buf) {
+                Err(ref err) if err.kind() == WouldBlock => (),
+                res => return res,
+            }
+            if self.conn.read_tls(&mut self.sock)? == 0 {
+                return Ok(0);
+            }
+            if let Err(err) = self.conn.process_new_packets() {
+                // let the peer know why the connection is failing
+                let _ = self.write_tls();
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+            self.answer()?;
+        }
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl io::Write for RustlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Earlier data must be sent before more is accepted, so that the connection stays
+        // writable for as long as it has something to send.
+        self.write_tls()?;
+        let len = self.conn.writer().write(buf)?;
+        self.answer()?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.conn.writer().flush()?;
+        self.write_tls()
+    }
+}
+
 #[cfg(any(feature = "ssl", feature = "nativetls"))]
 pub enum TlsStream {
     Live(SslStream<TcpStream>),
@@ -344,7 +978,18 @@ impl TlsStream {
         match *self {
             TlsStream::Live(ref sock) => sock.get_ref().peer_addr(),
             TlsStream::Handshake { ref sock, .. } => sock.get_ref().peer_addr(),
-            TlsStream::Upgrading => panic!("Tried to access actively upgrading TlsStream"),
+            TlsStream::Upgrading => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "The TlsStream is being upgraded.",
+            )),
+        }
+    }
+
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        match *self {
+            TlsStream::Live(ref sock) => sock.get_ref().take_error(),
+            TlsStream::Handshake { ref sock, .. } => sock.get_ref().take_error(),
+            TlsStream::Upgrading => Ok(None),
         }
     }
 
@@ -352,7 +997,63 @@ impl TlsStream {
         match *self {
             TlsStream::Live(ref sock) => sock.get_ref().local_addr(),
             TlsStream::Handshake { ref sock, .. } => sock.get_ref().local_addr(),
-            TlsStream::Upgrading => panic!("Tried to access actively upgrading TlsStream"),
+            TlsStream::Upgrading => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "The TlsStream is being upgraded.",
+            )),
         }
     }
 }
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use super::*;
+
+    #[test]
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn tls_client_domain() {
+        let url = url::Url::parse("wss://10.0.0.1:443").unwrap();
+
+        let options = TlsClientOptions::default();
+        assert!(options.domain(&url).is_err());
+
+        let options = TlsClientOptions {
+            accept_invalid_hostnames: true,
+            ..TlsClientOptions::default()
+        };
+        assert_eq!(options.domain(&url).unwrap(), "10.0.0.1");
+
+        let options = TlsClientOptions {
+            server_name: Some("service.example.com".into()),
+            ..TlsClientOptions::default()
+        };
+        assert_eq!(options.domain(&url).unwrap(), "service.example.com");
+    }
+
+    #[test]
+    #[cfg(any(feature = "ssl", feature = "nativetls"))]
+    fn take_tcp() {
+        let listener = ::mio::tcp::TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let sock = TcpStream::connect(&listener.local_addr().unwrap()).unwrap();
+
+        let mut stream = Stream::tcp(sock);
+        assert!(stream.take_tcp().is_some());
+        assert!(stream.is_tls());
+        assert!(stream.take_tcp().is_none());
+        assert!(stream.peer_addr().is_err());
+    }
+
+    #[test]
+    fn try_read_buf_fills_spare_capacity() {
+        let mut buf = Vec::with_capacity(8);
+        buf.extend_from_slice(b"abc");
+        let mut reader = io::Cursor::new(b"defghijk".to_vec());
+        assert_eq!(reader.try_read_buf(&mut buf).unwrap(), Some(5));
+        assert_eq!(buf, b"abcdefgh");
+
+        // a full buffer is extended before it is read into
+        assert_eq!(reader.try_read_buf(&mut buf).unwrap(), Some(3));
+        assert_eq!(buf, b"abcdefghijk");
+        assert_eq!(reader.try_read_buf(&mut buf).unwrap(), Some(0));
+    }
+}
@@ -0,0 +1,510 @@
+//! Deterministic tools for testing handlers without sockets or sleeps.
+//!
+//! A `VirtualLoop` runs real connections over in-memory streams and keeps its own clock. Timeouts
+//! requested with `Sender::timeout` only fire when the test advances the clock, so features such
+//! as keepalive pings, reconnection and idle timeouts can be tested quickly and without races.
+//!
+//! ```
+//! use std::cell::Cell;
+//! use std::rc::Rc;
+//! use std::time::Duration;
+//!
+//! use ws::testing::VirtualLoop;
+//! use ws::util::Token;
+//! use ws::{Handler, Handshake, Result, Sender};
+//!
+//! struct Idle {
+//!     out: Sender,
+//!     fired: Rc<Cell<bool>>,
+//! }
+//!
+//! impl Handler for Idle {
+//!     fn on_open(&mut self, _: Handshake) -> Result<()> {
+//!         self.out.timeout(60_000, Token(1))
+//!     }
+//!
+//!     fn on_timeout(&mut self, _: Token) -> Result<()> {
+//!         self.fired.set(true);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let fired = Rc::new(Cell::new(false));
+//! let handler_fired = fired.clone();
+//! let mut virt = VirtualLoop::new(move |out| Idle {
+//!     out,
+//!     fired: handler_fired.clone(),
+//! });
+//! virt.connect("ws://example.com/").unwrap();
+//!
+//! virt.advance(Duration::from_secs(59));
+//! assert!(!fired.get());
+//! virt.advance(Duration::from_secs(1));
+//! assert!(fired.get());
+//! ```
+use std::borrow::Borrow;
+use std::time::Duration;
+
+use mio;
+use mio::Token;
+use mio_extras::timer::{Builder as TimerBuilder, Timeout, Timer};
+use url;
+
+use super::Settings;
+use communication::{Command, ConnectTarget, Producers, Sender, Signal};
+use connection::Connection;
+use event::ErrorPhase;
+use factory::Factory;
+use io::{ConnectRequest, ALL};
+use pool::Buffers;
+use result::{Error, Kind, Result};
+use stream::{MemoryInput, MemoryStream, Stream};
+
+// A pair of handlers that answer each other forever would otherwise never become idle.
+const MAX_PASSES: usize = 100_000;
+
+struct Slot<H>
+where
+    H: ::handler::Handler,
+{
+    conn: Connection<H>,
+    input: MemoryInput,
+    request: Option<ConnectRequest>,
+}
+
+struct Pending {
+    id: usize,
+    at: Duration,
+    connection: Token,
+    connection_id: u64,
+    event: Token,
+    handle: Timeout,
+}
+
+/// An event loop with a virtual clock that runs both ends of each connection in memory.
+///
+/// Every call to `connect` creates a client connection with `Factory::client_connected` and the
+/// matching server connection with `Factory::server_connected`, joined by an in-memory stream.
+/// Handlers interact with the loop through their `Sender` exactly as they would with a
+/// `WebSocket`, and signals addressed to connections are applied by the same code as in a
+/// `WebSocket`.
+///
+/// Only what can be modeled without sockets is supported. Connections have no socket addresses,
+/// so connecting from a local address fails, and there is no listener to pause. Broadcast
+/// messages are not coalesced, and the timings recorded during the handshake use the real clock.
+pub struct VirtualLoop<F>
+where
+    F: Factory,
+{
+    factory: F,
+    settings: Settings,
+    now: Duration,
+    connections: Vec<Option<Slot<F::Handler>>>,
+    next_connection_id: u64,
+    queue_tx: mio::channel::SyncSender<Command>,
+    queue_rx: mio::channel::Receiver<Command>,
+    producers: Producers,
+    // only used to create the handles passed to `Handler::on_new_timeout`
+    timer: Timer<usize>,
+    timeouts: Vec<Pending>,
+    next_timeout: usize,
+}
+
+impl<F> VirtualLoop<F>
+where
+    F: Factory,
+{
+    /// Create a virtual loop with the default settings.
+    pub fn new(factory: F) -> VirtualLoop<F> {
+        VirtualLoop::with_settings(factory, Settings::default())
+    }
+
+    /// Create a virtual loop with the given settings.
+    pub fn with_settings(factory: F, settings: Settings) -> VirtualLoop<F> {
+        let (queue_tx, queue_rx) = mio::channel::sync_channel(settings.queue_size);
+        VirtualLoop {
+            factory,
+            settings,
+            now: Duration::from_secs(0),
+            connections: Vec::new(),
+            next_connection_id: 0,
+            queue_tx,
+            queue_rx,
+            producers: Producers::default(),
+            timer: TimerBuilder::default().build(),
+            timeouts: Vec::new(),
+            next_timeout: 0,
+        }
+    }
+
+    /// The virtual time that has passed since the loop was created.
+    #[inline]
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// A sender that broadcasts to every connection, like `WebSocket::broadcaster`.
+    pub fn broadcaster(&self) -> Sender {
+        Sender::new(ALL, self.queue_tx.clone(), 0).with_producers(self.producers.clone())
+    }
+
+    /// The tokens of the connections that have not been closed.
+    pub fn tokens(&self) -> Vec<Token> {
+        self.connections
+            .iter()
+            .enumerate()
+            .filter(|&(_, slot)| slot.is_some())
+            .map(|(index, _)| Token(index))
+            .collect()
+    }
+
+    /// Open a connection to the given URL and run the loop until the handshake completes.
+    /// Returns the tokens of the client and server connections.
+    pub fn connect(&mut self, url: &str) -> Result<(Token, Token)> {
+        let url = url::Url::parse(url).map_err(|err| {
+            Error::new(
+                Kind::Internal,
+                format!("Unable to parse {} as url due to {:?}", url, err),
+            )
+        })?;
+        let tokens = self.open(url)?;
+        self.run_until_idle();
+        Ok(tokens)
+    }
+
+    fn open(&mut self, url: url::Url) -> Result<(Token, Token)> {
+        let (client_end, server_end) = MemoryStream::pair();
+
+        let client = self.insert(client_end, true);
+        if let Err(err) = self.slot(client)
+            .map(|slot| slot.conn.as_client(url, Vec::new(), None))
+            .unwrap_or(Ok(()))
+        {
+            self.remove(client);
+            return Err(err);
+        }
+
+        let server = self.insert(server_end, false);
+        if let Some(slot) = self.slot(server) {
+            slot.conn.as_server()?;
+        }
+        Ok((client, server))
+    }
+
+    fn insert(&mut self, stream: MemoryStream, client: bool) -> Token {
+        let index = self.connections
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or_else(|| {
+                self.connections.push(None);
+                self.connections.len() - 1
+            });
+        let tok = Token(index);
+        let connection_id = self.next_connection_id;
+        self.next_connection_id = self.next_connection_id.wrapping_add(1);
+
+        let out = Sender::new(tok, self.queue_tx.clone(), connection_id)
+            .with_producers(self.producers.clone());
+        let handler = if client {
+            self.factory.client_connected(out)
+        } else {
+            self.factory.server_connected(out)
+        };
+        let input = stream.input();
+        self.connections[index] = Some(Slot {
+            conn: Connection::new(
+                tok,
+                Stream::memory(stream),
+                handler,
+                self.settings,
+                connection_id,
+                Buffers::new(&self.settings),
+            )
+            .with_deferred(self.producers.deferred()),
+            input,
+            request: None,
+        });
+        tok
+    }
+
+    fn slot(&mut self, tok: Token) -> Option<&mut Slot<F::Handler>> {
+        self.connections
+            .get_mut(tok.0)
+            .and_then(Option::as_mut)
+    }
+
+    fn remove(&mut self, tok: Token) {
+        let slot = self.connections.get_mut(tok.0).and_then(Option::take);
+        if let Some(slot) = slot {
+            self.cancel_timeouts(tok, slot.conn.connection_id());
+            self.factory.connection_lost(slot.conn.consume());
+        }
+    }
+
+    /// Process queued commands and move data between connections until nothing is left to do.
+    /// Returns the number of passes that made progress.
+    pub fn run_until_idle(&mut self) -> usize {
+        for pass in 0..MAX_PASSES {
+            let mut progress = false;
+            while let Ok(cmd) = self.queue_rx.try_recv() {
+                self.producers.dequeued();
+                progress = true;
+                self.handle_command(cmd);
+            }
+            for index in 0..self.connections.len() {
+                progress |= self.step(Token(index));
+            }
+            if !progress {
+                return pass;
+            }
+        }
+        panic!("The virtual loop did not become idle after {} passes.", MAX_PASSES);
+    }
+
+    // Service one connection, returning whether anything happened.
+    fn step(&mut self, tok: Token) -> bool {
+        let mut progress = false;
+        let active = match self.slot(tok) {
+            Some(slot) => {
+                let conn = &mut slot.conn;
+                if (conn.interest().is_readable() && slot.input.is_ready())
+                    || conn.is_read_pending()
+                {
+                    progress = true;
+                    if let Err(err) = conn.read() {
+                        conn.error(ErrorPhase::Read, err)
+                    }
+                }
+                if conn.events().is_writable() {
+                    progress = true;
+                    if let Err(err) = conn.write() {
+                        conn.error(ErrorPhase::Write, err)
+                    }
+                }
+                conn.events().is_readable() || conn.events().is_writable()
+            }
+            None => return false,
+        };
+        if !active {
+            trace!("Virtual connection {:?} closed.", tok);
+            self.remove(tok);
+            progress = true;
+        }
+        progress
+    }
+
+    /// Advance the virtual clock, firing every timeout that falls due in the order of their
+    /// deadlines and running the loop until idle after each one.
+    pub fn advance(&mut self, duration: Duration) {
+        let target = self.now + duration;
+        self.run_until_idle();
+        loop {
+            let next = self.timeouts
+                .iter()
+                .enumerate()
+                .filter(|&(_, pending)| pending.at <= target)
+                .min_by_key(|&(_, pending)| (pending.at, pending.id))
+                .map(|(index, _)| index);
+            let pending = match next {
+                Some(index) => self.timeouts.remove(index),
+                None => break,
+            };
+            self.now = pending.at;
+            self.timer.cancel_timeout(&pending.handle);
+            self.fire(&pending);
+            self.run_until_idle();
+        }
+        self.now = target;
+    }
+
+    fn fire(&mut self, pending: &Pending) {
+        let tokens = if pending.connection == ALL {
+            self.tokens()
+        } else {
+            vec![pending.connection]
+        };
+        for tok in tokens {
+            if let Some(slot) = self.slot(tok) {
+                if pending.connection == ALL || slot.conn.connection_id() == pending.connection_id {
+                    if let Err(err) = slot.conn.timeout_triggered(pending.event) {
+                        slot.conn.error(ErrorPhase::Timer, err)
+                    }
+                }
+            }
+        }
+    }
+
+    fn schedule(&mut self, connection: Token, connection_id: u64, delay: u64, event: Token) {
+        let id = self.next_timeout;
+        self.next_timeout += 1;
+        let handle = self.timer.set_timeout(Duration::from_millis(delay), id);
+        self.timeouts.push(Pending {
+            id,
+            at: self.now + Duration::from_millis(delay),
+            connection,
+            connection_id,
+            event,
+            handle: handle.clone(),
+        });
+
+        let tokens = if connection == ALL {
+            self.tokens()
+        } else {
+            vec![connection]
+        };
+        for tok in tokens {
+            if let Some(slot) = self.slot(tok) {
+                if let Err(err) = slot.conn.new_timeout(event, handle.clone()) {
+                    slot.conn.error(ErrorPhase::Timer, err)
+                }
+            }
+        }
+    }
+
+    // Drop the pending timeouts of a connection, or of all connections when the token is ALL.
+    fn cancel_timeouts(&mut self, connection: Token, connection_id: u64) {
+        let timer = &mut self.timer;
+        self.timeouts.retain(|pending| {
+            let cancel = connection == ALL
+                || (pending.connection == connection && pending.connection_id == connection_id);
+            if cancel {
+                timer.cancel_timeout(&pending.handle);
+            }
+            !cancel
+        });
+    }
+
+    fn cancel_connect(&mut self, target: &ConnectTarget) {
+        for tok in self.tokens() {
+            let request = match self.slot(tok) {
+                Some(slot) => {
+                    let conn = &mut slot.conn;
+                    let matches = match *target {
+                        ConnectTarget::Url(ref url) => conn.client_url() == Some(url),
+                        ConnectTarget::Token(target) => tok == target,
+                    };
+                    if !(matches && conn.is_client() && conn.is_connecting()) {
+                        continue;
+                    }
+                    conn.error(
+                        ErrorPhase::Handshake,
+                        Error::new(Kind::Cancelled, "The connection attempt was cancelled."),
+                    );
+                    slot.request.take()
+                }
+                None => continue,
+            };
+            self.remove(tok);
+
+            if let Some(request) = request {
+                if let Some(slot) = self.slot(request.requester) {
+                    if slot.conn.connection_id() == request.connection_id {
+                        let err =
+                            Error::new(Kind::Cancelled, "The connection attempt was cancelled.");
+                        if let Err(err) = slot.conn.connect_result(request.user_token, Err(err)) {
+                            slot.conn.error(ErrorPhase::Command, err)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_command(&mut self, cmd: Command) {
+        let token = cmd.token();
+        let connection_id = cmd.connection_id();
+        match cmd.into_signal() {
+            Signal::Timeout { delay, token: event } => {
+                self.schedule(token, connection_id, delay, event)
+            }
+            Signal::Cancel(timeout) => {
+                if let Some(id) = self.timer.cancel_timeout(&timeout) {
+                    self.timeouts.retain(|pending| pending.id != id);
+                }
+            }
+            Signal::CancelAllTimeouts => self.cancel_timeouts(token, connection_id),
+            Signal::Connect(url, local_addr, user_token) => {
+                let result = if local_addr.is_some() {
+                    Err(Error::new(
+                        Kind::Internal,
+                        "Virtual connections cannot be bound to a local address.",
+                    ))
+                } else {
+                    self.open(url).map(|(client, _)| client)
+                };
+                let requester = self.slot(token).map(|slot| slot.conn.connection_id());
+                if let (Some(connection_id), Some(user_token), Ok(client)) =
+                    (requester, user_token, result.as_ref())
+                {
+                    if let Some(slot) = self.slot(*client) {
+                        slot.request = Some(ConnectRequest {
+                            requester: token,
+                            connection_id,
+                            user_token,
+                        });
+                    }
+                }
+                match (self.slot(token), user_token) {
+                    (Some(slot), Some(user_token)) => {
+                        if let Err(err) = slot.conn.connect_result(user_token, result) {
+                            slot.conn.error(ErrorPhase::Command, err)
+                        }
+                    }
+                    (Some(slot), None) => {
+                        if let Err(err) = result {
+                            slot.conn.error(ErrorPhase::Command, err)
+                        }
+                    }
+                    (None, _) => {
+                        if let Err(err) = result {
+                            error!("Unable to establish virtual connection: {:?}", err);
+                        }
+                    }
+                }
+            }
+            Signal::CancelConnect(target) => self.cancel_connect(&target),
+            Signal::Tokens(reply) => {
+                let _ = reply.send(self.tokens());
+            }
+            Signal::CloseTokens(tokens, code, reason) => {
+                for tok in tokens {
+                    if let Some(slot) = self.slot(tok) {
+                        if let Err(err) = slot.conn.send_close(code, reason.borrow()) {
+                            slot.conn.error(ErrorPhase::Command, err)
+                        }
+                    }
+                }
+            }
+            Signal::Shutdown => {
+                for tok in self.tokens() {
+                    if let Some(slot) = self.slot(tok) {
+                        slot.conn.shutdown();
+                    }
+                }
+                self.factory.on_shutdown();
+            }
+            // there is no listener, so there is nothing to pause
+            Signal::PauseAccept | Signal::ResumeAccept => (),
+            // received frames belong to one connection, see `Sender::inject_frame`
+            Signal::Inject(_) if token == ALL => (),
+            signal => {
+                let tokens = if token == ALL {
+                    self.tokens()
+                } else {
+                    vec![token]
+                };
+                for tok in tokens {
+                    if let Some(slot) = self.slot(tok) {
+                        if token != ALL && slot.conn.connection_id() != connection_id {
+                            trace!("Connection disconnected while a signal was waiting in the queue.");
+                            continue;
+                        }
+                        if let Ok(Err(err)) = slot.conn.deliver(signal.clone()) {
+                            slot.conn.error(ErrorPhase::Command, err)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
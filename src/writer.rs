@@ -0,0 +1,159 @@
+use std::io;
+use std::io::Cursor;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use mio;
+use mio::Token;
+
+use result::Result;
+use stream::{Stream, TryWriteBuf};
+
+/// A write to perform on a stream outside of the event loop.
+pub struct Job {
+    pub token: Token,
+    pub connection_id: u64,
+    pub stream: Stream,
+    pub buffer: Cursor<Vec<u8>>,
+}
+
+/// The outcome of a `Job`, which hands the stream and buffer back to the connection.
+pub struct Completion {
+    pub token: Token,
+    pub connection_id: u64,
+    pub stream: Stream,
+    pub buffer: Cursor<Vec<u8>>,
+    pub result: io::Result<Option<usize>>,
+}
+
+/// A handle used by connections to submit writes to the pool.
+#[derive(Clone)]
+pub struct Writer {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Writer {
+    /// Submit a write, returning the job if the pool is no longer running.
+    pub fn submit(&self, job: Job) -> ::std::result::Result<(), Job> {
+        self.jobs.send(job).map_err(|err| err.0)
+    }
+}
+
+/// A pool of threads that write to TLS streams on behalf of the event loop.
+///
+/// Encrypting a large write, or renegotiating a session, can take long enough to delay every other
+/// connection on the loop. Writes submitted to the pool are performed on a worker thread and the
+/// stream is returned to the loop through the `completions` channel.
+pub struct WriterPool {
+    writer: Writer,
+    completions: mio::channel::Receiver<Completion>,
+}
+
+impl WriterPool {
+    pub fn new(threads: usize) -> Result<WriterPool> {
+        let (jobs_tx, jobs_rx) = mpsc::channel::<Job>();
+        let (completions_tx, completions_rx) = mio::channel::channel();
+        let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+
+        for i in 0..threads {
+            let jobs = jobs_rx.clone();
+            let completions = completions_tx.clone();
+            thread::Builder::new()
+                .name(format!("ws-writer-{}", i))
+                .spawn(move || loop {
+                    // The workers stop once every connection and the pool are dropped
+                    let job = match jobs.lock() {
+                        Ok(jobs) => match jobs.recv() {
+                            Ok(job) => job,
+                            Err(_) => return,
+                        },
+                        Err(_) => return,
+                    };
+                    let Job {
+                        token,
+                        connection_id,
+                        mut stream,
+                        mut buffer,
+                    } = job;
+                    let result = stream.try_write_buf(&mut buffer);
+                    let completion = Completion {
+                        token,
+                        connection_id,
+                        stream,
+                        buffer,
+                        result,
+                    };
+                    if completions.send(completion).is_err() {
+                        return;
+                    }
+                })?;
+        }
+
+        Ok(WriterPool {
+            writer: Writer { jobs: jobs_tx },
+            completions: completions_rx,
+        })
+    }
+
+    pub fn writer(&self) -> Writer {
+        self.writer.clone()
+    }
+
+    pub fn completions(&self) -> &mio::channel::Receiver<Completion> {
+        &self.completions
+    }
+}
+
+mod test {
+    #![allow(unused_imports, unused_variables, dead_code)]
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use mio::tcp::TcpStream;
+    use mio::{Events, Poll, PollOpt, Ready};
+
+    use super::*;
+
+    #[test]
+    fn write_on_worker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = Stream::tcp(TcpStream::connect(&addr).unwrap());
+        let (mut peer, _) = listener.accept().unwrap();
+
+        let pool = WriterPool::new(2).unwrap();
+        let poll = Poll::new().unwrap();
+        poll.register(
+            pool.completions(),
+            Token(0),
+            Ready::readable(),
+            PollOpt::edge(),
+        ).unwrap();
+
+        let job = Job {
+            token: Token(1),
+            connection_id: 7,
+            stream,
+            buffer: Cursor::new(b"hello".to_vec()),
+        };
+        assert!(pool.writer().submit(job).is_ok());
+
+        let mut events = Events::with_capacity(1);
+        let completion = loop {
+            if let Ok(completion) = pool.completions().try_recv() {
+                break completion;
+            }
+            poll.poll(&mut events, Some(Duration::from_secs(5))).unwrap();
+        };
+        assert_eq!(completion.token, Token(1));
+        assert_eq!(completion.connection_id, 7);
+        assert_eq!(completion.result.unwrap(), Some(5));
+        assert_eq!(completion.buffer.position(), 5);
+
+        let mut buf = [0; 5];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+}
@@ -0,0 +1,53 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use ws::{Builder, Settings};
+
+// Connections that arrive together are all accepted, even though the edge-triggered listener only
+// reports them once and each event accepts a single connection.
+#[test]
+fn burst_is_accepted() {
+    let server = Builder::new()
+        .with_settings(Settings {
+            max_accepts_per_event: 1,
+            ..Settings::default()
+        })
+        .spawn_local(|| |_| |_| Ok(()))
+        .unwrap();
+    let addr = server.addr();
+
+    let mut streams = (0..20)
+        .map(|_| TcpStream::connect(addr).unwrap())
+        .collect::<Vec<_>>();
+    for stream in &mut streams {
+        stream
+            .write_all(
+                b"GET / HTTP/1.1\r\n\
+                  Connection: Upgrade\r\n\
+                  Upgrade: websocket\r\n\
+                  Sec-WebSocket-Version: 13\r\n\
+                  Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+            )
+            .unwrap();
+    }
+    for stream in &mut streams {
+        let mut response = [0; 12];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"HTTP/1.1 101");
+    }
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn max_accepts_per_event_is_at_least_one() {
+    let result = Builder::new()
+        .with_settings(Settings {
+            max_accepts_per_event: 0,
+            ..Settings::default()
+        })
+        .build(|_| |_| Ok(()));
+    assert!(result.is_err());
+}
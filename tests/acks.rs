@@ -0,0 +1,155 @@
+extern crate url;
+extern crate ws;
+
+use std::sync::mpsc;
+
+use ws::{AckToken, Builder, Settings};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Sent(AckToken),
+    Received(ws::Message),
+    Acked(AckToken),
+    TimedOut(AckToken),
+}
+
+struct Client {
+    out: ws::Sender,
+    events: mpsc::Sender<Event>,
+}
+
+impl ws::Handler for Client {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        let token = self.out.send_tracked("hello")?;
+        self.events.send(Event::Sent(token)).unwrap();
+        Ok(())
+    }
+
+    fn on_ack(&mut self, token: AckToken) {
+        self.events.send(Event::Acked(token)).unwrap();
+        self.out.close(ws::CloseCode::Normal).unwrap();
+    }
+
+    fn on_ack_timeout(&mut self, token: AckToken) {
+        self.events.send(Event::TimedOut(token)).unwrap();
+        self.out.close(ws::CloseCode::Normal).unwrap();
+    }
+}
+
+struct Server {
+    events: mpsc::Sender<Event>,
+}
+
+impl ws::Handler for Server {
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        self.events.send(Event::Received(msg)).unwrap();
+        Ok(())
+    }
+}
+
+// Runs a client against a server and returns the events of both, in the order that they happened.
+fn exchange(client_settings: Settings, server_settings: Settings) -> Vec<Event> {
+    let (tx, rx) = mpsc::channel();
+
+    let server_events = tx.clone();
+    let server = Builder::new()
+        .with_settings(server_settings)
+        .spawn_local(move || {
+            let events = server_events.clone();
+            move |_| Server {
+                events: events.clone(),
+            }
+        })
+        .unwrap();
+
+    let mut client = Builder::new()
+        .with_settings(client_settings)
+        .build(move |out| Client {
+            out,
+            events: tx.clone(),
+        })
+        .unwrap();
+    client
+        .connect(url::Url::parse(&format!("ws://{}", server.addr())).unwrap())
+        .unwrap();
+    client.run().unwrap();
+
+    server.stop().unwrap();
+    rx.iter().collect()
+}
+
+#[test]
+fn tracked_message_is_acknowledged() {
+    let settings = Settings {
+        acks: true,
+        ..Settings::default()
+    };
+    let events = exchange(settings, settings);
+    assert_eq!(events.len(), 3);
+    let token = match events[0] {
+        Event::Sent(token) => token,
+        ref event => panic!("unexpected event {:?}", event),
+    };
+    // the server sees the message without its envelope
+    assert_eq!(events[1], Event::Received(ws::Message::text("hello")));
+    assert_eq!(events[2], Event::Acked(token));
+}
+
+#[test]
+fn unacknowledged_message_times_out() {
+    let events = exchange(
+        Settings {
+            acks: true,
+            ack_timeout_ms: 100,
+            ..Settings::default()
+        },
+        Settings::default(),
+    );
+    assert_eq!(events.len(), 3);
+    let token = match events[0] {
+        Event::Sent(token) => token,
+        ref event => panic!("unexpected event {:?}", event),
+    };
+    // a peer that does not use acknowledgments sees the envelope
+    assert_eq!(
+        events[1],
+        Event::Received(ws::Message::text(format!("\0ack:{}:hello", token)))
+    );
+    assert_eq!(events[2], Event::TimedOut(token));
+}
+
+struct Untracked {
+    out: ws::Sender,
+    failed: mpsc::Sender<bool>,
+}
+
+impl ws::Handler for Untracked {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        self.failed
+            .send(self.out.send_tracked("hello").is_err())
+            .unwrap();
+        self.out.close(ws::CloseCode::Normal)
+    }
+}
+
+#[test]
+fn tracking_requires_acks() {
+    let server = Builder::new().spawn_local(|| |_| |_| Ok(())).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let mut client = Builder::new()
+        .build(move |out| Untracked {
+            out,
+            failed: tx.clone(),
+        })
+        .unwrap();
+    // acknowledgments are disabled, and they can never be tracked across all connections
+    assert!(client.broadcaster().send_tracked("hello").is_err());
+    client
+        .connect(url::Url::parse(&format!("ws://{}", server.addr())).unwrap())
+        .unwrap();
+    client.run().unwrap();
+    assert!(rx.recv().unwrap());
+
+    server.stop().unwrap();
+}
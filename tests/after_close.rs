@@ -0,0 +1,128 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+
+use ws::{AfterClose, CloseCode, Frame, OpCode};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Frame(String),
+    Error(String),
+}
+
+struct Handler {
+    events: mpsc::Sender<Event>,
+}
+
+impl ws::Handler for Handler {
+    fn on_after_close_frame(&mut self, frame: Frame) -> ws::Result<()> {
+        let text = String::from_utf8(frame.into_data()).unwrap();
+        self.events.send(Event::Frame(text)).unwrap();
+        Ok(())
+    }
+
+    fn on_error(&mut self, err: ws::Error) {
+        self.events.send(Event::Error(err.details.into_owned())).unwrap();
+    }
+}
+
+// Open a raw connection and send a close frame followed by text frames in a single write.
+fn send_after_close(port: u16, settings: ws::Settings, texts: &[&str]) -> Vec<Event> {
+    let (tx, rx) = mpsc::channel();
+    let addr = format!("127.0.0.1:{}", port);
+    let (broadcaster, handle) = ws::Builder::new()
+        .with_settings(settings)
+        .spawn(addr.clone(), move || {
+            let tx = tx.clone();
+            move |_| Handler { events: tx.clone() }
+        })
+        .unwrap();
+
+    let mut stream = TcpStream::connect(&*addr).unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        )
+        .unwrap();
+    let mut buf = [0; 1024];
+    let read = stream.read(&mut buf).unwrap();
+    assert!(buf[..read].starts_with(b"HTTP/1.1 101"));
+
+    let mut data = Vec::new();
+    Frame::close(CloseCode::Normal, "")
+        .set_mask()
+        .format(&mut data)
+        .unwrap();
+    for text in texts {
+        Frame::message(text.as_bytes().to_vec(), OpCode::Text, true)
+            .set_mask()
+            .format(&mut data)
+            .unwrap();
+    }
+    stream.write_all(&data).unwrap();
+
+    // Wait for the server to disconnect
+    let mut rest = Vec::new();
+    let _ = stream.read_to_end(&mut rest);
+
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+    rx.try_iter().collect()
+}
+
+#[test]
+fn drop_after_close() {
+    let events = send_after_close(3039, ws::Settings::default(), &["one", "two"]);
+    assert!(events.is_empty());
+}
+
+#[test]
+fn deliver_after_close() {
+    let settings = ws::Settings {
+        after_close: AfterClose::Deliver,
+        ..ws::Settings::default()
+    };
+    let events = send_after_close(3040, settings, &["one", "two"]);
+    assert_eq!(
+        events,
+        vec![Event::Frame("one".into()), Event::Frame("two".into())]
+    );
+}
+
+#[test]
+fn abort_after_close() {
+    let settings = ws::Settings {
+        after_close: AfterClose::Abort(1),
+        ..ws::Settings::default()
+    };
+    let events = send_after_close(3041, settings, &["one", "two", "three"]);
+    assert_eq!(
+        events,
+        vec![Event::Error(
+            "Received too many frames after close frame.".into()
+        )]
+    );
+}
+
+#[test]
+fn max_bytes_after_close() {
+    let settings = ws::Settings {
+        after_close: AfterClose::Deliver,
+        max_after_close_bytes: 4,
+        ..ws::Settings::default()
+    };
+    let events = send_after_close(3042, settings, &["one", "two"]);
+    assert_eq!(
+        events,
+        vec![
+            Event::Frame("one".into()),
+            Event::Error("Exceeded max bytes received after close frame.".into()),
+        ]
+    );
+}
@@ -0,0 +1,60 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const MESSAGES: usize = 200;
+
+struct Handler {
+    out: ws::Sender,
+    received: Rc<RefCell<Vec<String>>>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // The first connection is the client
+        if self.out.connection_id() == 0 {
+            for i in 0..MESSAGES {
+                self.out.send(i.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        if self.out.connection_id() == 0 {
+            self.received.borrow_mut().push(msg.into_text()?);
+            if self.received.borrow().len() == MESSAGES {
+                return self.out.shutdown();
+            }
+            Ok(())
+        } else {
+            self.out.send(msg)
+        }
+    }
+}
+
+#[test]
+fn batched_echo() {
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let handler_received = received.clone();
+
+    let mut ws = ws::Builder::new()
+        .with_settings(ws::Settings {
+            batch_writes: true,
+            ..ws::Settings::default()
+        })
+        .build(move |out: ws::Sender| Handler {
+            out,
+            received: handler_received.clone(),
+        })
+        .unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3053").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3053").unwrap();
+
+    let expected: Vec<String> = (0..MESSAGES).map(|i| i.to_string()).collect();
+    assert_eq!(*received.borrow(), expected);
+}
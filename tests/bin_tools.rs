@@ -0,0 +1,26 @@
+#![cfg(feature = "bin-tools")]
+extern crate ws;
+
+use std::thread;
+use std::time::Duration;
+
+use ws::bin_tools::{self, Rate};
+use ws::Settings;
+
+#[test]
+fn probe_echo_server() {
+    thread::spawn(|| bin_tools::echo_server("127.0.0.1:3047", Settings::default()).unwrap());
+    thread::sleep(Duration::from_millis(100));
+
+    let report = bin_tools::latency_probe(
+        "ws://127.0.0.1:3047",
+        Rate {
+            per_second: 200,
+            messages: 10,
+        },
+    ).unwrap();
+
+    assert_eq!(report.samples.len(), 10);
+    assert_eq!(report.lost, 0);
+    assert!(report.max().unwrap() < Duration::from_secs(5));
+}
@@ -1,6 +1,11 @@
+extern crate url;
 extern crate ws;
 
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 struct Handler;
 impl ws::Handler for Handler {}
@@ -29,3 +34,105 @@ fn bind_try_multiple_addrs() {
     let local_addr = ws.local_addr().unwrap();
     assert_eq!(valid_addr, local_addr);
 }
+
+struct Client {
+    out: ws::Sender,
+    bind: Option<SocketAddr>,
+    opened: Arc<AtomicBool>,
+}
+
+impl ws::Handler for Client {
+    fn on_open(&mut self, shake: ws::Handshake) -> ws::Result<()> {
+        if let Some(bind) = self.bind {
+            assert_eq!(bind.ip(), shake.local_addr.unwrap().ip());
+        }
+        self.opened.store(true, Ordering::SeqCst);
+        self.out.shutdown()
+    }
+
+    fn on_error(&mut self, err: ws::Error) {
+        println!("Client error: {}", err);
+        self.out.shutdown().unwrap();
+    }
+}
+
+struct Factory {
+    bind: Option<SocketAddr>,
+    opened: Arc<AtomicBool>,
+}
+
+impl ws::Factory for Factory {
+    type Handler = Client;
+
+    fn connection_made(&mut self, _: ws::Sender) -> Client {
+        unreachable!()
+    }
+
+    fn client_connected(&mut self, out: ws::Sender) -> Client {
+        Client {
+            out,
+            bind: self.bind,
+            opened: self.opened.clone(),
+        }
+    }
+
+    fn server_connected(&mut self, out: ws::Sender) -> Client {
+        Client {
+            out,
+            bind: None,
+            opened: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+// Connect to a server on the same WebSocket and return whether the client connection opened.
+fn connect_bound(builder: &mut ws::Builder, bind: Option<SocketAddr>, port: u16) -> bool {
+    let opened = Arc::new(AtomicBool::new(false));
+    let mut ws = builder
+        .build(Factory {
+            bind,
+            opened: opened.clone(),
+        })
+        .unwrap();
+
+    // stop the event loop even if the client fails to connect
+    let watchdog = ws.broadcaster();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_secs(5));
+        let _ = watchdog.shutdown();
+    });
+
+    let url = url::Url::parse(&format!("ws://127.0.0.1:{}", port)).unwrap();
+    ws.connect(url).unwrap();
+    ws.listen(("127.0.0.1", port)).unwrap();
+    opened.load(Ordering::SeqCst)
+}
+
+#[test]
+fn client_local_bind() {
+    let bind: SocketAddr = "127.0.0.2:0".parse().unwrap();
+    assert!(connect_bound(
+        ws::Builder::new().with_local_bind(bind),
+        Some(bind),
+        3025
+    ));
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "safe")))]
+fn client_local_interface() {
+    let interface = ws::Interface::new("lo").unwrap();
+    assert_eq!(interface.name(), "lo");
+    assert!(connect_bound(
+        ws::Builder::new().with_local_interface(interface),
+        None,
+        3086
+    ));
+}
+
+#[test]
+fn interface_name() {
+    assert!(ws::Interface::new("").is_err());
+    assert!(ws::Interface::new("a-very-long-name").is_err());
+    assert!(ws::Interface::new("eth\0").is_err());
+}
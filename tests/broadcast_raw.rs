@@ -0,0 +1,94 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::{Frame, OpCode};
+
+const TICKS: usize = 3;
+
+fn tick() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    Frame::message(b"tick".to_vec(), OpCode::Text, true)
+        .format(&mut bytes)
+        .unwrap();
+    bytes
+}
+
+struct Handler {
+    out: ws::Sender,
+    received: Rc<RefCell<Vec<String>>>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // The second connection is the server side of the client
+        if self.out.connection_id() == 1 {
+            let frame = tick();
+            for _ in 0..TICKS {
+                self.out.broadcast_raw(frame.clone())?;
+            }
+            self.out.broadcast("done")?;
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        let text = msg.into_text()?;
+        if self.out.connection_id() == 1 {
+            // the client connection is sent the plain broadcast but not the unmasked frames
+            self.received.borrow_mut().push(format!("server: {}", text));
+        } else {
+            self.received.borrow_mut().push(text);
+        }
+        let received = self.received.borrow();
+        let done = received.iter().filter(|msg| msg.ends_with("done")).count();
+        if done == 2 {
+            return self.out.shutdown();
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn broadcast_raw_to_servers() {
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let handler_received = received.clone();
+
+    let mut ws = ws::WebSocket::new(move |out: ws::Sender| Handler {
+        out,
+        received: handler_received.clone(),
+    }).unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3097").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3097").unwrap();
+
+    let (server, client): (Vec<_>, Vec<_>) = received
+        .borrow()
+        .iter()
+        .cloned()
+        .partition(|msg| msg.starts_with("server: "));
+    assert_eq!(client, vec!["tick", "tick", "tick", "done"]);
+    assert_eq!(server, vec!["server: done"]);
+}
+
+#[test]
+fn broadcast_raw_rejects_partial_frames() {
+    let ws = ws::WebSocket::new(|_| |_| Ok(())).unwrap();
+    let out = ws.broadcaster();
+
+    let frame = tick();
+    assert!(out.broadcast_raw(frame[..frame.len() - 1].to_vec()).is_err());
+    assert!(out.broadcast_raw([&frame[..], &frame[..]].concat()).is_err());
+
+    let mut masked = Vec::new();
+    Frame::message(b"tick".to_vec(), OpCode::Text, true)
+        .set_mask()
+        .format(&mut masked)
+        .unwrap();
+    assert!(out.broadcast_raw(masked).is_err());
+
+    assert!(out.broadcast_raw(frame).is_ok());
+}
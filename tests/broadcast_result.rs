@@ -0,0 +1,102 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+
+use ws::util::Token;
+use ws::{Builder, ErrorKind, Factory, Frame, Sender};
+
+enum Event {
+    Connected(Token),
+    Broadcast(usize, Vec<Token>),
+}
+
+// A handler that refuses to send large messages
+struct Handler {
+    refuse: bool,
+}
+
+impl ws::Handler for Handler {
+    fn on_send_frame(&mut self, frame: Frame) -> ws::Result<Option<Frame>> {
+        if self.refuse && frame.payload().len() > 16 {
+            return Err(ws::Error::new(
+                ErrorKind::Capacity,
+                "Refusing a large message.",
+            ));
+        }
+        Ok(Some(frame))
+    }
+}
+
+struct Server {
+    events: mpsc::Sender<Event>,
+}
+
+impl Factory for Server {
+    type Handler = Handler;
+
+    // The second connection refuses large messages
+    fn connection_made(&mut self, out: Sender) -> Handler {
+        self.events.send(Event::Connected(out.token())).unwrap();
+        Handler {
+            refuse: out.connection_id() == 1,
+        }
+    }
+
+    fn on_broadcast_result(&mut self, sent: usize, failed: Vec<Token>) {
+        self.events.send(Event::Broadcast(sent, failed)).unwrap();
+    }
+}
+
+fn open(addr: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = [0; 12];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(&response, b"HTTP/1.1 101");
+    stream
+}
+
+fn connected(events: &mpsc::Receiver<Event>) -> Token {
+    match events.recv().unwrap() {
+        Event::Connected(token) => token,
+        Event::Broadcast(..) => panic!("unexpected broadcast result"),
+    }
+}
+
+fn broadcast_result(events: &mpsc::Receiver<Event>) -> (usize, Vec<Token>) {
+    match events.recv().unwrap() {
+        Event::Broadcast(sent, failed) => (sent, failed),
+        Event::Connected(_) => panic!("unexpected connection"),
+    }
+}
+
+#[test]
+fn partial_broadcast_is_reported() {
+    let (tx, events) = mpsc::channel();
+    let server = Builder::new()
+        .spawn_local(move || Server { events: tx.clone() })
+        .unwrap();
+
+    let _first = open(server.addr());
+    connected(&events);
+    let _second = open(server.addr());
+    let second = connected(&events);
+
+    server.broadcaster().send("small").unwrap();
+    assert_eq!(broadcast_result(&events), (2, vec![]));
+
+    server.broadcaster().send(vec![0; 4096]).unwrap();
+    assert_eq!(broadcast_result(&events), (1, vec![second]));
+
+    server.stop().unwrap();
+}
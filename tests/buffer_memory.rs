@@ -0,0 +1,78 @@
+extern crate ws;
+
+use std::sync::mpsc::{self, channel};
+use std::thread;
+use std::time::Duration;
+
+use ws::{CloseCode, Handshake, Result, Sender, Settings};
+
+struct Client {
+    out: Sender,
+    opened: mpsc::Sender<Sender>,
+    closed: mpsc::Sender<CloseCode>,
+}
+
+impl ws::Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.opened.send(self.out.clone()).unwrap();
+        Ok(())
+    }
+
+    fn on_close(&mut self, code: CloseCode, _: &str) {
+        self.closed.send(code).unwrap();
+    }
+
+    fn on_error(&mut self, _: ws::Error) {}
+}
+
+#[test]
+fn max_total_buffer_memory() {
+    // room for the initial buffers of a single connection
+    let (broadcaster, handle) = ws::Builder::new()
+        .with_settings(Settings {
+            in_buffer_capacity: 2048,
+            out_buffer_capacity: 2048,
+            max_total_buffer_memory: 4096,
+            ..Settings::default()
+        })
+        .spawn("127.0.0.1:3074", || {
+            |out: Sender| move |msg: ws::Message| out.send(msg)
+        })
+        .unwrap();
+
+    let (open_tx, open_rx) = channel();
+    let (close_tx, close_rx) = channel();
+    let first = {
+        let open_tx = open_tx.clone();
+        thread::spawn(move || {
+            ws::connect("ws://127.0.0.1:3074", |out| Client {
+                out,
+                opened: open_tx.clone(),
+                closed: close_tx.clone(),
+            })
+            .unwrap();
+        })
+    };
+    let out = open_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    // a second connection does not fit
+    let (second_close_tx, _second_close_rx) = channel();
+    let _ = ws::connect("ws://127.0.0.1:3074", |out| Client {
+        out,
+        opened: open_tx.clone(),
+        closed: second_close_tx.clone(),
+    });
+    assert!(open_rx.try_recv().is_err());
+    assert_eq!(broadcaster.tokens().unwrap().len(), 1);
+
+    // growing the buffers of the first connection fails it
+    out.send(vec![0u8; 16_384]).unwrap();
+    assert_eq!(
+        close_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+        CloseCode::Size
+    );
+    first.join().unwrap();
+
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+}
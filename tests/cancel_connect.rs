@@ -0,0 +1,105 @@
+#![cfg(feature = "testing")]
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use url::Url;
+use ws::testing::VirtualLoop;
+use ws::util::Token;
+use ws::{ErrorKind, Handshake, Result, Sender};
+
+const ATTEMPT: Token = Token(7);
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Opened(Token),
+    Connected(Token),
+    Cancelled(Token),
+    Error(Token),
+}
+
+type Log = Rc<RefCell<Vec<Event>>>;
+
+struct Client {
+    out: Sender,
+    log: Log,
+    by_url: bool,
+}
+
+impl ws::Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.log.borrow_mut().push(Event::Opened(self.out.token()));
+        // only the first client starts another connection
+        if self.out.token() != Token(0) {
+            return Ok(());
+        }
+        let url = Url::parse("ws://example.com/other").unwrap();
+        if self.by_url {
+            self.out.connect(url.clone())?;
+            self.out.cancel_connect(url)
+        } else {
+            self.out.connect_with_token(url, ATTEMPT)
+        }
+    }
+
+    fn on_connect_result(&mut self, user_token: Token, result: Result<Token>) -> Result<()> {
+        assert_eq!(user_token, ATTEMPT);
+        match result {
+            Ok(token) => {
+                self.log.borrow_mut().push(Event::Connected(token));
+                self.out.cancel_connect(token)
+            }
+            Err(err) => {
+                if let ErrorKind::Cancelled = err.kind {
+                    self.log.borrow_mut().push(Event::Cancelled(self.out.token()));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn on_error(&mut self, err: ws::Error) {
+        if let ErrorKind::Cancelled = err.kind {
+            self.log.borrow_mut().push(Event::Error(self.out.token()));
+        }
+    }
+}
+
+fn run(by_url: bool) -> (Log, Vec<Token>) {
+    let log = Log::default();
+    let mut virt = {
+        let log = log.clone();
+        VirtualLoop::new(move |out| Client {
+            out,
+            log: log.clone(),
+            by_url,
+        })
+    };
+    virt.connect("ws://example.com/").unwrap();
+    virt.run_until_idle();
+    let tokens = virt.tokens();
+    (log, tokens)
+}
+
+#[test]
+fn cancel_connect_by_token() {
+    let (log, tokens) = run(false);
+    let log = log.borrow();
+    assert!(log.contains(&Event::Connected(Token(2))));
+    assert!(log.contains(&Event::Error(Token(2))));
+    assert!(log.contains(&Event::Cancelled(Token(0))));
+    assert!(!log.contains(&Event::Opened(Token(2))));
+    assert_eq!(tokens, vec![Token(0), Token(1)]);
+}
+
+#[test]
+fn cancel_connect_by_url() {
+    let (log, tokens) = run(true);
+    let log = log.borrow();
+    assert!(log.contains(&Event::Error(Token(2))));
+    assert!(!log.contains(&Event::Cancelled(Token(0))));
+    assert!(!log.contains(&Event::Opened(Token(2))));
+    assert_eq!(tokens, vec![Token(0), Token(1)]);
+}
@@ -0,0 +1,59 @@
+extern crate ws;
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use ws::util::Token;
+use ws::{CloseCode, Handshake, Result, Sender};
+
+const STALE: Token = Token(1);
+const CANCELLED: Token = Token(2);
+const DONE: Token = Token(3);
+
+struct Server {
+    out: Sender,
+    fired: mpsc::Sender<(u64, Token)>,
+}
+
+impl ws::Handler for Server {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        if self.out.connection_id() == 0 {
+            // the timeout must not outlive the connection
+            self.out.timeout(200, STALE)?;
+            self.out.close(CloseCode::Normal)
+        } else {
+            self.out.timeout(200, CANCELLED)?;
+            self.out.cancel_all_timeouts()?;
+            self.out.timeout(400, DONE)
+        }
+    }
+
+    fn on_timeout(&mut self, event: Token) -> Result<()> {
+        self.fired.send((self.out.connection_id(), event)).unwrap();
+        self.out.close(CloseCode::Normal)
+    }
+}
+
+#[test]
+fn cancel_timeouts() {
+    let (tx, rx) = mpsc::channel();
+    let (broadcaster, handle) = ws::Builder::new()
+        .spawn("127.0.0.1:3075", move || {
+            let tx = tx.clone();
+            move |out| Server {
+                out,
+                fired: tx.clone(),
+            }
+        })
+        .unwrap();
+
+    // the second connection reuses the token of the first one
+    ws::connect("ws://127.0.0.1:3075", |_| |_| Ok(())).unwrap();
+    ws::connect("ws://127.0.0.1:3075", |_| |_| Ok(())).unwrap();
+
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), (1, DONE));
+    assert!(rx.try_recv().is_err());
+
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+}
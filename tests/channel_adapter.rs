@@ -0,0 +1,68 @@
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::thread;
+
+use ws::{Builder, CloseCode, Handler, Handshake, Message, Result, Sender};
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+struct Client {
+    out: Sender,
+    log: Log,
+}
+
+impl Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.out.send("hello")?;
+        self.out.send("bye")
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        self.log.borrow_mut().push(msg.into_text()?);
+        Ok(())
+    }
+
+    fn on_close(&mut self, code: CloseCode, _: &str) {
+        self.log.borrow_mut().push(format!("{:?}", code));
+    }
+}
+
+#[test]
+fn worker_owns_connection() {
+    let (adapter, connections) = ws::channel_adapter();
+    let server = Builder::new().spawn_local(move || adapter).unwrap();
+
+    let worker = thread::spawn(move || {
+        let channels = connections.recv().unwrap();
+        channels.outgoing.send(Message::text("welcome")).unwrap();
+        for msg in channels.incoming.iter() {
+            let text = msg.into_text().unwrap();
+            if text == "bye" {
+                // dropping the outgoing channel closes the connection
+                break;
+            }
+            channels.outgoing.send(Message::text(text.to_uppercase())).unwrap();
+        }
+        channels.token
+    });
+
+    let log = Log::default();
+    let client_log = log.clone();
+    ws::connect(server.url().as_str(), move |out| Client {
+        out,
+        log: client_log.clone(),
+    }).unwrap();
+
+    worker.join().unwrap();
+    assert_eq!(
+        *log.borrow(),
+        vec![
+            "welcome".to_string(),
+            "HELLO".to_string(),
+            format!("{:?}", CloseCode::Normal),
+        ]
+    );
+    server.stop().unwrap();
+}
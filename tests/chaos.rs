@@ -0,0 +1,78 @@
+#![cfg(feature = "chaos")]
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::chaos::{self, Chaos, Stats};
+use ws::{Builder, CloseCode, ErrorKind, Handler, Handshake, Message, Result, Sender};
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+struct Client {
+    out: Sender,
+    log: Log,
+}
+
+impl Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.out.send("ping")
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        self.log.borrow_mut().push(msg.into_text()?);
+        self.out.close(CloseCode::Normal)
+    }
+
+    fn on_error(&mut self, err: ws::Error) {
+        if let ErrorKind::Io(ref err) = err.kind {
+            self.log.borrow_mut().push(format!("{:?}", err.kind()));
+        }
+    }
+}
+
+fn connect(server: &ws::LocalServer) -> Vec<String> {
+    let log = Log::default();
+    let client_log = log.clone();
+    ws::connect(server.url().as_str(), move |out| Client {
+        out,
+        log: client_log.clone(),
+    }).unwrap();
+    let log = log.borrow().clone();
+    log
+}
+
+// The faults are injected into every stream in the process, so the scenarios share one test.
+#[test]
+fn inject_faults() {
+    let server = Builder::new()
+        .spawn_local(|| |out: Sender| move |msg| out.send(msg))
+        .unwrap();
+
+    chaos::enable(Chaos {
+        write_error: 1.0,
+        ..Chaos::default()
+    });
+    assert_eq!(connect(&server), vec!["BrokenPipe".to_string()]);
+    // the server shares the configuration, so only the kinds of faults are known
+    assert!(chaos::stats().write_errors > 0);
+
+    chaos::enable(Chaos {
+        disconnect: 1.0,
+        ..Chaos::default()
+    });
+    assert_eq!(connect(&server), vec!["ConnectionReset".to_string()]);
+    let stats = chaos::stats();
+    assert!(stats.disconnects > 0);
+    assert_eq!(
+        stats,
+        Stats {
+            disconnects: stats.disconnects,
+            ..Stats::default()
+        }
+    );
+
+    chaos::disable();
+    assert_eq!(connect(&server), vec!["ping".to_string()]);
+    server.stop().unwrap();
+}
@@ -0,0 +1,97 @@
+#![cfg(feature = "cli")]
+extern crate ws;
+
+use std::io::{self, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use ws::cli::{self, Options};
+use ws::{Handshake, Message, Request, Response, Result, Sender};
+
+#[derive(Clone, Default)]
+struct Transcript(Arc<Mutex<Vec<u8>>>);
+
+impl Transcript {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for Transcript {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Input that arrives slowly, as it would from someone typing
+struct Typing(Vec<&'static str>);
+
+impl Read for Typing {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() {
+            return Ok(0);
+        }
+        thread::sleep(Duration::from_millis(200));
+        let line = self.0.remove(0).as_bytes();
+        buf[..line.len()].copy_from_slice(line);
+        Ok(line.len())
+    }
+}
+
+struct Server {
+    out: Sender,
+}
+
+impl ws::Handler for Server {
+    fn on_request(&mut self, req: &Request) -> Result<Response> {
+        assert_eq!(req.header("x-test").map(|v| &v[..]), Some(&b"yes"[..]));
+        let mut res = Response::from_request(req)?;
+        if req.protocols()?.contains(&"chat") {
+            res.set_protocol("chat")?;
+        }
+        Ok(res)
+    }
+
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.out.send(Message::binary(vec![1, 2]))
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        self.out.send(msg)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        self.out.shutdown().unwrap();
+    }
+}
+
+#[test]
+fn cli_session() {
+    let server = thread::spawn(|| ws::listen("127.0.0.1:3055", |out| Server { out }).unwrap());
+    thread::sleep(Duration::from_millis(100));
+
+    let options = Options::from_args(vec![
+        "-H",
+        "X-Test: yes",
+        "-p",
+        "chat",
+        "ws://127.0.0.1:3055",
+    ]).unwrap();
+    let transcript = Transcript::default();
+    let input = BufReader::new(Typing(vec!["hello\n", "/close 4000 done\n"]));
+
+    let code = cli::run(options, input, transcript.clone()).unwrap();
+    server.join().unwrap();
+
+    let text = transcript.text();
+    assert_eq!(code, cli::EXIT_CLOSED, "{}", text);
+    assert!(text.contains("Connected to ws://127.0.0.1:3055 using chat"), "{}", text);
+    assert!(text.contains("<<< [binary 2 bytes] 01 02"), "{}", text);
+    assert!(text.contains(">>> hello"), "{}", text);
+    assert!(text.contains("<<< hello"), "{}", text);
+}
@@ -0,0 +1,137 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::CloseCode;
+
+type Codes = Rc<RefCell<Vec<(u64, CloseCode)>>>;
+
+struct Handler {
+    out: ws::Sender,
+    codes: Codes,
+    whitelist: bool,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // The first connection is the client
+        if self.out.connection_id() == 0 {
+            self.out.close(CloseCode::Other(1016))?;
+        }
+        Ok(())
+    }
+
+    fn accept_close_code(&mut self, code: CloseCode) -> bool {
+        self.whitelist && code == CloseCode::Other(1016)
+    }
+
+    fn on_close(&mut self, code: CloseCode, _: &str) {
+        self.codes
+            .borrow_mut()
+            .push((self.out.connection_id(), code));
+        if self.out.connection_id() == 0 {
+            self.out.shutdown().unwrap();
+        }
+    }
+
+    fn on_error(&mut self, _: ws::Error) {}
+}
+
+fn close_with_unassigned_code(port: u16, whitelist: bool) -> Vec<(u64, CloseCode)> {
+    let codes = Codes::default();
+    let handler_codes = codes.clone();
+
+    let mut ws = ws::WebSocket::new(move |out: ws::Sender| Handler {
+        out,
+        codes: handler_codes.clone(),
+        whitelist,
+    }).unwrap();
+
+    let url = url::Url::parse(&format!("ws://127.0.0.1:{}", port)).unwrap();
+    ws.connect(url).unwrap();
+    ws.listen(&*format!("127.0.0.1:{}", port)).unwrap();
+
+    let codes = codes.borrow().clone();
+    codes
+}
+
+#[test]
+fn unassigned_close_code_rejected() {
+    assert_eq!(
+        close_with_unassigned_code(3037, false),
+        vec![(0, CloseCode::Protocol)]
+    );
+}
+
+#[test]
+fn unassigned_close_code_whitelisted() {
+    assert_eq!(
+        close_with_unassigned_code(3038, true),
+        vec![(1, CloseCode::Other(1016)), (0, CloseCode::Other(1016))]
+    );
+}
+
+type Closes = Rc<RefCell<Vec<(CloseCode, String)>>>;
+
+struct Policy {
+    out: ws::Sender,
+    closes: Closes,
+}
+
+impl ws::Handler for Policy {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // The first connection is the client
+        if self.out.connection_id() == 0 {
+            self.out.send(vec![0u8; 64])?;
+        }
+        Ok(())
+    }
+
+    fn close_code_for_error(&mut self, err: &ws::Error) -> CloseCode {
+        match err.kind {
+            ws::ErrorKind::Capacity => CloseCode::Policy,
+            _ => CloseCode::Error,
+        }
+    }
+
+    fn close_reason_for_error(&mut self, _: &ws::Error) -> String {
+        "Message too large.".into()
+    }
+
+    fn on_close(&mut self, code: CloseCode, reason: &str) {
+        if self.out.connection_id() == 0 {
+            self.closes.borrow_mut().push((code, reason.into()));
+            self.out.shutdown().unwrap();
+        }
+    }
+
+    fn on_error(&mut self, _: ws::Error) {}
+}
+
+#[test]
+fn close_code_for_error() {
+    let closes = Closes::default();
+    let handler_closes = closes.clone();
+
+    let mut ws = ws::Builder::new()
+        .with_settings(ws::Settings {
+            max_message_size: 16,
+            ..ws::Settings::default()
+        })
+        .build(move |out: ws::Sender| Policy {
+            out,
+            closes: handler_closes.clone(),
+        })
+        .unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3096").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3096").unwrap();
+
+    assert_eq!(
+        *closes.borrow(),
+        vec![(CloseCode::Policy, "Message too large.".to_string())]
+    );
+}
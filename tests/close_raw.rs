@@ -0,0 +1,98 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+
+use ws::{Builder, CloseCode};
+
+struct Raw {
+    reasons: mpsc::Sender<(CloseCode, Vec<u8>)>,
+}
+
+impl ws::Handler for Raw {
+    fn on_close_raw(&mut self, code: CloseCode, reason: &[u8]) {
+        self.reasons.send((code, reason.to_vec())).unwrap();
+    }
+}
+
+struct Lossy {
+    reasons: mpsc::Sender<(CloseCode, String)>,
+}
+
+impl ws::Handler for Lossy {
+    fn on_close(&mut self, code: CloseCode, reason: &str) {
+        self.reasons.send((code, reason.to_owned())).unwrap();
+    }
+}
+
+// Opens a connection, sends a close frame with a reason that is not valid UTF-8 and returns the
+// close frame sent in answer.
+fn close_with_invalid_reason(addr: SocketAddr) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = Vec::new();
+    let mut byte = [0];
+    while !response.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        response.push(byte[0]);
+    }
+    assert!(response.starts_with(b"HTTP/1.1 101"));
+
+    // a masked close frame with code 1000 and the reason 0xff 0xfe, masked with a zero key
+    stream
+        .write_all(&[0x88, 0x84, 0, 0, 0, 0, 0x03, 0xe8, 0xff, 0xfe])
+        .unwrap();
+    let mut frame = [0; 4];
+    stream.read_exact(&mut frame).unwrap();
+    frame.to_vec()
+}
+
+#[test]
+fn raw_reason_is_delivered() {
+    let (tx, reasons) = mpsc::channel();
+    let server = Builder::new()
+        .spawn_local(move || {
+            let tx = tx.clone();
+            move |_| Raw {
+                reasons: tx.clone(),
+            }
+        })
+        .unwrap();
+
+    let answer = close_with_invalid_reason(server.addr());
+    assert_eq!(reasons.recv().unwrap(), (CloseCode::Normal, vec![0xff, 0xfe]));
+    // the peer is told that its close frame was invalid
+    assert_eq!(answer, vec![0x88, 0x02, 0x03, 0xef]);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn raw_reason_is_converted_by_default() {
+    let (tx, reasons) = mpsc::channel();
+    let server = Builder::new()
+        .spawn_local(move || {
+            let tx = tx.clone();
+            move |_| Lossy {
+                reasons: tx.clone(),
+            }
+        })
+        .unwrap();
+
+    close_with_invalid_reason(server.addr());
+    assert_eq!(
+        reasons.recv().unwrap(),
+        (CloseCode::Normal, "\u{fffd}\u{fffd}".to_owned())
+    );
+
+    server.stop().unwrap();
+}
@@ -0,0 +1,101 @@
+extern crate ws;
+
+use std::sync::mpsc;
+
+use ws::{Builder, CloseCode, ErrorKind, Handshake, LongCloseReasons, Result, Sender, Settings};
+
+struct Client {
+    out: Sender,
+    results: mpsc::Sender<Result<()>>,
+}
+
+impl ws::Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.results
+            .send(self.out.close_with_reason(CloseCode::Normal, "é".repeat(100)))
+            .unwrap();
+        self.out.close(CloseCode::Normal)
+    }
+}
+
+struct Server {
+    reasons: mpsc::Sender<String>,
+}
+
+impl ws::Handler for Server {
+    fn on_close(&mut self, _: CloseCode, reason: &str) {
+        self.reasons.send(reason.to_owned()).unwrap();
+    }
+}
+
+// Closes with a reason of 200 bytes and returns the result of the call and the reason that the
+// server received.
+fn close_long(settings: Settings) -> (Result<()>, String) {
+    let (tx, reasons) = mpsc::channel();
+    let server = Builder::new()
+        .spawn_local(move || {
+            move |_| Server {
+                reasons: tx.clone(),
+            }
+        })
+        .unwrap();
+
+    let (tx, results) = mpsc::channel();
+    let mut ws = Builder::new()
+        .with_settings(settings)
+        .build(move |out| Client {
+            out,
+            results: tx.clone(),
+        })
+        .unwrap();
+    ws.connect(server.url()).unwrap();
+    ws.run().unwrap();
+
+    let result = results.recv().unwrap();
+    let reason = reasons.recv().unwrap();
+    server.stop().unwrap();
+    (result, reason)
+}
+
+#[test]
+fn long_reasons_are_truncated() {
+    let (result, reason) = close_long(Settings::default());
+    assert!(result.is_ok());
+    // 61 two byte characters fit in 123 bytes
+    assert_eq!(reason, "é".repeat(61));
+
+    let (result, reason) = close_long(Settings {
+        max_close_reason: 10,
+        ..Settings::default()
+    });
+    assert!(result.is_ok());
+    assert_eq!(reason, "é".repeat(5));
+}
+
+#[test]
+fn long_reasons_are_rejected() {
+    let (result, reason) = close_long(Settings {
+        long_close_reasons: LongCloseReasons::Reject,
+        ..Settings::default()
+    });
+    match result {
+        Err(ws::Error {
+            kind: ErrorKind::Internal,
+            ..
+        }) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+    // the plain close that followed was sent instead
+    assert_eq!(reason, "");
+}
+
+#[test]
+fn max_close_reason_is_bounded() {
+    let result = Builder::new()
+        .with_settings(Settings {
+            max_close_reason: ws::limits::MAX_CLOSE_REASON + 1,
+            ..Settings::default()
+        })
+        .build(|_| |_| Ok(()));
+    assert!(result.is_err());
+}
@@ -0,0 +1,83 @@
+#![cfg(feature = "testing")]
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::testing::VirtualLoop;
+use ws::util::Token;
+use ws::{CloseCode, Sender};
+
+type Log = Rc<RefCell<Vec<(Token, CloseCode, String)>>>;
+
+// Clients record the close they receive, servers do nothing until told to close.
+struct Peer {
+    out: Sender,
+    log: Log,
+    client: bool,
+}
+
+impl ws::Handler for Peer {
+    fn on_close(&mut self, code: CloseCode, reason: &str) {
+        if self.client {
+            self.log
+                .borrow_mut()
+                .push((self.out.token(), code, reason.into()));
+        }
+    }
+}
+
+struct Factory {
+    log: Log,
+}
+
+impl ws::Factory for Factory {
+    type Handler = Peer;
+
+    fn connection_made(&mut self, out: Sender) -> Peer {
+        Peer {
+            out,
+            log: self.log.clone(),
+            client: false,
+        }
+    }
+
+    fn client_connected(&mut self, out: Sender) -> Peer {
+        Peer {
+            out,
+            log: self.log.clone(),
+            client: true,
+        }
+    }
+}
+
+#[test]
+fn close_selected_connections() {
+    let log = Log::default();
+    let mut virt = VirtualLoop::new(Factory { log: log.clone() });
+    let (first_client, first_server) = virt.connect("ws://example.com/").unwrap();
+    let (second_client, _) = virt.connect("ws://example.com/").unwrap();
+    let (third_client, third_server) = virt.connect("ws://example.com/").unwrap();
+
+    virt.broadcaster()
+        .close_tokens(
+            vec![first_server, third_server, Token(100)],
+            CloseCode::Policy,
+            "unauthenticated",
+        )
+        .unwrap();
+    virt.run_until_idle();
+
+    let mut closed = log.borrow().clone();
+    closed.sort_by_key(|&(token, _, _)| token);
+    assert_eq!(
+        closed,
+        vec![
+            (first_client, CloseCode::Policy, "unauthenticated".to_string()),
+            (third_client, CloseCode::Policy, "unauthenticated".to_string()),
+        ]
+    );
+    let open = virt.tokens();
+    assert_eq!(open.len(), 2);
+    assert!(open.contains(&second_client));
+}
@@ -0,0 +1,145 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::util::Token;
+
+const REQUEST: Token = Token(42);
+
+type Results = Rc<RefCell<Vec<(Token, Result<Token, ws::ErrorKind>)>>>;
+
+#[derive(PartialEq)]
+enum Role {
+    Initiator,
+    Requested,
+    Server,
+}
+
+struct Client {
+    out: ws::Sender,
+    url: url::Url,
+    role: Role,
+    results: Results,
+}
+
+impl ws::Handler for Client {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        match self.role {
+            Role::Initiator => self.out.connect_with_token(self.url.clone(), REQUEST),
+            Role::Requested => self.out.shutdown(),
+            Role::Server => Ok(()),
+        }
+    }
+
+    fn on_connect_result(
+        &mut self,
+        user_token: Token,
+        result: ws::Result<Token>,
+    ) -> ws::Result<()> {
+        let failed = result.is_err();
+        self.results
+            .borrow_mut()
+            .push((user_token, result.map_err(|err| err.kind)));
+        if failed {
+            self.out.shutdown()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+struct Factory {
+    url: url::Url,
+    clients: usize,
+    results: Results,
+}
+
+impl ws::Factory for Factory {
+    type Handler = Client;
+
+    fn connection_made(&mut self, _: ws::Sender) -> Client {
+        unreachable!()
+    }
+
+    fn client_connected(&mut self, out: ws::Sender) -> Client {
+        self.clients += 1;
+        Client {
+            out,
+            url: self.url.clone(),
+            role: if self.clients == 1 {
+                Role::Initiator
+            } else {
+                Role::Requested
+            },
+            results: self.results.clone(),
+        }
+    }
+
+    fn server_connected(&mut self, out: ws::Sender) -> Client {
+        Client {
+            out,
+            url: self.url.clone(),
+            role: Role::Server,
+            results: self.results.clone(),
+        }
+    }
+}
+
+fn run(port: u16, max_connections: usize) -> Results {
+    let url = url::Url::parse(&format!("ws://127.0.0.1:{}", port)).unwrap();
+    let results = Results::default();
+
+    let mut ws = ws::Builder::new()
+        .with_settings(ws::Settings {
+            max_connections,
+            ..ws::Settings::default()
+        })
+        .build(Factory {
+            url: url.clone(),
+            clients: 0,
+            results: results.clone(),
+        })
+        .unwrap();
+
+    ws.connect(url).unwrap();
+    ws.listen(("127.0.0.1", port)).unwrap();
+    results
+}
+
+#[test]
+fn connect_with_token() {
+    let results = run(3028, 100);
+    let results = results.borrow();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, REQUEST);
+    assert!(results[0].1.is_ok());
+}
+
+#[test]
+fn connect_with_token_failure() {
+    let results = run(3029, 2);
+    let results = results.borrow();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, REQUEST);
+    match results[0].1 {
+        Err(ws::ErrorKind::Capacity) => (),
+        ref other => panic!("Unexpected connect result: {:?}", other),
+    }
+}
+
+#[test]
+fn connect_with_token_from_broadcaster() {
+    let ws = ws::WebSocket::new(|_| |_| Ok(())).unwrap();
+    let url = url::Url::parse("ws://127.0.0.1:3088").unwrap();
+    match ws.broadcaster().connect_with_token(url, REQUEST) {
+        Err(ref err) => match err.kind {
+            ws::ErrorKind::Internal => (),
+            ref other => panic!("Unexpected error: {:?}", other),
+        },
+        Ok(()) => panic!("A broadcaster requested a connect result"),
+    }
+}
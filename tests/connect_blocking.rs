@@ -0,0 +1,55 @@
+extern crate ws;
+
+use std::net::TcpListener;
+use std::sync::mpsc;
+
+use ws::{connect_blocking, Builder, CloseCode, ErrorKind};
+
+#[test]
+fn sender_is_ready() {
+    let (tx, received) = mpsc::channel();
+    let server = Builder::new()
+        .spawn_local(move || {
+            let tx = tx.clone();
+            move |_| {
+                let tx = tx.clone();
+                move |msg: ws::Message| {
+                    tx.send(msg.into_text()?).unwrap();
+                    Ok(())
+                }
+            }
+        })
+        .unwrap();
+
+    let (out, handle) = connect_blocking(server.url().as_str()).unwrap();
+    out.send("Hello").unwrap();
+    assert_eq!(received.recv().unwrap(), "Hello");
+    out.close(CloseCode::Normal).unwrap();
+    handle.join().unwrap().unwrap();
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn refused_connection_fails() {
+    // find a port that nothing listens on
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let err = connect_blocking(format!("ws://{}", addr)).unwrap_err();
+    match err.kind {
+        ErrorKind::Io(_) => (),
+        kind => panic!("unexpected error kind {:?}", kind),
+    }
+}
+
+#[test]
+fn invalid_url_fails() {
+    let err = connect_blocking("not a url").unwrap_err();
+    match err.kind {
+        ErrorKind::Internal => (),
+        kind => panic!("unexpected error kind {:?}", kind),
+    }
+}
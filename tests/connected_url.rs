@@ -0,0 +1,57 @@
+extern crate url;
+extern crate ws;
+
+use std::net::SocketAddr;
+use std::sync::mpsc;
+
+use ws::{Builder, CloseCode, Handshake, Result, Sender};
+
+type Connected = (Option<url::Url>, Option<SocketAddr>);
+
+struct Handler {
+    out: Sender,
+    connected: mpsc::Sender<Connected>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, shake: Handshake) -> Result<()> {
+        self.connected
+            .send((shake.connected_url().cloned(), shake.connected_addr()))
+            .unwrap();
+        if shake.connected_url().is_some() {
+            self.out.close(CloseCode::Normal)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn connected_url() {
+    let (server_tx, server_connected) = mpsc::channel();
+    let server = Builder::new()
+        .spawn_local(move || {
+            move |out| Handler {
+                out,
+                connected: server_tx.clone(),
+            }
+        })
+        .unwrap();
+
+    let (client_tx, client_connected) = mpsc::channel();
+    let mut ws = Builder::new()
+        .build(move |out| Handler {
+            out,
+            connected: client_tx.clone(),
+        })
+        .unwrap();
+    ws.connect(server.url()).unwrap();
+    ws.run().unwrap();
+
+    assert_eq!(
+        client_connected.recv().unwrap(),
+        (Some(server.url()), Some(server.addr()))
+    );
+    assert_eq!(server_connected.recv().unwrap(), (None, None));
+
+    server.stop().unwrap();
+}
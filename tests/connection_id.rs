@@ -0,0 +1,55 @@
+extern crate ws;
+
+use std::sync::mpsc;
+
+use ws::{Builder, CloseCode, Handshake, Result, Sender};
+
+struct Server {
+    out: Sender,
+    opened: mpsc::Sender<(u64, u64)>,
+}
+
+impl ws::Handler for Server {
+    fn on_open(&mut self, shake: Handshake) -> Result<()> {
+        self.opened
+            .send((self.out.connection_id(), shake.connection_id))
+            .unwrap();
+        Ok(())
+    }
+}
+
+struct Client {
+    out: Sender,
+}
+
+impl ws::Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.out.close(CloseCode::Normal)
+    }
+}
+
+// Tokens are reused once a connection is removed, but connection ids are not
+#[test]
+fn connection_ids_are_not_reused() {
+    let (tx, opened) = mpsc::channel();
+    let server = Builder::new()
+        .spawn_local(move || {
+            move |out| Server {
+                out,
+                opened: tx.clone(),
+            }
+        })
+        .unwrap();
+
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        ws::connect(server.url().to_string(), |out| Client { out }).unwrap();
+        let (sender_id, handshake_id) = opened.recv().unwrap();
+        assert_eq!(sender_id, handshake_id);
+        ids.push(sender_id);
+    }
+    ids.dedup();
+    assert_eq!(ids.len(), 3);
+
+    server.stop().unwrap();
+}
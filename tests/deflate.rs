@@ -3,8 +3,13 @@ extern crate env_logger;
 extern crate url;
 extern crate ws;
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use ws::deflate::DeflateHandler;
+use ws::deflate::{DeflateBuilder, DeflateOffer, DeflatePool, DeflateSettings, InflatePool};
 use ws::{Builder, Message, Sender, Settings, WebSocket};
+use ws::{Handler, Handshake, MessageInfo, Request, Result};
 
 #[test]
 fn round_trip() {
@@ -76,3 +81,481 @@ fn fragment() {
 
     ws.listen("127.0.0.1:3024").unwrap();
 }
+
+#[test]
+fn parse_offer() {
+    let offer = DeflateOffer::parse(
+        "permessage-deflate; client_no_context_takeover; server_max_window_bits=10; client_max_window_bits",
+    ).unwrap();
+    assert_eq!(
+        offer,
+        DeflateOffer {
+            server_no_context_takeover: false,
+            client_no_context_takeover: true,
+            server_max_window_bits: Some(10),
+            client_max_window_bits: Some(15),
+        }
+    );
+    assert_eq!(
+        offer.to_string(),
+        "permessage-deflate; client_no_context_takeover; server_max_window_bits=10; client_max_window_bits=15"
+    );
+
+    assert!(DeflateOffer::parse("permessage-deflate; server_max_window_bits=16").is_err());
+    assert!(DeflateOffer::parse(
+        "permessage-deflate; client_no_context_takeover; client_no_context_takeover"
+    )
+    .is_err());
+    assert!(DeflateOffer::parse("permessage-deflate; unknown").is_err());
+    assert!(DeflateOffer::parse("x-webkit-deflate-frame").is_err());
+}
+
+type Negotiated = Rc<RefCell<Vec<(&'static str, DeflateOffer)>>>;
+
+struct Peer {
+    name: &'static str,
+    offers: &'static [&'static str],
+    out: Sender,
+    negotiated: Negotiated,
+}
+
+impl Handler for Peer {
+    fn build_request(&mut self, url: &url::Url) -> Result<Request> {
+        let mut req = Request::from_url(url)?;
+        for offer in self.offers {
+            req.add_extension(offer);
+        }
+        Ok(req)
+    }
+
+    fn on_open(&mut self, shake: Handshake) -> Result<()> {
+        let offer = DeflateOffer::from_response(&shake.response)?.unwrap();
+        self.negotiated.borrow_mut().push((self.name, offer));
+        if self.name == "Client" {
+            self.out.send("hello")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        if self.name == "Server" {
+            self.out.send(msg)
+        } else {
+            assert_eq!(msg.as_text().unwrap(), "hello");
+            self.out.shutdown()
+        }
+    }
+}
+
+fn negotiate(
+    addr: &str,
+    server: DeflateSettings,
+    offers: &'static [&'static str],
+    client_offers: Vec<DeflateOffer>,
+) -> Vec<(&'static str, DeflateOffer)> {
+    let negotiated = Negotiated::default();
+    let mut name = "Client";
+
+    let mut ws = WebSocket::new(|out: Sender| {
+        let peer = Peer {
+            name: name,
+            offers: if name == "Client" { offers } else { &[] },
+            out: out,
+            negotiated: negotiated.clone(),
+        };
+        let deflate = if name == "Client" {
+            // the offers are supplied by the peer unless the client has its own
+            DeflateBuilder::new()
+                .with_offers(client_offers.clone())
+                .build(peer)
+        } else {
+            DeflateBuilder::new().with_settings(server).build(peer)
+        };
+        name = "Server";
+        deflate
+    }).unwrap();
+
+    ws.connect(url::Url::parse(&format!("ws://{}", addr)).unwrap())
+        .unwrap();
+    ws.listen(addr).unwrap();
+
+    let result = negotiated.borrow().clone();
+    result
+}
+
+#[test]
+fn skip_invalid_offer() {
+    let negotiated = negotiate(
+        "127.0.0.1:3069",
+        DeflateSettings::default(),
+        &["permessage-deflate; server_max_window_bits=20"],
+        Vec::new(),
+    );
+
+    // the invalid offer is skipped in favor of the one added by the client's DeflateHandler
+    assert_eq!(negotiated.len(), 2);
+    for &(_, offer) in &negotiated {
+        assert_eq!(offer.server_max_window_bits, Some(15));
+        assert_eq!(offer.client_max_window_bits, Some(15));
+    }
+}
+
+#[test]
+fn fall_back_to_context_takeover() {
+    let negotiated = negotiate(
+        "127.0.0.1:3070",
+        DeflateSettings {
+            accept_no_context_takeover: false,
+            ..Default::default()
+        },
+        &["permessage-deflate; server_no_context_takeover; server_max_window_bits=10"],
+        Vec::new(),
+    );
+
+    // the server won't reset its window, so the client's plain offer is chosen instead
+    assert_eq!(negotiated.len(), 2);
+    for &(_, offer) in &negotiated {
+        assert!(!offer.server_no_context_takeover);
+        assert_eq!(offer.server_max_window_bits, Some(15));
+    }
+}
+
+#[test]
+fn client_offers() {
+    let small = DeflateOffer {
+        server_no_context_takeover: true,
+        server_max_window_bits: Some(10),
+        client_max_window_bits: Some(15),
+        ..Default::default()
+    };
+    let plain = DeflateOffer {
+        client_max_window_bits: Some(15),
+        ..Default::default()
+    };
+
+    // the first offer is preferred when the server can satisfy it
+    let negotiated = negotiate(
+        "127.0.0.1:3091",
+        DeflateSettings::default(),
+        &[],
+        vec![small, plain],
+    );
+    assert_eq!(negotiated.len(), 2);
+    for &(_, offer) in &negotiated {
+        assert!(offer.server_no_context_takeover);
+        assert_eq!(offer.server_max_window_bits, Some(10));
+    }
+
+    // otherwise the server falls back to the next one
+    let negotiated = negotiate(
+        "127.0.0.1:3092",
+        DeflateSettings {
+            accept_no_context_takeover: false,
+            ..Default::default()
+        },
+        &[],
+        vec![small, plain],
+    );
+    assert_eq!(negotiated.len(), 2);
+    for &(_, offer) in &negotiated {
+        assert!(!offer.server_no_context_takeover);
+        assert_eq!(offer.server_max_window_bits, Some(15));
+    }
+}
+
+struct Pooled {
+    client: bool,
+    out: Sender,
+    pool: DeflatePool,
+    idle: Rc<RefCell<Vec<usize>>>,
+}
+
+impl Handler for Pooled {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        if self.client {
+            self.out.send("first")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        if !self.client {
+            return self.out.send(msg);
+        }
+        self.idle.borrow_mut().push(self.pool.len());
+        if msg.as_text()? == "first" {
+            self.out.send("second")
+        } else {
+            self.out.shutdown()
+        }
+    }
+}
+
+#[test]
+fn pooled_contexts() {
+    let pool = DeflatePool::new(4);
+    let idle = Rc::new(RefCell::new(Vec::new()));
+    let mut builder = DeflateBuilder::new();
+    builder
+        .with_settings(DeflateSettings {
+            request_no_context_takeover: true,
+            ..Default::default()
+        })
+        .with_pool(pool.clone());
+
+    let mut client = true;
+
+    let mut ws = WebSocket::new(|out: Sender| {
+        let handler = Pooled {
+            client: client,
+            out: out,
+            pool: pool.clone(),
+            idle: idle.clone(),
+        };
+        client = false;
+        builder.build(handler)
+    }).unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3072").unwrap();
+
+    ws.connect(url).unwrap();
+
+    ws.listen("127.0.0.1:3072").unwrap();
+
+    // both connections reset their contexts after each message, so they take turns using one
+    // compressor and one decompressor from the pool
+    assert_eq!(*idle.borrow(), vec![2, 2]);
+    assert_eq!(pool.len(), 2);
+}
+
+struct Compressed {
+    out: Sender,
+    compressed: Rc<RefCell<Vec<bool>>>,
+}
+
+impl Handler for Compressed {
+    fn on_message_with_meta(&mut self, _: Message, info: MessageInfo) -> Result<()> {
+        self.compressed.borrow_mut().push(info.compressed);
+        self.out.shutdown()
+    }
+}
+
+#[test]
+fn message_compressed() {
+    let compressed = Rc::new(RefCell::new(Vec::new()));
+
+    let mut ws = Builder::new()
+        .with_settings(Settings {
+            message_info: true,
+            ..Default::default()
+        })
+        .build(|out: Sender| {
+            // the first connection is the client
+            if out.connection_id() == 0 {
+                out.send("compress me").unwrap();
+            }
+            DeflateHandler::new(Compressed {
+                out,
+                compressed: compressed.clone(),
+            })
+        })
+        .unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3076").unwrap();
+
+    ws.connect(url).unwrap();
+
+    ws.listen("127.0.0.1:3076").unwrap();
+
+    assert_eq!(*compressed.borrow(), vec![true]);
+}
+
+struct Offloaded {
+    out: Sender,
+    received: Rc<RefCell<Vec<(String, bool)>>>,
+}
+
+impl Handler for Offloaded {
+    fn on_message_with_meta(&mut self, msg: Message, info: MessageInfo) -> Result<()> {
+        let mut received = self.received.borrow_mut();
+        received.push((msg.into_text()?, info.compressed));
+        if received.len() == 4 {
+            self.out.shutdown()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn offload_decompression() {
+    let large = "offload ".repeat(100_000);
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let mut builder = DeflateBuilder::new();
+    builder.with_settings(DeflateSettings {
+        offload_threshold: 64,
+        ..Default::default()
+    });
+
+    let mut ws = Builder::new()
+        .with_settings(Settings {
+            message_info: true,
+            ..Default::default()
+        })
+        .build(|out: Sender| {
+            // the first connection is the client
+            if out.connection_id() == 0 {
+                out.send(large.clone()).unwrap();
+                out.send("small").unwrap();
+                out.send(large.clone()).unwrap();
+                out.send("last").unwrap();
+            }
+            builder
+                .build(Offloaded {
+                    out: out.clone(),
+                    received: received.clone(),
+                })
+                .with_sender(out)
+        })
+        .unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3082").unwrap();
+
+    ws.connect(url).unwrap();
+
+    ws.listen("127.0.0.1:3082").unwrap();
+
+    // the small messages wait for the large ones before them
+    assert_eq!(
+        *received.borrow(),
+        vec![
+            (large.clone(), true),
+            ("small".into(), true),
+            (large.clone(), true),
+            ("last".into(), true),
+        ]
+    );
+}
+
+struct Counted {
+    out: Sender,
+    expected: usize,
+    received: Rc<RefCell<Vec<String>>>,
+}
+
+impl Handler for Counted {
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        let mut received = self.received.borrow_mut();
+        received.push(msg.into_text()?);
+        if received.len() == self.expected {
+            self.out.shutdown()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn offload_queue_bounded() {
+    let messages = (0..20)
+        .map(|i| format!("offload {} ", i).repeat(10_000))
+        .collect::<Vec<_>>();
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let pool = InflatePool::new(1);
+    let mut builder = DeflateBuilder::new();
+    builder
+        .with_settings(DeflateSettings {
+            offload_threshold: 64,
+            offload_queue_size: 2,
+            ..Default::default()
+        })
+        .with_inflate_pool(pool.clone());
+
+    let mut ws = WebSocket::new(|out: Sender| {
+        // the first connection is the client
+        if out.connection_id() == 0 {
+            for msg in &messages {
+                out.send(msg.clone()).unwrap();
+            }
+        }
+        builder
+            .build(Counted {
+                out: out.clone(),
+                expected: messages.len(),
+                received: received.clone(),
+            })
+            .with_sender(out)
+    }).unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3093").unwrap();
+
+    ws.connect(url).unwrap();
+
+    ws.listen("127.0.0.1:3093").unwrap();
+
+    // reading pauses while the queue is full instead of failing the connection
+    assert_eq!(*received.borrow(), messages);
+    assert_eq!(pool.threads(), 1);
+}
+
+const UPDATES: [&str; 2] = [
+    r#"{"type":"update","id":1,"price":10}"#,
+    r#"{"type":"update","id":2,"price":20}"#,
+];
+
+struct Updates {
+    out: Sender,
+    received: Rc<RefCell<Vec<String>>>,
+}
+
+impl Handler for Updates {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        // the first connection is the client, which sends once the dictionary is agreed
+        if self.out.connection_id() == 0 {
+            for update in &UPDATES {
+                self.out.send(*update)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        let mut received = self.received.borrow_mut();
+        received.push(msg.into_text()?);
+        if received.len() == UPDATES.len() {
+            self.out.shutdown()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn preset_dictionary() {
+    static DICTIONARY: &[u8] = br#"{"type":"update","id":,"price":}"#;
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let mut builder = DeflateBuilder::new();
+    builder.with_settings(DeflateSettings {
+        request_no_context_takeover: true,
+        preset_dictionary: Some(DICTIONARY),
+        ..Default::default()
+    });
+
+    let mut ws = WebSocket::new(|out: Sender| {
+        builder.build(Updates {
+            out,
+            received: received.clone(),
+        })
+    })
+    .unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3095").unwrap();
+
+    ws.connect(url).unwrap();
+
+    ws.listen("127.0.0.1:3095").unwrap();
+
+    assert_eq!(*received.borrow(), UPDATES);
+}
@@ -0,0 +1,107 @@
+extern crate ws;
+
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::rc::Rc;
+use std::thread;
+
+use ws::{Builder, Handler, Handshake, Result, Settings};
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+struct Client {
+    log: Log,
+}
+
+impl Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.log.borrow_mut().push("open".into());
+        Ok(())
+    }
+
+    fn on_error(&mut self, err: ws::Error) {
+        self.log.borrow_mut().push(format!("{:?}", err.kind));
+    }
+}
+
+// A server that writes its handshake response and the start of a binary frame with a payload of
+// `length` bytes in one write, then hangs up once the client responds.
+fn server(length: u64, sent: usize) -> (String, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                break;
+            }
+        }
+
+        let mut data = b"HTTP/1.1 101 Switching Protocols\r\n\
+            Connection: Upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Accept: unchecked\r\n\r\n"
+            .to_vec();
+        data.push(0x82);
+        data.push(127);
+        for shift in (0..8).rev() {
+            data.push((length >> (shift * 8)) as u8);
+        }
+        data.extend(vec![0; sent]);
+        reader.get_mut().write_all(&data).unwrap();
+
+        let _ = reader.get_mut().read(&mut [0; 64]);
+    });
+    (url, handle)
+}
+
+fn connect(url: &str, settings: Settings) -> Vec<String> {
+    let log = Log::default();
+    let client_log = log.clone();
+    let mut ws = Builder::new()
+        .with_settings(settings)
+        .build(move |_| Client {
+            log: client_log.clone(),
+        })
+        .unwrap();
+    ws.connect(url.parse().unwrap()).unwrap();
+    ws.run().unwrap();
+    let log = log.borrow().clone();
+    log
+}
+
+#[test]
+fn jumbo_frame_after_response() {
+    let (url, server) = server(1 << 20, 16);
+    let log = connect(
+        &url,
+        Settings {
+            max_fragment_size: 1024,
+            ..Settings::default()
+        },
+    );
+    server.join().unwrap();
+    // the server may reset the connection after the error, which is reported as well
+    assert_eq!(log[0], "Capacity");
+    assert!(!log.contains(&"open".to_string()));
+}
+
+#[test]
+fn response_remainder_over_buffer_capacity() {
+    let (url, server) = server(1200, 1200);
+    let log = connect(
+        &url,
+        Settings {
+            in_buffer_capacity: 512,
+            in_buffer_grow: false,
+            ..Settings::default()
+        },
+    );
+    server.join().unwrap();
+    // the server may reset the connection after the error, which is reported as well
+    assert_eq!(log[0], "Capacity");
+    assert!(!log.contains(&"open".to_string()));
+}
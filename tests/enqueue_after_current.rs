@@ -0,0 +1,110 @@
+#![cfg(feature = "testing")]
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::testing::VirtualLoop;
+use ws::util::Token;
+use ws::{Frame, OpCode, Result, Sender};
+
+type Log = Rc<RefCell<Vec<(Token, String)>>>;
+
+struct Peer {
+    out: Sender,
+    log: Log,
+}
+
+impl ws::Handler for Peer {
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Frame>> {
+        let payload = String::from_utf8_lossy(frame.payload()).into_owned();
+        match frame.opcode() {
+            OpCode::Text if payload == "ask" => {
+                // take over the frame and answer it
+                self.out.send("queued")?;
+                self.out.enqueue_after_current("first")?;
+                self.out.enqueue_after_current("second")?;
+                Ok(None)
+            }
+            OpCode::Text | OpCode::Pong => {
+                self.log.borrow_mut().push((
+                    self.out.token(),
+                    format!("{:?} {}", frame.opcode(), payload),
+                ));
+                Ok(Some(frame))
+            }
+            _ => Ok(Some(frame)),
+        }
+    }
+}
+
+#[test]
+fn enqueue_after_current() {
+    let log = Log::default();
+    let senders = Rc::new(RefCell::new(Vec::new()));
+    let mut virt = {
+        let log = log.clone();
+        let senders = senders.clone();
+        VirtualLoop::new(move |out: Sender| {
+            senders.borrow_mut().push(out.clone());
+            Peer {
+                out,
+                log: log.clone(),
+            }
+        })
+    };
+    let (client, _) = virt.connect("ws://example.com/").unwrap();
+    virt.run_until_idle();
+
+    let out = senders
+        .borrow()
+        .iter()
+        .find(|out| out.token() == client)
+        .unwrap()
+        .clone();
+    // both frames reach the server in the same read
+    out.send("ask").unwrap();
+    out.ping(b"ping".to_vec()).unwrap();
+    virt.run_until_idle();
+
+    let received = log
+        .borrow()
+        .iter()
+        .filter(|&&(token, _)| token == client)
+        .map(|&(_, ref event)| event.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        received,
+        vec!["Text first", "Text second", "Pong ping", "Text queued"]
+    );
+}
+
+#[test]
+fn enqueue_after_current_outside_frame() {
+    let log = Log::default();
+    let senders = Rc::new(RefCell::new(Vec::new()));
+    let mut virt = {
+        let log = log.clone();
+        let senders = senders.clone();
+        VirtualLoop::new(move |out: Sender| {
+            senders.borrow_mut().push(out.clone());
+            Peer {
+                out,
+                log: log.clone(),
+            }
+        })
+    };
+    let (client, server) = virt.connect("ws://example.com/").unwrap();
+    virt.run_until_idle();
+
+    // without a frame being handled this is the same as send
+    let out = senders
+        .borrow()
+        .iter()
+        .find(|out| out.token() == server)
+        .unwrap()
+        .clone();
+    out.enqueue_after_current("hello").unwrap();
+    virt.run_until_idle();
+    assert_eq!(*log.borrow(), vec![(client, "Text hello".to_string())]);
+}
@@ -0,0 +1,79 @@
+extern crate ws;
+
+use std::io::{Cursor, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc;
+
+use ws::{Builder, CloseCode, Frame, Handshake, Sender};
+
+const SIZE: usize = 16 << 20;
+
+struct Handler {
+    out: Sender,
+    events: mpsc::Sender<String>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: Handshake) -> ws::Result<()> {
+        self.out.send(vec![1u8; SIZE])
+    }
+
+    fn on_eof(&mut self) -> ws::Result<()> {
+        self.events.send("eof".into()).unwrap();
+        Ok(())
+    }
+
+    fn on_close(&mut self, code: CloseCode, _: &str) {
+        self.events.send(format!("{:?}", code)).unwrap();
+    }
+}
+
+// A client that shuts down its side of the connection while a large message is being written to
+// it still receives the whole message.
+#[test]
+fn writes_finish_after_eof() {
+    let (tx, events) = mpsc::channel();
+    let server = Builder::new()
+        .spawn_local(move || {
+            move |out| Handler {
+                out,
+                events: tx.clone(),
+            }
+        })
+        .unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = Vec::new();
+    let mut byte = [0];
+    while !response.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        response.push(byte[0]);
+    }
+    assert!(response.starts_with(b"HTTP/1.1 101"));
+
+    // the message is being written once its first bytes arrive
+    let mut received = vec![0; 2];
+    stream.read_exact(&mut received).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    stream.read_to_end(&mut received).unwrap();
+    let mut cursor = Cursor::new(received);
+    let mut payload = Vec::new();
+    while let Some(frame) = Frame::parse(&mut cursor, u64::max_value()).unwrap() {
+        payload.extend(frame.into_data());
+    }
+    assert_eq!(payload.len(), SIZE);
+
+    assert_eq!(events.recv().unwrap(), "eof");
+    assert_eq!(events.recv().unwrap(), "Abnormal");
+
+    server.stop().unwrap();
+}
@@ -0,0 +1,149 @@
+#![cfg(feature = "testing")]
+extern crate ws;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::time::Duration;
+
+use ws::testing::VirtualLoop;
+use ws::util::Token;
+use ws::{
+    Direction, ErrorEvent, ErrorKind, ErrorPhase, Frame, Handshake, Message, Request, Response,
+    Result, Sender,
+};
+
+const FAIL: Token = Token(1);
+
+type Log = Rc<RefCell<Vec<(bool, ErrorPhase, Option<Direction>)>>>;
+
+// Servers fail whatever they are asked to do, clients behave.
+struct Peer {
+    out: Sender,
+    log: Log,
+    client: bool,
+    reject: bool,
+}
+
+fn fail() -> ws::Error {
+    ws::Error::new(ErrorKind::Custom(Box::new(fmt::Error)), "failed")
+}
+
+impl ws::Handler for Peer {
+    fn on_request(&mut self, req: &Request) -> Result<Response> {
+        if self.reject {
+            Err(fail())
+        } else {
+            Response::from_request(req)
+        }
+    }
+
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        if self.client {
+            Ok(())
+        } else {
+            self.out.timeout(1_000, FAIL)
+        }
+    }
+
+    fn on_message(&mut self, _: Message) -> Result<()> {
+        Err(fail())
+    }
+
+    fn on_send_frame(&mut self, frame: Frame) -> Result<Option<Frame>> {
+        if self.client {
+            Ok(Some(frame))
+        } else {
+            Err(fail())
+        }
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<()> {
+        Err(fail())
+    }
+
+    fn on_error_event(&mut self, event: ErrorEvent) {
+        assert_eq!(event.token, self.out.token());
+        self.log
+            .borrow_mut()
+            .push((self.client, event.phase, event.direction));
+    }
+}
+
+struct Factory {
+    log: Log,
+    senders: Rc<RefCell<Vec<Sender>>>,
+    reject: bool,
+}
+
+impl Factory {
+    fn peer(&mut self, out: Sender, client: bool) -> Peer {
+        self.senders.borrow_mut().push(out.clone());
+        Peer {
+            out,
+            log: self.log.clone(),
+            client,
+            reject: self.reject,
+        }
+    }
+}
+
+impl ws::Factory for Factory {
+    type Handler = Peer;
+
+    fn connection_made(&mut self, out: Sender) -> Peer {
+        self.peer(out, false)
+    }
+
+    fn client_connected(&mut self, out: Sender) -> Peer {
+        self.peer(out, true)
+    }
+}
+
+fn setup(reject: bool) -> (VirtualLoop<Factory>, Log, Rc<RefCell<Vec<Sender>>>) {
+    let log = Log::default();
+    let senders = Rc::new(RefCell::new(Vec::new()));
+    let virt = VirtualLoop::new(Factory {
+        log: log.clone(),
+        senders: senders.clone(),
+        reject,
+    });
+    (virt, log, senders)
+}
+
+#[test]
+fn handshake_error() {
+    let (mut virt, log, _) = setup(true);
+    virt.connect("ws://example.com/").unwrap();
+
+    assert_eq!(
+        log.borrow().first(),
+        Some(&(false, ErrorPhase::Handshake, Some(Direction::Inbound)))
+    );
+}
+
+#[test]
+fn open_connection_errors() {
+    let (mut virt, log, senders) = setup(false);
+    virt.connect("ws://example.com/").unwrap();
+    assert!(log.borrow().is_empty());
+    let (client, server) = {
+        let senders = senders.borrow();
+        (senders[0].clone(), senders[1].clone())
+    };
+
+    virt.advance(Duration::from_millis(1_000));
+    client.send("hello").unwrap();
+    virt.run_until_idle();
+    server.send("hello").unwrap();
+    virt.run_until_idle();
+
+    assert_eq!(
+        *log.borrow(),
+        vec![
+            (false, ErrorPhase::Timer, None),
+            (false, ErrorPhase::Read, Some(Direction::Inbound)),
+            (false, ErrorPhase::Command, Some(Direction::Outbound)),
+        ]
+    );
+}
@@ -0,0 +1,77 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use ws::{EventOrder, Settings};
+
+const MESSAGES: usize = 200;
+const SIZE: usize = 16_384;
+
+struct Handler {
+    out: ws::Sender,
+    received: Rc<Cell<usize>>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // Both endpoints flood each other, so their sockets stay readable and writable
+        for _ in 0..MESSAGES {
+            self.out.send(vec![0; SIZE])?;
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        assert_eq!(msg.len(), SIZE);
+        // The first connection is the client
+        if self.out.connection_id() == 0 {
+            self.received.set(self.received.get() + 1);
+            if self.received.get() == MESSAGES * 2 {
+                return self.out.shutdown();
+            }
+            Ok(())
+        } else {
+            self.out.send(msg)
+        }
+    }
+}
+
+fn flood(event_order: EventOrder) -> usize {
+    let received = Rc::new(Cell::new(0));
+    let handler_received = received.clone();
+
+    let (addr, mut ws) = ws::Builder::new()
+        .with_settings(Settings {
+            event_order,
+            ..Settings::default()
+        })
+        .build(move |out: ws::Sender| Handler {
+            out,
+            received: handler_received.clone(),
+        })
+        .unwrap()
+        .listen_local()
+        .unwrap();
+    ws.connect(url::Url::parse(&format!("ws://{}", addr)).unwrap())
+        .unwrap();
+    ws.run().unwrap();
+
+    received.get()
+}
+
+#[test]
+fn read_first() {
+    assert_eq!(flood(EventOrder::ReadFirst), MESSAGES * 2);
+}
+
+#[test]
+fn write_first() {
+    assert_eq!(flood(EventOrder::WriteFirst), MESSAGES * 2);
+}
+
+#[test]
+fn alternate() {
+    assert_eq!(flood(EventOrder::Alternate), MESSAGES * 2);
+}
@@ -0,0 +1,96 @@
+extern crate url;
+extern crate ws;
+
+use ws::{CloseCode, Message, Sender, WebSocket, WsEvent};
+
+#[test]
+fn connection_lifecycle_events() {
+    let mut name = "Client";
+
+    let mut ws = WebSocket::new(|output: Sender| {
+        if name == "Client" {
+            output.send("ping").unwrap();
+        }
+
+        let handler = move |msg: Message| {
+            if name == "Server" {
+                output.send(msg)
+            } else {
+                output.close(CloseCode::Normal)?;
+                output.shutdown()
+            }
+        };
+
+        name = "Server";
+
+        handler
+    }).unwrap();
+
+    let events = ws.subscribe_events();
+
+    let url = url::Url::parse("ws://127.0.0.1:3026").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3026").unwrap();
+
+    let mut accepted = 0;
+    let mut complete = 0;
+    for event in events.try_iter() {
+        match event {
+            WsEvent::Accepted { .. } => accepted += 1,
+            WsEvent::HandshakeComplete { .. } => complete += 1,
+            _ => (),
+        }
+    }
+
+    assert_eq!(accepted, 1);
+    assert_eq!(complete, 2);
+}
+
+#[test]
+fn full_observer_drops_events() {
+    let mut ws = ws::Builder::new()
+        .with_settings(ws::Settings {
+            event_queue_size: 1,
+            ..ws::Settings::default()
+        })
+        .build(|output: Sender| {
+            output.send("ping").unwrap();
+            move |_| {
+                output.close(CloseCode::Normal)?;
+                output.shutdown()
+            }
+        })
+        .unwrap();
+
+    let events = ws.subscribe_events();
+    // a receiver that hangs up is removed without affecting the others
+    drop(ws.subscribe_events());
+
+    let url = url::Url::parse("ws://127.0.0.1:3087").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3087").unwrap();
+
+    assert_eq!(events.try_iter().count(), 1);
+}
+
+#[test]
+#[cfg(not(any(feature = "ssl", feature = "nativetls")))]
+fn failed_connect_closed_event() {
+    let mut ws = WebSocket::new(|_| |_| Ok(())).unwrap();
+    let events = ws.subscribe_events();
+
+    // without a TLS feature the connection is discarded before it is registered
+    let url = url::Url::parse("wss://127.0.0.1:3089").unwrap();
+    ws.connect(url).unwrap();
+    ws.broadcaster().shutdown().unwrap();
+    ws.run().unwrap();
+
+    let closed = events
+        .try_iter()
+        .filter(|event| match *event {
+            WsEvent::Closed { .. } => true,
+            _ => false,
+        })
+        .count();
+    assert_eq!(closed, 1);
+}
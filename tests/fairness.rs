@@ -0,0 +1,73 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const MESSAGES: usize = 20;
+
+struct Handler {
+    out: ws::Sender,
+    received: Rc<RefCell<Vec<String>>>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // The first connection is the client
+        if self.out.connection_id() == 0 {
+            for i in 0..MESSAGES {
+                self.out.send(i.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        self.received.borrow_mut().push(msg.into_text()?);
+        if self.received.borrow().len() == MESSAGES {
+            return self.out.shutdown();
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn one_message_per_read() {
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let handler_received = received.clone();
+
+    let mut ws = ws::Builder::new()
+        .with_settings(ws::Settings {
+            max_messages_per_read: 1,
+            ..ws::Settings::default()
+        })
+        .build(move |out: ws::Sender| Handler {
+            out,
+            received: handler_received.clone(),
+        })
+        .unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3035").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3035").unwrap();
+
+    let expected: Vec<String> = (0..MESSAGES).map(|i| i.to_string()).collect();
+    assert_eq!(*received.borrow(), expected);
+}
+
+#[test]
+fn zero_messages_per_read() {
+    let result = ws::Builder::new()
+        .with_settings(ws::Settings {
+            max_messages_per_read: 0,
+            ..ws::Settings::default()
+        })
+        .build(|_| |_| Ok(()));
+    match result {
+        Err(ref err) => match err.kind {
+            ws::ErrorKind::Internal => (),
+            ref other => panic!("Unexpected error: {:?}", other),
+        },
+        Ok(_) => panic!("Built a WebSocket that can never read a message"),
+    }
+}
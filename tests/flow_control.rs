@@ -0,0 +1,70 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+struct Handler {
+    out: ws::Sender,
+    received: Rc<Cell<usize>>,
+    woken: Rc<Cell<bool>>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // The first connection is the client
+        if self.out.connection_id() == 0 {
+            for text in &["one", "two", "three"] {
+                self.out.send(*text)?;
+            }
+        } else {
+            self.out.suspend_read()?;
+            let out = self.out.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                out.wake().unwrap();
+            });
+        }
+        Ok(())
+    }
+
+    fn on_wake(&mut self) -> ws::Result<()> {
+        // nothing may be read while suspended
+        assert_eq!(self.received.get(), 0);
+        self.woken.set(true);
+        self.out.resume_read()
+    }
+
+    fn on_message(&mut self, _: ws::Message) -> ws::Result<()> {
+        assert!(self.woken.get());
+        self.received.set(self.received.get() + 1);
+        if self.received.get() == 3 {
+            self.out.shutdown()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn suspend_and_resume_read() {
+    let received = Rc::new(Cell::new(0));
+    let woken = Rc::new(Cell::new(false));
+    let handler_received = received.clone();
+    let handler_woken = woken.clone();
+
+    let mut ws = ws::WebSocket::new(move |out| Handler {
+        out,
+        received: handler_received.clone(),
+        woken: handler_woken.clone(),
+    }).unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3046").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3046").unwrap();
+
+    assert!(woken.get());
+    assert_eq!(received.get(), 3);
+}
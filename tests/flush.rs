@@ -0,0 +1,49 @@
+extern crate ws;
+
+use std::sync::mpsc::{self, channel};
+use std::thread;
+use std::time::Duration;
+
+use ws::{Handshake, Result, Sender};
+
+struct Client {
+    out: Sender,
+    opened: mpsc::Sender<Sender>,
+}
+
+impl ws::Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.opened.send(self.out.clone()).unwrap();
+        Ok(())
+    }
+
+    fn on_error(&mut self, _: ws::Error) {}
+}
+
+#[test]
+fn flush() {
+    let (broadcaster, handle) = ws::Builder::new()
+        .spawn("127.0.0.1:3094", || |_: Sender| |_: ws::Message| Ok(()))
+        .unwrap();
+
+    let (open_tx, open_rx) = channel();
+    let client = thread::spawn(move || {
+        ws::connect("ws://127.0.0.1:3094", |out| Client {
+            out,
+            opened: open_tx.clone(),
+        })
+        .unwrap();
+    });
+    let out = open_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    out.send(vec![7u8; 1 << 20]).unwrap();
+    out.flush(Duration::from_secs(5)).unwrap();
+    broadcaster.flush(Duration::from_secs(5)).unwrap();
+
+    out.close(ws::CloseCode::Normal).unwrap();
+    client.join().unwrap();
+    assert!(out.flush(Duration::from_secs(5)).is_err());
+
+    broadcaster.shutdown().unwrap();
+    handle.join().unwrap().unwrap();
+}
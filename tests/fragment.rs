@@ -0,0 +1,65 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Fragments = Rc<RefCell<Vec<(usize, usize, bool)>>>;
+
+struct Handler {
+    out: ws::Sender,
+    fragments: Fragments,
+}
+
+impl ws::Handler for Handler {
+    fn on_fragment(
+        &mut self,
+        frame: &ws::Frame,
+        index: usize,
+        accumulated_bytes: usize,
+        is_last: bool,
+    ) -> ws::Result<()> {
+        assert!(frame.payload().len() <= 4);
+        self.fragments
+            .borrow_mut()
+            .push((index, accumulated_bytes, is_last));
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        assert_eq!(msg.as_text()?, "Hello fragments");
+        self.out.shutdown()
+    }
+}
+
+#[test]
+fn fragment_positions() {
+    let fragments = Fragments::default();
+    let server_fragments = fragments.clone();
+
+    let mut ws = ws::Builder::new()
+        .with_settings(ws::Settings {
+            fragment_size: 4,
+            ..ws::Settings::default()
+        })
+        .build(move |out: ws::Sender| {
+            // The first connection is the client
+            if out.connection_id() == 0 {
+                out.send("Hello fragments").unwrap();
+            }
+            Handler {
+                out,
+                fragments: server_fragments.clone(),
+            }
+        })
+        .unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3030").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3030").unwrap();
+
+    assert_eq!(
+        *fragments.borrow(),
+        vec![(0, 4, false), (1, 8, false), (2, 12, false), (3, 15, true)]
+    );
+}
@@ -0,0 +1,70 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+struct Handler {
+    out: ws::Sender,
+    frames: Rc<RefCell<Vec<usize>>>,
+    messages: usize,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // The first connection is the client
+        if self.out.connection_id() == 0 {
+            self.out.send("abcdefghij")?;
+            self.out.send(vec![0u8; 10])?;
+            self.out.send_with_fragment_size(vec![0u8; 10], 2)?;
+        }
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> ws::Result<Option<ws::Frame>> {
+        if self.out.connection_id() != 0 {
+            self.frames.borrow_mut().push(frame.payload().len());
+        }
+        Ok(Some(frame))
+    }
+
+    fn on_message(&mut self, _: ws::Message) -> ws::Result<()> {
+        self.messages += 1;
+        if self.messages == 3 {
+            self.out.shutdown()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn fragment_size_by_message_type() {
+    let frames = Rc::new(RefCell::new(Vec::new()));
+    let handler_frames = frames.clone();
+
+    let mut ws = ws::Builder::new()
+        .with_settings(ws::Settings {
+            fragment_size: 5,
+            text_fragment_size: Some(4),
+            ..ws::Settings::default()
+        })
+        .build(move |out| Handler {
+            out,
+            frames: handler_frames.clone(),
+            messages: 0,
+        })
+        .unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3048").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3048").unwrap();
+
+    assert_eq!(*frames.borrow(), vec![4, 4, 2, 5, 5, 2, 2, 2, 2, 2]);
+}
+
+#[test]
+fn send_with_zero_fragment_size() {
+    let ws = ws::WebSocket::new(|_| |_| Ok(())).unwrap();
+    assert!(ws.broadcaster().send_with_fragment_size("hi", 0).is_err());
+}
@@ -0,0 +1,83 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const HEAD: &str = "GET / HTTP/1.1\r\n\
+                    Connection: Upgrade\r\n\
+                    Upgrade: websocket\r\n\
+                    Sec-WebSocket-Version: 13\r\n\
+                    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+
+struct Handler {
+    errors: mpsc::Sender<String>,
+}
+
+impl ws::Handler for Handler {
+    fn on_error(&mut self, err: ws::Error) {
+        self.errors.send(err.details.into_owned()).unwrap();
+    }
+}
+
+// Send each part of a handshake request in turn and return the status line of the response.
+fn handshake(port: u16, body: ws::HandshakeBody, parts: &[&str]) -> (String, Vec<String>) {
+    let (tx, rx) = mpsc::channel();
+    let addr = format!("127.0.0.1:{}", port);
+    let (broadcaster, handle) = ws::Builder::new()
+        .with_settings(ws::Settings {
+            max_handshake_size: 1024,
+            handshake_body: body,
+            ..ws::Settings::default()
+        })
+        .spawn(addr.clone(), move || {
+            let tx = tx.clone();
+            move |_| Handler { errors: tx.clone() }
+        })
+        .unwrap();
+
+    let mut stream = TcpStream::connect(&*addr).unwrap();
+    for part in parts {
+        stream.write_all(part.as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+    }
+    let mut buf = [0; 1024];
+    let read = stream.read(&mut buf).unwrap();
+    let response = String::from_utf8_lossy(&buf[..read]).into_owned();
+    drop(stream);
+
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+    let status = response.lines().next().unwrap_or("").to_owned();
+    (status, rx.try_iter().collect())
+}
+
+#[test]
+fn body_rejected() {
+    let head = format!("{}Content-Length: 5\r\n\r\n", HEAD);
+    let (status, errors) = handshake(3056, ws::HandshakeBody::Reject, &[&head, "hello"]);
+    assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+    assert_eq!(errors, vec!["Received a handshake request with a body."]);
+}
+
+#[test]
+fn body_ignored() {
+    let head = format!("{}Content-Length: 5\r\n\r\n", HEAD);
+    let (status, errors) = handshake(3057, ws::HandshakeBody::Ignore, &[&head, "hel", "lo"]);
+    assert_eq!(status, "HTTP/1.1 101 Switching Protocols");
+    assert!(errors.is_empty());
+
+    let head = format!("{}Content-Length: 5000\r\n\r\n", HEAD);
+    let (status, _) = handshake(3058, ws::HandshakeBody::Ignore, &[&head, "hello"]);
+    assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+}
+
+#[test]
+fn oversized_headers() {
+    let head = format!("{}X-Padding: {}\r\n", HEAD, "a".repeat(2000));
+    let (status, errors) = handshake(3059, ws::HandshakeBody::Reject, &[&head]);
+    assert_eq!(status, "HTTP/1.1 431 Request Header Fields Too Large");
+    assert_eq!(errors, vec!["Handshake request headers exceeded 1024 bytes."]);
+}
@@ -0,0 +1,91 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use ws::{Builder, HandshakeStats, Settings};
+
+// Sends a handshake request with the given version, key and origin and returns the stream along
+// with the status of the response.
+fn handshake(addr: SocketAddr, version: &str, key: &str, origin: &str) -> (TcpStream, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Origin: {}\r\n\
+         Connection: Upgrade\r\n\
+         Upgrade: websocket\r\n\
+         Sec-WebSocket-Version: {}\r\n\
+         Sec-WebSocket-Key: {}\r\n\r\n",
+        addr, origin, version, key
+    )
+    .unwrap();
+    let mut response = [0; 12];
+    stream.read_exact(&mut response).unwrap();
+    let status = String::from_utf8_lossy(&response[9..]).into_owned();
+    (stream, status)
+}
+
+#[test]
+fn outcomes_are_counted() {
+    let server = Builder::new()
+        .with_settings(Settings {
+            version_strict: true,
+            request_key_strict: true,
+            same_origin_only: true,
+            ..Settings::default()
+        })
+        .spawn_local(|| |_| |_| Ok(()))
+        .unwrap();
+    let addr = server.addr();
+    let origin = format!("http://{}", addr);
+    let key = "q16eN37NCfVwUChPvBdk4g==";
+
+    assert_eq!(handshake(addr, "13", key, &origin).1, "101");
+    assert_eq!(handshake(addr, "13", key, &origin).1, "101");
+    assert_eq!(handshake(addr, "8", key, &origin).1, "426");
+    assert_eq!(handshake(addr, "13", "short", &origin).1, "400");
+    assert_eq!(handshake(addr, "13", key, "http://evil.example").1, "403");
+
+    let expected = HandshakeStats {
+        accepted: 2,
+        bad_request: 1,
+        forbidden: 1,
+        upgrade_required: 1,
+        ..HandshakeStats::default()
+    };
+    // an outcome is counted once its response is written, which may be just after it is read
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.broadcaster().handshake_stats() != expected && Instant::now() < deadline {
+        sleep(Duration::from_millis(10));
+    }
+    assert_eq!(server.broadcaster().handshake_stats(), expected);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn refused_connections_are_counted() {
+    let server = Builder::new()
+        .with_settings(Settings {
+            max_connections_per_ip: 1,
+            ..Settings::default()
+        })
+        .spawn_local(|| |_| |_| Ok(()))
+        .unwrap();
+    let addr = server.addr();
+    let origin = format!("http://{}", addr);
+
+    let (_first, status) = handshake(addr, "13", "q16eN37NCfVwUChPvBdk4g==", &origin);
+    assert_eq!(status, "101");
+    let mut second = TcpStream::connect(addr).unwrap();
+    let mut response = [0; 12];
+    second.read_exact(&mut response).unwrap();
+    assert_eq!(&response, b"HTTP/1.1 429");
+    assert_eq!(server.broadcaster().handshake_stats().too_many_requests, 1);
+
+    server.stop().unwrap();
+}
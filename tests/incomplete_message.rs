@@ -0,0 +1,105 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use ws::{CloseCode, Frame, OpCode};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Incomplete(Vec<(OpCode, String)>),
+    Close(CloseCode),
+}
+
+struct Handler {
+    events: mpsc::Sender<Event>,
+}
+
+impl ws::Handler for Handler {
+    fn on_incomplete_message(&mut self, fragments: Vec<Frame>) {
+        let fragments = fragments
+            .into_iter()
+            .map(|frame| {
+                let opcode = frame.opcode();
+                (opcode, String::from_utf8(frame.into_data()).unwrap())
+            })
+            .collect();
+        self.events.send(Event::Incomplete(fragments)).unwrap();
+    }
+
+    fn on_close(&mut self, code: CloseCode, _: &str) {
+        self.events.send(Event::Close(code)).unwrap();
+    }
+}
+
+// Open a raw connection, send the given frames and drop the connection.
+fn send_and_drop(frames: Vec<Frame>) -> Vec<Event> {
+    let (tx, rx) = mpsc::channel();
+    let server = ws::Builder::new()
+        .spawn_local(move || {
+            move |_| Handler {
+                events: tx.clone(),
+            }
+        })
+        .unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        )
+        .unwrap();
+    let mut buf = [0; 1024];
+    let read = stream.read(&mut buf).unwrap();
+    assert!(buf[..read].starts_with(b"HTTP/1.1 101"));
+
+    let mut data = Vec::new();
+    for mut frame in frames {
+        frame.set_mask().format(&mut data).unwrap();
+    }
+    stream.write_all(&data).unwrap();
+    drop(stream);
+
+    let mut events = Vec::new();
+    while let Ok(event) = rx.recv_timeout(Duration::from_secs(5)) {
+        events.push(event);
+        if let Some(&Event::Close(_)) = events.last() {
+            break;
+        }
+    }
+    server.stop().unwrap();
+    events
+}
+
+#[test]
+fn salvage_fragments() {
+    let events = send_and_drop(vec![
+        Frame::message(b"hel".to_vec(), OpCode::Text, false),
+        Frame::message(b"lo".to_vec(), OpCode::Continue, false),
+    ]);
+    assert_eq!(
+        events,
+        vec![
+            Event::Incomplete(vec![
+                (OpCode::Text, "hel".into()),
+                (OpCode::Continue, "lo".into()),
+            ]),
+            Event::Close(CloseCode::Abnormal),
+        ]
+    );
+}
+
+#[test]
+fn complete_message() {
+    let events = send_and_drop(vec![
+        Frame::message(b"hel".to_vec(), OpCode::Text, false),
+        Frame::message(b"lo".to_vec(), OpCode::Continue, true),
+    ]);
+    assert_eq!(events, vec![Event::Close(CloseCode::Abnormal)]);
+}
@@ -0,0 +1,113 @@
+#![cfg(feature = "testing")]
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::testing::VirtualLoop;
+use ws::{CloseCode, Frame, LargeMessages, Message, Result, Sender, Settings};
+
+#[derive(Default)]
+struct Log {
+    fragments: Vec<(usize, usize, bool)>,
+    messages: Vec<Message>,
+    closed: Vec<CloseCode>,
+}
+
+struct Recorder {
+    log: Rc<RefCell<Log>>,
+}
+
+impl ws::Handler for Recorder {
+    fn on_fragment(
+        &mut self,
+        _: &Frame,
+        index: usize,
+        accumulated_bytes: usize,
+        is_last: bool,
+    ) -> Result<()> {
+        self.log
+            .borrow_mut()
+            .fragments
+            .push((index, accumulated_bytes, is_last));
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        self.log.borrow_mut().messages.push(msg);
+        Ok(())
+    }
+
+    fn on_close(&mut self, code: CloseCode, _: &str) {
+        self.log.borrow_mut().closed.push(code);
+    }
+
+    fn on_error(&mut self, _: ws::Error) {}
+}
+
+fn exchange<F>(large_messages: LargeMessages, send: F) -> Rc<RefCell<Log>>
+where
+    F: FnOnce(&Sender),
+{
+    let log = Rc::new(RefCell::new(Log::default()));
+    let senders = Rc::new(RefCell::new(Vec::new()));
+    let mut virt = {
+        let log = log.clone();
+        let senders = senders.clone();
+        VirtualLoop::with_settings(
+            move |out: Sender| {
+                senders.borrow_mut().push(out);
+                Recorder { log: log.clone() }
+            },
+            Settings {
+                max_message_size: 8,
+                large_messages,
+                ..Settings::default()
+            },
+        )
+    };
+    virt.connect("ws://example.com/").unwrap();
+
+    let client = senders.borrow()[0].clone();
+    send(&client);
+    virt.run_until_idle();
+    log
+}
+
+#[test]
+fn reject_large_messages() {
+    let log = exchange(LargeMessages::Reject, |out| {
+        out.send("small").unwrap();
+        out.send("too large to accept").unwrap();
+    });
+    let log = log.borrow();
+    assert_eq!(log.messages, vec![Message::text("small")]);
+    assert_eq!(log.closed.first(), Some(&CloseCode::Size));
+}
+
+#[test]
+fn reject_large_fragmented_messages() {
+    let log = exchange(LargeMessages::Reject, |out| {
+        out.send_with_fragment_size("too large to accept", 4).unwrap();
+    });
+    let log = log.borrow();
+    assert_eq!(log.fragments, vec![(0, 4, false), (1, 8, false)]);
+    assert!(log.messages.is_empty());
+    assert_eq!(log.closed.first(), Some(&CloseCode::Size));
+}
+
+#[test]
+fn stream_large_messages() {
+    let log = exchange(LargeMessages::Stream, |out| {
+        out.send_with_fragment_size("fragmented message", 6).unwrap();
+        out.send("unfragmented message").unwrap();
+        out.send("small").unwrap();
+    });
+    let log = log.borrow();
+    assert_eq!(
+        log.fragments,
+        vec![(0, 6, false), (1, 12, false), (2, 18, true), (0, 20, true)]
+    );
+    assert_eq!(log.messages, vec![Message::text("small")]);
+    assert!(log.closed.is_empty());
+}
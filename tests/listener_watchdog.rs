@@ -0,0 +1,118 @@
+#![cfg(target_os = "linux")]
+extern crate libc;
+extern crate net2;
+extern crate ws;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use net2::TcpBuilder;
+use ws::{Builder, Error, Sender, Settings};
+
+struct Factory {
+    events: mpsc::Sender<String>,
+}
+
+impl ws::Factory for Factory {
+    type Handler = Handler;
+
+    fn connection_made(&mut self, out: Sender) -> Handler {
+        Handler { out }
+    }
+
+    fn on_listener_error(&mut self, err: Error) {
+        self.events.send(err.to_string()).unwrap();
+    }
+}
+
+struct Handler {
+    out: Sender,
+}
+
+impl ws::Handler for Handler {
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        self.out.send(msg)
+    }
+}
+
+fn set_open_files_limit(limit: libc::rlim_t) {
+    let mut rlimit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    unsafe {
+        assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlimit), 0);
+        rlimit.rlim_cur = limit;
+        assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &rlimit), 0);
+    }
+}
+
+fn open_files_limit() -> libc::rlim_t {
+    let mut rlimit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    unsafe {
+        assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlimit), 0);
+    }
+    rlimit.rlim_cur
+}
+
+// The listener stops accepting while the process is out of file descriptors, is closed by the
+// watchdog, and is bound again once descriptors are available.
+#[test]
+fn listener_is_bound_again() {
+    let (tx, events) = mpsc::channel();
+    let server = Builder::new()
+        .with_settings(Settings {
+            max_accept_failures: 3,
+            listener_rebind_delay_ms: 200,
+            timer_tick_ms: 10,
+            ..Settings::default()
+        })
+        .spawn_local(move || Factory { events: tx.clone() })
+        .unwrap();
+    let addr = server.addr();
+
+    // the socket is created while descriptors are available and connects once they are not
+    let socket = TcpBuilder::new_v4().unwrap();
+    let limit = open_files_limit();
+    let open = fs::read_dir("/proc/self/fd").unwrap().count();
+    // reading the directory used a descriptor, so no more can be opened at this limit
+    set_open_files_limit(open as libc::rlim_t - 1);
+    let stalled = socket.connect(addr);
+
+    let err = events.recv_timeout(Duration::from_secs(5)).unwrap();
+    set_open_files_limit(limit);
+    assert!(err.contains("failed to accept 3 connections in a row"), "{}", err);
+    drop(stalled);
+
+    // connections are refused until the listener is bound again
+    let mut stream = None;
+    for _ in 0..50 {
+        if let Ok(s) = TcpStream::connect(addr) {
+            stream = Some(s);
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let mut stream = stream.expect("the listener was not bound again");
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = [0; 12];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(&response, b"HTTP/1.1 101");
+
+    server.stop().unwrap();
+}
@@ -0,0 +1,174 @@
+#[cfg(feature = "ssl")]
+extern crate openssl;
+extern crate ws;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+
+use ws::util::Token;
+use ws::{ListenerTls, WebSocket};
+
+// Sends a handshake request over `stream` and returns the status line of the response.
+fn handshake<S: Read + Write>(stream: &mut S, addr: SocketAddr) -> String {
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Connection: Upgrade\r\n\
+         Upgrade: websocket\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        addr
+    )
+    .unwrap();
+    let mut response = [0; 12];
+    stream.read_exact(&mut response).unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[test]
+fn connections_report_their_listener() {
+    let (tx, listeners) = mpsc::channel();
+    let ws = WebSocket::new(move |out: ws::Sender| {
+        tx.send(out.listener()).unwrap();
+        |_| Ok(())
+    })
+    .unwrap();
+    let mut ws = ws.bind("127.0.0.1:0").unwrap();
+    let token = ws.add_listener("127.0.0.1:0", ListenerTls::Plain).unwrap();
+    let primary = ws.local_addr().unwrap();
+    let added = ws.listener_addr(token).unwrap();
+    assert_ne!(primary, added);
+    assert_eq!(
+        ws.listener_addr(Token(0)).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+
+    let broadcaster = ws.broadcaster();
+    let server = thread::spawn(move || ws.run().unwrap());
+
+    let mut stream = TcpStream::connect(primary).unwrap();
+    assert_eq!(handshake(&mut stream, primary), "HTTP/1.1 101");
+    assert_eq!(listeners.recv().unwrap(), None);
+
+    let mut stream = TcpStream::connect(added).unwrap();
+    assert_eq!(handshake(&mut stream, added), "HTTP/1.1 101");
+    assert_eq!(listeners.recv().unwrap(), Some(token));
+
+    broadcaster.shutdown().unwrap();
+    server.join().unwrap();
+}
+
+#[cfg(feature = "ssl")]
+mod tls {
+    use std::net::TcpStream as StdTcpStream;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslAcceptor, SslConnector, SslMethod, SslStream, SslVerifyMode};
+    use openssl::x509::{X509Builder, X509NameBuilder};
+    use ws;
+    use ws::util::{TcpStream, Token};
+    use ws::{ListenerTls, WebSocket};
+
+    use super::handshake;
+
+    fn acceptor() -> SslAcceptor {
+        let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut cert = X509Builder::new().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&pkey).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        cert.set_serial_number(&serial).unwrap();
+        cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let cert = cert.build();
+
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        builder.set_private_key(&pkey).unwrap();
+        builder.set_certificate(&cert).unwrap();
+        builder.build()
+    }
+
+    // Reports the listener of each connection that it upgrades.
+    struct Handler {
+        out: ws::Sender,
+        ssl: Rc<SslAcceptor>,
+        upgraded: mpsc::Sender<Option<Token>>,
+    }
+
+    impl ws::Handler for Handler {
+        fn upgrade_ssl_server(&mut self, sock: TcpStream) -> ws::Result<SslStream<TcpStream>> {
+            self.upgraded.send(self.out.listener()).unwrap();
+            self.ssl.accept(sock).map_err(From::from)
+        }
+    }
+
+    #[test]
+    fn plaintext_and_tls_listeners() {
+        let (tx, upgraded) = mpsc::channel();
+        let (ready_tx, ready) = mpsc::channel();
+        // the acceptor is not Send, so the WebSocket is built on the thread that runs it
+        let server = thread::spawn(move || {
+            let ssl = Rc::new(acceptor());
+            let ws = WebSocket::new(move |out| Handler {
+                out,
+                ssl: ssl.clone(),
+                upgraded: tx.clone(),
+            })
+            .unwrap();
+            let mut ws = ws.bind("127.0.0.1:0").unwrap();
+            let token = ws
+                .add_listener("127.0.0.1:0", ListenerTls::Encrypted)
+                .unwrap();
+            ready_tx
+                .send((
+                    ws.local_addr().unwrap(),
+                    ws.listener_addr(token).unwrap(),
+                    token,
+                    ws.broadcaster(),
+                ))
+                .unwrap();
+            ws.run().unwrap();
+        });
+        let (plain, encrypted, token, broadcaster) = ready.recv().unwrap();
+
+        let mut stream = StdTcpStream::connect(plain).unwrap();
+        assert_eq!(handshake(&mut stream, plain), "HTTP/1.1 101");
+
+        let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+        builder.set_verify(SslVerifyMode::empty());
+        let mut stream = builder
+            .build()
+            .configure()
+            .unwrap()
+            .verify_hostname(false)
+            .connect("localhost", StdTcpStream::connect(encrypted).unwrap())
+            .unwrap();
+        assert_eq!(handshake(&mut stream, encrypted), "HTTP/1.1 101");
+
+        // only the connection of the TLS listener was upgraded
+        assert_eq!(upgraded.recv().unwrap(), Some(token));
+        assert!(upgraded.try_recv().is_err());
+
+        broadcaster.shutdown().unwrap();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+#![cfg(feature = "testing")]
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::testing::VirtualLoop;
+use ws::{Message, MessageInfo, Result, Sender, Settings};
+
+type Log = Rc<RefCell<Vec<(Message, Option<MessageInfo>)>>>;
+
+struct Recorder {
+    log: Log,
+}
+
+impl ws::Handler for Recorder {
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        self.log.borrow_mut().push((msg, None));
+        Ok(())
+    }
+
+    fn on_message_with_meta(&mut self, msg: Message, info: MessageInfo) -> Result<()> {
+        self.log.borrow_mut().push((msg, Some(info)));
+        Ok(())
+    }
+}
+
+fn exchange(settings: Settings) -> Log {
+    let log = Log::default();
+    let senders = Rc::new(RefCell::new(Vec::new()));
+    let mut virt = {
+        let log = log.clone();
+        let senders = senders.clone();
+        VirtualLoop::with_settings(
+            move |out: Sender| {
+                senders.borrow_mut().push(out);
+                Recorder { log: log.clone() }
+            },
+            settings,
+        )
+    };
+    virt.connect("ws://example.com/").unwrap();
+
+    let client = senders.borrow()[0].clone();
+    client.send("one").unwrap();
+    client.send(vec![2u8]).unwrap();
+    client.send("three").unwrap();
+    virt.run_until_idle();
+    log
+}
+
+#[test]
+fn message_info() {
+    let log = exchange(Settings {
+        message_info: true,
+        ..Settings::default()
+    });
+    let log = log.borrow();
+    assert_eq!(log.len(), 3);
+
+    let infos = log
+        .iter()
+        .map(|&(_, info)| info.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        infos.iter().map(|info| info.sequence).collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+    assert!(infos[0].received <= infos[1].received);
+    assert!(infos[1].received <= infos[2].received);
+
+    assert_eq!(log[0].0, Message::text("one"));
+    assert_eq!(log[1].0, Message::binary(vec![2u8]));
+    assert_eq!(log[2].0, Message::text("three"));
+}
+
+#[test]
+fn message_info_disabled() {
+    let log = exchange(Settings::default());
+    let log = log.borrow();
+    assert_eq!(log.len(), 3);
+    assert!(log.iter().all(|&(_, ref info)| info.is_none()));
+}
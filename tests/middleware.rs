@@ -0,0 +1,98 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Log {
+    events: Mutex<Vec<String>>,
+}
+
+struct Logger(Arc<Log>);
+
+impl ws::Middleware for Logger {
+    fn on_open(&self, out: &ws::Sender, _: &ws::Handshake) -> ws::Result<()> {
+        let mut events = self.0.events.lock().unwrap();
+        events.push(format!("open {}", out.connection_id()));
+        Ok(())
+    }
+
+    fn on_message(&self, out: &ws::Sender, msg: &ws::Message) -> ws::Result<bool> {
+        let mut events = self.0.events.lock().unwrap();
+        events.push(format!("message {} {}", out.connection_id(), msg));
+        Ok(true)
+    }
+
+    fn on_close(&self, out: &ws::Sender, code: ws::CloseCode, _: &str) {
+        let mut events = self.0.events.lock().unwrap();
+        events.push(format!("close {} {:?}", out.connection_id(), code));
+    }
+}
+
+// Drop any message that mentions a secret
+struct Censor;
+
+impl ws::Middleware for Censor {
+    fn on_message(&self, _: &ws::Sender, msg: &ws::Message) -> ws::Result<bool> {
+        Ok(!msg.as_text()?.contains("secret"))
+    }
+}
+
+struct Handler {
+    out: ws::Sender,
+    received: Rc<RefCell<Vec<String>>>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // The first connection is the client
+        if self.out.connection_id() == 0 {
+            self.out.send("hello")?;
+            self.out.send("a secret")?;
+            self.out.send("goodbye")?;
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        let text = msg.into_text()?;
+        self.received.borrow_mut().push(text.clone());
+        if text == "goodbye" {
+            self.out.close(ws::CloseCode::Normal)?;
+        }
+        Ok(())
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        self.out.shutdown().unwrap();
+    }
+}
+
+#[test]
+fn middleware_chain() {
+    let log = Arc::new(Log::default());
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let handler_received = received.clone();
+
+    let mut ws = ws::Builder::new()
+        .with_middleware(vec![Box::new(Logger(log.clone())), Box::new(Censor)])
+        .build(move |out| Handler {
+            out,
+            received: handler_received.clone(),
+        })
+        .unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3052").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3052").unwrap();
+
+    assert_eq!(*received.borrow(), vec!["hello", "goodbye"]);
+
+    let events = log.events.lock().unwrap();
+    assert!(events.contains(&"open 0".to_owned()));
+    assert!(events.contains(&"open 1".to_owned()));
+    assert!(events.contains(&"message 1 a secret".to_owned()));
+    assert!(events.contains(&"close 0 Normal".to_owned()));
+}
@@ -0,0 +1,41 @@
+extern crate ws;
+
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn pause_and_resume_accepting() {
+    let (broadcaster, handle) = ws::Builder::new()
+        .spawn("127.0.0.1:3073", || {
+            |out: ws::Sender| move |msg: ws::Message| out.send(msg)
+        })
+        .unwrap();
+
+    broadcaster.pause_accepting().unwrap();
+    // the tokens request is answered after the pause has been applied
+    assert!(broadcaster.tokens().unwrap().is_empty());
+
+    let (tx, rx) = channel();
+    let client = thread::spawn(move || {
+        ws::connect("ws://127.0.0.1:3073", |out| {
+            out.send("Hello").unwrap();
+            let tx = tx.clone();
+            move |msg: ws::Message| {
+                tx.send(msg.into_text()?).unwrap();
+                out.close(ws::CloseCode::Normal)
+            }
+        })
+        .unwrap();
+    });
+
+    assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+    assert!(broadcaster.tokens().unwrap().is_empty());
+
+    broadcaster.resume_accepting().unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), "Hello");
+
+    client.join().unwrap();
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+}
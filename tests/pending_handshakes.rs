@@ -0,0 +1,82 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use ws::{Builder, ErrorKind, Settings};
+
+struct Handler {
+    errors: mpsc::Sender<String>,
+}
+
+impl ws::Handler for Handler {
+    fn on_error(&mut self, err: ws::Error) {
+        self.errors.send(format!("{:?}", err.kind)).unwrap();
+    }
+}
+
+fn connect(server: &ws::LocalServer) -> TcpStream {
+    let stream = TcpStream::connect(server.addr()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+// Read until the server closes the connection, returning what it sent.
+fn read_all(mut stream: TcpStream) -> Vec<u8> {
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn reap_stalled_handshakes() {
+    let (tx, rx) = mpsc::channel();
+    let server = Builder::new()
+        .with_settings(Settings {
+            max_pending_handshakes: 1,
+            handshake_timeout_ms: 200,
+            ..Settings::default()
+        })
+        .spawn_local(move || {
+            move |_| Handler {
+                errors: tx.clone(),
+            }
+        })
+        .unwrap();
+
+    let start = Instant::now();
+    let mut stalled = connect(&server);
+    stalled.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+
+    // the only handshake slot is taken, so the next peer is turned away immediately
+    let refused = connect(&server);
+    assert!(read_all(refused).is_empty());
+    assert!(start.elapsed() < Duration::from_millis(200));
+
+    assert!(read_all(stalled).is_empty());
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+        format!("{:?}", ErrorKind::HandshakeTimeout)
+    );
+
+    let mut client = connect(&server);
+    client
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        )
+        .unwrap();
+    let mut buf = [0; 1024];
+    let read = client.read(&mut buf).unwrap();
+    assert!(buf[..read].starts_with(b"HTTP/1.1 101"));
+
+    server.stop().unwrap();
+}
@@ -0,0 +1,57 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+// Open one connection to a server that allows a single connection per address, then return the
+// response to a second connection.
+fn reject_second(settings: ws::Settings, port: u16) -> String {
+    let addr = ("127.0.0.1", port);
+    let (broadcaster, handle) = ws::Builder::new()
+        .with_settings(ws::Settings {
+            max_connections_per_ip: 1,
+            ..settings
+        })
+        .spawn(addr, || |_| |_| Ok(()))
+        .unwrap();
+
+    // Complete the handshake of the first connection so that it is open at shutdown
+    let mut first = TcpStream::connect(addr).unwrap();
+    first
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        )
+        .unwrap();
+    let mut buf = [0; 1024];
+    let read = first.read(&mut buf).unwrap();
+    assert!(buf[..read].starts_with(b"HTTP/1.1 101"));
+
+    let mut second = TcpStream::connect(addr).unwrap();
+
+    let mut response = String::new();
+    second.read_to_string(&mut response).unwrap();
+
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+    response
+}
+
+#[test]
+fn max_connections_per_ip() {
+    let response = reject_second(ws::Settings::default(), 3033);
+    assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+}
+
+#[test]
+fn max_connections_per_ip_tls() {
+    // a client that may expect TLS is not sent a plaintext response
+    let settings = ws::Settings {
+        tls_auto_detect: true,
+        ..ws::Settings::default()
+    };
+    assert_eq!(reject_second(settings, 3090), "");
+}
@@ -0,0 +1,73 @@
+extern crate ws;
+
+use std::sync::mpsc::{self, channel};
+use std::thread;
+use std::time::Duration;
+
+use ws::{Builder, Handshake, Message, PersistentBroadcaster, Result};
+
+struct Server {
+    opened: mpsc::Sender<()>,
+}
+
+impl ws::Handler for Server {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.opened.send(()).unwrap();
+        Ok(())
+    }
+}
+
+// Run a server on the given port with the builder, connect a client to it and broadcast a
+// message to the client through the persistent broadcaster.
+fn serve(builder: &Builder, broadcaster: &PersistentBroadcaster, port: u16) {
+    let (open_tx, open_rx) = channel();
+    let ws = builder
+        .build(move |_| Server {
+            opened: open_tx.clone(),
+        })
+        .unwrap()
+        .bind(("127.0.0.1", port))
+        .unwrap();
+    assert!(broadcaster.is_attached());
+    let server = thread::spawn(move || ws.run().map(|_| ()));
+
+    let (msg_tx, msg_rx) = channel();
+    let client = thread::spawn(move || {
+        ws::connect(format!("ws://127.0.0.1:{}", port), |out| {
+            let msg_tx = msg_tx.clone();
+            move |msg: Message| {
+                msg_tx.send(msg.into_text()?).unwrap();
+                out.close(ws::CloseCode::Normal)
+            }
+        })
+        .unwrap();
+    });
+
+    open_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    broadcaster.send("hello").unwrap();
+    assert_eq!(
+        msg_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+        "hello"
+    );
+    client.join().unwrap();
+
+    broadcaster.shutdown().unwrap();
+    assert!(server.join().unwrap().is_ok());
+}
+
+#[test]
+fn persistent_broadcaster() {
+    let broadcaster = PersistentBroadcaster::new();
+    assert!(broadcaster.send("lost").is_err());
+
+    let mut builder = Builder::new();
+    builder.with_persistent_broadcaster(broadcaster.clone());
+
+    serve(&builder, &broadcaster, 3080);
+    assert!(!broadcaster.is_attached());
+    assert!(broadcaster.send("lost").is_err());
+
+    // the same broadcaster reaches the replacement WebSocket
+    serve(&builder, &broadcaster, 3081);
+    assert!(!broadcaster.is_attached());
+}
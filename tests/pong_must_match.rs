@@ -0,0 +1,137 @@
+#![cfg(feature = "testing")]
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::testing::VirtualLoop;
+use ws::{Frame, Handshake, OpCode, Result, Sender, Settings};
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Client {
+    Echo,
+    Unsolicited,
+    Mismatched,
+}
+
+// Servers ping twice when a connection opens unless the client sends an unsolicited pong, clients
+// answer according to their mode.
+struct Peer {
+    out: Sender,
+    log: Log,
+    client: Option<Client>,
+    ping: bool,
+}
+
+impl ws::Handler for Peer {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        match self.client {
+            None if self.ping => {
+                self.out.ping(b"one".to_vec())?;
+                self.out.ping(b"two".to_vec())
+            }
+            None => Ok(()),
+            Some(Client::Unsolicited) => self.out.pong(b"surprise".to_vec()),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn on_frame(&mut self, mut frame: Frame) -> Result<Option<Frame>> {
+        if frame.opcode() == OpCode::Ping && self.client == Some(Client::Mismatched) {
+            // the automatic pong echoes the altered payload
+            *frame.payload_mut() = b"three".to_vec();
+        }
+        if frame.opcode() == OpCode::Pong {
+            let payload = String::from_utf8(frame.payload().clone()).unwrap();
+            self.log.borrow_mut().push(payload);
+        }
+        Ok(Some(frame))
+    }
+
+    fn on_error(&mut self, err: ws::Error) {
+        self.log.borrow_mut().push(err.details.into_owned());
+    }
+}
+
+struct Factory {
+    log: Log,
+    mode: Client,
+}
+
+impl ws::Factory for Factory {
+    type Handler = Peer;
+
+    fn connection_made(&mut self, out: Sender) -> Peer {
+        Peer {
+            out,
+            log: self.log.clone(),
+            client: None,
+            ping: self.mode != Client::Unsolicited,
+        }
+    }
+
+    fn client_connected(&mut self, out: Sender) -> Peer {
+        Peer {
+            out,
+            log: self.log.clone(),
+            client: Some(self.mode),
+            ping: false,
+        }
+    }
+}
+
+// Returns the log and the number of connections that are still open.
+fn run(mode: Client) -> (Vec<String>, usize) {
+    let log = Log::default();
+    let mut virt = VirtualLoop::with_settings(
+        Factory {
+            log: log.clone(),
+            mode,
+        },
+        Settings {
+            pong_must_match: true,
+            ..Settings::default()
+        },
+    );
+    virt.connect("ws://example.com/").unwrap();
+    let open = virt.tokens().len();
+    let log = log.borrow().clone();
+    (log, open)
+}
+
+#[test]
+fn matching_pongs() {
+    let (log, open) = run(Client::Echo);
+    assert_eq!(log, vec!["one".to_string(), "two".to_string()]);
+    assert_eq!(open, 2);
+}
+
+#[test]
+fn unsolicited_pong() {
+    let (log, open) = run(Client::Unsolicited);
+    assert_eq!(
+        log,
+        vec![
+            "surprise".to_string(),
+            "Received a pong without having sent a ping.".to_string(),
+        ]
+    );
+    assert_eq!(open, 0);
+}
+
+#[test]
+fn mismatched_pong() {
+    let (log, open) = run(Client::Mismatched);
+    assert_eq!(
+        log,
+        vec![
+            "three".to_string(),
+            "Received a pong that does not match the payload of an outstanding ping.".to_string(),
+            // the second pong arrives after the connection failed
+            "three".to_string(),
+        ]
+    );
+    assert_eq!(open, 0);
+}
@@ -0,0 +1,44 @@
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::{Builder, CloseCode, Message, Sender, Settings};
+
+// Each preset must be usable as is, including for a message that spans many frames and buffers.
+#[test]
+fn presets_echo() {
+    let presets = vec![
+        Settings::for_low_latency(),
+        Settings::for_high_throughput(),
+        Settings::for_constrained_memory(),
+    ];
+    let payload = vec![7u8; 100_000];
+
+    for settings in presets {
+        let server = Builder::new()
+            .with_settings(settings)
+            .spawn_local(|| |out: Sender| move |msg| out.send(msg))
+            .unwrap();
+
+        let received = Rc::new(RefCell::new(None));
+        let client_received = received.clone();
+        let sent = payload.clone();
+        let mut ws = Builder::new()
+            .with_settings(settings)
+            .build(move |out: Sender| {
+                out.send(sent.clone()).unwrap();
+                let received = client_received.clone();
+                move |msg: Message| {
+                    *received.borrow_mut() = Some(msg.into_data());
+                    out.close(CloseCode::Normal)
+                }
+            })
+            .unwrap();
+        ws.connect(server.url()).unwrap();
+        ws.run().unwrap();
+        assert_eq!(*received.borrow(), Some(payload.clone()));
+
+        server.stop().unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+extern crate url;
+extern crate ws;
+
+use std::thread;
+
+use ws::{CloseCode, Handler, Handshake, ProducerStats, Result, Sender};
+
+struct Peer {
+    out: Sender,
+}
+
+impl Handler for Peer {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        if self.out.connection_id() != 0 {
+            return Ok(());
+        }
+        let ticker = self.out.register_producer("ticker");
+        let spare = ticker.clone();
+        thread::spawn(move || {
+            for _ in 0..3 {
+                ticker.send("tick").unwrap();
+            }
+        }).join()
+            .unwrap();
+
+        let stats = self.out.producer_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].senders, 1);
+        assert_eq!(spare.producer(), Some("ticker"));
+        assert_eq!(self.out.producer(), None);
+
+        self.out.register_producer("closer").close(CloseCode::Normal)
+    }
+
+    fn on_close(&mut self, _: CloseCode, _: &str) {
+        self.out.shutdown().unwrap();
+    }
+}
+
+#[test]
+fn producer_statistics() {
+    let mut ws = ws::WebSocket::new(|out| Peer { out }).unwrap();
+    ws.connect(url::Url::parse("ws://127.0.0.1:3061").unwrap())
+        .unwrap();
+    let ws = ws.listen("127.0.0.1:3061").unwrap();
+
+    assert_eq!(
+        ws.producer_stats(),
+        vec![
+            ProducerStats {
+                name: "ticker".into(),
+                senders: 0,
+                enqueued: 3,
+                failed: 0,
+            },
+            ProducerStats {
+                name: "closer".into(),
+                senders: 0,
+                enqueued: 1,
+                failed: 0,
+            },
+        ]
+    );
+}
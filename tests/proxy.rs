@@ -0,0 +1,185 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::rc::Rc;
+use std::thread;
+
+use ws::{Builder, CloseCode, ErrorKind, Handler, Handshake, Message, Proxy, ProxyAuth, Result,
+         Sender};
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+struct Client {
+    out: Sender,
+    log: Log,
+}
+
+impl Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.out.send("through the tunnel")
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        self.log.borrow_mut().push(msg.into_text()?);
+        self.out.close(CloseCode::Normal)
+    }
+
+    fn on_error(&mut self, err: ws::Error) {
+        self.log.borrow_mut().push(format!("{:?}", err.kind));
+    }
+}
+
+fn echo_server(port: u16) -> Sender {
+    let (broadcaster, _) = Builder::new()
+        .spawn(("127.0.0.1", port), || |out: Sender| move |msg| out.send(msg))
+        .unwrap();
+    broadcaster
+}
+
+// A proxy that challenges every request without the expected credentials. When `close` is set,
+// challenges are answered by closing the connection.
+fn proxy(port: u16, expected: &'static str, close: bool) -> thread::JoinHandle<Vec<String>> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+    thread::spawn(move || {
+        let mut requests = Vec::new();
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut head = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                        break;
+                    }
+                    head.push(line.trim().to_string());
+                }
+                if head.is_empty() {
+                    break;
+                }
+                requests.push(head.join("\n"));
+
+                let mut stream = reader.get_ref().try_clone().unwrap();
+                let authorization = format!("Proxy-Authorization: {}", expected);
+                if head.contains(&authorization) {
+                    let target = head[0].split(' ').nth(1).unwrap().to_string();
+                    let upstream = TcpStream::connect(target).unwrap();
+                    stream
+                        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                        .unwrap();
+                    let mut down = upstream.try_clone().unwrap();
+                    let mut up = stream.try_clone().unwrap();
+                    let pipe = thread::spawn(move || {
+                        let _ = io::copy(&mut down, &mut up);
+                        let _ = up.shutdown(Shutdown::Write);
+                    });
+                    let mut upstream = upstream;
+                    let _ = io::copy(&mut reader, &mut upstream);
+                    let _ = upstream.shutdown(Shutdown::Write);
+                    let _ = pipe.join();
+                    return requests;
+                }
+
+                let challenge = "HTTP/1.1 407 Proxy Authentication Required\r\n\
+                                 Proxy-Authenticate: Negotiate\r\n\
+                                 Proxy-Authenticate: Basic realm=\"test\"\r\n\
+                                 Proxy-Authenticate: Bearer realm=\"test\"\r\n";
+                if close {
+                    write!(stream, "{}Connection: close\r\n\r\ndenied", challenge).unwrap();
+                    break;
+                }
+                write!(stream, "{}Content-Length: 6\r\n\r\ndenied", challenge).unwrap();
+                if requests.len() > 1 {
+                    return requests;
+                }
+            }
+        }
+        requests
+    })
+}
+
+fn connect(proxy: u16, server: u16, auth: ProxyAuth) -> Vec<String> {
+    let log = Log::default();
+    let mut ws = Builder::new()
+        .with_proxy(Proxy::new("127.0.0.1", proxy))
+        .with_proxy_auth(auth)
+        .build(|out| Client {
+            out,
+            log: log.clone(),
+        })
+        .unwrap();
+    let url = url::Url::parse(&format!("ws://127.0.0.1:{}", server)).unwrap();
+    ws.connect(url).unwrap();
+    ws.run().unwrap();
+    let log = log.borrow().clone();
+    log
+}
+
+#[test]
+fn basic_auth_after_reconnect() {
+    let server = echo_server(3063);
+    let proxy = proxy(3062, "Basic dXNlcjpzZWNyZXQ=", true);
+
+    let log = connect(
+        3062,
+        3063,
+        ProxyAuth::Basic {
+            username: "user".into(),
+            password: "secret".into(),
+        },
+    );
+    assert_eq!(log, vec!["through the tunnel".to_string()]);
+
+    let requests = proxy.join().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0].starts_with("CONNECT 127.0.0.1:3063 HTTP/1.1"));
+    assert!(!requests[0].contains("Proxy-Authorization"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn bearer_auth_on_same_connection() {
+    let server = echo_server(3065);
+    let proxy = proxy(3064, "Bearer token", false);
+
+    let log = connect(3064, 3065, ProxyAuth::Bearer("token".into()));
+    assert_eq!(log, vec!["through the tunnel".to_string()]);
+    assert_eq!(proxy.join().unwrap().len(), 2);
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn rejected_credentials() {
+    let proxy = proxy(3066, "Bearer token", false);
+
+    let log = connect(3066, 3067, ProxyAuth::Bearer("expired".into()));
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0], format!("{:?}", ErrorKind::ProxyAuthentication));
+    assert_eq!(proxy.join().unwrap().len(), 2);
+}
+
+#[test]
+fn given_proxy_overrides_system_proxy() {
+    let server = echo_server(3099);
+    let proxy = proxy(3098, "Bearer token", false);
+
+    let log = Log::default();
+    let mut ws = Builder::new()
+        .use_system_proxy(true)
+        .with_proxy(Proxy::new("127.0.0.1", 3098))
+        .with_proxy_auth(ProxyAuth::Bearer("token".into()))
+        .build(|out| Client {
+            out,
+            log: log.clone(),
+        })
+        .unwrap();
+    ws.connect(url::Url::parse("ws://127.0.0.1:3099").unwrap())
+        .unwrap();
+    ws.run().unwrap();
+    assert_eq!(*log.borrow(), vec!["through the tunnel".to_string()]);
+    assert_eq!(proxy.join().unwrap().len(), 2);
+    server.shutdown().unwrap();
+}
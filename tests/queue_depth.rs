@@ -0,0 +1,15 @@
+extern crate ws;
+
+use ws::Builder;
+
+#[test]
+fn depth_counts_all_senders() {
+    let ws = Builder::new().build(|_| |_| Ok(())).unwrap();
+    let broadcaster = ws.broadcaster();
+    let other = ws.broadcaster();
+
+    assert_eq!(broadcaster.send_with_feedback("one").unwrap(), 1);
+    assert_eq!(other.send_with_feedback("two").unwrap(), 2);
+    broadcaster.ping(Vec::new()).unwrap();
+    assert_eq!(other.send_with_feedback("four").unwrap(), 4);
+}
@@ -0,0 +1,71 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+
+struct Handler {
+    errors: mpsc::Sender<String>,
+}
+
+impl ws::Handler for Handler {
+    fn on_error(&mut self, err: ws::Error) {
+        self.errors.send(err.details.into_owned()).unwrap();
+    }
+}
+
+// Send a handshake request with the given key and return the status line of the response.
+fn handshake(port: u16, strict: bool, key: &str) -> (String, Vec<String>) {
+    let (tx, rx) = mpsc::channel();
+    let addr = format!("127.0.0.1:{}", port);
+    let (broadcaster, handle) = ws::Builder::new()
+        .with_settings(ws::Settings {
+            request_key_strict: strict,
+            ..ws::Settings::default()
+        })
+        .spawn(addr.clone(), move || {
+            let tx = tx.clone();
+            move |_| Handler { errors: tx.clone() }
+        })
+        .unwrap();
+
+    let mut stream = TcpStream::connect(&*addr).unwrap();
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\n\
+         Connection: Upgrade\r\n\
+         Upgrade: websocket\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         Sec-WebSocket-Key: {}\r\n\r\n",
+        key
+    ).unwrap();
+    let mut buf = [0; 1024];
+    let read = stream.read(&mut buf).unwrap();
+    let response = String::from_utf8_lossy(&buf[..read]).into_owned();
+    drop(stream);
+
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+    let status = response.lines().next().unwrap_or("").to_owned();
+    (status, rx.try_iter().collect())
+}
+
+#[test]
+fn malformed_key_rejected() {
+    let (status, errors) = handshake(3049, true, "not-a-key");
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    assert_eq!(errors, vec!["Received malformed WebSocket key: not-a-key"]);
+}
+
+#[test]
+fn malformed_key_accepted_by_default() {
+    let (status, _) = handshake(3050, false, "not-a-key");
+    assert_eq!(status, "HTTP/1.1 101 Switching Protocols");
+}
+
+#[test]
+fn valid_key_accepted() {
+    let (status, errors) = handshake(3051, true, "q16eN37NCfVwUChPvBdk4g==");
+    assert_eq!(status, "HTTP/1.1 101 Switching Protocols");
+    assert!(errors.is_empty());
+}
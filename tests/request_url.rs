@@ -0,0 +1,58 @@
+extern crate ws;
+
+use std::sync::mpsc;
+
+use ws::{Builder, CloseCode, Handshake, Request, Response, Result, Sender};
+
+struct Gateway {
+    requests: mpsc::Sender<(String, String)>,
+}
+
+impl ws::Handler for Gateway {
+    fn on_request(&mut self, req: &Request) -> Result<Response> {
+        let host = String::from_utf8_lossy(req.header("host").unwrap()).into_owned();
+        self.requests
+            .send((host, req.resource().to_owned()))
+            .unwrap();
+        Response::from_request(req)
+    }
+}
+
+struct Client {
+    out: Sender,
+}
+
+impl ws::Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.out.close(CloseCode::Normal)
+    }
+}
+
+// The server sees the Host header and resource as a gateway that compares them byte for byte
+// would.
+#[test]
+fn host_and_resource() {
+    let (tx, requests) = mpsc::channel();
+    let server = Builder::new()
+        .spawn_local(move || {
+            let tx = tx.clone();
+            move |_| Gateway {
+                requests: tx.clone(),
+            }
+        })
+        .unwrap();
+    let port = server.addr().port();
+
+    let url = format!("ws://127.0.0.1:{}/a%2fb%7e?q=%7e%zz", port);
+    ws::connect(url, |out| Client { out }).unwrap();
+
+    assert_eq!(
+        requests.recv().unwrap(),
+        (
+            format!("127.0.0.1:{}", port),
+            "/a%2Fb~?q=~%25zz".to_owned()
+        )
+    );
+
+    server.stop().unwrap();
+}
@@ -0,0 +1,52 @@
+extern crate ws;
+
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use ws::{CloseCode, Message, Sender, WebSocket};
+
+// Send a message to the server and return its echo.
+fn echo(port: u16) -> String {
+    let (tx, rx) = channel();
+    ws::connect(format!("ws://127.0.0.1:{}", port), |out: Sender| {
+        out.send("Hello").unwrap();
+        let tx = tx.clone();
+        move |msg: Message| {
+            tx.send(msg.into_text()?).unwrap();
+            out.close(CloseCode::Normal)
+        }
+    })
+    .unwrap();
+    rx.recv_timeout(Duration::from_secs(5)).unwrap()
+}
+
+#[test]
+fn listen_twice() {
+    let ws = WebSocket::new(|out: Sender| move |msg| out.send(msg))
+        .unwrap()
+        .bind("127.0.0.1:3083")
+        .unwrap();
+    assert!(ws.bind("127.0.0.1:3084").is_err());
+}
+
+#[test]
+fn run_again_after_shutdown() {
+    let (tx, rx) = channel();
+    let server = thread::spawn(move || {
+        let mut ws = WebSocket::new(|out: Sender| move |msg| out.send(msg)).unwrap();
+        for _ in 0..2 {
+            // the listening socket is closed when the event loop stops
+            ws = ws.bind("127.0.0.1:3085").unwrap();
+            tx.send(ws.broadcaster()).unwrap();
+            ws.run_in_place().unwrap();
+        }
+    });
+
+    for _ in 0..2 {
+        let broadcaster = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(echo(3085), "Hello");
+        broadcaster.shutdown().unwrap();
+    }
+    server.join().unwrap();
+}
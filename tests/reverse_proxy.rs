@@ -0,0 +1,142 @@
+extern crate url;
+extern crate ws;
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use ws::proxy::ReverseProxy;
+use ws::{Builder, CloseCode, Handler, Handshake, Message, Result, Sender};
+
+type Close = (CloseCode, String);
+
+/// Uppercases text, or closes when it is told to, and reports the close that ends it.
+struct Upstream {
+    out: Sender,
+    closes: mpsc::Sender<Close>,
+}
+
+impl Handler for Upstream {
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        let text = msg.into_text()?;
+        if text == "kick" {
+            return self.out.close_with_reason(CloseCode::Policy, "kicked");
+        }
+        self.out.send(text.to_uppercase())
+    }
+
+    fn on_close(&mut self, code: CloseCode, reason: &str) {
+        self.closes.send((code, reason.into())).unwrap();
+    }
+}
+
+/// Sends its messages once open and closes with `close` after every reply has arrived.
+struct Client {
+    out: Sender,
+    send: Vec<&'static str>,
+    close: Option<CloseCode>,
+    received: Vec<String>,
+    done: mpsc::Sender<(Vec<String>, Close)>,
+}
+
+impl Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        for msg in &self.send {
+            self.out.send(*msg)?;
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        self.received.push(msg.into_text()?);
+        match self.close {
+            Some(code) if self.received.len() == self.send.len() => {
+                self.out.close_with_reason(code, "done")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn on_close(&mut self, code: CloseCode, reason: &str) {
+        let received = self.received.split_off(0);
+        self.done.send((received, (code, reason.into()))).unwrap();
+    }
+}
+
+fn run_client(
+    url: &url::Url,
+    send: Vec<&'static str>,
+    close: Option<CloseCode>,
+) -> (Vec<String>, Close) {
+    let (done, results) = mpsc::channel();
+    ws::connect(url.as_str(), move |out| Client {
+        out,
+        send: send.clone(),
+        close,
+        received: Vec::new(),
+        done: done.clone(),
+    })
+    .unwrap();
+    results.recv().unwrap()
+}
+
+#[test]
+fn relays_messages_and_close_codes() {
+    let (closes_tx, closes) = mpsc::channel();
+    let upstream = Builder::new()
+        .spawn_local(move || {
+            move |out| Upstream {
+                out,
+                closes: closes_tx.clone(),
+            }
+        })
+        .unwrap();
+    let upstream_url = upstream.url();
+    let proxy = Builder::new()
+        .spawn_local(move || ReverseProxy::new(upstream_url))
+        .unwrap();
+
+    // closed by the client
+    let (received, closed) = run_client(
+        &proxy.url(),
+        vec!["hello", "world"],
+        Some(CloseCode::Other(4000)),
+    );
+    assert_eq!(received, vec!["HELLO", "WORLD"]);
+    assert_eq!(closed.0, CloseCode::Other(4000));
+    assert_eq!(
+        closes.recv_timeout(Duration::from_secs(5)).unwrap(),
+        (CloseCode::Other(4000), "done".into())
+    );
+
+    // closed by the upstream server
+    let (received, closed) = run_client(&proxy.url(), vec!["kick"], None);
+    assert!(received.is_empty());
+    assert_eq!(closed, (CloseCode::Policy, "kicked".into()));
+    assert_eq!(
+        closes.recv_timeout(Duration::from_secs(5)).unwrap().0,
+        CloseCode::Policy
+    );
+
+    proxy.stop().unwrap();
+    upstream.stop().unwrap();
+}
+
+#[test]
+fn closes_when_upstream_is_unavailable() {
+    // a server that is stopped before the proxy connects to it
+    let upstream = Builder::new()
+        .spawn_local(|| |out: Sender| move |msg| out.send(msg))
+        .unwrap();
+    let upstream_url = upstream.url();
+    upstream.stop().unwrap();
+
+    let proxy = Builder::new()
+        .spawn_local(move || ReverseProxy::new(upstream_url))
+        .unwrap();
+
+    let (received, closed) = run_client(&proxy.url(), vec!["hello"], None);
+    assert!(received.is_empty());
+    assert_eq!(closed.0, CloseCode::Error);
+
+    proxy.stop().unwrap();
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "rustls")]
+extern crate rcgen;
+extern crate rustls;
+extern crate ws;
+
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, StreamOwned};
+
+const SIZE: usize = 1 << 18;
+
+fn configs() -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let cert = certified.cert.der().clone();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+        certified.signing_key.serialize_der(),
+    ));
+    let server = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], key)
+        .unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let client = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    (Arc::new(server), Arc::new(client))
+}
+
+fn write_frame<W: Write>(stream: &mut W, opcode: u8, payload: &[u8]) {
+    let mut frame = vec![0x80 | opcode];
+    if payload.len() < 126 {
+        frame.push(0x80 | payload.len() as u8);
+    } else {
+        frame.push(0x80 | 127);
+        frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    let mask = [1, 2, 3, 4];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    stream.write_all(&frame).unwrap();
+}
+
+// Read a frame, returning its first byte and payload.
+fn read_frame<R: Read>(stream: &mut R) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).unwrap();
+    let len = match head[1] {
+        126 => {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            u16::from_be_bytes(len) as usize
+        }
+        127 => {
+            let mut len = [0; 8];
+            stream.read_exact(&mut len).unwrap();
+            u64::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).unwrap();
+    (head[0], payload)
+}
+
+fn read_message<R: Read>(stream: &mut R) -> (u8, Vec<u8>) {
+    let (mut head, mut payload) = read_frame(stream);
+    let opcode = head & 0x0F;
+    while head & 0x80 == 0 {
+        let (next, more) = read_frame(stream);
+        head = next;
+        payload.extend(more);
+    }
+    (opcode, payload)
+}
+
+#[test]
+fn rustls_server_config() {
+    let (server_config, client_config) = configs();
+    let server = ws::Builder::new()
+        .with_rustls_server_config(server_config)
+        .spawn_local(|| |out: ws::Sender| move |msg| out.send(msg))
+        .unwrap();
+
+    let conn =
+        ClientConnection::new(client_config, ServerName::try_from("localhost").unwrap()).unwrap();
+    let mut stream = StreamOwned::new(conn, TcpStream::connect(server.addr()).unwrap());
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Host: localhost\r\n\
+              Upgrade: websocket\r\n\
+              Connection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+              Sec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .unwrap();
+
+    let mut response = Vec::new();
+    let mut byte = [0; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        response.push(byte[0]);
+    }
+    assert!(response.starts_with(b"HTTP/1.1 101"));
+
+    write_frame(&mut stream, 0x1, b"Hello");
+    assert_eq!(read_message(&mut stream), (0x1, b"Hello".to_vec()));
+
+    // large enough for the socket to block while it is written
+    write_frame(&mut stream, 0x2, &vec![7; SIZE]);
+    let (opcode, payload) = read_message(&mut stream);
+    assert_eq!(opcode, 0x2);
+    assert!(payload == vec![7; SIZE]);
+
+    server.stop().unwrap();
+}
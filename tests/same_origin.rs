@@ -0,0 +1,76 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+
+struct Handler {
+    errors: mpsc::Sender<String>,
+}
+
+impl ws::Handler for Handler {
+    fn on_error(&mut self, err: ws::Error) {
+        self.errors.send(err.details.into_owned()).unwrap();
+    }
+}
+
+// Send a handshake request with the given origin and return the status line of the response.
+fn handshake(port: u16, same_origin_only: bool, origin: &str) -> (String, Vec<String>) {
+    let (tx, rx) = mpsc::channel();
+    let addr = format!("127.0.0.1:{}", port);
+    let (broadcaster, handle) = ws::Builder::new()
+        .with_settings(ws::Settings {
+            same_origin_only,
+            ..ws::Settings::default()
+        })
+        .spawn(addr.clone(), move || {
+            let tx = tx.clone();
+            move |_| Handler { errors: tx.clone() }
+        })
+        .unwrap();
+
+    let mut stream = TcpStream::connect(&*addr).unwrap();
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Origin: {}\r\n\
+         Connection: Upgrade\r\n\
+         Upgrade: websocket\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        addr, origin
+    ).unwrap();
+    let mut buf = [0; 1024];
+    let read = stream.read(&mut buf).unwrap();
+    let response = String::from_utf8_lossy(&buf[..read]).into_owned();
+    drop(stream);
+
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+    let status = response.lines().next().unwrap_or("").to_owned();
+    (status, rx.try_iter().collect())
+}
+
+#[test]
+fn cross_origin_rejected() {
+    let (status, errors) = handshake(3077, true, "http://evil.com");
+    assert_eq!(status, "HTTP/1.1 403 Forbidden");
+    assert_eq!(
+        errors,
+        vec!["Origin http://evil.com does not match host 127.0.0.1:3077."]
+    );
+}
+
+#[test]
+fn cross_origin_accepted_by_default() {
+    let (status, _) = handshake(3078, false, "http://evil.com");
+    assert_eq!(status, "HTTP/1.1 101 Switching Protocols");
+}
+
+#[test]
+fn same_origin_accepted() {
+    let (status, errors) = handshake(3079, true, "http://127.0.0.1:3079");
+    assert_eq!(status, "HTTP/1.1 101 Switching Protocols");
+    assert!(errors.is_empty());
+}
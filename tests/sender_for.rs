@@ -0,0 +1,63 @@
+extern crate ws;
+
+use std::sync::mpsc;
+use std::thread;
+
+use ws::util::Token;
+use ws::{Builder, CloseCode, Handshake, Message, Result, Sender};
+
+struct Server {
+    out: Sender,
+    opened: mpsc::Sender<(Token, u64)>,
+}
+
+impl ws::Handler for Server {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.opened
+            .send((self.out.token(), self.out.connection_id()))
+            .unwrap();
+        Ok(())
+    }
+}
+
+#[test]
+fn send_to_a_single_connection() {
+    let (tx, opened) = mpsc::channel();
+    let server = Builder::new()
+        .spawn_local(move || {
+            move |out| Server {
+                out,
+                opened: tx.clone(),
+            }
+        })
+        .unwrap();
+
+    let (tx, received) = mpsc::channel();
+    let url = server.url().to_string();
+    let client = thread::spawn(move || {
+        ws::connect(url, |out| {
+            let tx = tx.clone();
+            move |msg: Message| {
+                tx.send(msg.into_text()?).unwrap();
+                out.close(CloseCode::Normal)
+            }
+        }).unwrap();
+    });
+
+    let (token, connection_id) = opened.recv().unwrap();
+    let broadcaster = server.broadcaster();
+    // a sender for a connection that is gone reaches nobody
+    broadcaster
+        .sender_for(token, connection_id.wrapping_add(1))
+        .send("stale")
+        .unwrap();
+    broadcaster
+        .sender_for(token, connection_id)
+        .send("direct")
+        .unwrap();
+
+    client.join().unwrap();
+    assert_eq!(received.try_iter().collect::<Vec<_>>(), vec!["direct"]);
+
+    server.stop().unwrap();
+}
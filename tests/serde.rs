@@ -0,0 +1,50 @@
+#![cfg(feature = "serde")]
+extern crate serde_json;
+extern crate ws;
+
+use ws::{CloseCode, Interface, OpCode, Settings};
+
+#[test]
+fn close_code_round_trip() {
+    for code in &[CloseCode::Normal, CloseCode::Policy, CloseCode::Other(4000)] {
+        let json = serde_json::to_string(code).unwrap();
+        assert_eq!(serde_json::from_str::<CloseCode>(&json).unwrap(), *code);
+    }
+    assert_eq!(serde_json::to_string(&CloseCode::Away).unwrap(), "1001");
+}
+
+#[test]
+fn opcode_round_trip() {
+    let json = serde_json::to_string(&OpCode::Text).unwrap();
+    assert_eq!(serde_json::from_str::<OpCode>(&json).unwrap(), OpCode::Text);
+}
+
+#[test]
+fn settings_from_partial_config() {
+    let settings: Settings =
+        serde_json::from_str(r#"{"max_connections": 10000, "tcp_nodelay": true}"#).unwrap();
+    assert_eq!(settings.max_connections, 10000);
+    assert!(settings.tcp_nodelay);
+    assert_eq!(settings.queue_size, Settings::default().queue_size);
+
+    let json = serde_json::to_string(&settings).unwrap();
+    let round_trip: Settings = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip.max_connections, 10000);
+}
+
+#[test]
+fn interface_as_name() {
+    let settings: Settings = serde_json::from_str(r#"{"local_interface": "eth0"}"#).unwrap();
+    assert_eq!(
+        settings.local_interface,
+        Some(Interface::new("eth0").unwrap())
+    );
+    assert_eq!(
+        serde_json::to_string(&Interface::new("eth0").unwrap()).unwrap(),
+        r#""eth0""#
+    );
+
+    assert!(serde_json::from_str::<Interface>(r#""""#).is_err());
+    assert!(serde_json::from_str::<Interface>(r#""a-very-long-interface""#).is_err());
+    assert!(serde_json::from_str::<Interface>(r#"{"name": [0], "len": 200}"#).is_err());
+}
@@ -0,0 +1,90 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use ws::{CloseCode, Factory, Handler, Handshake, Message, Result, Sender, Settings};
+
+const PORT: u16 = 3060;
+
+struct Peer {
+    out: Sender,
+    client: bool,
+    log: Rc<RefCell<Vec<String>>>,
+}
+
+impl Handler for Peer {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        if self.client {
+            Ok(())
+        } else {
+            self.out.send("0123456789")
+        }
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        assert!(self.client);
+        self.log.borrow_mut().push(msg.into_text()?);
+        self.out.send("hello world")
+    }
+
+    fn on_close(&mut self, code: CloseCode, _: &str) {
+        if self.client {
+            self.log.borrow_mut().push(format!("{:?}", code));
+            self.out.shutdown().unwrap();
+        }
+    }
+}
+
+struct Tailored {
+    log: Rc<RefCell<Vec<String>>>,
+}
+
+impl Factory for Tailored {
+    type Handler = Peer;
+
+    fn connection_made(&mut self, _: Sender) -> Peer {
+        unreachable!()
+    }
+
+    fn client_connected(&mut self, out: Sender) -> Peer {
+        Peer {
+            out,
+            client: true,
+            log: self.log.clone(),
+        }
+    }
+
+    fn server_connected(&mut self, out: Sender) -> Peer {
+        Peer {
+            out,
+            client: false,
+            log: self.log.clone(),
+        }
+    }
+
+    fn settings_for(&mut self, addr: &SocketAddr, default: Settings) -> Settings {
+        if addr.port() == PORT {
+            // the client connecting to the server keeps the defaults
+            default
+        } else {
+            Settings {
+                max_fragment_size: 4,
+                ..default
+            }
+        }
+    }
+}
+
+#[test]
+fn per_connection_settings() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut ws = ws::WebSocket::new(Tailored { log: log.clone() }).unwrap();
+    let url = url::Url::parse(&format!("ws://127.0.0.1:{}", PORT)).unwrap();
+    ws.connect(url).unwrap();
+    ws.listen(("127.0.0.1", PORT)).unwrap();
+
+    assert_eq!(*log.borrow(), vec!["0123456789", "Protocol"]);
+}
@@ -0,0 +1,98 @@
+extern crate ws;
+
+use ws::{Builder, ErrorKind, Settings};
+
+fn check(settings: Settings, setting: &str) {
+    let err = settings.validate().unwrap_err();
+    match err.kind {
+        ErrorKind::Internal => (),
+        ref kind => panic!("unexpected error kind {:?}", kind),
+    }
+    assert!(
+        err.to_string().contains(setting),
+        "{:?} does not mention {}",
+        err,
+        setting
+    );
+    assert!(Builder::new()
+        .with_settings(settings)
+        .build(|_| |_| Ok(()))
+        .is_err());
+}
+
+#[test]
+fn defaults_and_presets_are_valid() {
+    Settings::default().validate().unwrap();
+    Settings::for_low_latency().validate().unwrap();
+    Settings::for_high_throughput().validate().unwrap();
+    Settings::for_constrained_memory().validate().unwrap();
+}
+
+#[test]
+fn invalid_combinations() {
+    let defaults = Settings::default();
+    check(
+        Settings {
+            queue_size: 0,
+            ..defaults
+        },
+        "queue_size",
+    );
+    check(
+        Settings {
+            max_connections: usize::MAX / 2,
+            queue_size: 3,
+            ..defaults
+        },
+        "max_connections",
+    );
+    check(
+        Settings {
+            fragment_size: 0,
+            ..defaults
+        },
+        "fragment_size",
+    );
+    check(
+        Settings {
+            text_fragment_size: Some(0),
+            ..defaults
+        },
+        "text_fragment_size",
+    );
+    check(
+        Settings {
+            binary_fragment_size: Some(0),
+            ..defaults
+        },
+        "binary_fragment_size",
+    );
+    check(
+        Settings {
+            in_buffer_capacity: ws::limits::MAX_FRAME_HEADER - 1,
+            ..defaults
+        },
+        "in_buffer_capacity",
+    );
+    check(
+        Settings {
+            max_messages_per_read: 0,
+            ..defaults
+        },
+        "max_messages_per_read",
+    );
+    check(
+        Settings {
+            timer_tick_ms: 0,
+            ..defaults
+        },
+        "timer_tick_ms",
+    );
+    check(
+        Settings {
+            max_close_reason: ws::limits::MAX_CLOSE_REASON + 1,
+            ..defaults
+        },
+        "max_close_reason",
+    );
+}
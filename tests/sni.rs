@@ -0,0 +1,167 @@
+#![cfg(feature = "ssl")]
+extern crate openssl;
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::TcpStream as StdTcpStream;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslConnector, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::{X509Builder, X509NameBuilder};
+use ws::util::TcpStream;
+use ws::{Builder, LocalServer, Settings};
+
+fn acceptor() -> SslAcceptor {
+    let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+
+    let mut cert = X509Builder::new().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&pkey).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    cert.set_serial_number(&serial).unwrap();
+    cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+    let cert = cert.build();
+
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    builder.set_private_key(&pkey).unwrap();
+    builder.set_certificate(&cert).unwrap();
+    builder.build()
+}
+
+// Reports the server name given to the upgrade and to the handshake.
+struct Handler {
+    ssl: Rc<SslAcceptor>,
+    names: mpsc::Sender<(&'static str, Option<String>)>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, shake: ws::Handshake) -> ws::Result<()> {
+        self.names.send(("open", shake.server_name)).unwrap();
+        Ok(())
+    }
+
+    fn upgrade_ssl_server(&mut self, _: TcpStream) -> ws::Result<SslStream<TcpStream>> {
+        panic!("the server name is read before upgrading");
+    }
+
+    fn upgrade_ssl_server_for(
+        &mut self,
+        sock: TcpStream,
+        server_name: Option<&str>,
+    ) -> ws::Result<SslStream<TcpStream>> {
+        self.names
+            .send(("upgrade", server_name.map(Into::into)))
+            .unwrap();
+        self.ssl.accept(sock).map_err(From::from)
+    }
+}
+
+fn server(names: mpsc::Sender<(&'static str, Option<String>)>) -> LocalServer {
+    Builder::new()
+        .with_settings(Settings {
+            encrypt_server: true,
+            sniff_server_name: true,
+            ..Settings::default()
+        })
+        .spawn_local(move || {
+            let ssl = Rc::new(acceptor());
+            move |_| Handler {
+                ssl: ssl.clone(),
+                names: names.clone(),
+            }
+        })
+        .unwrap()
+}
+
+#[test]
+fn server_name_is_read() {
+    let (tx, names) = mpsc::channel();
+    let server = server(tx);
+
+    let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+    builder.set_verify(SslVerifyMode::empty());
+    let mut stream = builder
+        .build()
+        .configure()
+        .unwrap()
+        .verify_hostname(false)
+        .connect(
+            "tenant.example",
+            StdTcpStream::connect(server.addr()).unwrap(),
+        )
+        .unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = [0; 12];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(&response, b"HTTP/1.1 101");
+
+    let name = Some("tenant.example".to_string());
+    assert_eq!(names.recv().unwrap(), ("upgrade", name.clone()));
+    assert_eq!(names.recv().unwrap(), ("open", name));
+
+    drop(stream);
+    server.stop().unwrap();
+}
+
+// A ClientHello with only the server_name extension, which is enough to be read although it does
+// not complete a TLS handshake.
+fn client_hello(name: &[u8]) -> Vec<u8> {
+    let mut extension = vec![0, 0, 0, name.len() as u8 + 5, 0, name.len() as u8 + 3];
+    extension.extend_from_slice(&[0, 0, name.len() as u8]);
+    extension.extend_from_slice(name);
+
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&[7; 32]);
+    body.extend_from_slice(&[0, 0, 2, 0x13, 0x01, 1, 0, 0, extension.len() as u8]);
+    body.extend_from_slice(&extension);
+
+    let mut record = vec![0x16, 0x03, 0x01, 0, body.len() as u8 + 4];
+    record.extend_from_slice(&[0x01, 0, 0, body.len() as u8]);
+    record.extend_from_slice(&body);
+    record
+}
+
+#[test]
+fn split_client_hello_is_read() {
+    let (tx, names) = mpsc::channel();
+    let server = server(tx);
+
+    let hello = client_hello(b"split.example");
+    let mut stream = StdTcpStream::connect(server.addr()).unwrap();
+    stream.write_all(&hello[..20]).unwrap();
+    sleep(Duration::from_millis(150));
+    stream.write_all(&hello[20..]).unwrap();
+
+    assert_eq!(
+        names.recv().unwrap(),
+        ("upgrade", Some("split.example".to_string()))
+    );
+
+    drop(stream);
+    server.stop().unwrap();
+}
@@ -0,0 +1,52 @@
+#![cfg(unix)]
+extern crate url;
+extern crate ws;
+
+use std::io;
+use std::net::TcpListener;
+use std::sync::mpsc;
+
+use ws::{Builder, Error, ErrorKind};
+
+struct Client {
+    errors: mpsc::Sender<String>,
+}
+
+impl ws::Handler for Client {
+    fn on_socket_error(&mut self, err: io::Error) {
+        self.errors
+            .send(format!("socket: {:?}", err.kind()))
+            .unwrap();
+    }
+
+    fn on_error(&mut self, err: Error) {
+        let kind = match err.kind {
+            ErrorKind::Io(ref err) => format!("{:?}", err.kind()),
+            ref other => format!("{:?}", other),
+        };
+        self.errors.send(format!("error: {}", kind)).unwrap();
+    }
+}
+
+// A refused connection is reported with its cause rather than as a failure to write the
+// handshake to a socket that is not connected.
+#[test]
+fn connection_refused() {
+    let addr = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+
+    let (tx, errors) = mpsc::channel();
+    let mut ws = Builder::new()
+        .build(move |_| Client { errors: tx.clone() })
+        .unwrap();
+    ws.connect(url::Url::parse(&format!("ws://{}", addr)).unwrap())
+        .unwrap();
+    ws.run().unwrap();
+
+    assert_eq!(
+        errors.try_iter().collect::<Vec<_>>(),
+        vec!["socket: ConnectionRefused", "error: ConnectionRefused"]
+    );
+}
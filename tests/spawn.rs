@@ -0,0 +1,39 @@
+extern crate ws;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+#[test]
+fn spawn_with_local_state() {
+    let (broadcaster, handle) = ws::Builder::new()
+        .spawn("127.0.0.1:3031", || {
+            let messages = Rc::new(Cell::new(0));
+            move |out: ws::Sender| {
+                let messages = messages.clone();
+                move |msg: ws::Message| {
+                    messages.set(messages.get() + 1);
+                    assert_eq!(messages.get(), 1);
+                    out.send(msg)
+                }
+            }
+        })
+        .unwrap();
+
+    ws::connect("ws://127.0.0.1:3031", |out| {
+        out.send("Hello").unwrap();
+        move |msg: ws::Message| {
+            assert_eq!(msg.as_text()?, "Hello");
+            out.close(ws::CloseCode::Normal)
+        }
+    })
+    .unwrap();
+
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+}
+
+#[test]
+fn spawn_bind_failure() {
+    let result = ws::Builder::new().spawn("256.0.0.1:0", || |_| |_| Ok(()));
+    assert!(result.is_err());
+}
@@ -0,0 +1,51 @@
+extern crate ws;
+
+use std::net::TcpStream;
+use std::sync::mpsc;
+
+use ws::{Builder, CloseCode, Message, Sender};
+
+#[test]
+fn spawn_local_echo() {
+    let server = Builder::new()
+        .spawn_local(|| |out: Sender| move |msg| out.send(msg))
+        .unwrap();
+    assert!(server.addr().ip().is_loopback());
+    assert_ne!(server.addr().port(), 0);
+    assert_eq!(server.url().port(), Some(server.addr().port()));
+
+    let (tx, rx) = mpsc::channel();
+    ws::connect(server.url().as_str(), |out| {
+        out.send("echo").unwrap();
+        let tx = tx.clone();
+        move |msg: Message| {
+            tx.send(msg).unwrap();
+            out.close(CloseCode::Normal)
+        }
+    }).unwrap();
+    assert_eq!(rx.recv().unwrap(), Message::text("echo"));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn drop_stops_server() {
+    let addr = {
+        let server = Builder::new()
+            .spawn_local(|| |_: Sender| |_| Ok(()))
+            .unwrap();
+        TcpStream::connect(server.addr()).unwrap();
+        server.addr()
+    };
+    // the guard waited for the event loop to finish, so the listener is closed
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn stop_after_shutdown() {
+    let server = Builder::new()
+        .spawn_local(|| |_: Sender| |_| Ok(()))
+        .unwrap();
+    server.broadcaster().shutdown().unwrap();
+    server.stop().unwrap();
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "testing")]
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ws::testing::VirtualLoop;
+use ws::{Message, Result, Sender};
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+struct Recorder {
+    log: Log,
+    borrow: bool,
+}
+
+impl ws::Handler for Recorder {
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        self.log.borrow_mut().push(format!("owned {}", msg));
+        Ok(())
+    }
+
+    fn on_text_borrowed(&mut self, text: &str) -> Result<()> {
+        self.log.borrow_mut().push(format!("borrowed {}", text));
+        Ok(())
+    }
+
+    fn borrows_text(&self) -> bool {
+        self.borrow
+    }
+}
+
+fn deliver(borrow: bool) -> Vec<String> {
+    let log = Log::default();
+    let senders = Rc::new(RefCell::new(Vec::new()));
+    let mut virt = {
+        let log = log.clone();
+        let senders = senders.clone();
+        VirtualLoop::new(move |out: Sender| {
+            senders.borrow_mut().push(out);
+            Recorder {
+                log: log.clone(),
+                borrow,
+            }
+        })
+    };
+    virt.connect("ws://example.com/").unwrap();
+
+    let client = senders.borrow()[0].clone();
+    client.send("whole").unwrap();
+    client.send_with_fragment_size("fragmented", 4).unwrap();
+    virt.run_until_idle();
+    let log = log.borrow().clone();
+    log
+}
+
+#[test]
+fn owned_text() {
+    assert_eq!(deliver(false), vec!["owned whole", "owned fragmented"]);
+}
+
+#[test]
+fn borrowed_text() {
+    assert_eq!(deliver(true), vec!["borrowed whole", "borrowed fragmented"]);
+}
@@ -0,0 +1,75 @@
+extern crate ws;
+
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use ws::util::Token;
+use ws::{Builder, CloseCode, ErrorKind, Handshake, Result, Sender, Settings};
+
+const PROBE: Token = Token(1);
+
+struct Client {
+    out: Sender,
+    started: Option<Instant>,
+    elapsed: mpsc::Sender<Duration>,
+}
+
+impl ws::Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        self.started = Some(Instant::now());
+        self.out.timeout(5, PROBE)
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<()> {
+        let started = self
+            .started
+            .expect("timeout fired before the connection opened");
+        self.elapsed.send(started.elapsed()).unwrap();
+        self.out.close(CloseCode::Normal)
+    }
+}
+
+#[test]
+fn short_timeouts_with_short_ticks() {
+    let server = Builder::new().spawn_local(|| |_| |_| Ok(())).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let mut ws = Builder::new()
+        .with_settings(Settings {
+            timer_tick_ms: 1,
+            ..Settings::default()
+        })
+        .build(move |out| Client {
+            out,
+            started: None,
+            elapsed: tx.clone(),
+        })
+        .unwrap();
+    ws.connect(server.url()).unwrap();
+    ws.run().unwrap();
+
+    // with the default tick of 100 ms the timeout would fire about 100 ms late
+    let elapsed = rx.recv().unwrap();
+    assert!(elapsed >= Duration::from_millis(3), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(60), "{:?}", elapsed);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn zero_tick_is_rejected() {
+    let result = Builder::new()
+        .with_settings(Settings {
+            timer_tick_ms: 0,
+            ..Settings::default()
+        })
+        .build(|_| |_| Ok(()));
+    match result {
+        Err(ws::Error {
+            kind: ErrorKind::Internal,
+            ..
+        }) => (),
+        Err(err) => panic!("unexpected error {:?}", err),
+        Ok(_) => panic!("a timer tick of 0 was accepted"),
+    }
+}
@@ -0,0 +1,51 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+struct Handler {
+    out: ws::Sender,
+    opened: Rc<Cell<usize>>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, shake: ws::Handshake) -> ws::Result<()> {
+        let timings = shake.timings();
+        let request = timings.request.unwrap();
+        let response = timings.response.unwrap();
+
+        assert!(timings.tls_established.is_none());
+        assert!(timings.started <= request);
+        assert!(request <= response);
+        assert_eq!(timings.elapsed(), Some(response - timings.started));
+        assert_eq!(shake.connected_at(), timings.started);
+
+        // The sender was created as the connection was accepted or initiated
+        assert!(self.out.connected_at() <= shake.connected_at());
+        assert_eq!(self.out.clone().connected_at(), self.out.connected_at());
+        assert!(self.out.uptime() >= response - timings.started);
+
+        // Both the server and the client endpoint have opened
+        self.opened.set(self.opened.get() + 1);
+        if self.opened.get() == 2 {
+            self.out.shutdown()?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn handshake_timings() {
+    let opened = Rc::new(Cell::new(0));
+    let mut ws = ws::WebSocket::new(|out| Handler {
+        out,
+        opened: opened.clone(),
+    })
+    .unwrap();
+    let url = url::Url::parse("ws://127.0.0.1:3032").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3032").unwrap();
+
+    assert_eq!(opened.get(), 2);
+}
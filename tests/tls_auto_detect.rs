@@ -0,0 +1,148 @@
+#![cfg(feature = "ssl")]
+extern crate openssl;
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslConnector, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::{X509Builder, X509NameBuilder};
+use ws::util::TcpStream;
+
+fn acceptor() -> SslAcceptor {
+    let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+
+    let mut cert = X509Builder::new().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&pkey).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    cert.set_serial_number(&serial).unwrap();
+    cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+    let cert = cert.build();
+
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    builder.set_private_key(&pkey).unwrap();
+    builder.set_certificate(&cert).unwrap();
+    builder.build()
+}
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+// Clients send the scheme that they connected with and servers echo it back.
+struct Handler {
+    out: ws::Sender,
+    ssl: Rc<SslAcceptor>,
+    log: Log,
+    scheme: Option<String>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        match self.scheme {
+            Some(ref scheme) => self.out.send(&scheme[..]),
+            None => Ok(()),
+        }
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        if self.scheme.is_none() {
+            return self.out.send(msg);
+        }
+        self.log.borrow_mut().push(msg.into_text()?);
+        if self.log.borrow().len() == 2 {
+            self.out.shutdown()?;
+        }
+        Ok(())
+    }
+
+    fn upgrade_ssl_server(&mut self, sock: TcpStream) -> ws::Result<SslStream<TcpStream>> {
+        self.ssl.accept(sock).map_err(From::from)
+    }
+
+    fn upgrade_ssl_client(
+        &mut self,
+        sock: TcpStream,
+        url: &url::Url,
+    ) -> ws::Result<SslStream<TcpStream>> {
+        self.scheme = Some(url.scheme().into());
+        let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+        builder.set_verify(SslVerifyMode::empty());
+        builder
+            .build()
+            .configure()
+            .unwrap()
+            .use_server_name_indication(false)
+            .verify_hostname(false)
+            .connect("", sock)
+            .map_err(From::from)
+    }
+}
+
+struct Factory {
+    ssl: Rc<SslAcceptor>,
+    log: Log,
+}
+
+impl Factory {
+    fn handler(&self, out: ws::Sender, scheme: Option<String>) -> Handler {
+        Handler {
+            out,
+            ssl: self.ssl.clone(),
+            log: self.log.clone(),
+            scheme,
+        }
+    }
+}
+
+impl ws::Factory for Factory {
+    type Handler = Handler;
+
+    fn connection_made(&mut self, out: ws::Sender) -> Handler {
+        self.handler(out, None)
+    }
+
+    fn client_connected(&mut self, out: ws::Sender) -> Handler {
+        // wss clients replace the scheme once they are upgraded
+        self.handler(out, Some("ws".into()))
+    }
+}
+
+#[test]
+fn serve_both_schemes() {
+    let log = Log::default();
+    let mut ws = ws::Builder::new()
+        .with_settings(ws::Settings {
+            tls_auto_detect: true,
+            ..ws::Settings::default()
+        })
+        .build(Factory {
+            ssl: Rc::new(acceptor()),
+            log: log.clone(),
+        })
+        .unwrap();
+
+    ws.connect(url::Url::parse("ws://127.0.0.1:3068").unwrap())
+        .unwrap();
+    ws.connect(url::Url::parse("wss://127.0.0.1:3068").unwrap())
+        .unwrap();
+    ws.listen("127.0.0.1:3068").unwrap();
+
+    let mut log = log.borrow().clone();
+    log.sort();
+    assert_eq!(log, vec!["ws".to_string(), "wss".to_string()]);
+}
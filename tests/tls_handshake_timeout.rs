@@ -0,0 +1,142 @@
+#![cfg(feature = "ssl")]
+extern crate openssl;
+extern crate url;
+extern crate ws;
+
+use std::cell::Cell;
+use std::io::Read;
+use std::net;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslConnector, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::{X509Builder, X509NameBuilder};
+use ws::util::{TcpStream, Token};
+
+const CHECK: Token = Token(1);
+
+fn acceptor() -> SslAcceptor {
+    let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+
+    let mut cert = X509Builder::new().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&pkey).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    cert.set_serial_number(&serial).unwrap();
+    cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+    let cert = cert.build();
+
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    builder.set_private_key(&pkey).unwrap();
+    builder.set_certificate(&cert).unwrap();
+    builder.build()
+}
+
+struct Handler {
+    out: ws::Sender,
+    ssl: Rc<SslAcceptor>,
+    timed_out: Rc<Cell<bool>>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // The first connection is the client, which outlives the stalled peer
+        if self.out.connection_id() == 0 {
+            self.out.timeout(1_000, CHECK)?;
+        }
+        Ok(())
+    }
+
+    fn on_timeout(&mut self, _: Token) -> ws::Result<()> {
+        self.out.send("still open")
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        if self.out.connection_id() == 0 {
+            assert!(self.timed_out.get());
+            self.out.shutdown()
+        } else {
+            self.out.send(msg)
+        }
+    }
+
+    fn on_error(&mut self, err: ws::Error) {
+        match err.kind {
+            ws::ErrorKind::TlsTimeout => self.timed_out.set(true),
+            _ => panic!("Unexpected error {}", err),
+        }
+    }
+
+    fn upgrade_ssl_server(&mut self, sock: TcpStream) -> ws::Result<SslStream<TcpStream>> {
+        self.ssl.accept(sock).map_err(From::from)
+    }
+
+    fn upgrade_ssl_client(
+        &mut self,
+        sock: TcpStream,
+        _: &url::Url,
+    ) -> ws::Result<SslStream<TcpStream>> {
+        let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+        builder.set_verify(SslVerifyMode::empty());
+        builder
+            .build()
+            .configure()
+            .unwrap()
+            .use_server_name_indication(false)
+            .verify_hostname(false)
+            .connect("", sock)
+            .map_err(From::from)
+    }
+}
+
+#[test]
+fn stalled_tls_handshake() {
+    let ssl = Rc::new(acceptor());
+    let timed_out = Rc::new(Cell::new(false));
+    let handler_timed_out = timed_out.clone();
+
+    let mut ws = ws::Builder::new()
+        .with_settings(ws::Settings {
+            encrypt_server: true,
+            tls_handshake_timeout_ms: 300,
+            ..ws::Settings::default()
+        })
+        .build(move |out: ws::Sender| Handler {
+            out,
+            ssl: ssl.clone(),
+            timed_out: handler_timed_out.clone(),
+        })
+        .unwrap();
+
+    // a peer that connects but never sends a client hello
+    let stalled = thread::spawn(|| {
+        thread::sleep(Duration::from_millis(100));
+        let mut stream = net::TcpStream::connect("127.0.0.1:3054").unwrap();
+        let started = Instant::now();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        started.elapsed()
+    });
+
+    let url = url::Url::parse("wss://127.0.0.1:3054").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3054").unwrap();
+
+    assert!(timed_out.get());
+    assert!(stalled.join().unwrap() < Duration::from_secs(1));
+}
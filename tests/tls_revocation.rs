@@ -0,0 +1,47 @@
+#![cfg(feature = "ssl")]
+extern crate url;
+extern crate ws;
+
+use std::net;
+
+use ws::WsEvent;
+
+// Connects a client configured by `configure` and returns the details of the first error that it
+// reports.
+fn connect<C>(configure: C) -> String
+where
+    C: FnOnce(&mut ws::Builder),
+{
+    // the client fails before it starts the handshake, so the listener never has to answer
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = url::Url::parse(&format!("wss://{}", listener.local_addr().unwrap())).unwrap();
+
+    let mut builder = ws::Builder::new();
+    builder.with_tls_server_name("localhost");
+    configure(&mut builder);
+    let mut ws = builder.build(|_| |_| Ok(())).unwrap();
+    let events = ws.subscribe_events();
+    ws.connect(url).unwrap();
+    ws.run().unwrap();
+
+    events
+        .try_iter()
+        .filter_map(|event| match event {
+            WsEvent::Error { details, .. } => Some(details),
+            _ => None,
+        })
+        .next()
+        .expect("the connection did not fail")
+}
+
+#[test]
+fn missing_crl_file() {
+    let details = connect(|builder| {
+        builder.with_tls_crl_file("/nonexistent/revoked.pem");
+    });
+    assert!(
+        details.contains("Failed to load CRL file /nonexistent/revoked.pem"),
+        "{}",
+        details
+    );
+}
@@ -0,0 +1,75 @@
+extern crate ws;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+#[test]
+fn broadcaster_tokens() {
+    let (broadcaster, handle) = ws::Builder::new()
+        .spawn("127.0.0.1:3036", || {
+            |out: ws::Sender| move |msg: ws::Message| out.send(msg)
+        })
+        .unwrap();
+    assert!(broadcaster.tokens().unwrap().is_empty());
+
+    let server = broadcaster.clone();
+    ws::connect("ws://127.0.0.1:3036", |out| {
+        out.send("Hello").unwrap();
+        let server = server.clone();
+        move |_| {
+            // the server has answered, so the connection is open on its side
+            assert_eq!(server.tokens()?.len(), 1);
+            out.close(ws::CloseCode::Normal)
+        }
+    })
+    .unwrap();
+
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+    assert!(broadcaster.tokens().is_err());
+}
+
+#[test]
+fn broadcaster_close_tokens() {
+    let (broadcaster, handle) = ws::Builder::new()
+        .spawn("127.0.0.1:3071", || {
+            |out: ws::Sender| move |msg: ws::Message| out.send(msg)
+        })
+        .unwrap();
+
+    struct Client {
+        out: ws::Sender,
+        server: ws::Sender,
+        closed: Rc<Cell<bool>>,
+    }
+
+    impl ws::Handler for Client {
+        fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+            self.out.send("Hello")
+        }
+
+        fn on_message(&mut self, _: ws::Message) -> ws::Result<()> {
+            let tokens = self.server.tokens()?;
+            self.server
+                .close_tokens(tokens, ws::CloseCode::Policy, "kicked")
+        }
+
+        fn on_close(&mut self, code: ws::CloseCode, reason: &str) {
+            assert_eq!(code, ws::CloseCode::Policy);
+            assert_eq!(reason, "kicked");
+            self.closed.set(true);
+        }
+    }
+
+    let closed = Rc::new(Cell::new(false));
+    ws::connect("ws://127.0.0.1:3071", |out| Client {
+        out,
+        server: broadcaster.clone(),
+        closed: closed.clone(),
+    })
+    .unwrap();
+
+    assert!(closed.get());
+    broadcaster.shutdown().unwrap();
+    assert!(handle.join().unwrap().is_ok());
+}
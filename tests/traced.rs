@@ -0,0 +1,67 @@
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct Trace {
+    sent: Vec<ws::MessageId>,
+    written: Vec<ws::MessageId>,
+}
+
+struct Handler {
+    out: ws::Sender,
+    trace: Rc<RefCell<Trace>>,
+    echoes: usize,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        // The first connection is the client
+        if self.out.connection_id() == 0 {
+            for text in &["one", "two", "three"] {
+                let id = self.out.send_traced(*text)?;
+                self.trace.borrow_mut().sent.push(id);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        if self.out.connection_id() == 0 {
+            self.echoes += 1;
+            if self.echoes == 3 {
+                return self.out.shutdown();
+            }
+            Ok(())
+        } else {
+            self.out.send(msg)
+        }
+    }
+
+    fn on_message_written(&mut self, meta: ws::MessageMeta) {
+        assert!(meta.enqueued.elapsed().as_secs() < 10);
+        self.trace.borrow_mut().written.push(meta.id);
+    }
+}
+
+#[test]
+fn message_written() {
+    let trace = Rc::new(RefCell::new(Trace::default()));
+    let handler_trace = trace.clone();
+
+    let mut ws = ws::WebSocket::new(move |out: ws::Sender| Handler {
+        out,
+        trace: handler_trace.clone(),
+        echoes: 0,
+    }).unwrap();
+
+    let url = url::Url::parse("ws://127.0.0.1:3044").unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3044").unwrap();
+
+    let trace = trace.borrow();
+    assert_eq!(trace.sent.len(), 3);
+    assert_eq!(trace.written, trace.sent);
+}
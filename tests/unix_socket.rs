@@ -0,0 +1,59 @@
+#![cfg(unix)]
+extern crate url;
+extern crate ws;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::net::TcpStream;
+use std::os::unix::net::UnixListener;
+use std::thread;
+
+struct Handler {
+    out: ws::Sender,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, shake: ws::Handshake) -> ws::Result<()> {
+        // The first connection is the client
+        if self.out.connection_id() == 0 {
+            self.out.send("over a unix socket")
+        } else {
+            assert_eq!(shake.request.resource(), "/chat?room=1");
+            Ok(())
+        }
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        if self.out.connection_id() == 0 {
+            assert_eq!(msg.as_text()?, "over a unix socket");
+            self.out.shutdown()
+        } else {
+            self.out.send(msg)
+        }
+    }
+}
+
+#[test]
+fn connect_unix_socket() {
+    let path = env::temp_dir().join(format!("ws-rs-test-{}.sock", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    // Relay the unix socket to the TCP listener of the WebSocket
+    thread::spawn(move || {
+        let (unix, _) = listener.accept().unwrap();
+        let tcp = TcpStream::connect("127.0.0.1:3045").unwrap();
+        let (mut unix_read, mut tcp_write) = (unix.try_clone().unwrap(), tcp.try_clone().unwrap());
+        thread::spawn(move || io::copy(&mut unix_read, &mut tcp_write));
+        let (mut tcp_read, mut unix_write) = (tcp, unix);
+        let _ = io::copy(&mut tcp_read, &mut unix_write);
+    });
+
+    let mut ws = ws::WebSocket::new(|out| Handler { out }).unwrap();
+    let url = url::Url::parse(&format!("ws+unix://{}:/chat?room=1", path.display())).unwrap();
+    ws.connect(url).unwrap();
+    ws.listen("127.0.0.1:3045").unwrap();
+
+    fs::remove_file(&path).unwrap();
+}
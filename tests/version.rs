@@ -0,0 +1,102 @@
+extern crate ws;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{channel, Sender as ChannelSender};
+use std::thread;
+use std::time::Duration;
+
+use ws::{Handshake, Result, Settings, Version};
+
+struct Server {
+    versions: ChannelSender<Version>,
+}
+
+impl ws::Handler for Server {
+    fn on_open(&mut self, shake: Handshake) -> Result<()> {
+        self.versions.send(shake.version).unwrap();
+        Ok(())
+    }
+}
+
+// Send a handshake request with the given version header and return the response head.
+fn handshake(settings: Settings, version: &str) -> (String, Option<Version>) {
+    let (tx, rx) = channel();
+    let (addr, socket) = ws::Builder::new()
+        .with_settings(settings)
+        .build(move |_| Server {
+            versions: tx.clone(),
+        })
+        .unwrap()
+        .listen_local()
+        .unwrap();
+
+    let handle = socket.broadcaster();
+    let t = thread::spawn(move || {
+        socket.run().unwrap();
+    });
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(
+            format!(
+                "GET / HTTP/1.1\r\n\
+                 Connection: Upgrade\r\n\
+                 Upgrade: websocket\r\n\
+                 {}\
+                 Sec-WebSocket-Key: q16eN37NCfVwUChPvBdk4g==\r\n\r\n",
+                version
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+    let mut response = Vec::new();
+    let mut buf = [0; 512];
+    while !response.ends_with(b"\r\n\r\n") {
+        let len = stream.read(&mut buf).unwrap();
+        if len == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..len]);
+    }
+    let opened = rx.recv_timeout(Duration::from_millis(500)).ok();
+
+    handle.shutdown().unwrap();
+    assert!(t.join().is_ok());
+    (String::from_utf8(response).unwrap(), opened)
+}
+
+#[test]
+fn unsupported_version() {
+    let strict = Settings {
+        version_strict: true,
+        ..Settings::default()
+    };
+    for version in &["Sec-WebSocket-Version: 8\r\n", ""] {
+        let (response, opened) = handshake(strict, version);
+        assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(response.contains("Sec-WebSocket-Version: 13\r\n"));
+        assert_eq!(opened, None);
+    }
+}
+
+#[test]
+fn lenient_version() {
+    for version in &["Sec-WebSocket-Version: 8\r\n", ""] {
+        let (response, opened) = handshake(Settings::default(), version);
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert_eq!(opened, Some(Version::Rfc6455));
+    }
+}
+
+#[test]
+fn supported_version() {
+    let strict = Settings {
+        version_strict: true,
+        ..Settings::default()
+    };
+    let (response, opened) = handshake(strict, "Sec-WebSocket-Version: 13\r\n");
+    assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert_eq!(opened, Some(Version::Rfc6455));
+}
@@ -0,0 +1,206 @@
+#![cfg(feature = "testing")]
+extern crate url;
+extern crate ws;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use ws::testing::VirtualLoop;
+use ws::util::{Timeout, Token};
+use ws::{CloseCode, Frame, Handshake, Message, OpCode, Result, Sender};
+
+const PING: Token = Token(1);
+const IDLE: Token = Token(2);
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+// Clients greet the server and ping every five seconds, servers echo messages and close
+// connections that have been idle for twelve seconds.
+struct Peer {
+    out: Sender,
+    log: Log,
+    client: bool,
+    idle: Option<Timeout>,
+}
+
+impl ws::Handler for Peer {
+    fn on_open(&mut self, _: Handshake) -> Result<()> {
+        if self.client {
+            self.out.send("hello")?;
+            self.out.timeout(5_000, PING)
+        } else {
+            self.out.timeout(12_000, IDLE)
+        }
+    }
+
+    fn on_new_timeout(&mut self, event: Token, timeout: Timeout) -> Result<()> {
+        if event == IDLE {
+            if let Some(old) = self.idle.take() {
+                self.out.cancel(old)?;
+            }
+            self.idle = Some(timeout);
+        }
+        Ok(())
+    }
+
+    fn on_timeout(&mut self, event: Token) -> Result<()> {
+        if event == PING {
+            self.out.ping(b"keepalive".to_vec())?;
+            self.out.timeout(5_000, PING)
+        } else {
+            self.log.borrow_mut().push("idle".into());
+            self.out.close(CloseCode::Away)
+        }
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Frame>> {
+        if self.client {
+            if frame.opcode() == OpCode::Pong {
+                self.log.borrow_mut().push("pong".into());
+            }
+        } else {
+            // any activity resets the idle timeout
+            self.out.timeout(12_000, IDLE)?;
+        }
+        Ok(Some(frame))
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        if self.client {
+            self.log.borrow_mut().push(msg.into_text()?);
+            Ok(())
+        } else {
+            self.out.send(msg)
+        }
+    }
+}
+
+struct Factory {
+    log: Log,
+}
+
+impl Factory {
+    fn peer(&self, out: Sender, client: bool) -> Peer {
+        Peer {
+            out,
+            log: self.log.clone(),
+            client,
+            idle: None,
+        }
+    }
+}
+
+impl ws::Factory for Factory {
+    type Handler = Peer;
+
+    fn connection_made(&mut self, out: Sender) -> Peer {
+        self.peer(out, false)
+    }
+
+    fn client_connected(&mut self, out: Sender) -> Peer {
+        self.peer(out, true)
+    }
+}
+
+fn setup() -> (VirtualLoop<Factory>, Log) {
+    let log = Log::default();
+    let virt = VirtualLoop::new(Factory { log: log.clone() });
+    (virt, log)
+}
+
+#[test]
+fn echo_without_time() {
+    let (mut virt, log) = setup();
+    virt.connect("ws://example.com/").unwrap();
+
+    assert_eq!(*log.borrow(), vec!["hello".to_string()]);
+    assert_eq!(virt.tokens().len(), 2);
+    assert_eq!(virt.now(), Duration::from_secs(0));
+}
+
+#[test]
+fn keepalive_pings() {
+    let (mut virt, log) = setup();
+    virt.connect("ws://example.com/").unwrap();
+
+    virt.advance(Duration::from_millis(4_999));
+    assert_eq!(log.borrow().len(), 1);
+
+    virt.advance(Duration::from_millis(1));
+    assert_eq!(*log.borrow(), vec!["hello".to_string(), "pong".to_string()]);
+
+    virt.advance(Duration::from_secs(20));
+    assert_eq!(log.borrow().iter().filter(|entry| *entry == "pong").count(), 5);
+    assert_eq!(virt.now(), Duration::from_secs(25));
+    // the pings keep cancelling the idle timeout of the server
+    assert_eq!(virt.tokens().len(), 2);
+}
+
+#[test]
+fn idle_timeout_closes() {
+    let log = Log::default();
+    let factory_log = log.clone();
+    let mut virt = VirtualLoop::new(move |out: Sender| Peer {
+        out,
+        log: factory_log.clone(),
+        client: false,
+        idle: None,
+    });
+    let (client, server) = virt.connect("ws://example.com/").unwrap();
+    assert_eq!(virt.tokens(), vec![client, server]);
+
+    virt.advance(Duration::from_millis(11_999));
+    assert!(log.borrow().is_empty());
+
+    virt.advance(Duration::from_millis(1));
+    // both ends run an idle timeout, but the closing handshake of the first one completes
+    // before the timeout of the other end fires
+    assert_eq!(*log.borrow(), vec!["idle".to_string()]);
+    assert!(virt.tokens().is_empty());
+}
+
+#[test]
+fn broadcast() {
+    let (mut virt, log) = setup();
+    virt.connect("ws://example.com/").unwrap();
+    virt.connect("ws://example.com/").unwrap();
+    assert_eq!(virt.tokens().len(), 4);
+
+    virt.broadcaster().send("all").unwrap();
+    virt.run_until_idle();
+    // each client receives the message from its server and the echo of its own copy
+    assert_eq!(
+        log.borrow().iter().filter(|entry| *entry == "all").count(),
+        4
+    );
+}
+
+#[test]
+fn queue_depth() {
+    let (mut virt, _) = setup();
+    let broadcaster = virt.broadcaster();
+    assert_eq!(broadcaster.send_with_feedback("one").unwrap(), 1);
+    assert_eq!(broadcaster.send_with_feedback("two").unwrap(), 2);
+
+    virt.run_until_idle();
+    assert_eq!(broadcaster.send_with_feedback("three").unwrap(), 1);
+}
+
+#[test]
+fn connect_from_unsupported() {
+    let (mut virt, _) = setup();
+    let url = url::Url::parse("ws://example.com/").unwrap();
+    let broadcaster = virt.broadcaster();
+
+    // there are no sockets to bind, so the request fails instead of ignoring the address
+    broadcaster
+        .connect_from(url.clone(), "127.0.0.1:0".parse().unwrap())
+        .unwrap();
+    virt.run_until_idle();
+    assert!(virt.tokens().is_empty());
+
+    broadcaster.connect(url).unwrap();
+    virt.run_until_idle();
+    assert_eq!(virt.tokens().len(), 2);
+}